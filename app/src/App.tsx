@@ -235,20 +235,34 @@ function App() {
     ingestChats(chats);
   }, [chats, ingestChats]);
 
-  // Callback when messages are pushed via WebSocket
-  const handleMessagesUpdate = useCallback(
+  // Callback when new or changed messages are pushed via WebSocket
+  const handleMessagesDelta = useCallback(
     (chatId: number, newMessages: Message[], total: number) => {
       applyWsUpdate(chatId, newMessages, total);
     },
     [applyWsUpdate],
   );
 
+  // Updates were lost; refetch instead of trusting the cache
+  const handleResync = useCallback(
+    (chatIds: number[], chatList: boolean) => {
+      for (const chatId of chatIds) {
+        queryClient.invalidateQueries({ queryKey: ["messages", chatId] });
+      }
+      if (chatList) {
+        queryClient.invalidateQueries({ queryKey: ["chats"] });
+      }
+    },
+    [queryClient],
+  );
+
   // Set up WebSocket connection
-  const { subscribe, connectionState } = useWebSocket({
-    onMessagesUpdate: handleMessagesUpdate,
-    onDbChanged: () => {
+  const { subscribe, unsubscribe, connectionState } = useWebSocket({
+    onMessagesDelta: handleMessagesDelta,
+    onChatListChanged: () => {
       queryClient.invalidateQueries({ queryKey: ["chats"] });
     },
+    onResync: handleResync,
   });
 
   const showToast = useCallback(
@@ -275,11 +289,12 @@ function App() {
   );
 
   // Subscribe to WebSocket updates when chat is selected
+  const subscribedChatId = selectedChat?.id;
   useEffect(() => {
-    if (selectedChat) {
-      subscribe(selectedChat.id);
-    }
-  }, [selectedChat, subscribe]);
+    if (subscribedChatId === undefined) return;
+    subscribe(subscribedChatId);
+    return () => unsubscribe(subscribedChatId);
+  }, [subscribedChatId, subscribe, unsubscribe]);
 
   const focusSearch = useCallback(() => {
     chatListRef.current?.focusSearch();
@@ -389,7 +404,7 @@ function App() {
 
       if (response.ok) {
         // Don't refetch - the WebSocket will push the updated messages
-        // and handleMessagesUpdate will merge them properly
+        // and handleMessagesDelta will merge them properly
       } else {
         // Remove the optimistic message on error
         rollbackOptimistic(tempMessageId);
@@ -959,22 +974,28 @@ function App() {
               isGroup={selectedChat.is_group}
             />
 
-            {/* Compose box */}
-            <div className="flex-shrink-0">
-              <ComposeBox
-                ref={composeBoxRef}
-                messageText={messageText}
-                setMessageText={setMessageText}
-                onSend={handleSend}
-                onSendAttachment={handleSendAttachment}
-                sending={sending}
-                suggestionCache={suggestionCache}
-                onAcceptSuggestion={handleAcceptSuggestion}
-                onEscape={handleComposeEscape}
-                onTabAction={handleTabAction}
-                chats={chats}
-              />
-            </div>
+            {/* Compose box; imported chats are history only */}
+            {selectedChat.read_only ? (
+              <div className="flex-shrink-0 px-4 py-3 text-center text-sm text-gray-500 dark:text-gray-400">
+                Imported chat — read only
+              </div>
+            ) : (
+              <div className="flex-shrink-0">
+                <ComposeBox
+                  ref={composeBoxRef}
+                  messageText={messageText}
+                  setMessageText={setMessageText}
+                  onSend={handleSend}
+                  onSendAttachment={handleSendAttachment}
+                  sending={sending}
+                  suggestionCache={suggestionCache}
+                  onAcceptSuggestion={handleAcceptSuggestion}
+                  onEscape={handleComposeEscape}
+                  onTabAction={handleTabAction}
+                  chats={chats}
+                />
+              </div>
+            )}
           </div>
         ) : (
           <div className="flex-1 flex items-center justify-center text-gray-500 dark:text-gray-400">
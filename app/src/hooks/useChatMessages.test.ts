@@ -1,6 +1,6 @@
 import { describe, expect, it } from "vitest";
 import type { Message } from "../types";
-import { mergeIncomingMessages } from "./useChatMessages";
+import { mergeDeltaMessages, mergeIncomingMessages } from "./useChatMessages";
 
 const createMessage = (overrides: Partial<Message>): Message => ({
   id: 1,
@@ -48,3 +48,31 @@ describe("mergeIncomingMessages", () => {
     ]);
   });
 });
+
+describe("mergeDeltaMessages", () => {
+  it("replaces changed messages without dropping newer ones", () => {
+    const prev = [
+      createMessage({ id: 1, time: 100, text: "hi" }),
+      createMessage({ id: 2, time: 200 }),
+    ];
+    const delta = [createMessage({ id: 1, time: 100, text: "hello" })];
+
+    expect(mergeDeltaMessages(prev, delta)).toEqual([
+      createMessage({ id: 1, time: 100, text: "hello" }),
+      createMessage({ id: 2, time: 200 }),
+    ]);
+  });
+
+  it("appends new messages and drops optimistic ones once ours arrives", () => {
+    const prev = [
+      createMessage({ id: 1, time: 100 }),
+      createMessage({ id: 1700000000001, time: 300, is_from_me: true }),
+    ];
+    const delta = [createMessage({ id: 3, time: 250, is_from_me: true })];
+
+    expect(mergeDeltaMessages(prev, delta)).toEqual([
+      createMessage({ id: 1, time: 100 }),
+      createMessage({ id: 3, time: 250, is_from_me: true }),
+    ]);
+  });
+});
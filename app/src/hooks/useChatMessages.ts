@@ -29,6 +29,28 @@ export const mergeIncomingMessages = (
   return [...olderMessages, ...incoming];
 };
 
+/** Apply a WebSocket delta: replace changed messages in place, add new ones */
+export const mergeDeltaMessages = (
+  prev: Message[],
+  delta: Message[],
+): Message[] => {
+  if (delta.length === 0) return prev;
+
+  const byId = new Map(delta.map((m) => [m.id, m]));
+  const updated = prev.map((m) => byId.get(m.id) ?? m);
+  const known = new Set(prev.map((m) => m.id));
+  const added = delta.filter((m) => !known.has(m.id));
+  if (added.length === 0) return updated;
+
+  // Our own sent message arriving replaces its optimistic placeholder
+  const sentArrived = added.some((m) => m.is_from_me);
+  const kept = sentArrived
+    ? updated.filter((m) => m.id < OPTIMISTIC_ID_THRESHOLD)
+    : updated;
+
+  return [...kept, ...added].sort((a, b) => a.time - b.time || a.id - b.id);
+};
+
 const flattenMessages = (data: InfiniteData<MessagesResponse> | undefined) => {
   if (!data) return [];
   const orderedPages = [...data.pages].reverse();
@@ -119,7 +141,7 @@ export function useChatMessages(activeChatId: number | null) {
         ["messages", chatId],
         (data) => {
           const baseMessages = flattenMessages(data);
-          const merged = mergeIncomingMessages(baseMessages, newMessages);
+          const merged = mergeDeltaMessages(baseMessages, newMessages);
           const hasMoreFromCache = data
             ? data.pages[data.pages.length - 1]?.has_more
             : undefined;
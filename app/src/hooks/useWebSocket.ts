@@ -1,5 +1,5 @@
 import { useCallback, useEffect, useRef, useState } from "react";
-import type { Message } from "../types";
+import type { DbChangeEvent, Message } from "../types";
 
 // ============================================================================
 // WEBSOCKET HOOK
@@ -10,12 +10,18 @@ import type { Message } from "../types";
 // - Automatic connection on mount
 // - Automatic reconnection with exponential backoff
 // - Message parsing and type-safe callbacks
-// - Subscription management (which chat to receive updates for)
+// - Subscriptions to any number of chats plus the chat list
+// - Heartbeats (ping/pong); a silent connection is dropped and reconnected
+//
+// The server speaks protocol v2 (see backend/src/api/ws.rs): it only sends
+// messages that are new or changed since the newest ROWID we've seen per chat.
+// We remember that ROWID, so after a reconnect the server replays what we missed.
 //
 // Usage:
-//   const { subscribe, connectionState } = useWebSocket({
-//     onMessagesUpdate: (chatId, messages) => setMessages(messages),
-//     onDbChanged: () => refetchChats(),
+//   const { subscribe, unsubscribe, connectionState } = useWebSocket({
+//     onMessagesDelta: (chatId, messages, total) => mergeMessages(messages),
+//     onChatListChanged: () => refetchChats(),
+//     onResync: (chatIds) => refetchMessages(chatIds),
 //   });
 //
 //   // When user selects a chat:
@@ -29,18 +35,40 @@ export type ConnectionState =
   | "disconnected"
   | "reconnecting";
 
+const PROTOCOL_VERSION = 2;
+
 /** Message types we can receive from the server */
-interface MessagesUpdateMessage {
-  type: "messages_update";
+interface HelloMessage {
+  type: "hello";
+  version: number;
+  heartbeat_interval_ms: number;
+}
+
+interface PongMessage {
+  type: "pong";
+  nonce: number | null;
+  timestamp: number;
+}
+
+interface MessagesDeltaMessage {
+  type: "messages_delta";
   chat_id: number;
+  /** New and changed messages, oldest first */
   messages: Message[];
   total: number;
-  timestamp: number;
+  last_rowid: number;
+  event: DbChangeEvent | null;
 }
 
-interface DbChangedMessage {
-  type: "db_changed";
-  timestamp: number;
+interface ChatListChangedMessage {
+  type: "chat_list_changed";
+  event: DbChangeEvent;
+}
+
+interface ResyncMessage {
+  type: "resync";
+  chat_ids: number[];
+  chat_list: boolean;
 }
 
 interface ErrorMessage {
@@ -48,28 +76,36 @@ interface ErrorMessage {
   message: string;
 }
 
-type ServerMessage = MessagesUpdateMessage | DbChangedMessage | ErrorMessage;
+type ServerMessage =
+  | HelloMessage
+  | PongMessage
+  | MessagesDeltaMessage
+  | ChatListChangedMessage
+  | ResyncMessage
+  | ErrorMessage;
 
 /** Configuration for the WebSocket hook */
 interface UseWebSocketOptions {
-  /** Called when new messages are received for the subscribed chat */
-  onMessagesUpdate?: (
+  /** Called with new or changed messages for a subscribed chat */
+  onMessagesDelta?: (
     chatId: number,
     messages: Message[],
     total: number,
   ) => void;
   /** Called when the database changes (for refreshing chat list) */
-  onDbChanged?: () => void;
+  onChatListChanged?: () => void;
+  /** Called when updates were lost and these chats must be refetched */
+  onResync?: (chatIds: number[], chatList: boolean) => void;
   /** Called on connection errors */
   onError?: (error: string) => void;
 }
 
 /** Return value of the useWebSocket hook */
 interface UseWebSocketReturn {
-  /** Subscribe to updates for a specific chat */
+  /** Subscribe to updates for a chat */
   subscribe: (chatId: number) => void;
-  /** Unsubscribe from chat updates */
-  unsubscribe: () => void;
+  /** Unsubscribe from a chat's updates */
+  unsubscribe: (chatId: number) => void;
   /** Current connection state */
   connectionState: ConnectionState;
   /** Whether the socket is connected */
@@ -83,10 +119,13 @@ const INITIAL_RETRY_DELAY = 1000; // 1 second
 const MAX_RETRY_DELAY = 30000; // 30 seconds
 const RETRY_MULTIPLIER = 2; // Double the delay each time
 
+// Used until the server's hello says otherwise
+const DEFAULT_HEARTBEAT_INTERVAL = 30000;
+
 export function useWebSocket(
   options: UseWebSocketOptions = {},
 ): UseWebSocketReturn {
-  const { onMessagesUpdate, onDbChanged, onError } = options;
+  const { onMessagesDelta, onChatListChanged, onResync, onError } = options;
 
   // WebSocket instance ref (persists across re-renders)
   const wsRef = useRef<WebSocket | null>(null);
@@ -101,20 +140,51 @@ export function useWebSocket(
   // Track if we should reconnect (false when intentionally closing)
   const shouldReconnectRef = useRef(true);
 
-  // Current subscribed chat ID
-  const subscribedChatRef = useRef<number | null>(null);
+  // Subscribed chats -> newest ROWID received (null until the server tells us)
+  const subscriptionsRef = useRef(new Map<number, number | null>());
+
+  // Heartbeat timer and the nonce of the ping still awaiting a pong
+  const heartbeatRef = useRef<ReturnType<typeof setInterval> | null>(null);
+  const pendingPingRef = useRef<number | null>(null);
 
   // Store callbacks in refs to avoid re-creating the connection on callback changes
-  const onMessagesUpdateRef = useRef(onMessagesUpdate);
-  const onDbChangedRef = useRef(onDbChanged);
+  const onMessagesDeltaRef = useRef(onMessagesDelta);
+  const onChatListChangedRef = useRef(onChatListChanged);
+  const onResyncRef = useRef(onResync);
   const onErrorRef = useRef(onError);
 
   // Update refs when callbacks change
   useEffect(() => {
-    onMessagesUpdateRef.current = onMessagesUpdate;
-    onDbChangedRef.current = onDbChanged;
+    onMessagesDeltaRef.current = onMessagesDelta;
+    onChatListChangedRef.current = onChatListChanged;
+    onResyncRef.current = onResync;
     onErrorRef.current = onError;
-  }, [onMessagesUpdate, onDbChanged, onError]);
+  }, [onMessagesDelta, onChatListChanged, onResync, onError]);
+
+  const stopHeartbeat = useCallback(() => {
+    if (heartbeatRef.current !== null) {
+      clearInterval(heartbeatRef.current);
+      heartbeatRef.current = null;
+    }
+    pendingPingRef.current = null;
+  }, []);
+
+  const startHeartbeat = useCallback(
+    (ws: WebSocket, intervalMs: number) => {
+      stopHeartbeat();
+      heartbeatRef.current = setInterval(() => {
+        if (pendingPingRef.current !== null) {
+          // No pong since the last ping: the connection is dead
+          ws.close();
+          return;
+        }
+        const nonce = Date.now();
+        pendingPingRef.current = nonce;
+        ws.send(JSON.stringify({ type: "ping", nonce }));
+      }, intervalMs);
+    },
+    [stopHeartbeat],
+  );
 
   // Connect to WebSocket
   const connect = useCallback(() => {
@@ -135,14 +205,17 @@ export function useWebSocket(
       setConnectionState("connected");
       retryDelayRef.current = INITIAL_RETRY_DELAY; // Reset retry delay on success
 
-      // Re-subscribe if we had a subscription before reconnecting
-      if (subscribedChatRef.current !== null) {
-        const message = JSON.stringify({
-          type: "subscribe",
-          chat_id: subscribedChatRef.current,
-        });
-        ws.send(message);
-      }
+      ws.send(JSON.stringify({ type: "hello", version: PROTOCOL_VERSION }));
+      startHeartbeat(ws, DEFAULT_HEARTBEAT_INTERVAL);
+
+      // Re-subscribe with our last-seen ROWIDs so the server replays what we missed
+      const chats = [...subscriptionsRef.current].map(
+        ([chatId, lastSeenRowid]) => ({
+          chat_id: chatId,
+          last_seen_rowid: lastSeenRowid,
+        }),
+      );
+      ws.send(JSON.stringify({ type: "subscribe", chats, chat_list: true }));
     };
 
     ws.onmessage = (event) => {
@@ -150,15 +223,31 @@ export function useWebSocket(
         const data: ServerMessage = JSON.parse(event.data);
 
         switch (data.type) {
-          case "messages_update":
-            onMessagesUpdateRef.current?.(
-              data.chat_id,
-              data.messages,
-              data.total,
-            );
+          case "hello":
+            startHeartbeat(ws, data.heartbeat_interval_ms);
+            break;
+          case "pong":
+            if (data.nonce === pendingPingRef.current) {
+              pendingPingRef.current = null;
+            }
+            break;
+          case "messages_delta":
+            // Ignore stragglers for chats we've since unsubscribed from
+            if (!subscriptionsRef.current.has(data.chat_id)) break;
+            subscriptionsRef.current.set(data.chat_id, data.last_rowid);
+            if (data.messages.length > 0) {
+              onMessagesDeltaRef.current?.(
+                data.chat_id,
+                data.messages,
+                data.total,
+              );
+            }
             break;
-          case "db_changed":
-            onDbChangedRef.current?.();
+          case "chat_list_changed":
+            onChatListChangedRef.current?.();
+            break;
+          case "resync":
+            onResyncRef.current?.(data.chat_ids, data.chat_list);
             break;
           case "error":
             console.error("[WebSocket] Server error:", data.message);
@@ -172,6 +261,7 @@ export function useWebSocket(
 
     ws.onclose = () => {
       wsRef.current = null;
+      stopHeartbeat();
 
       if (shouldReconnectRef.current) {
         setConnectionState("reconnecting");
@@ -194,27 +284,28 @@ export function useWebSocket(
     ws.onerror = () => {
       // The onclose handler will be called after this
     };
-  }, []);
+  }, [startHeartbeat, stopHeartbeat]);
 
   // Subscribe to a chat
   const subscribe = useCallback((chatId: number) => {
-    subscribedChatRef.current = chatId;
+    if (subscriptionsRef.current.has(chatId)) return;
+    subscriptionsRef.current.set(chatId, null);
 
     if (wsRef.current?.readyState === WebSocket.OPEN) {
       const message = JSON.stringify({
         type: "subscribe",
-        chat_id: chatId,
+        chats: [{ chat_id: chatId, last_seen_rowid: null }],
       });
       wsRef.current.send(message);
     }
   }, []);
 
-  // Unsubscribe from chat updates
-  const unsubscribe = useCallback(() => {
-    subscribedChatRef.current = null;
+  // Unsubscribe from a chat's updates
+  const unsubscribe = useCallback((chatId: number) => {
+    if (!subscriptionsRef.current.delete(chatId)) return;
 
     if (wsRef.current?.readyState === WebSocket.OPEN) {
-      const message = JSON.stringify({ type: "unsubscribe" });
+      const message = JSON.stringify({ type: "unsubscribe", chats: [chatId] });
       wsRef.current.send(message);
     }
   }, []);
@@ -226,9 +317,10 @@ export function useWebSocket(
 
     return () => {
       shouldReconnectRef.current = false;
+      stopHeartbeat();
       wsRef.current?.close();
     };
-  }, [connect]);
+  }, [connect, stopHeartbeat]);
 
   return {
     subscribe,
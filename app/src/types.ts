@@ -7,6 +7,17 @@ export interface Chat {
   is_group: boolean;
   handles: string[];
   chat_identifier: string | null;
+  unread_count?: number;
+  last_read_message_time?: number | null;
+  origin?: string; // "imessage", "ios-backup", "android-import" or "whatsapp-import"; imported chats have negative ids
+  read_only?: boolean; // imported chats can't be sent to
+  merged_chat_ids?: number[]; // with ?merged=true: every chat this entry stands for, newest (id) first
+  person_id?: number; // 1:1 chats: the person at the other end, see /people/:id
+}
+
+export interface UnreadChatsResponse {
+  chats: Chat[];
+  total_unread: number;
 }
 
 export interface ChatsResponse {
@@ -20,13 +31,66 @@ export interface SearchChatsResponse {
   query: string;
 }
 
+// Offsets are UTF-16 code units into MessageSearchResult.snippet
+export interface SearchHighlight {
+  start: number;
+  length: number;
+}
+
+export interface MessageSearchResult {
+  chat_id: number;
+  message_id: number;
+  guid: string;
+  snippet: string;
+  highlights: SearchHighlight[];
+  time: number;
+  is_from_me: boolean;
+  handle: string | null;
+  contact_name: string | null;
+}
+
+export interface MessageSearchResponse {
+  results: MessageSearchResult[];
+  query: string;
+  indexing: boolean; // still building the index; results are partial
+  progress?: number; // 0-1, while indexing
+}
+
 export interface ChatsByIdsResponse {
   chats: Chat[];
 }
 
+export type TapbackKind =
+  | "love"
+  | "like"
+  | "dislike"
+  | "laugh"
+  | "emphasize"
+  | "question"
+  | "emoji"
+  | "sticker";
+
 export interface Reaction {
   emoji: string;
   is_from_me: boolean;
+  handle: string | null;
+  contact_name: string | null;
+  time: number;
+  kind: TapbackKind;
+  part: number; // part of the message reacted to, e.g. one photo of several (0 for the first)
+  sticker_attachment_id?: number;
+}
+
+export interface ReactionGroup {
+  emoji: string;
+  kind: TapbackKind;
+  count: number;
+  reactors: Reaction[];
+}
+
+export interface MessageReactionsResponse {
+  guid: string;
+  groups: ReactionGroup[];
 }
 
 export interface Attachment {
@@ -37,6 +101,20 @@ export interface Attachment {
   total_bytes: number;
 }
 
+// Offsets are UTF-16 code units into Message.text
+export interface TextRun {
+  start: number;
+  length: number;
+  part: number | null;
+  mention: string | null;
+  link: string | null;
+  attachment_guid: string | null;
+  bold: boolean;
+  italic: boolean;
+  underline: boolean;
+  strikethrough: boolean;
+}
+
 export interface Message {
   id: number;
   guid?: string;
@@ -47,14 +125,77 @@ export interface Message {
   contact_name: string | null;
   reactions: Reaction[];
   attachments: Attachment[];
+  runs?: TextRun[];
+  reply_to_guid?: string | null;
+  reply_to_part?: number | null;
+  reply_preview?: string | null;
+  edited_at?: number | null;
+  is_unsent?: boolean;
+  edit_history?: MessageEdit[];
+  is_delivered?: boolean;
+  is_read?: boolean;
+  date_delivered?: number | null;
+  date_read?: number | null;
+  error?: number | null;
+  kind?: MessageKind;
+  target_handle?: string | null;
+  target_contact_name?: string | null;
+  group_title?: string | null;
+  origin?: string; // set on imported messages, e.g. "android-import" or "whatsapp-import"
+  service?: string; // "iMessage", "SMS", ...
+  chat_id?: number; // the chat the message came from (chat.db messages; tells merged timelines apart)
+}
+
+export type MessageKind =
+  | "message"
+  | "participant_added"
+  | "participant_removed"
+  | "participant_left"
+  | "group_renamed"
+  | "group_photo_changed"
+  | "group_photo_removed"
+  | "system";
+
+export interface MessageEdit {
+  part: number;
+  time: number;
+  text: string | null;
+}
+
+export interface ThreadResponse {
+  chat_id: number;
+  thread_guid: string;
+  messages: Message[];
 }
 
 export interface MessagesResponse {
   messages: Message[];
   total: number;
   has_more: boolean;
+  has_newer?: boolean;
+  // Opaque keyset cursors: pass as `before` / `after` to /chats/:id/messages
+  older_cursor?: string | null;
+  newer_cursor?: string | null;
+}
+
+export interface MonthCount {
+  month: string; // "YYYY-MM", local time
+  count: number;
 }
 
+// /chats/:id/messages?at=YYYY-MM-DD
+export interface DateJumpResponse extends MessagesResponse {
+  anchor_guid: string | null;
+  months: MonthCount[];
+}
+
+// What changed in chat.db, as broadcast over the WebSocket
+export type DbChangeEvent =
+  | { type: "new_messages"; chat_id: number; rowids: number[] }
+  | { type: "reaction_changed"; chat_id: number; message_guids: string[] }
+  | { type: "message_edited"; chat_id: number; rowids: number[] }
+  | { type: "chat_list_changed" };
+
 export interface DraftResponse {
   draft_text: string;
 }
@@ -82,6 +223,25 @@ export interface ContactContext {
   updated_at: number;
 }
 
+export interface Person {
+  id: number;
+  display_name: string | null;
+  photo_handle: string | null; // load via /contacts/:handle/photo
+  handles: string[];
+  created_at: number;
+  updated_at: number;
+}
+
+export interface PersonDetail extends Person {
+  contexts: ContactContext[]; // one per handle with learned context
+}
+
+export interface PeopleResponse {
+  people: Person[];
+  total: number;
+  has_more: boolean;
+}
+
 export type SuggestionActionType = "send" | "call" | "facetime" | "switch_chat";
 
 export interface SuggestionAction {
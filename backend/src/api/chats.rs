@@ -1,8 +1,18 @@
+use crate::chat_schema;
 use crate::context_db::ContextDb;
+use crate::ios_backup;
 use crate::models::{
-    ChatsByIdsRequest, ChatsByIdsResponse, PaginationParams, SearchChatsResponse, SearchParams,
+    page_limit, AroundParams, ChatsByIdsRequest, ChatsByIdsResponse, MessagesParams,
+    PaginationParams, SearchChatsResponse, SearchParams,
 };
-use crate::services::messages::{fetch_chats, fetch_chats_by_ids, fetch_messages, fetch_search_chats};
+use crate::services::chat_summaries::{
+    fetch_chats, fetch_chats_by_ids, fetch_search_chats, fetch_unread_chats, merged_chat_ids,
+};
+use crate::services::messages::{
+    fetch_merged_messages, fetch_merged_messages_page, fetch_messages, fetch_messages_around,
+    fetch_messages_at_date, fetch_messages_page, fetch_thread, MessageCursor, PageDirection,
+};
+use crate::services::search_query::local_day_start_ms;
 use crate::state::AppState;
 use axum::{
     extract::{Query, State},
@@ -12,58 +22,61 @@ use axum::{
 use std::sync::Arc;
 
 pub async fn health() -> impl IntoResponse {
-    Json(serde_json::json!({"status": "ok"}))
+    // `schema` is null if chat.db couldn't be read at startup
+    Json(serde_json::json!({
+        "status": "ok",
+        "schema": chat_schema::installed().map(|schema| schema.profile()),
+        "origin": ios_backup::chat_origin(),
+        // Present when serving an iPhone backup instead of chat.db
+        "backup": ios_backup::installed().map(|backup| backup.info()),
+    }))
 }
 
 pub async fn get_chats(
     State(state): State<Arc<AppState>>,
     Query(params): Query<PaginationParams>,
 ) -> impl IntoResponse {
-    let context_db = match ContextDb::open() {
-        Ok(db) => db,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": format!("Failed to open context db: {}", e)
-                })),
-            )
-                .into_response();
-        }
-    };
-
-    let conn = match state.chat_pool.get() {
-        Ok(conn) => conn,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": format!("Failed to open chat db: {}", e)
-                })),
-            )
-                .into_response();
-        }
-    };
+    let chat_pool = state.chat_pool.clone();
+    let chat_summaries = state.chat_summaries.clone();
+    let contact_resolve_tx = state.contact_resolve_tx.clone();
 
-    match fetch_chats(
-        &conn,
-        &state.contact_resolve_tx,
-        &context_db,
-        params.limit,
-        params.offset,
-    ) {
-        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
-        Err(e) => {
-            let error_msg = format!(
+    // The summary cache may still be loading from chat.db
+    let result = tokio::task::spawn_blocking(move || {
+        let context_db =
+            ContextDb::open().map_err(|e| format!("Failed to open context db: {}", e))?;
+        let conn = chat_pool
+            .get()
+            .map_err(|e| format!("Failed to open chat db: {}", e))?;
+        fetch_chats(
+            &conn,
+            &chat_summaries,
+            &contact_resolve_tx,
+            &context_db,
+            page_limit(params.limit),
+            params.offset,
+            params.merged,
+        )
+        .map_err(|e| {
+            format!(
                 "Failed to fetch chats: {}. Make sure Full Disk Access is granted.",
                 e
-            );
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": error_msg})),
             )
-                .into_response()
-        }
+        })
+    })
+    .await;
+
+    match result {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": error_msg})),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to fetch chats"})),
+        )
+            .into_response(),
     }
 }
 
@@ -75,47 +88,70 @@ pub async fn get_chats_by_ids(
         return (StatusCode::OK, Json(ChatsByIdsResponse { chats: vec![] })).into_response();
     }
 
-    let context_db = match ContextDb::open() {
-        Ok(db) => db,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": format!("Failed to open context db: {}", e)
-                })),
-            )
-                .into_response();
-        }
-    };
+    let chat_pool = state.chat_pool.clone();
+    let chat_summaries = state.chat_summaries.clone();
+    let contact_resolve_tx = state.contact_resolve_tx.clone();
 
-    let conn = match state.chat_pool.get() {
-        Ok(conn) => conn,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": format!("Failed to open chat db: {}", e)
-                })),
-            )
-                .into_response();
-        }
-    };
+    let result = tokio::task::spawn_blocking(move || {
+        let context_db =
+            ContextDb::open().map_err(|e| format!("Failed to open context db: {}", e))?;
+        let conn = chat_pool
+            .get()
+            .map_err(|e| format!("Failed to open chat db: {}", e))?;
+        fetch_chats_by_ids(
+            &conn,
+            &chat_summaries,
+            &contact_resolve_tx,
+            &context_db,
+            &request.ids,
+        )
+        .map_err(|e| format!("Failed to fetch chats by ids: {}", e))
+    })
+    .await;
 
-    match fetch_chats_by_ids(
-        &conn,
-        &state.contact_resolve_tx,
-        &context_db,
-        &request.ids,
-    ) {
-        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
-        Err(e) => {
-            let error_msg = format!("Failed to fetch chats by ids: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": error_msg})),
-            )
-                .into_response()
-        }
+    match result {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": error_msg})),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to fetch chats by ids"})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_unread_chats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let chat_pool = state.chat_pool.clone();
+    let chat_summaries = state.chat_summaries.clone();
+    let contact_resolve_tx = state.contact_resolve_tx.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let context_db =
+            ContextDb::open().map_err(|e| format!("Failed to open context db: {}", e))?;
+        let conn = chat_pool
+            .get()
+            .map_err(|e| format!("Failed to open chat db: {}", e))?;
+        fetch_unread_chats(&conn, &chat_summaries, &contact_resolve_tx, &context_db)
+            .map_err(|e| format!("Failed to fetch unread chats: {}", e))
+    })
+    .await;
+
+    match result {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": error_msg})),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to fetch unread chats"})),
+        )
+            .into_response(),
     }
 }
 
@@ -124,7 +160,7 @@ pub async fn search_chats(
     Query(params): Query<SearchParams>,
 ) -> impl IntoResponse {
     // Don't search if query is too short
-    if params.q.trim().len() < 1 {
+    if params.q.trim().is_empty() {
         return (
             StatusCode::OK,
             Json(SearchChatsResponse {
@@ -135,69 +171,94 @@ pub async fn search_chats(
             .into_response();
     }
 
-    let context_db = match ContextDb::open() {
-        Ok(db) => db,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": format!("Failed to open context db: {}", e)
-                })),
-            )
-                .into_response();
-        }
-    };
+    let chat_pool = state.chat_pool.clone();
+    let chat_summaries = state.chat_summaries.clone();
 
-    let conn = match state.chat_pool.get() {
-        Ok(conn) => conn,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({
-                    "error": format!("Failed to open chat db: {}", e)
-                })),
-            )
-                .into_response();
-        }
-    };
+    let result = tokio::task::spawn_blocking(move || {
+        let context_db =
+            ContextDb::open().map_err(|e| format!("Failed to open context db: {}", e))?;
+        let conn = chat_pool
+            .get()
+            .map_err(|e| format!("Failed to open chat db: {}", e))?;
+        fetch_search_chats(
+            &conn,
+            &chat_summaries,
+            &context_db,
+            &params.q,
+            params.limit,
+        )
+        .map_err(|e| format!("Failed to search chats: {}", e))
+    })
+    .await;
 
-    match fetch_search_chats(
-        &conn,
-        &context_db,
-        &params.q,
-        params.limit,
-    ) {
-        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
-        Err(e) => {
-            let error_msg = format!("Failed to search chats: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": error_msg})),
-            )
-                .into_response()
-        }
+    match result {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": error_msg})),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to search chats"})),
+        )
+            .into_response(),
     }
 }
 
 pub async fn get_messages(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(chat_id): axum::extract::Path<i64>,
-    Query(params): Query<PaginationParams>,
-) -> impl IntoResponse {
+    Query(params): Query<MessagesParams>,
+) -> axum::response::Response {
+    if let Some(at) = params.at {
+        return get_messages_at_date(state, chat_id, &at, page_limit(params.limit)).await;
+    }
+
+    let direction = match (params.before.as_deref(), params.after.as_deref()) {
+        (Some(cursor), _) => MessageCursor::parse(cursor).map(PageDirection::Before),
+        (None, Some(cursor)) => MessageCursor::parse(cursor).map(PageDirection::After),
+        (None, None) => None,
+    };
+    if direction.is_none() && (params.before.is_some() || params.after.is_some()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "Invalid message cursor"})),
+        )
+            .into_response();
+    }
+
     let chat_pool = state.chat_pool.clone();
-    let limit = params.limit;
+    let chat_summaries = state.chat_summaries.clone();
+    let limit = page_limit(params.limit);
     let offset = params.offset;
+    let merged = params.merged;
 
     let result = tokio::task::spawn_blocking(move || {
         let conn = chat_pool.get().map_err(|e| e.to_string())?;
         let context_db = ContextDb::open().map_err(|e| e.to_string())?;
-        fetch_messages(
-            &conn,
-            chat_id,
-            &context_db,
-            limit,
-            offset,
-        )
+        let chat_ids = if merged {
+            merged_chat_ids(&conn, &chat_summaries, &context_db, chat_id)
+                .map_err(|e| e.to_string())?
+        } else {
+            vec![chat_id]
+        };
+        match (direction, chat_ids.len() > 1) {
+            (Some(direction), false) => {
+                fetch_messages_page(&conn, chat_id, &context_db, limit, direction)
+            }
+            (Some(direction), true) => {
+                fetch_merged_messages_page(&conn, &chat_ids, &context_db, limit, direction)
+            }
+            (None, false) => fetch_messages(
+                &conn,
+                chat_id,
+                &context_db,
+                limit,
+                offset,
+            ),
+            (None, true) => fetch_merged_messages(&conn, &chat_ids, &context_db, limit, offset),
+        }
         .map_err(|e| e.to_string())
     })
     .await;
@@ -218,3 +279,119 @@ pub async fn get_messages(
             .into_response(),
     }
 }
+
+async fn get_messages_at_date(
+    state: Arc<AppState>,
+    chat_id: i64,
+    at: &str,
+    limit: i64,
+) -> axum::response::Response {
+    let Some(unix_ms) = chrono::NaiveDate::parse_from_str(at, "%Y-%m-%d")
+        .ok()
+        .and_then(local_day_start_ms)
+    else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "Expected a date like 2023-05-01"})),
+        )
+            .into_response();
+    };
+
+    let chat_pool = state.chat_pool.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        fetch_messages_at_date(&conn, chat_id, &context_db, unix_ms, limit)
+            .map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to fetch messages: {}", error_msg)
+            })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to fetch messages"})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_messages_around(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path((chat_id, guid)): axum::extract::Path<(i64, String)>,
+    Query(params): Query<AroundParams>,
+) -> impl IntoResponse {
+    let chat_pool = state.chat_pool.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        fetch_messages_around(&conn, chat_id, &guid, &context_db, page_limit(params.limit))
+            .map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(response))) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Ok(None)) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Message not found in this chat"})),
+        )
+            .into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to fetch messages: {}", error_msg)
+            })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to fetch messages"})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_thread(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path((chat_id, guid)): axum::extract::Path<(i64, String)>,
+) -> impl IntoResponse {
+    let chat_pool = state.chat_pool.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        fetch_thread(&conn, chat_id, &guid, &context_db).map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(response))) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Ok(None)) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Message not found in this chat"})),
+        )
+            .into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to fetch thread: {}", error_msg)
+            })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to fetch thread"})),
+        )
+            .into_response(),
+    }
+}
@@ -9,6 +9,7 @@ use crate::models::{
 };
 use crate::openrouter::{OpenRouterClient};
 use crate::services::messages::fetch_messages_for_extraction;
+use crate::services::vcard::export_vcard;
 use crate::services::openrouter_config::{get_openrouter_api_key, get_openrouter_model};
 use crate::state::AppState;
 use axum::{
@@ -53,6 +54,51 @@ pub async fn get_contact_context(Path(handle): Path<String>) -> impl IntoRespons
     }
 }
 
+// Export a contact as a vCard 3.0 file.
+// Inputs: `handle` path param (URL-encoded); the card covers the handle's whole person.
+// Output: 200 + text/vcard with the AI notes in NOTE; 404 if nothing is known; 500 on DB errors.
+pub async fn get_contact_vcard(Path(handle): Path<String>) -> impl IntoResponse {
+    // URL decode the handle (it may contain + signs encoded as %2B)
+    let handle = urlencoding::decode(&handle)
+        .unwrap_or(std::borrow::Cow::Borrowed(&handle))
+        .to_string();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        export_vcard(&handle, &context_db).map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(vcard))) => (
+            StatusCode::OK,
+            [
+                ("Content-Type", "text/vcard; charset=utf-8"),
+                ("Content-Disposition", "attachment; filename=\"contact.vcf\""),
+            ],
+            vcard,
+        )
+            .into_response(),
+        Ok(Ok(None)) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Contact not found" })),
+        )
+            .into_response(),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to export contact: {}", e)
+            })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": "Export task failed" })),
+        )
+            .into_response(),
+    }
+}
+
 // Replace the notes field for a contact context.
 // Inputs: `handle` path param and JSON body with `notes` string.
 // Output: 200 + ok flag on success; 500 on DB errors.
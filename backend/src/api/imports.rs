@@ -0,0 +1,235 @@
+use crate::context_db::ContextDb;
+use crate::import_db::{ImportDb, ImportSummary};
+use crate::services::android_import::import_android_backup;
+use crate::services::contacts::HandleMatcher;
+use crate::services::vcard::import_vcards as import_vcard_file;
+use crate::services::whatsapp_import::{import_whatsapp_export, WhatsAppExport};
+use crate::state::{AppState, DbChangeEvent};
+use axum::{
+    body::Bytes,
+    extract::{Multipart, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// Largest upload accepted by the import endpoints. Archives with MMS or
+/// WhatsApp media run to hundreds of megabytes.
+pub const IMPORT_BODY_LIMIT: usize = 1024 * 1024 * 1024;
+
+// Import an Android "SMS Backup & Restore" XML archive.
+// Inputs: the archive's contents as the request body.
+// Output: 200 + import counts; 400 if the archive can't be parsed.
+// Imported threads appear in /chats straight away; clients are told to refetch.
+pub async fn import_android(State(state): State<Arc<AppState>>, body: Bytes) -> impl IntoResponse {
+    run_import(
+        state,
+        "Android archive",
+        move |matcher, import_db, context_db| {
+            import_android_backup(&body[..], matcher, import_db, context_db)
+                .map_err(|e| format!("Failed to import Android archive: {}", e))
+        },
+    )
+    .await
+}
+
+// Import a WhatsApp chat export.
+// Inputs: multipart form with a `chat` file (the exported .txt, whose file name
+// gives the chat title), any number of `media` files exported with it, and
+// `me`, my name in the export (optional for 1:1 chats).
+// Output: 200 + import counts; 400 if the form or export can't be parsed.
+pub async fn import_whatsapp(
+    State(state): State<Arc<AppState>>,
+    multipart: Multipart,
+) -> impl IntoResponse {
+    let (export, me) = match read_whatsapp_upload(multipart).await {
+        Ok(upload) => upload,
+        Err(error_msg) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": error_msg })),
+            )
+                .into_response()
+        }
+    };
+    run_import(
+        state,
+        "WhatsApp export",
+        move |matcher, import_db, context_db| {
+            import_whatsapp_export(export, me.as_deref(), matcher, import_db, context_db)
+                .map_err(|e| format!("Failed to import WhatsApp export: {}", e))
+        },
+    )
+    .await
+}
+
+/// Collect the fields of an `/imports/whatsapp` form
+async fn read_whatsapp_upload(
+    mut multipart: Multipart,
+) -> Result<(WhatsAppExport, Option<String>), String> {
+    let mut chat: Option<(Option<String>, String)> = None;
+    let mut media = HashMap::new();
+    let mut me = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| e.to_string())? {
+        let name = field.name().unwrap_or_default().to_string();
+        let file_name = field.file_name().map(str::to_string);
+        match name.as_str() {
+            "chat" => {
+                let text = field.text().await.map_err(|e| e.to_string())?;
+                chat = Some((file_name, text));
+            }
+            "media" => {
+                let file_name = file_name.ok_or("media files need a file name")?;
+                let data = field.bytes().await.map_err(|e| e.to_string())?;
+                media.insert(file_name, data.to_vec());
+            }
+            "me" => {
+                let value = field.text().await.map_err(|e| e.to_string())?;
+                me = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+            }
+            _ => return Err(format!("Unexpected field '{}'", name)),
+        }
+    }
+    let (file_name, text) = chat.ok_or("Missing the 'chat' file")?;
+    Ok((
+        WhatsAppExport {
+            file_name,
+            text,
+            media,
+        },
+        me,
+    ))
+}
+
+// Import contacts from a .vcf file (vCard 3.0 or 4.0), e.g. a Google Contacts
+// export, for machines without Contacts.app.
+// Inputs: the .vcf file's contents as the request body.
+// Output: 200 + contact/handle/photo counts; 400 if the file can't be parsed.
+// Names, birthdays and photos land in the people directory; clients are told to refetch.
+pub async fn import_vcards(State(state): State<Arc<AppState>>, body: Bytes) -> impl IntoResponse {
+    let chat_pool = state.chat_pool.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let mut matcher = HandleMatcher::load(&conn).map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        import_vcard_file(&String::from_utf8_lossy(&body), &mut matcher, &context_db)
+            .map_err(|e| format!("Failed to import vCards: {}", e))
+    })
+    .await;
+
+    match result {
+        Ok(Ok(summary)) => {
+            info!(target: "imports", ?summary, "Imported vCards");
+            let _ = state.db_change_tx.send(DbChangeEvent::ChatListChanged);
+            (StatusCode::OK, Json(summary)).into_response()
+        }
+        Ok(Err(error_msg)) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": error_msg })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": "Import task failed" })),
+        )
+            .into_response(),
+    }
+}
+
+/// Run an import off the async runtime, then publish the new chats
+async fn run_import(
+    state: Arc<AppState>,
+    kind: &'static str,
+    import: impl FnOnce(&mut HandleMatcher, &mut ImportDb, &ContextDb) -> Result<ImportSummary, String>
+        + Send
+        + 'static,
+) -> axum::response::Response {
+    let chat_pool = state.chat_pool.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let mut matcher = HandleMatcher::load(&conn).map_err(|e| e.to_string())?;
+        let mut import_db = ImportDb::open().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        import(&mut matcher, &mut import_db, &context_db)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(summary)) => {
+            info!(target: "imports", ?summary, "Imported {}", kind);
+            refresh_chat_list(&state);
+            (StatusCode::OK, Json(summary)).into_response()
+        }
+        Ok(Err(error_msg)) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": error_msg })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": "Import task failed" })),
+        )
+            .into_response(),
+    }
+}
+
+/// Merge newly imported chats into the chat list and tell clients to refetch it
+fn refresh_chat_list(state: &AppState) {
+    match state.chat_summaries.lock() {
+        Ok(mut summaries) => {
+            if let Err(e) = summaries.reload_imports() {
+                error!(target: "imports", "Failed to reload imported chats: {}", e);
+                summaries.invalidate();
+            }
+        }
+        Err(_) => error!(target: "imports", "chat summary cache lock poisoned"),
+    }
+    let _ = state.db_change_tx.send(DbChangeEvent::ChatListChanged);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{FromRequest, Request};
+
+    async fn multipart(body: &str) -> Multipart {
+        let request = Request::builder()
+            .header("content-type", "multipart/form-data; boundary=X")
+            .body(axum::body::Body::from(body.replace('\n', "\r\n")))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_whatsapp_uploads() {
+        let body = "--X
+Content-Disposition: form-data; name=\"chat\"; filename=\"WhatsApp Chat - Climbing.txt\"
+
+1/5/24, 9:41 PM - Ann: IMG-1.jpg (file attached)
+--X
+Content-Disposition: form-data; name=\"media\"; filename=\"IMG-1.jpg\"
+
+jpeg
+--X
+Content-Disposition: form-data; name=\"me\"
+
+ Sam 
+--X--
+";
+        let (export, me) = read_whatsapp_upload(multipart(body).await).await.unwrap();
+        assert_eq!(export.file_name.as_deref(), Some("WhatsApp Chat - Climbing.txt"));
+        assert_eq!(export.text, "1/5/24, 9:41 PM - Ann: IMG-1.jpg (file attached)");
+        assert_eq!(export.media["IMG-1.jpg"], b"jpeg");
+        assert_eq!(me.as_deref(), Some("Sam"));
+
+        let body = "--X
+Content-Disposition: form-data; name=\"path\"
+
+/etc/passwd
+--X--
+";
+        assert!(read_whatsapp_upload(multipart(body).await).await.is_err());
+    }
+}
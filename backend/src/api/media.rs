@@ -1,34 +1,90 @@
-use crate::services::contacts::fetch_contact_photo;
+use crate::context_db::ContextDb;
+use crate::services::avatars::{chat_avatar, contact_avatar, Avatar};
+use crate::services::chat_summaries::fetch_chats_by_ids;
 use crate::services::messages::fetch_attachment_file;
 use crate::state::AppState;
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
 };
 use std::sync::Arc;
 
-pub async fn get_contact_photo(Path(handle): Path<String>) -> impl IntoResponse {
+// Serve a contact's photo, resized and cached, or a generated initials avatar.
+// Inputs: `handle` path param (URL-encoded); optional If-None-Match header.
+// Output: 200 + image/jpeg or image/svg+xml with an ETag; 304 if the ETag still matches.
+pub async fn get_contact_photo(Path(handle): Path<String>, headers: HeaderMap) -> impl IntoResponse {
     // URL decode the handle (it may contain + signs encoded as %2B)
     let handle = urlencoding::decode(&handle)
         .unwrap_or(std::borrow::Cow::Borrowed(&handle))
         .to_string();
 
-    let handle_clone = handle.clone();
-    let result = tokio::task::spawn_blocking(move || fetch_contact_photo(&handle_clone)).await;
+    let result = tokio::task::spawn_blocking(move || {
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        contact_avatar(&handle, &context_db).map_err(|e| e.to_string())
+    })
+    .await;
 
     match result {
-        Ok(Ok(Some(photo_data))) => (
-            StatusCode::OK,
-            [("Content-Type", "image/jpeg"), ("Cache-Control", "max-age=3600")],
-            photo_data,
-        )
-            .into_response(),
-        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "No photo found").into_response(),
+        Ok(Ok(avatar)) => avatar_response(avatar, &headers),
         _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch photo").into_response(),
     }
 }
 
+// Serve a chat's avatar: the contact's for 1:1 chats, a composite of up to four
+// participants for groups.
+// Inputs: `chat_id` path param; optional If-None-Match header.
+// Output: 200 + image with an ETag; 304 if unchanged; 404 for unknown chats.
+pub async fn get_chat_avatar(
+    State(state): State<Arc<AppState>>,
+    Path(chat_id): Path<i64>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let chat_pool = state.chat_pool.clone();
+    let chat_summaries = state.chat_summaries.clone();
+    let contact_resolve_tx = state.contact_resolve_tx.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        let found = fetch_chats_by_ids(&conn, &chat_summaries, &contact_resolve_tx, &context_db, &[chat_id])
+            .map_err(|e| e.to_string())?;
+        match found.chats.first() {
+            Some(chat) => chat_avatar(chat, &context_db).map(Some).map_err(|e| e.to_string()),
+            None => Ok(None),
+        }
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(avatar))) => avatar_response(avatar, &headers),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "Chat not found").into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to build avatar").into_response(),
+    }
+}
+
+/// 304 when the client's copy is current, the image otherwise. Clients
+/// revalidate every time so a changed photo shows up straight away.
+fn avatar_response(avatar: Avatar, headers: &HeaderMap) -> axum::response::Response {
+    let cache_headers = [
+        (header::ETAG, avatar.etag.clone()),
+        (header::CACHE_CONTROL, "no-cache".to_string()),
+    ];
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| avatar.matches(value));
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+    (
+        StatusCode::OK,
+        cache_headers,
+        [(header::CONTENT_TYPE, avatar.content_type)],
+        avatar.bytes,
+    )
+        .into_response()
+}
+
 pub async fn get_attachment(
     State(state): State<Arc<AppState>>,
     Path(attachment_id): Path<i64>,
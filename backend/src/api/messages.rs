@@ -1,9 +1,18 @@
-use crate::models::{DraftRequest, DraftResponse, SendAttachmentRequest, SendRequest, SendResponse};
+use crate::context_db::ContextDb;
+use crate::models::{
+    DraftRequest, DraftResponse, MessageSearchResponse, SearchParams, SendAttachmentRequest,
+    SendRequest, SendResponse,
+};
+use crate::search_index::SearchIndex;
 use crate::services::applescript::{
     send_attachment_to_group_via_applescript, send_attachment_via_applescript,
     send_to_group_via_applescript, send_via_applescript,
 };
+use crate::services::messages::fetch_message_reactions;
+use crate::services::search::search_messages as search_message_index;
+use crate::services::search_query::parse_search_query;
 use axum::{
+    extract::Query,
     http::StatusCode,
     response::{IntoResponse, Json},
 };
@@ -114,3 +123,97 @@ pub async fn send_attachment(
     }
 }
 
+pub async fn get_message_reactions(
+    axum::extract::State(state): axum::extract::State<std::sync::Arc<crate::state::AppState>>,
+    axum::extract::Path(guid): axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let chat_pool = state.chat_pool.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        fetch_message_reactions(&conn, &guid, &context_db).map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(response))) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Ok(None)) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Message not found"})),
+        )
+            .into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to fetch reactions: {}", error_msg)
+            })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to fetch reactions"})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn search_messages(
+    axum::extract::State(state): axum::extract::State<std::sync::Arc<crate::state::AppState>>,
+    Query(params): Query<SearchParams>,
+) -> impl IntoResponse {
+    if params.q.trim().is_empty() {
+        return (
+            StatusCode::OK,
+            Json(MessageSearchResponse {
+                results: vec![],
+                query: params.q,
+                indexing: false,
+                progress: None,
+            }),
+        )
+            .into_response();
+    }
+
+    let query = match parse_search_query(&params.q) {
+        Ok(query) => query,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": format!("Invalid search query: {}", e.message),
+                    "position": e.position,
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    let chat_pool = state.chat_pool.clone();
+
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        // Only the background indexer writes the index; a query searches what it has reached
+        let index = SearchIndex::open().map_err(|e| e.to_string())?;
+        search_message_index(&conn, &index, &context_db, &query, &params.q, params.limit)
+            .map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(error_msg)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to search messages: {}", error_msg)
+            })),
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": "Failed to search messages"})),
+        )
+            .into_response(),
+    }
+}
@@ -1,7 +1,9 @@
 pub mod ai;
 pub mod chats;
 pub mod context;
+pub mod imports;
 pub mod media;
 pub mod messages;
+pub mod people;
 pub mod suggestions;
 pub mod ws;
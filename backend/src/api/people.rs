@@ -0,0 +1,246 @@
+use crate::context_db::ContextDb;
+use crate::models::{
+    MergePeopleRequest, PeopleParams, PeopleResponse, PersonResponse, SplitPersonRequest,
+};
+use crate::services::contacts::person_key;
+use crate::state::{AppState, DbChangeEvent};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json},
+};
+use std::sync::Arc;
+
+// List the people directory, named people first, alphabetically.
+// Inputs: optional `q` (matches names and handles), `limit`, `offset`.
+// Output: 200 + people with their handles; 500 on DB errors.
+pub async fn get_people(Query(params): Query<PeopleParams>) -> impl IntoResponse {
+    let context_db = match ContextDb::open() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": format!("Failed to open context db: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    match context_db.list_people(params.q.as_deref(), params.limit, params.offset) {
+        Ok((people, total)) => {
+            let has_more = params.offset + (people.len() as i64) < total;
+            (
+                StatusCode::OK,
+                Json(PeopleResponse {
+                    people,
+                    total,
+                    has_more,
+                }),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to list people: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+// Load one person with their handles and the context learned for each handle.
+// Output: 200 + person JSON; 404 when missing; 500 on DB errors.
+pub async fn get_person(Path(id): Path<i64>) -> impl IntoResponse {
+    let context_db = match ContextDb::open() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": format!("Failed to open context db: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    match person_response(&context_db, id) {
+        Ok(Some(person)) => (StatusCode::OK, Json(person)).into_response(),
+        Ok(None) => person_not_found(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to fetch person: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+// Merge other people into this one, e.g. someone's phone and work email.
+// Inputs: `id` path param and JSON `{ "ids": [..] }` of the people to fold in.
+// Behavior: their handles move over; their name and photo fill in any this
+// person lacks; they are then deleted.
+// Output: 200 + merged person; 404 if any person is missing; 500 on DB errors.
+pub async fn merge_people(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<MergePeopleRequest>,
+) -> impl IntoResponse {
+    let context_db = match ContextDb::open() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": format!("Failed to open context db: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    for person_id in std::iter::once(&id).chain(&req.ids) {
+        match context_db.get_person(*person_id) {
+            Ok(Some(_)) => {}
+            Ok(None) => return person_not_found(),
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({
+                        "error": format!("Failed to fetch person: {}", e)
+                    })),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    if let Err(e) = context_db.merge_people(id, &req.ids) {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to merge people: {}", e)
+            })),
+        )
+            .into_response();
+    }
+
+    // Chat names may have changed
+    let _ = state.db_change_tx.send(DbChangeEvent::ChatListChanged);
+    match person_response(&context_db, id) {
+        Ok(Some(person)) => (StatusCode::OK, Json(person)).into_response(),
+        Ok(None) => person_not_found(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to fetch person: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+// Split handles off a person into a new person, undoing a wrong merge.
+// Inputs: `id` path param and JSON `{ "handles": [..] }`, each one of the person's.
+// Output: 200 + the new person; 400 if a handle isn't the person's or none
+// would be left; 404 when the person is missing; 500 on DB errors.
+pub async fn split_person(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(req): Json<SplitPersonRequest>,
+) -> impl IntoResponse {
+    let context_db = match ContextDb::open() {
+        Ok(db) => db,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": format!("Failed to open context db: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    let person = match context_db.get_person(id) {
+        Ok(Some(person)) => person,
+        Ok(None) => return person_not_found(),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": format!("Failed to fetch person: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    let owned: Vec<String> = person.handles.iter().map(|handle| person_key(handle)).collect();
+    if let Some(handle) = req
+        .handles
+        .iter()
+        .find(|handle| !owned.contains(&person_key(handle)))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("{} isn't one of this person's handles", handle)
+            })),
+        )
+            .into_response();
+    }
+    let moving: Vec<String> = req.handles.iter().map(|handle| person_key(handle)).collect();
+    if req.handles.is_empty() || owned.iter().all(|key| moving.contains(key)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "Split off some of the person's handles, but not all of them"
+            })),
+        )
+            .into_response();
+    }
+
+    match context_db.split_person(id, &req.handles) {
+        Ok(new_person) => {
+            let _ = state.db_change_tx.send(DbChangeEvent::ChatListChanged);
+            (StatusCode::OK, Json(new_person)).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to split person: {}", e)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// A person plus the context stored under each of their handles
+fn person_response(
+    context_db: &ContextDb,
+    id: i64,
+) -> Result<Option<PersonResponse>, Box<dyn std::error::Error>> {
+    let Some(person) = context_db.get_person(id)? else {
+        return Ok(None);
+    };
+    let mut contexts = Vec::new();
+    for handle in &person.handles {
+        if let Some(context) = context_db.get_context(handle)? {
+            contexts.push(context);
+        }
+    }
+    Ok(Some(PersonResponse { person, contexts }))
+}
+
+fn person_not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "Person not found" })),
+    )
+        .into_response()
+}
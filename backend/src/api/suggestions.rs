@@ -2,7 +2,8 @@ use crate::context_db::ContextDb;
 use crate::extraction::MessageForExtraction;
 use crate::models::{SuggestRequest, SuggestResponse, SuggestedAction, SuggestedActionType};
 use crate::openrouter::{ChatMessage, OpenRouterClient};
-use crate::services::messages::{fetch_chats_by_ids, fetch_recent_messages_for_suggestion};
+use crate::services::chat_summaries::fetch_chats_by_ids;
+use crate::services::messages::fetch_recent_messages_for_suggestion;
 use crate::services::openrouter_config::get_openrouter_api_key;
 use crate::state::{AppState, SUGGESTION_CACHE_TTL, SuggestionCacheEntry};
 use axum::{
@@ -165,6 +166,7 @@ async fn suggest_message_service(
     let chat_display_name = match state.chat_pool.get() {
         Ok(conn) => fetch_chats_by_ids(
             &conn,
+            &state.chat_summaries,
             &state.contact_resolve_tx,
             &context_db,
             &[req.chat_id],
@@ -1,14 +1,124 @@
 use crate::context_db::ContextDb;
-use crate::services::messages::fetch_messages;
-use crate::state::AppState;
+use crate::models::MessagesDelta;
+use crate::services::messages::{fetch_messages_delta, latest_chat_rowid};
+use crate::state::{AppState, DbChangeEvent};
 use axum::{
-    extract::{ws::WebSocket, ws::WebSocketUpgrade, State},
+    extract::{ws::Message as WsMessage, ws::WebSocket, ws::WebSocketUpgrade, State},
     response::IntoResponse,
 };
+use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt};
-use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
 use tracing::{info, warn};
 
+// ============================================================================
+// WEBSOCKET PROTOCOL (v2)
+// ============================================================================
+//
+// Server -> client on connect:
+//   {"type": "hello", "version": 2, "heartbeat_interval_ms": 30000}
+//
+// Client -> server:
+//   {"type": "hello", "version": 2}                  optional version check
+//   {"type": "subscribe", "chats": [{"chat_id": 1, "last_seen_rowid": 480}],
+//    "chat_list": true}
+//   {"type": "unsubscribe", "chats": [1], "chat_list": false}
+//   {"type": "ping", "nonce": ...}                   at least every heartbeat interval
+//
+// Server -> client:
+//   messages_delta     new/changed messages for a subscribed chat since the
+//                      client's last-seen ROWID, with the next `last_rowid`
+//   chat_list_changed  something in chat.db changed; refresh the chat list
+//   resync             deltas were lost (slow client); refetch the listed chats
+//   pong, error
+//
+// Subscribing with `last_seen_rowid` replies with everything missed since then,
+// so a reconnecting client catches up without refetching the page. Without it
+// the subscription starts from the chat's newest message.
+// ============================================================================
+
+/// Version of the protocol above; bumped on breaking changes
+const PROTOCOL_VERSION: u32 = 2;
+/// How often clients should ping
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Connections that stay silent this long are closed
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(90);
+/// Most new messages sent in one delta; a client further behind is told to resync
+const DELTA_LIMIT: i64 = 200;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Hello {
+        version: u32,
+    },
+    Subscribe {
+        #[serde(default)]
+        chats: Vec<ChatSubscription>,
+        #[serde(default)]
+        chat_list: bool,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        chats: Vec<i64>,
+        #[serde(default)]
+        chat_list: bool,
+    },
+    Ping {
+        #[serde(default)]
+        nonce: Option<serde_json::Value>,
+    },
+}
+
+#[derive(Deserialize)]
+struct ChatSubscription {
+    chat_id: i64,
+    /// Newest ROWID the client already has; None to start from now
+    last_seen_rowid: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Hello {
+        version: u32,
+        heartbeat_interval_ms: u64,
+    },
+    Pong {
+        nonce: Option<serde_json::Value>,
+        timestamp: i64,
+    },
+    MessagesDelta {
+        chat_id: i64,
+        #[serde(flatten)]
+        delta: MessagesDelta,
+        /// The change that produced this delta; None when catching up on subscribe
+        event: Option<&'a DbChangeEvent>,
+    },
+    ChatListChanged {
+        event: &'a DbChangeEvent,
+    },
+    Resync {
+        chat_ids: Vec<i64>,
+        chat_list: bool,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// What one connection is subscribed to
+#[derive(Default)]
+struct Subscriptions {
+    /// chat_id -> newest ROWID already sent to the client
+    chats: BTreeMap<i64, i64>,
+    chat_list: bool,
+}
+
 /// HTTP handler that upgrades the connection to WebSocket
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
@@ -22,153 +132,352 @@ pub async fn ws_handler(
 /// Handles an individual WebSocket connection
 async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     // Split the WebSocket into sender and receiver halves
-    // This allows us to send and receive concurrently
     let (mut sender, mut receiver) = socket.split();
 
     // Subscribe to database change events
     // Each WebSocket connection gets its own receiver from the broadcast channel
     let mut db_rx = state.db_change_tx.subscribe();
 
-    // Track which chat the client is subscribed to (if any)
-    let subscribed_chat: Arc<Mutex<Option<i64>>> = Arc::new(Mutex::new(None));
-    let subscribed_chat_clone = subscribed_chat.clone();
-
-    // Clone state for the message sender task
-    let state_clone = state.clone();
-
-    // Spawn a task to handle incoming messages from the client
-    let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
-            if let axum::extract::ws::Message::Text(text) = msg {
-                // Parse the incoming message
-                if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&text) {
-                    if let Some(msg_type) = parsed.get("type").and_then(|v| v.as_str()) {
-                        match msg_type {
-                            "subscribe" => {
-                                // Client wants to subscribe to a specific chat
-                                if let Some(chat_id) = parsed.get("chat_id").and_then(|v| v.as_i64()) {
-                                    let mut guard = subscribed_chat_clone.lock().unwrap();
-                                    *guard = Some(chat_id);
-                                    info!(target: "ws", "Client subscribed to chat {}", chat_id);
-                                }
-                            }
-                            "unsubscribe" => {
-                                let mut guard = subscribed_chat_clone.lock().unwrap();
-                                *guard = None;
-                                info!(target: "ws", "Client unsubscribed from chat");
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-            }
-        }
-    });
+    let mut subscriptions = Subscriptions::default();
+    let mut last_heard = Instant::now();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    let hello = ServerMessage::Hello {
+        version: PROTOCOL_VERSION,
+        heartbeat_interval_ms: HEARTBEAT_INTERVAL.as_millis() as u64,
+    };
+    if !send(&mut sender, &hello).await {
+        return;
+    }
 
     info!(target: "ws", "WebSocket handler entering main loop");
 
-    // Main loop: wait for database changes and send updates to client
     loop {
         tokio::select! {
-            // When the database changes, fetch and send updated data
-            result = db_rx.recv() => {
-                info!(target: "ws", "Received event from broadcast channel");
-                match result {
-                    Ok(event) => {
-                        // Get the subscribed chat ID (if any)
-                        let chat_id = {
-                            subscribed_chat.lock().unwrap().clone()
-                        };
+            incoming = receiver.next() => {
+                let text = match incoming {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    // Protocol-level pings and other frames still count as activity
+                    Some(Ok(_)) => {
+                        last_heard = Instant::now();
+                        continue;
+                    }
+                };
+                last_heard = Instant::now();
 
-                        info!(target: "ws", "Processing db change, subscribed_chat = {:?}", chat_id);
-
-                        // Build the update message
-                        let update = if let Some(chat_id) = chat_id {
-                            let chat_pool = state_clone.chat_pool.clone();
-
-                            let fetch_result = tokio::task::spawn_blocking(move || {
-                                let conn = chat_pool.get().map_err(|e| e.to_string())?;
-                                let context_db = ContextDb::open().map_err(|e| e.to_string())?;
-                                fetch_messages(
-                                    &conn,
-                                    chat_id,
-                                    &context_db,
-                                    50,
-                                    0,
-                                )
-                                .map_err(|e| e.to_string())
-                            })
-                            .await;
-
-                            match fetch_result {
-                                Ok(Ok(messages_response)) => {
-                                    info!(
-                                        target: "ws",
-                                        "Fetched {} messages for chat {}",
-                                        messages_response.messages.len(),
-                                        chat_id
-                                    );
-                                    serde_json::json!({
-                                        "type": "messages_update",
-                                        "chat_id": chat_id,
-                                        "messages": messages_response.messages,
-                                        "total": messages_response.total,
-                                        "timestamp": event.timestamp,
-                                    })
-                                }
-                                Ok(Err(e)) => {
-                                    warn!(target: "ws", "Error fetching messages: {}", e);
-                                    serde_json::json!({
-                                        "type": "error",
-                                        "message": format!("Failed to fetch messages: {}", e),
-                                    })
-                                }
-                                Err(_) => {
-                                    warn!(target: "ws", "Error fetching messages: join error");
-                                    serde_json::json!({
-                                        "type": "error",
-                                        "message": "Failed to fetch messages".to_string(),
-                                    })
-                                }
-                            }
-                        } else {
-                            info!(target: "ws", "No chat subscribed, sending db_changed");
-                            // No specific chat subscribed, just send a generic update notification
-                            serde_json::json!({
-                                "type": "db_changed",
-                                "timestamp": event.timestamp,
-                            })
+                let message = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!(target: "ws", "Unrecognized client message: {}", e);
+                        let error = ServerMessage::Error {
+                            message: format!("Unrecognized message: {}", e),
                         };
+                        if !send(&mut sender, &error).await {
+                            break;
+                        }
+                        continue;
+                    }
+                };
 
-                        // Send the update to the client
-                        info!(
-                            target: "ws",
-                            "Sending WebSocket message: type={}",
-                            update.get("type").unwrap()
-                        );
-                        if sender.send(axum::extract::ws::Message::Text(update.to_string().into())).await.is_err() {
-                            warn!(target: "ws", "Failed to send WebSocket message (client disconnected)");
-                            // Client disconnected
+                if !handle_client_message(message, &state, &mut subscriptions, &mut sender).await {
+                    break;
+                }
+            }
+            result = db_rx.recv() => {
+                match result {
+                    Ok(event) => {
+                        if !handle_change(&event, &state, &mut subscriptions, &mut sender).await {
                             break;
                         }
-                        info!(target: "ws", "WebSocket message sent successfully");
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                        // We missed some events (slow consumer)
-                        warn!(target: "ws", "WebSocket client lagged, missed {} events", n);
+                    Err(RecvError::Lagged(n)) => {
+                        // Events were dropped, so deltas can't be trusted: have the
+                        // client refetch and restart its subscriptions from now
+                        warn!(target: "ws", "WebSocket client lagged, missed {} events; resyncing", n);
+                        let chat_ids: Vec<i64> = subscriptions.chats.keys().copied().collect();
+                        for (chat_id, rowid) in latest_rowids(&state, chat_ids.clone()).await {
+                            subscriptions.chats.insert(chat_id, rowid);
+                        }
+                        let resync = ServerMessage::Resync {
+                            chat_ids,
+                            chat_list: subscriptions.chat_list,
+                        };
+                        if !send(&mut sender, &resync).await {
+                            break;
+                        }
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    Err(RecvError::Closed) => {
                         // Broadcast channel closed (shouldn't happen)
                         break;
                     }
                 }
             }
-            // If the receive task completes (client disconnected), exit
-            _ = &mut recv_task => {
-                break;
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() > HEARTBEAT_TIMEOUT {
+                    info!(target: "ws", "No heartbeat from client in {:?}, closing", HEARTBEAT_TIMEOUT);
+                    break;
+                }
             }
         }
     }
 
     info!(target: "ws", "WebSocket client disconnected");
 }
+
+/// Apply one client message. Returns false once the connection should close.
+async fn handle_client_message(
+    message: ClientMessage,
+    state: &Arc<AppState>,
+    subscriptions: &mut Subscriptions,
+    sender: &mut SplitSink<WebSocket, WsMessage>,
+) -> bool {
+    match message {
+        ClientMessage::Hello { version } => {
+            if version == PROTOCOL_VERSION {
+                return true;
+            }
+            let error = ServerMessage::Error {
+                message: format!(
+                    "Unsupported protocol version {} (server speaks {})",
+                    version, PROTOCOL_VERSION
+                ),
+            };
+            send(sender, &error).await;
+            false
+        }
+        ClientMessage::Subscribe { chats, chat_list } => {
+            subscriptions.chat_list |= chat_list;
+            for chat in chats {
+                info!(target: "ws", "Client subscribed to chat {}", chat.chat_id);
+                let after_rowid = match chat.last_seen_rowid {
+                    Some(rowid) => Some(rowid),
+                    None => latest_rowids(state, vec![chat.chat_id])
+                        .await
+                        .first()
+                        .map(|(_, rowid)| *rowid),
+                };
+                let Some(after_rowid) = after_rowid else {
+                    continue;
+                };
+                subscriptions.chats.insert(chat.chat_id, after_rowid);
+                if !send_delta(state, subscriptions, sender, chat.chat_id, None).await {
+                    return false;
+                }
+            }
+            true
+        }
+        ClientMessage::Unsubscribe { chats, chat_list } => {
+            for chat_id in chats {
+                subscriptions.chats.remove(&chat_id);
+                info!(target: "ws", "Client unsubscribed from chat {}", chat_id);
+            }
+            if chat_list {
+                subscriptions.chat_list = false;
+            }
+            true
+        }
+        ClientMessage::Ping { nonce } => {
+            let pong = ServerMessage::Pong {
+                nonce,
+                timestamp: chrono::Utc::now().timestamp_millis(),
+            };
+            send(sender, &pong).await
+        }
+    }
+}
+
+/// Forward a database change to the subscriptions it touches.
+/// Returns false once the client has gone away.
+async fn handle_change(
+    event: &DbChangeEvent,
+    state: &Arc<AppState>,
+    subscriptions: &mut Subscriptions,
+    sender: &mut SplitSink<WebSocket, WsMessage>,
+) -> bool {
+    if let Some(chat_id) = event.chat_id() {
+        if subscriptions.chats.contains_key(&chat_id)
+            && !send_delta(state, subscriptions, sender, chat_id, Some(event)).await
+        {
+            return false;
+        }
+    }
+
+    if subscriptions.chat_list {
+        return send(sender, &ServerMessage::ChatListChanged { event }).await;
+    }
+    true
+}
+
+/// Send a subscribed chat everything since its last-seen ROWID, plus the
+/// messages `event` says changed. Returns false once the client has gone away.
+async fn send_delta(
+    state: &Arc<AppState>,
+    subscriptions: &mut Subscriptions,
+    sender: &mut SplitSink<WebSocket, WsMessage>,
+    chat_id: i64,
+    event: Option<&DbChangeEvent>,
+) -> bool {
+    let Some(&after_rowid) = subscriptions.chats.get(&chat_id) else {
+        return true;
+    };
+    let (changed_rowids, changed_guids) = match event {
+        Some(DbChangeEvent::MessageEdited { rowids, .. }) => (rowids.clone(), Vec::new()),
+        Some(DbChangeEvent::ReactionChanged { message_guids, .. }) => {
+            (Vec::new(), message_guids.clone())
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+
+    let chat_pool = state.chat_pool.clone();
+    let fetch_result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        fetch_messages_delta(
+            &conn,
+            chat_id,
+            &context_db,
+            after_rowid,
+            &changed_rowids,
+            &changed_guids,
+            DELTA_LIMIT,
+        )
+        .map_err(|e| e.to_string())
+    })
+    .await;
+
+    let delta = match fetch_result {
+        Ok(Ok(delta)) => delta,
+        Ok(Err(e)) => {
+            warn!(target: "ws", "Error fetching messages: {}", e);
+            let error = ServerMessage::Error {
+                message: format!("Failed to fetch messages: {}", e),
+            };
+            return send(sender, &error).await;
+        }
+        Err(_) => {
+            warn!(target: "ws", "Error fetching messages: join error");
+            let error = ServerMessage::Error {
+                message: "Failed to fetch messages".to_string(),
+            };
+            return send(sender, &error).await;
+        }
+    };
+
+    if !delta.complete {
+        // Too far behind for a delta; start over from the newest message
+        info!(target: "ws", "Chat {} is more than {} messages behind, resyncing", chat_id, DELTA_LIMIT);
+        for (chat_id, rowid) in latest_rowids(state, vec![chat_id]).await {
+            subscriptions.chats.insert(chat_id, rowid);
+        }
+        let resync = ServerMessage::Resync {
+            chat_ids: vec![chat_id],
+            chat_list: false,
+        };
+        return send(sender, &resync).await;
+    }
+
+    // A change may not touch anything visible (e.g. a reaction to a reaction)
+    if event.is_some() && delta.messages.is_empty() && delta.last_rowid == after_rowid {
+        return true;
+    }
+
+    subscriptions.chats.insert(chat_id, delta.last_rowid);
+    info!(
+        target: "ws",
+        "Sending {} new/changed messages for chat {}",
+        delta.messages.len(),
+        chat_id
+    );
+    send(
+        sender,
+        &ServerMessage::MessagesDelta {
+            chat_id,
+            delta,
+            event,
+        },
+    )
+    .await
+}
+
+/// Newest ROWID of each chat; chats that fail to load are left out
+async fn latest_rowids(state: &Arc<AppState>, chat_ids: Vec<i64>) -> Vec<(i64, i64)> {
+    let chat_pool = state.chat_pool.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        chat_ids
+            .into_iter()
+            .map(|chat_id| {
+                latest_chat_rowid(&conn, chat_id)
+                    .map(|rowid| (chat_id, rowid))
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Result<Vec<_>, String>>()
+    })
+    .await;
+
+    match result {
+        Ok(Ok(rowids)) => rowids,
+        Ok(Err(e)) => {
+            warn!(target: "ws", "Error reading latest ROWIDs: {}", e);
+            Vec::new()
+        }
+        Err(_) => {
+            warn!(target: "ws", "Error reading latest ROWIDs: join error");
+            Vec::new()
+        }
+    }
+}
+
+/// Serialize and send one message. Returns false if the client disconnected.
+async fn send(sender: &mut SplitSink<WebSocket, WsMessage>, message: &ServerMessage<'_>) -> bool {
+    let text = match serde_json::to_string(message) {
+        Ok(text) => text,
+        Err(e) => {
+            warn!(target: "ws", "Failed to serialize WebSocket message: {}", e);
+            return true;
+        }
+    };
+    if sender.send(WsMessage::Text(text)).await.is_err() {
+        warn!(target: "ws", "Failed to send WebSocket message (client disconnected)");
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_client_messages() {
+        let subscribe: ClientMessage = serde_json::from_str(
+            r#"{"type": "subscribe", "chats": [{"chat_id": 4, "last_seen_rowid": 90}, {"chat_id": 5}]}"#,
+        )
+        .unwrap();
+        match subscribe {
+            ClientMessage::Subscribe { chats, chat_list } => {
+                assert!(!chat_list);
+                assert_eq!(chats[0].chat_id, 4);
+                assert_eq!(chats[0].last_seen_rowid, Some(90));
+                assert_eq!(chats[1].last_seen_rowid, None);
+            }
+            _ => panic!("expected subscribe"),
+        }
+
+        let ping: ClientMessage = serde_json::from_str(r#"{"type": "ping"}"#).unwrap();
+        assert!(matches!(ping, ClientMessage::Ping { nonce: None }));
+    }
+
+    #[test]
+    fn serializes_resync() {
+        let resync = ServerMessage::Resync {
+            chat_ids: vec![4],
+            chat_list: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&resync).unwrap(),
+            serde_json::json!({"type": "resync", "chat_ids": [4], "chat_list": true})
+        );
+    }
+}
@@ -0,0 +1,209 @@
+// chat.db Schema Module
+// Messages.app's schema drifts between macOS releases: columns such as
+// `date_edited`, `thread_originator_guid` or `associated_message_emoji` only
+// exist on newer systems. The columns are read from `PRAGMA table_info` once at
+// startup, and queries name optional columns through `column()`, which swaps in
+// a neutral literal when this chat.db doesn't have them.
+
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::OnceLock;
+
+const TABLES: &[&str] = &["message", "chat", "handle", "attachment"];
+
+/// Columns every supported release has; without them messages can't be read.
+/// (`ROWID` is implicit and never listed by `table_info`.)
+const REQUIRED_COLUMNS: &[(&str, &str)] = &[
+    ("message", "guid"),
+    ("message", "text"),
+    ("message", "handle_id"),
+    ("message", "date"),
+    ("message", "is_from_me"),
+    ("chat", "chat_identifier"),
+    ("handle", "id"),
+    ("attachment", "filename"),
+];
+
+/// Columns that come and go, with the literal used in their place when missing
+const OPTIONAL_COLUMNS: &[(&str, &str, &str)] = &[
+    ("message", "attributedBody", "NULL"),
+    ("message", "associated_message_guid", "NULL"),
+    ("message", "associated_message_type", "0"),
+    ("message", "associated_message_emoji", "NULL"),
+    ("message", "balloon_bundle_id", "NULL"),
+    ("message", "cache_has_attachments", "0"),
+    ("message", "date_delivered", "0"),
+    ("message", "date_edited", "0"),
+    ("message", "date_read", "0"),
+    ("message", "date_retracted", "0"),
+    ("message", "error", "0"),
+    ("message", "group_action_type", "0"),
+    ("message", "group_title", "NULL"),
+    ("message", "is_delivered", "0"),
+    // Without read state nothing should look unread
+    ("message", "is_read", "1"),
+    ("message", "item_type", "0"),
+    ("message", "message_summary_info", "NULL"),
+    ("message", "other_handle", "0"),
+    ("message", "service", "NULL"),
+    ("message", "thread_originator_guid", "NULL"),
+    ("message", "thread_originator_part", "NULL"),
+    ("chat", "display_name", "NULL"),
+    ("attachment", "mime_type", "NULL"),
+    ("attachment", "total_bytes", "0"),
+    ("attachment", "transfer_name", "NULL"),
+];
+
+/// User-visible capabilities and the `message` column each depends on
+const FEATURES: &[(&str, &str)] = &[
+    ("rich_text", "attributedBody"),
+    ("tapbacks", "associated_message_type"),
+    ("custom_emoji_tapbacks", "associated_message_emoji"),
+    ("inline_replies", "thread_originator_guid"),
+    ("edits", "date_edited"),
+    ("unsend", "date_retracted"),
+    ("read_receipts", "date_read"),
+    ("timeline_events", "item_type"),
+];
+
+static SCHEMA: OnceLock<ChatDbSchema> = OnceLock::new();
+
+/// Columns present in the chat.db tables we query
+pub struct ChatDbSchema {
+    columns: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// What `/health` reports about the detected schema
+#[derive(Serialize)]
+pub struct SchemaProfile {
+    /// False if a required column is missing; most endpoints will fail
+    pub compatible: bool,
+    /// `table.column` entries
+    pub missing_required: Vec<String>,
+    /// `table.column` entries that read as their fallback instead
+    pub missing_optional: Vec<String>,
+    pub features: BTreeMap<&'static str, bool>,
+    /// Column count per table
+    pub tables: BTreeMap<String, usize>,
+}
+
+impl ChatDbSchema {
+    pub fn detect(conn: &Connection) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut columns = BTreeMap::new();
+        for table in TABLES {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+            let names = stmt
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<Result<BTreeSet<_>, _>>()?;
+            columns.insert(table.to_string(), names);
+        }
+        Ok(ChatDbSchema { columns })
+    }
+
+    pub fn has(&self, table: &str, column: &str) -> bool {
+        self.columns
+            .get(table)
+            .is_some_and(|columns| columns.contains(column))
+    }
+
+    pub fn profile(&self) -> SchemaProfile {
+        let missing = |columns: &mut dyn Iterator<Item = (&str, &str)>| {
+            columns
+                .filter(|(table, column)| !self.has(table, column))
+                .map(|(table, column)| format!("{}.{}", table, column))
+                .collect::<Vec<_>>()
+        };
+        let missing_required = missing(&mut REQUIRED_COLUMNS.iter().copied());
+        let missing_optional = missing(&mut OPTIONAL_COLUMNS.iter().map(|(t, c, _)| (*t, *c)));
+
+        SchemaProfile {
+            compatible: missing_required.is_empty(),
+            missing_required,
+            missing_optional,
+            features: FEATURES
+                .iter()
+                .map(|(feature, column)| (*feature, self.has("message", column)))
+                .collect(),
+            tables: self
+                .columns
+                .iter()
+                .map(|(table, columns)| (table.clone(), columns.len()))
+                .collect(),
+        }
+    }
+
+    /// SQL for `alias.column`, or the column's fallback literal if it's missing
+    fn column_sql(&self, table: &str, alias: &str, column: &str) -> String {
+        if !self.has(table, column) {
+            if let Some((_, _, fallback)) = OPTIONAL_COLUMNS
+                .iter()
+                .find(|(t, c, _)| *t == table && *c == column)
+            {
+                return fallback.to_string();
+            }
+        }
+        qualified(alias, column)
+    }
+}
+
+fn qualified(alias: &str, column: &str) -> String {
+    if alias.is_empty() {
+        column.to_string()
+    } else {
+        format!("{}.{}", alias, column)
+    }
+}
+
+/// Detect the schema of the live chat.db; later `column()` calls follow it
+pub fn install(conn: &Connection) -> Result<&'static ChatDbSchema, Box<dyn std::error::Error>> {
+    let schema = ChatDbSchema::detect(conn)?;
+    Ok(SCHEMA.get_or_init(|| schema))
+}
+
+pub fn installed() -> Option<&'static ChatDbSchema> {
+    SCHEMA.get()
+}
+
+/// SQL naming `table.column` as `alias.column` (bare with an empty alias), or a
+/// neutral literal when this chat.db lacks the column. Until `install` runs,
+/// every column is assumed present.
+pub fn column(table: &str, alias: &str, column: &str) -> String {
+    match SCHEMA.get() {
+        Some(schema) => schema.column_sql(table, alias, column),
+        None => qualified(alias, column),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_for_missing_optional_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "
+            CREATE TABLE message (guid TEXT, text TEXT, handle_id INTEGER, date INTEGER,
+                                  is_from_me INTEGER, attributedBody BLOB, is_read INTEGER);
+            CREATE TABLE chat (chat_identifier TEXT);
+            CREATE TABLE handle (id TEXT);
+            CREATE TABLE attachment (mime_type TEXT);
+            ",
+        )
+        .unwrap();
+        let schema = ChatDbSchema::detect(&conn).unwrap();
+
+        assert_eq!(schema.column_sql("message", "m", "attributedBody"), "m.attributedBody");
+        assert_eq!(schema.column_sql("message", "m", "date_edited"), "0");
+        assert_eq!(schema.column_sql("message", "", "thread_originator_guid"), "NULL");
+        assert_eq!(schema.column_sql("chat", "c", "display_name"), "NULL");
+
+        let profile = schema.profile();
+        assert!(!profile.compatible);
+        assert_eq!(profile.missing_required, vec!["attachment.filename"]);
+        assert!(profile.missing_optional.contains(&"message.date_edited".to_string()));
+        assert!(profile.features["rich_text"]);
+        assert!(!profile.features["edits"]);
+    }
+}
@@ -0,0 +1,336 @@
+// Configuration Module
+// Where the backend reads its data and how it serves it. Settings are layered,
+// later sources winning: built-in defaults, the TOML file, environment
+// variables, then command-line flags. `chat_db` can point at any copy of a
+// Messages database (an archived snapshot, a synthetic test fixture), so the
+// backend also runs away from ~/Library/Messages, e.g. on Linux CI.
+
+use clap::Parser;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Messages database to serve; only ever opened read-only
+    pub chat_db: PathBuf,
+    /// Unencrypted iPhone backup directory to serve instead of `chat_db`
+    pub ios_backup: Option<PathBuf>,
+    /// Contact context database. The search index and photo cache live next to it.
+    pub context_db: PathBuf,
+    /// Contacts.app data directory, read for names, numbers and emails
+    pub address_book: PathBuf,
+    /// `host:port` for HTTP and the WebSocket
+    pub listen: String,
+    /// Browser origins allowed to call the API: the app's dev server and,
+    /// as "null", the packaged app loaded from a file
+    pub allowed_origins: Vec<String>,
+    /// Read-only connections kept open to chat.db
+    pub pool_size: u32,
+    pub assist: AssistConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssistConfig {
+    /// OpenRouter model tried first by /api/assist/stream
+    pub primary_model: String,
+    /// Model used when the primary one fails
+    pub fallback_model: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `tracing` filter directives, e.g. `server=info,ws=info,watcher=debug`
+    pub filter: String,
+    /// Append logs to this file instead of writing them to stdout
+    pub file: Option<PathBuf>,
+}
+
+/// Command-line flags; each can also be set through the environment variable shown
+#[derive(Debug, Default, Parser)]
+#[command(version, about = "myMessage backend")]
+pub struct Cli {
+    /// TOML settings file [default: ~/.imessage-companion/config.toml, if it exists]
+    #[arg(long, env = "MYMESSAGE_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Messages database to serve (any copy of a chat.db)
+    #[arg(long, env = "MYMESSAGE_CHAT_DB")]
+    pub chat_db: Option<PathBuf>,
+    /// Unencrypted iPhone backup to serve instead of a chat.db
+    #[arg(long, env = "MYMESSAGE_IOS_BACKUP")]
+    pub ios_backup: Option<PathBuf>,
+    /// Contact context database
+    #[arg(long, env = "MYMESSAGE_CONTEXT_DB")]
+    pub context_db: Option<PathBuf>,
+    /// Contacts.app data directory
+    #[arg(long, env = "MYMESSAGE_ADDRESS_BOOK")]
+    pub address_book: Option<PathBuf>,
+    /// Address to listen on, as host:port
+    #[arg(long, env = "MYMESSAGE_LISTEN")]
+    pub listen: Option<String>,
+    /// Browser origins allowed to call the API, comma-separated
+    #[arg(long, env = "MYMESSAGE_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub allowed_origins: Option<Vec<String>>,
+    /// Read-only connections kept open to chat.db
+    #[arg(long, env = "MYMESSAGE_POOL_SIZE")]
+    pub pool_size: Option<u32>,
+    /// Primary assist model
+    #[arg(long, env = "MYMESSAGE_PRIMARY_MODEL")]
+    pub primary_model: Option<String>,
+    /// Fallback assist model
+    #[arg(long, env = "MYMESSAGE_FALLBACK_MODEL")]
+    pub fallback_model: Option<String>,
+    /// Log filter directives
+    #[arg(long, env = "RUST_LOG")]
+    pub log_filter: Option<String>,
+    /// Append logs to this file instead of stdout
+    #[arg(long, env = "MYMESSAGE_LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            chat_db: default_chat_db(),
+            ios_backup: None,
+            context_db: home_dir().join(".imessage-companion/context.db"),
+            address_book: home_dir().join("Library/Application Support/AddressBook"),
+            listen: "127.0.0.1:3883".to_string(),
+            allowed_origins: ["http://localhost:5173", "http://127.0.0.1:5173", "null"]
+                .map(str::to_string)
+                .to_vec(),
+            pool_size: 4,
+            assist: AssistConfig::default(),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for AssistConfig {
+    fn default() -> Self {
+        AssistConfig {
+            primary_model: "anthropic/claude-opus-4.5".to_string(),
+            fallback_model: "anthropic/claude-3.5-sonnet".to_string(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: "openrouter=info".to_string(),
+            file: None,
+        }
+    }
+}
+
+impl Config {
+    /// Build the settings for this run from the config file and `cli`
+    pub fn load(cli: &Cli) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = match &cli.config {
+            Some(path) => Some(expand_home(path)),
+            // The default file is optional; an explicitly named one isn't
+            None => Some(default_config_path()).filter(|path| path.exists()),
+        };
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                Self::parse(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?
+            }
+            None => Config::default(),
+        };
+        config.apply(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config: Config = toml::from_str(text)?;
+        config.chat_db = expand_home(&config.chat_db);
+        config.ios_backup = config.ios_backup.as_deref().map(expand_home);
+        config.context_db = expand_home(&config.context_db);
+        config.address_book = expand_home(&config.address_book);
+        config.log.file = config.log.file.as_deref().map(expand_home);
+        Ok(config)
+    }
+
+    /// Overlay flags and environment variables (clap has already merged the two)
+    fn apply(&mut self, cli: &Cli) {
+        if let Some(chat_db) = &cli.chat_db {
+            self.chat_db = expand_home(chat_db);
+        }
+        if let Some(ios_backup) = &cli.ios_backup {
+            self.ios_backup = Some(expand_home(ios_backup));
+        }
+        if let Some(context_db) = &cli.context_db {
+            self.context_db = expand_home(context_db);
+        }
+        if let Some(address_book) = &cli.address_book {
+            self.address_book = expand_home(address_book);
+        }
+        if let Some(listen) = &cli.listen {
+            self.listen = listen.clone();
+        }
+        if let Some(origins) = &cli.allowed_origins {
+            self.allowed_origins = origins.clone();
+        }
+        if let Some(pool_size) = cli.pool_size {
+            self.pool_size = pool_size;
+        }
+        if let Some(model) = &cli.primary_model {
+            self.assist.primary_model = model.clone();
+        }
+        if let Some(model) = &cli.fallback_model {
+            self.assist.fallback_model = model.clone();
+        }
+        if let Some(filter) = &cli.log_filter {
+            self.log.filter = filter.clone();
+        }
+        if let Some(file) = &cli.log_file {
+            self.log.file = Some(expand_home(file));
+        }
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.pool_size == 0 {
+            return Err("pool_size must be at least 1".into());
+        }
+        // A backup's sms.db is located once the backup is opened
+        if self.ios_backup.is_none() && !self.chat_db.is_file() {
+            return Err(format!("chat.db not found at {}", self.chat_db.display()).into());
+        }
+        Ok(())
+    }
+
+    /// Directory holding the backend's own data (context, search index, photos)
+    pub fn data_dir(&self) -> &Path {
+        match self.context_db.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        }
+    }
+
+    /// The index is keyed by `message.ROWID`, so each chat.db copy gets its own
+    pub fn search_db(&self) -> PathBuf {
+        if self.chat_db == default_chat_db() {
+            return self.data_dir().join("search.db");
+        }
+        // FNV-1a: stable across runs and toolchains, unlike `DefaultHasher`
+        let hash = self
+            .chat_db
+            .to_string_lossy()
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+        self.data_dir().join(format!("search-{:016x}.db", hash))
+    }
+
+    pub fn imports_db(&self) -> PathBuf {
+        self.data_dir().join("imports.db")
+    }
+
+    pub fn photo_cache_dir(&self) -> PathBuf {
+        self.data_dir().join("photos")
+    }
+}
+
+fn home_dir() -> PathBuf {
+    PathBuf::from(std::env::var("HOME").unwrap_or_default())
+}
+
+fn default_chat_db() -> PathBuf {
+    home_dir().join("Library/Messages/chat.db")
+}
+
+fn default_config_path() -> PathBuf {
+    home_dir().join(".imessage-companion/config.toml")
+}
+
+/// Resolve a leading `~/` against $HOME
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => home_dir().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Make `config` the settings every module reads through `current()`
+pub fn install(config: Config) -> &'static Config {
+    CONFIG.get_or_init(|| config)
+}
+
+/// Settings for this run; the defaults until `install` runs
+pub fn current() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_file_then_flags_over_defaults() {
+        let mut config = Config::parse(
+            r#"
+            chat_db = "~/fixtures/chat.db"
+            pool_size = 2
+
+            [assist]
+            primary_model = "openai/gpt-4o"
+
+            [log]
+            file = "/var/log/mymessage.log"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.chat_db, home_dir().join("fixtures/chat.db"));
+        assert_eq!(config.assist.fallback_model, "anthropic/claude-3.5-sonnet");
+
+        config.apply(&Cli {
+            chat_db: Some(PathBuf::from("/tmp/snapshot.db")),
+            pool_size: Some(8),
+            ..Default::default()
+        });
+        assert_eq!(config.chat_db, PathBuf::from("/tmp/snapshot.db"));
+        assert_eq!(config.pool_size, 8);
+        assert_eq!(config.assist.primary_model, "openai/gpt-4o");
+        assert_eq!(config.listen, "127.0.0.1:3883");
+        assert!(config.allowed_origins.contains(&"null".to_string()));
+        assert_eq!(config.log.file, Some(PathBuf::from("/var/log/mymessage.log")));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(Config::parse("chatdb = \"/tmp/chat.db\"").is_err());
+        assert!(Config::parse("[assist]\nmodel = \"x\"").is_err());
+    }
+
+    #[test]
+    fn keeps_data_next_to_context_db() {
+        let config = Config {
+            context_db: PathBuf::from("/srv/mymessage/context.db"),
+            ..Config::default()
+        };
+        assert_eq!(config.search_db(), PathBuf::from("/srv/mymessage/search.db"));
+        assert_eq!(config.photo_cache_dir(), PathBuf::from("/srv/mymessage/photos"));
+
+        let snapshot = Config {
+            chat_db: PathBuf::from("/archive/chat.db"),
+            ..config.clone()
+        };
+        let other = Config {
+            chat_db: PathBuf::from("/archive/2019/chat.db"),
+            ..config
+        };
+        assert_ne!(snapshot.search_db(), other.search_db());
+        assert!(snapshot.search_db().starts_with("/srv/mymessage"));
+    }
+}
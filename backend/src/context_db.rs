@@ -1,9 +1,15 @@
 // Context Database Module
 // Manages the local SQLite database for storing AI-extracted contact context
+// and the people directory: each person owns one or more handles (phone
+// numbers, emails), a display name and a photo. Contact-name lookups for
+// handles go through the person that owns them.
 
+use crate::config;
+use crate::services::address_book::ContactCard;
+use crate::services::contacts::person_key;
 use rusqlite::{Connection, params};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::HashMap;
 
 /// Contact context learned from conversations
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,6 +24,21 @@ pub struct ContactContext {
     pub updated_at: i64,
 }
 
+/// A person and the handles that reach them
+#[derive(Debug, Clone, Serialize)]
+pub struct Person {
+    pub id: i64,
+    pub display_name: Option<String>,
+    /// Handle whose Contacts photo stands for the person (`/contacts/:handle/photo`)
+    pub photo_handle: Option<String>,
+    /// Phone numbers and emails, spelled as first seen
+    pub handles: Vec<String>,
+    /// From Contacts: YYYY-MM-DD, or --MM-DD without a year
+    pub birthday: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct BasicInfo {
     pub birthday: Option<String>,
@@ -34,23 +55,26 @@ pub struct ContextDb {
 impl ContextDb {
     /// Open or create the context database
     pub fn open() -> Result<Self, Box<dyn std::error::Error>> {
-        let db_path = Self::get_db_path()?;
+        let db_path = &config::current().context_db;
 
         // Ensure directory exists
         if let Some(parent) = db_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        let conn = Connection::open(&db_path)?;
+        let conn = Connection::open(db_path)?;
         let db = ContextDb { conn };
         db.init_schema()?;
         Ok(db)
     }
 
-    /// Get the database file path
-    fn get_db_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
-        let home = std::env::var("HOME")?;
-        Ok(PathBuf::from(home).join(".imessage-companion").join("context.db"))
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, Box<dyn std::error::Error>> {
+        let db = ContextDb {
+            conn: Connection::open_in_memory()?,
+        };
+        db.init_schema()?;
+        Ok(db)
     }
 
     /// Initialize database schema
@@ -73,8 +97,62 @@ impl ContextDb {
             );
 
             CREATE INDEX IF NOT EXISTS idx_contact_context_name ON contact_context(display_name);
+
+            CREATE TABLE IF NOT EXISTS person (
+                id INTEGER PRIMARY KEY,
+                display_name TEXT,
+                photo_handle TEXT,
+                created_at INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+
+            -- One row per handle, keyed by `person_key` so every spelling of a
+            -- number or address finds the same person
+            CREATE TABLE IF NOT EXISTS person_handle (
+                handle_key TEXT PRIMARY KEY,
+                handle TEXT NOT NULL,
+                person_id INTEGER NOT NULL REFERENCES person(id)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_person_handle_person ON person_handle(person_id);
             "
         )?;
+
+        let version: i64 = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < 1 {
+            self.migrate_name_cache()?;
+            self.conn.execute_batch("PRAGMA user_version = 1")?;
+        }
+        if version < 2 {
+            self.conn.execute_batch(
+                "ALTER TABLE person ADD COLUMN birthday TEXT;
+                 PRAGMA user_version = 2;",
+            )?;
+        }
+        if version < 3 {
+            // Content hash of a photo imported for the person (see contacts.rs)
+            self.conn.execute_batch(
+                "ALTER TABLE person ADD COLUMN photo_hash TEXT;
+                 PRAGMA user_version = 3;",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Names used to be cached on `contact_context`, one row per handle
+    /// variant; give each distinct handle a person instead
+    fn migrate_name_cache(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT handle, display_name FROM contact_context
+             WHERE display_name IS NOT NULL AND display_name != ''
+             ORDER BY created_at, handle",
+        )?;
+        let named = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (handle, name) in named {
+            self.set_cached_contact_name(&handle, &name)?;
+        }
         Ok(())
     }
 
@@ -82,14 +160,20 @@ impl ContextDb {
     // Contact Cache Operations
     // ============================================================================
 
+    /// The name of the person owning `handle`, else a name saved with its context
     pub fn get_cached_contact_name(
         &self,
         handle: &str,
     ) -> Result<Option<String>, Box<dyn std::error::Error>> {
         let result = self.conn.query_row(
-            "SELECT display_name FROM contact_context
-             WHERE handle = ?1 AND display_name IS NOT NULL AND display_name != ''",
-            params![handle],
+            "SELECT p.display_name FROM person_handle ph
+             JOIN person p ON p.id = ph.person_id
+             WHERE ph.handle_key = ?1 AND p.display_name IS NOT NULL AND p.display_name != ''
+             UNION ALL
+             SELECT display_name FROM contact_context
+             WHERE handle = ?2 AND display_name IS NOT NULL AND display_name != ''
+             LIMIT 1",
+            params![person_key(handle), handle],
             |row| row.get(0),
         );
 
@@ -100,40 +184,46 @@ impl ContextDb {
         }
     }
 
+    /// Name the person owning `handle`, creating them if needed. A name the
+    /// person already has is kept.
     pub fn set_cached_contact_name(
         &self,
         handle: &str,
         display_name: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs() as i64;
-
+        let person_id = self.ensure_person(handle)?;
         self.conn.execute(
-            "INSERT INTO contact_context (handle, display_name, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?3)
-             ON CONFLICT(handle) DO UPDATE SET
-                display_name = CASE
-                    WHEN display_name IS NULL OR display_name = '' THEN ?2
-                    ELSE display_name
-                END,
-                updated_at = ?3",
-            params![handle, display_name, now],
+            "UPDATE person SET display_name = ?1, updated_at = ?2
+             WHERE id = ?3 AND (display_name IS NULL OR display_name = '')",
+            params![display_name, unix_now()?, person_id],
         )?;
         Ok(())
     }
 
+    /// The person owning each handle, by `person_key`
+    pub fn person_ids(&self) -> Result<HashMap<String, i64>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare("SELECT handle_key, person_id FROM person_handle")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
     pub fn search_cached_contacts_by_name(
         &self,
         query: &str,
     ) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
         let query_pattern = format!("%{}%", query.to_lowercase());
         let mut stmt = self.conn.prepare(
-            "SELECT handle, display_name FROM contact_context
+            "SELECT handle, display_name FROM (
+                 SELECT ph.handle, p.display_name, p.updated_at FROM person_handle ph
+                 JOIN person p ON p.id = ph.person_id
+                 UNION
+                 SELECT handle, display_name, updated_at FROM contact_context
+             )
              WHERE display_name IS NOT NULL
                AND display_name != ''
                AND LOWER(display_name) LIKE ?1
-             ORDER BY updated_at DESC
+             GROUP BY handle
+             ORDER BY MAX(updated_at) DESC
              LIMIT 200",
         )?;
 
@@ -145,6 +235,260 @@ impl ContextDb {
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
     }
 
+    // ============================================================================
+    // People Operations
+    // ============================================================================
+
+    /// The person owning `handle`, created with no name if there is none yet
+    pub fn ensure_person(&self, handle: &str) -> Result<i64, Box<dyn std::error::Error>> {
+        if let Some(person_id) = self.person_id(handle)? {
+            return Ok(person_id);
+        }
+
+        let now = unix_now()?;
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO person (photo_handle, created_at, updated_at) VALUES (?1, ?2, ?2)",
+            params![handle, now],
+        )?;
+        let person_id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO person_handle (handle_key, handle, person_id) VALUES (?1, ?2, ?3)",
+            params![person_key(handle), handle, person_id],
+        )?;
+        tx.commit()?;
+        Ok(person_id)
+    }
+
+    /// The person owning `handle`, if anyone does
+    pub fn person_id(&self, handle: &str) -> Result<Option<i64>, Box<dyn std::error::Error>> {
+        let result = self.conn.query_row(
+            "SELECT person_id FROM person_handle WHERE handle_key = ?1",
+            params![person_key(handle)],
+            |row| row.get(0),
+        );
+        match result {
+            Ok(person_id) => Ok(Some(person_id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Hash of the photo imported for `handle`'s person, if any
+    pub fn person_photo_hash(&self, handle: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let result = self.conn.query_row(
+            "SELECT p.photo_hash FROM person_handle ph JOIN person p ON p.id = ph.person_id
+             WHERE ph.handle_key = ?1",
+            params![person_key(handle)],
+            |row| row.get(0),
+        );
+        match result {
+            Ok(hash) => Ok(hash),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub fn set_person_photo_hash(&self, person_id: i64, hash: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn.execute(
+            "UPDATE person SET photo_hash = ?2, updated_at = ?3 WHERE id = ?1",
+            params![person_id, hash, unix_now()?],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_person(&self, id: i64) -> Result<Option<Person>, Box<dyn std::error::Error>> {
+        Ok(self.load_people("WHERE id = ?1", params![id])?.pop())
+    }
+
+    /// People by name, those whose name or handle contains `query` if given,
+    /// and the number matching
+    pub fn list_people(
+        &self,
+        query: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Person>, i64), Box<dyn std::error::Error>> {
+        let pattern = format!("%{}%", query.unwrap_or("").trim().to_lowercase());
+        let filter = "WHERE LOWER(COALESCE(display_name, '')) LIKE ?1
+                OR id IN (SELECT person_id FROM person_handle WHERE LOWER(handle) LIKE ?1)";
+        let total = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM person {}", filter),
+            params![pattern],
+            |row| row.get(0),
+        )?;
+        let people = self.load_people(
+            &format!(
+                "{} ORDER BY display_name IS NULL, LOWER(display_name), id LIMIT ?2 OFFSET ?3",
+                filter
+            ),
+            params![pattern, limit, offset],
+        )?;
+        Ok((people, total))
+    }
+
+    /// Fold `others` into `person_id`: their handles move over, and their name
+    /// and photo fill in any the person lacks. Returns the merged person.
+    pub fn merge_people(
+        &self,
+        person_id: i64,
+        others: &[i64],
+    ) -> Result<Option<Person>, Box<dyn std::error::Error>> {
+        let now = unix_now()?;
+        let tx = self.conn.unchecked_transaction()?;
+        for other in others.iter().filter(|other| **other != person_id) {
+            tx.execute(
+                "UPDATE person SET
+                    display_name = COALESCE(NULLIF(display_name, ''),
+                        (SELECT display_name FROM person WHERE id = ?2)),
+                    photo_handle = COALESCE(photo_handle,
+                        (SELECT photo_handle FROM person WHERE id = ?2)),
+                    photo_hash = COALESCE(photo_hash,
+                        (SELECT photo_hash FROM person WHERE id = ?2)),
+                    updated_at = ?3
+                 WHERE id = ?1",
+                params![person_id, other, now],
+            )?;
+            tx.execute(
+                "UPDATE person_handle SET person_id = ?1 WHERE person_id = ?2",
+                params![person_id, other],
+            )?;
+            tx.execute("DELETE FROM person WHERE id = ?1", params![other])?;
+        }
+        tx.commit()?;
+        self.get_person(person_id)
+    }
+
+    /// Move `handles` off `person_id` onto a new person with the same name and
+    /// imported photo. Returns the new person.
+    pub fn split_person(
+        &self,
+        person_id: i64,
+        handles: &[String],
+    ) -> Result<Person, Box<dyn std::error::Error>> {
+        let now = unix_now()?;
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO person (display_name, photo_hash, created_at, updated_at)
+             SELECT display_name, photo_hash, ?2, ?2 FROM person WHERE id = ?1",
+            params![person_id, now],
+        )?;
+        let new_id = tx.last_insert_rowid();
+        for handle in handles {
+            tx.execute(
+                "UPDATE person_handle SET person_id = ?1 WHERE handle_key = ?2 AND person_id = ?3",
+                params![new_id, person_key(handle), person_id],
+            )?;
+        }
+        // Each side keeps a photo from a handle it still has
+        for id in [person_id, new_id] {
+            tx.execute(
+                "UPDATE person SET
+                    photo_handle = (SELECT handle FROM person_handle WHERE person_id = ?1
+                                    ORDER BY rowid LIMIT 1),
+                    updated_at = ?2
+                 WHERE id = ?1 AND NOT EXISTS (
+                     SELECT 1 FROM person_handle
+                     WHERE person_id = ?1 AND handle = person.photo_handle
+                 )",
+                params![id, now],
+            )?;
+        }
+        tx.commit()?;
+        self.get_person(new_id)?
+            .ok_or_else(|| "Split person missing".into())
+    }
+
+    /// Load cards from Contacts in one transaction. A card's handles join the
+    /// person already owning one of them, or a new person, and every person
+    /// owning them takes the card's name and birthday. Handles split off onto
+    /// another person stay there.
+    pub fn apply_contact_cards(&self, cards: &[ContactCard]) -> Result<(), Box<dyn std::error::Error>> {
+        let now = unix_now()?;
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut owner_stmt =
+                tx.prepare("SELECT person_id FROM person_handle WHERE handle_key = ?1")?;
+            let mut insert_person = tx.prepare(
+                "INSERT INTO person (photo_handle, created_at, updated_at) VALUES (?1, ?2, ?2)",
+            )?;
+            let mut insert_handle = tx.prepare(
+                "INSERT OR IGNORE INTO person_handle (handle_key, handle, person_id)
+                 VALUES (?1, ?2, ?3)",
+            )?;
+            // Only touch people whose details changed, so reloads keep `updated_at`
+            let mut update_person = tx.prepare(
+                "UPDATE person SET display_name = ?2, birthday = COALESCE(?3, birthday),
+                    updated_at = ?4
+                 WHERE id = ?1
+                   AND (display_name IS NOT ?2 OR (?3 IS NOT NULL AND birthday IS NOT ?3))",
+            )?;
+
+            for card in cards {
+                let Some(first) = card.handles.first() else {
+                    continue;
+                };
+                let mut owners: Vec<i64> = Vec::new();
+                let mut unowned: Vec<&String> = Vec::new();
+                for handle in &card.handles {
+                    match owner_stmt.query_row(params![person_key(handle)], |row| row.get(0)) {
+                        Ok(person_id) if !owners.contains(&person_id) => owners.push(person_id),
+                        Ok(_) => {}
+                        Err(rusqlite::Error::QueryReturnedNoRows) => unowned.push(handle),
+                        Err(e) => return Err(Box::new(e)),
+                    }
+                }
+                if owners.is_empty() {
+                    insert_person.execute(params![first, now])?;
+                    owners.push(tx.last_insert_rowid());
+                }
+                for handle in unowned {
+                    insert_handle.execute(params![person_key(handle), handle, owners[0]])?;
+                }
+                for person_id in owners {
+                    update_person.execute(params![person_id, card.name, card.birthday, now])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// People matching a WHERE/ORDER BY/LIMIT tail over `person`, with their handles
+    fn load_people(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<Person>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, display_name, photo_handle, birthday, created_at, updated_at FROM person {}",
+            filter
+        ))?;
+        let mut people = stmt
+            .query_map(params, |row| {
+                Ok(Person {
+                    id: row.get(0)?,
+                    display_name: row.get(1)?,
+                    photo_handle: row.get(2)?,
+                    handles: Vec::new(),
+                    birthday: row.get(3)?,
+                    created_at: row.get(4)?,
+                    updated_at: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT handle FROM person_handle WHERE person_id = ?1 ORDER BY rowid")?;
+        for person in &mut people {
+            person.handles = stmt
+                .query_map(params![person.id], |row| row.get(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+        Ok(people)
+    }
+
     // ============================================================================
     // Contact Context Operations
     // ============================================================================
@@ -235,3 +579,72 @@ impl ContextDb {
     }
 
 }
+
+fn unix_now() -> Result<i64, Box<dyn std::error::Error>> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_person_per_number_however_spelled() {
+        let db = ContextDb::open_in_memory().unwrap();
+        db.set_cached_contact_name("+1 (555) 000-1234", "Sam Rivera").unwrap();
+        db.set_cached_contact_name("5550001234", "Sam").unwrap();
+        db.set_cached_contact_name("sam@example.com", "Sam Rivera").unwrap();
+
+        let (people, total) = db.list_people(None, 10, 0).unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(people[0].handles, vec!["+1 (555) 000-1234"]);
+        assert_eq!(db.get_cached_contact_name("+15550001234").unwrap().as_deref(), Some("Sam Rivera"));
+
+        let phone = db.person_id("+15550001234").unwrap().unwrap();
+        let email = db.person_id("Sam@Example.com").unwrap().unwrap();
+        db.set_person_photo_hash(email, "5a3f").unwrap();
+        let merged = db.merge_people(phone, &[email]).unwrap().unwrap();
+        assert_eq!(merged.handles, vec!["+1 (555) 000-1234", "sam@example.com"]);
+        assert_eq!(db.get_person(email).unwrap().map(|p| p.id), None);
+        // The absorbed person's imported photo moves over
+        assert_eq!(db.person_photo_hash("+15550001234").unwrap().as_deref(), Some("5a3f"));
+
+        let split = db.split_person(phone, &["SAM@example.com".to_string()]).unwrap();
+        assert_eq!(split.handles, vec!["sam@example.com"]);
+        assert_eq!(split.display_name.as_deref(), Some("Sam Rivera"));
+        assert_eq!(split.photo_handle.as_deref(), Some("sam@example.com"));
+        assert_eq!(db.get_person(phone).unwrap().unwrap().handles.len(), 1);
+        assert_eq!(db.person_photo_hash("sam@example.com").unwrap().as_deref(), Some("5a3f"));
+        assert_eq!(db.person_photo_hash("+15550001234").unwrap().as_deref(), Some("5a3f"));
+
+        // A person reusing the absorbed id starts without a photo
+        db.set_cached_contact_name("+15550009999", "Alex Kim").unwrap();
+        assert_eq!(db.person_photo_hash("+15550009999").unwrap(), None);
+    }
+
+    #[test]
+    fn moves_cached_names_onto_people() {
+        let db = ContextDb::open_in_memory().unwrap();
+        db.conn
+            .execute_batch(
+                "
+                INSERT INTO contact_context (handle, display_name, created_at, updated_at) VALUES
+                    ('+15550001234', 'Sam Rivera', 1, 1),
+                    ('15550001234', 'Sam Rivera', 1, 1),
+                    ('5550001234', 'Sam Rivera', 1, 1);
+                DROP TABLE person;
+                DROP TABLE person_handle;
+                PRAGMA user_version = 0;
+                ",
+            )
+            .unwrap();
+        db.init_schema().unwrap();
+
+        let (people, _) = db.list_people(Some("rivera"), 10, 0).unwrap();
+        assert_eq!(people.len(), 1);
+        assert_eq!(people[0].handles, vec!["+15550001234"]);
+        assert_eq!(people[0].photo_handle.as_deref(), Some("+15550001234"));
+    }
+}
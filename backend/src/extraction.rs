@@ -417,7 +417,7 @@ fn merge_notes(existing: Option<&str>, new_notes: &str, max_sentences: usize) ->
 }
 
 fn split_sentences(text: &str) -> Vec<String> {
-    text.split(|c| c == '.' || c == '!' || c == '?' || c == '\n')
+    text.split(['.', '!', '?', '\n'])
         .map(|s| s.trim())
         .filter(|s| !s.is_empty())
         .map(|s| s.to_string())
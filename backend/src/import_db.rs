@@ -0,0 +1,874 @@
+// Import Database Module
+// Sidecar SQLite database for conversations imported from other apps (Android
+// SMS backups, ...). chat.db is read-only, so imported threads live here, next
+// to context.db, and are merged into the chat list and message endpoints.
+//
+// Imported chats, messages and attachments are exposed with negated ids, so
+// they can never collide with chat.db ROWIDs: imported chat 3 is chat -3 in
+// `/chats` and `/chats/-3/messages`.
+
+use crate::config;
+use crate::context_db::ContextDb;
+use crate::extraction::MessageForExtraction;
+use crate::models::{
+    Attachment, DateJumpResponse, Message, MessageKind, MessagesResponse, MonthCount,
+    ThreadResponse,
+};
+use crate::search_index::IndexedMessage;
+use crate::services::contacts::get_contact_name;
+use crate::services::messages::{AttachmentFile, MessageCursor, PageDirection};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Whether a chat, message or attachment id refers to an imported row
+pub fn is_imported(id: i64) -> bool {
+    id < 0
+}
+
+/// A conversation to create or extend
+pub struct NewChat<'a> {
+    /// `Chat::origin`, e.g. `ORIGIN_ANDROID_IMPORT`
+    pub origin: &'a str,
+    /// Identifies the conversation within its origin, so re-imports extend it
+    pub source_key: &'a str,
+    /// Group name, if the source has one
+    pub display_name: Option<&'a str>,
+    /// Participants other than me
+    pub handles: &'a [String],
+}
+
+pub struct NewMessage {
+    /// Sender; None when `is_from_me`
+    pub handle: Option<String>,
+    pub is_from_me: bool,
+    pub text: Option<String>,
+    /// Unix ms
+    pub time: i64,
+    /// Transport shown to the user, e.g. "SMS" or "MMS"
+    pub service: &'static str,
+    pub attachments: Vec<NewAttachment>,
+}
+
+pub struct NewAttachment {
+    pub filename: Option<String>,
+    pub mime_type: Option<String>,
+    /// Content copied into the import database; None when the archive
+    /// didn't include the file
+    pub data: Option<Vec<u8>>,
+}
+
+/// Counts reported back to the client after an import
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct ImportSummary {
+    pub chats: usize,
+    pub messages: usize,
+    /// Messages skipped because an earlier import already has them
+    pub duplicates: usize,
+    pub attachments: usize,
+}
+
+/// An imported chat as the chat list needs it
+pub struct ImportedChatRow {
+    /// Negated id, as used in the API
+    pub chat_id: i64,
+    pub origin: String,
+    pub display_name: Option<String>,
+    pub handles: Vec<String>,
+    pub last_message_text: Option<String>,
+    pub last_message_time: Option<i64>,
+    pub last_message_is_from_me: Option<bool>,
+}
+
+pub struct ImportDb {
+    conn: Connection,
+}
+
+impl ImportDb {
+    /// Open or create the configured import database
+    pub fn open() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::open_at(&config::current().imports_db())
+    }
+
+    /// Open or create the import database at `db_path`
+    pub fn open_at(db_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(db_path)?;
+        // Imports write while request handlers read
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        let db = ImportDb { conn };
+        db.init_schema()?;
+        Ok(db)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self, Box<dyn std::error::Error>> {
+        let db = ImportDb {
+            conn: Connection::open_in_memory()?,
+        };
+        db.init_schema()?;
+        Ok(db)
+    }
+
+    fn init_schema(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS imported_chat (
+                id INTEGER PRIMARY KEY,
+                origin TEXT NOT NULL,
+                source_key TEXT NOT NULL,
+                display_name TEXT,
+                UNIQUE (origin, source_key)
+            );
+
+            CREATE TABLE IF NOT EXISTS imported_chat_handle (
+                chat_id INTEGER NOT NULL,
+                handle TEXT NOT NULL,
+                UNIQUE (chat_id, handle)
+            );
+
+            CREATE TABLE IF NOT EXISTS imported_message (
+                id INTEGER PRIMARY KEY,
+                chat_id INTEGER NOT NULL,
+                handle TEXT,
+                is_from_me INTEGER NOT NULL,
+                text TEXT,
+                time INTEGER NOT NULL,
+                service TEXT NOT NULL
+            );
+
+            -- Re-importing the same archive must not duplicate messages
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_imported_message_identity ON imported_message(
+                chat_id, time, is_from_me, COALESCE(handle, ''), COALESCE(text, '')
+            );
+            CREATE INDEX IF NOT EXISTS idx_imported_message_chat_time
+                ON imported_message(chat_id, time, id);
+
+            CREATE TABLE IF NOT EXISTS imported_attachment (
+                id INTEGER PRIMARY KEY,
+                message_id INTEGER NOT NULL,
+                filename TEXT,
+                mime_type TEXT,
+                data BLOB
+            );
+            CREATE INDEX IF NOT EXISTS idx_imported_attachment_message
+                ON imported_attachment(message_id);
+            ",
+        )?;
+        Ok(())
+    }
+
+    /// Run an import in one transaction; nothing is kept if it fails
+    pub fn import<T>(
+        &mut self,
+        write: impl FnOnce(&mut ImportWriter) -> Result<T, Box<dyn std::error::Error>>,
+    ) -> Result<(T, ImportSummary), Box<dyn std::error::Error>> {
+        let mut writer = ImportWriter {
+            tx: self.conn.transaction()?,
+            chat_ids: HashMap::new(),
+            summary: ImportSummary::default(),
+        };
+        let result = write(&mut writer)?;
+        let ImportWriter { tx, summary, .. } = writer;
+        tx.commit()?;
+        Ok((result, summary))
+    }
+
+    /// Every imported chat with its participants and newest message
+    pub fn chat_rows(&self) -> Result<Vec<ImportedChatRow>, Box<dyn std::error::Error>> {
+        let mut handles: HashMap<i64, Vec<String>> = HashMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT chat_id, handle FROM imported_chat_handle ORDER BY rowid")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (chat_id, handle) = row?;
+            handles.entry(chat_id).or_default().push(handle);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT c.id, c.origin, c.display_name, m.text, m.time, m.is_from_me
+             FROM imported_chat c
+             LEFT JOIN imported_message m ON m.id = (
+                 SELECT id FROM imported_message
+                 WHERE chat_id = c.id
+                 ORDER BY time DESC, id DESC
+                 LIMIT 1
+             )",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                let id: i64 = row.get(0)?;
+                Ok(ImportedChatRow {
+                    chat_id: -id,
+                    origin: row.get(1)?,
+                    display_name: row.get(2)?,
+                    handles: handles.remove(&id).unwrap_or_default(),
+                    last_message_text: row.get(3)?,
+                    last_message_time: row.get(4)?,
+                    last_message_is_from_me: row.get::<_, Option<bool>>(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Newest `limit` messages after skipping `offset`, oldest first
+    pub fn fetch_messages(
+        &self,
+        chat_id: i64,
+        context_db: &ContextDb,
+        limit: i64,
+        offset: i64,
+    ) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+        let total = self.count_messages(chat_id)?;
+        let mut messages = self.load_messages(
+            "WHERE m.chat_id = ?1 ORDER BY m.time DESC, m.id DESC LIMIT ?2 OFFSET ?3",
+            params![-chat_id, limit, offset],
+            context_db,
+        )?;
+        messages.reverse();
+
+        let has_more = offset + (messages.len() as i64) < total;
+        Ok(page_response(messages, total, has_more, offset > 0))
+    }
+
+    /// Keyset page next to a cursor from a previous page
+    pub fn fetch_messages_page(
+        &self,
+        chat_id: i64,
+        context_db: &ContextDb,
+        limit: i64,
+        direction: PageDirection,
+    ) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+        let total = self.count_messages(chat_id)?;
+        let (messages, has_more_beyond) =
+            self.load_messages_beside(chat_id, context_db, limit, &direction)?;
+
+        let (has_more, has_newer) = match direction {
+            PageDirection::Before(_) => (has_more_beyond, true),
+            PageDirection::After(_) => (true, has_more_beyond),
+        };
+        Ok(page_response(messages, total, has_more, has_newer))
+    }
+
+    /// `count` messages either side of the message with `guid`, plus the
+    /// message itself. `None` if the message isn't in this chat.
+    pub fn fetch_messages_around(
+        &self,
+        chat_id: i64,
+        guid: &str,
+        context_db: &ContextDb,
+        count: i64,
+    ) -> Result<Option<MessagesResponse>, Box<dyn std::error::Error>> {
+        let Some(cursor) = self.find_message_cursor(chat_id, guid)? else {
+            return Ok(None);
+        };
+        self.messages_around(chat_id, context_db, cursor, count, count)
+            .map(Some)
+    }
+
+    /// Jump to a date, as `messages::fetch_messages_at_date` does for chat.db
+    pub fn fetch_messages_at_date(
+        &self,
+        chat_id: i64,
+        context_db: &ContextDb,
+        unix_ms: i64,
+        limit: i64,
+    ) -> Result<DateJumpResponse, Box<dyn std::error::Error>> {
+        let first_on_or_after = self
+            .conn
+            .query_row(
+                "SELECT time, id FROM imported_message
+                 WHERE chat_id = ?1 AND time >= ?2
+                 ORDER BY time ASC, id ASC LIMIT 1",
+                params![-chat_id, unix_ms],
+                |row| {
+                    Ok(MessageCursor {
+                        date: row.get(0)?,
+                        rowid: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        let (page, anchor_guid) = match first_on_or_after {
+            Some(cursor) => {
+                // Keep a little context above the target, the rest of the page below it
+                let older = limit / 4;
+                let newer = (limit - older - 1).max(0);
+                let guid = message_guid(cursor.rowid);
+                (self.messages_around(chat_id, context_db, cursor, older, newer)?, Some(guid))
+            }
+            None => (self.fetch_messages(chat_id, context_db, limit, 0)?, None),
+        };
+
+        let mut stmt = self.conn.prepare(
+            "SELECT strftime('%Y-%m', time / 1000, 'unixepoch', 'localtime') as month, COUNT(*)
+             FROM imported_message
+             WHERE chat_id = ?1
+             GROUP BY month
+             ORDER BY month ASC",
+        )?;
+        let months = stmt
+            .query_map(params![-chat_id], |row| {
+                Ok(MonthCount {
+                    month: row.get(0)?,
+                    count: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(DateJumpResponse {
+            page,
+            anchor_guid,
+            months,
+        })
+    }
+
+    /// Imported messages carry no replies, so a thread is just the message
+    pub fn fetch_thread(
+        &self,
+        chat_id: i64,
+        guid: &str,
+        context_db: &ContextDb,
+    ) -> Result<Option<ThreadResponse>, Box<dyn std::error::Error>> {
+        let Some(cursor) = self.find_message_cursor(chat_id, guid)? else {
+            return Ok(None);
+        };
+        let messages = self.load_messages("WHERE m.id = ?1", params![cursor.rowid], context_db)?;
+        Ok(Some(ThreadResponse {
+            chat_id,
+            thread_guid: guid.to_string(),
+            messages,
+        }))
+    }
+
+    /// File contents and MIME type of an imported attachment
+    pub fn fetch_attachment(
+        &self,
+        attachment_id: i64,
+    ) -> Result<Option<AttachmentFile>, Box<dyn std::error::Error>> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT data, mime_type FROM imported_attachment WHERE id = ?1",
+                params![-attachment_id],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<Vec<u8>>>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        Ok(match row {
+            Some((Some(data), mime_type)) => Some((data, mime_type)),
+            _ => None,
+        })
+    }
+
+    /// Messages with text after `after_id`, for the search index, and the last
+    /// id scanned (None once there is nothing newer)
+    pub fn messages_for_index(
+        &self,
+        after_id: i64,
+        limit: i64,
+    ) -> Result<(Vec<IndexedMessage>, Option<i64>), Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, chat_id, text, time, is_from_me, handle
+             FROM imported_message
+             WHERE id > ?1
+             ORDER BY id
+             LIMIT ?2",
+        )?;
+        let rows = stmt
+            .query_map(params![after_id, limit], |row| {
+                let id: i64 = row.get(0)?;
+                let text: Option<String> = row.get(2)?;
+                let message = IndexedMessage {
+                    rowid: -id,
+                    guid: message_guid(id),
+                    chat_id: -row.get::<_, i64>(1)?,
+                    text: text.unwrap_or_default(),
+                    time: row.get(3)?,
+                    is_from_me: row.get(4)?,
+                    handle: row.get(5)?,
+                };
+                Ok((id, Some(message).filter(|m| !m.text.trim().is_empty())))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let last_id = rows.last().map(|(id, _)| *id);
+        Ok((rows.into_iter().filter_map(|(_, message)| message).collect(), last_id))
+    }
+
+    /// A chat's messages with text, oldest first, for contact analysis
+    pub fn fetch_messages_for_extraction(
+        &self,
+        chat_id: i64,
+    ) -> Result<Vec<MessageForExtraction>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT text, time, is_from_me FROM imported_message
+             WHERE chat_id = ?1 AND text IS NOT NULL AND TRIM(text) != ''
+             ORDER BY time, id",
+        )?;
+        let rows = stmt.query_map(params![-chat_id], |row| {
+            Ok(MessageForExtraction {
+                text: row.get(0)?,
+                // Unix seconds, as for chat.db messages
+                timestamp: row.get::<_, i64>(1)? / 1000,
+                is_from_me: row.get(2)?,
+            })
+        })?;
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+    }
+
+    /// Cursor for the message with `guid` in `chat_id`
+    fn find_message_cursor(
+        &self,
+        chat_id: i64,
+        guid: &str,
+    ) -> Result<Option<MessageCursor>, Box<dyn std::error::Error>> {
+        let Some(id) = guid
+            .strip_prefix("import:")
+            .and_then(|id| id.parse::<i64>().ok())
+        else {
+            return Ok(None);
+        };
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT time, id FROM imported_message WHERE chat_id = ?1 AND id = ?2",
+                params![-chat_id, id],
+                |row| {
+                    Ok(MessageCursor {
+                        date: row.get(0)?,
+                        rowid: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// `older` messages before the message at `cursor`, the message itself, then `newer` after it
+    fn messages_around(
+        &self,
+        chat_id: i64,
+        context_db: &ContextDb,
+        cursor: MessageCursor,
+        older: i64,
+        newer: i64,
+    ) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+        let total = self.count_messages(chat_id)?;
+        let (mut messages, has_more) = self.load_messages_beside(
+            chat_id,
+            context_db,
+            older,
+            &PageDirection::Before(cursor),
+        )?;
+        messages.extend(self.load_messages("WHERE m.id = ?1", params![cursor.rowid], context_db)?);
+        let (newer_messages, has_newer) =
+            self.load_messages_beside(chat_id, context_db, newer, &PageDirection::After(cursor))?;
+        messages.extend(newer_messages);
+        Ok(page_response(messages, total, has_more, has_newer))
+    }
+
+    /// Up to `limit` messages on one side of a cursor, oldest first, and
+    /// whether more exist beyond them
+    fn load_messages_beside(
+        &self,
+        chat_id: i64,
+        context_db: &ContextDb,
+        limit: i64,
+        direction: &PageDirection,
+    ) -> Result<(Vec<Message>, bool), Box<dyn std::error::Error>> {
+        // One extra row tells us whether more exist beyond this page
+        let (mut messages, reverse) = match direction {
+            PageDirection::Before(cursor) => (
+                self.load_messages(
+                    "WHERE m.chat_id = ?1 AND (m.time, m.id) < (?2, ?3)
+                     ORDER BY m.time DESC, m.id DESC LIMIT ?4",
+                    params![-chat_id, cursor.date, cursor.rowid, limit + 1],
+                    context_db,
+                )?,
+                true,
+            ),
+            PageDirection::After(cursor) => (
+                self.load_messages(
+                    "WHERE m.chat_id = ?1 AND (m.time, m.id) > (?2, ?3)
+                     ORDER BY m.time ASC, m.id ASC LIMIT ?4",
+                    params![-chat_id, cursor.date, cursor.rowid, limit + 1],
+                    context_db,
+                )?,
+                false,
+            ),
+        };
+        let has_more = messages.len() as i64 > limit;
+        messages.truncate(limit.max(0) as usize);
+        if reverse {
+            messages.reverse();
+        }
+        Ok((messages, has_more))
+    }
+
+    fn count_messages(&self, chat_id: i64) -> Result<i64, Box<dyn std::error::Error>> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM imported_message WHERE chat_id = ?1",
+            params![-chat_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Messages matching `filter` (a WHERE/ORDER BY/LIMIT tail over
+    /// `imported_message m`), with their attachments
+    fn load_messages(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+        context_db: &ContextDb,
+    ) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT m.id, m.handle, m.is_from_me, m.text, m.time, c.origin, m.service
+             FROM imported_message m
+             JOIN imported_chat c ON c.id = m.chat_id
+             {}",
+            filter
+        ))?;
+        let mut messages = stmt
+            .query_map(params, |row| {
+                let id: i64 = row.get(0)?;
+                let handle: Option<String> = row.get(1)?;
+                Ok(Message {
+                    id: -id,
+                    guid: Some(message_guid(id)),
+                    text: row.get(3)?,
+                    time: row.get(4)?,
+                    is_from_me: row.get(2)?,
+                    contact_name: handle
+                        .as_ref()
+                        .and_then(|h| get_contact_name(h, context_db)),
+                    handle,
+                    reactions: Vec::new(),
+                    attachments: Vec::new(),
+                    runs: Vec::new(),
+                    reply_to_guid: None,
+                    reply_to_part: None,
+                    reply_preview: None,
+                    edited_at: None,
+                    is_unsent: false,
+                    edit_history: Vec::new(),
+                    // Archived messages were delivered and read long ago
+                    is_delivered: true,
+                    is_read: true,
+                    date_delivered: None,
+                    date_read: None,
+                    error: None,
+                    kind: MessageKind::Message,
+                    target_handle: None,
+                    target_contact_name: None,
+                    group_title: None,
+                    origin: Some(row.get(5)?),
+                    service: row.get(6)?,
+                    chat_id: None,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        self.attach_attachments(&mut messages)?;
+        Ok(messages)
+    }
+
+    fn attach_attachments(
+        &self,
+        messages: &mut [Message],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, filename, mime_type, COALESCE(LENGTH(data), 0)
+             FROM imported_attachment WHERE message_id = ?1 ORDER BY id",
+        )?;
+        for message in messages.iter_mut() {
+            let attachments = stmt
+                .query_map(params![-message.id], |row| {
+                    let filename: Option<String> = row.get(1)?;
+                    Ok(Attachment {
+                        id: -row.get::<_, i64>(0)?,
+                        transfer_name: filename.clone(),
+                        filename,
+                        mime_type: row.get(2)?,
+                        total_bytes: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            if !attachments.is_empty() && message.text.is_none() {
+                message.text = Some("📎 Attachment".to_string());
+            }
+            message.attachments = attachments;
+        }
+        Ok(())
+    }
+}
+
+/// Guid of an imported message, from its positive `imported_message.id`
+fn message_guid(id: i64) -> String {
+    format!("import:{}", id)
+}
+
+fn page_response(
+    messages: Vec<Message>,
+    total: i64,
+    has_more: bool,
+    has_newer: bool,
+) -> MessagesResponse {
+    // Cursors carry the positive `imported_message.id`
+    let cursor = |message: &Message| {
+        MessageCursor {
+            date: message.time,
+            rowid: -message.id,
+        }
+        .encode()
+    };
+    MessagesResponse {
+        older_cursor: messages.first().map(cursor),
+        newer_cursor: messages.last().map(cursor),
+        messages,
+        total,
+        has_more,
+        has_newer,
+    }
+}
+
+/// Writes one import; see `ImportDb::import`
+pub struct ImportWriter<'a> {
+    tx: Transaction<'a>,
+    /// (origin, source_key) -> imported_chat.id
+    chat_ids: HashMap<(String, String), i64>,
+    summary: ImportSummary,
+}
+
+impl ImportWriter<'_> {
+    /// Id of the chat, created on first use; new participants are added to it
+    pub fn chat(&mut self, chat: &NewChat) -> Result<i64, Box<dyn std::error::Error>> {
+        let key = (chat.origin.to_string(), chat.source_key.to_string());
+        if let Some(chat_id) = self.chat_ids.get(&key) {
+            return Ok(*chat_id);
+        }
+
+        let existing: Option<i64> = self
+            .tx
+            .query_row(
+                "SELECT id FROM imported_chat WHERE origin = ?1 AND source_key = ?2",
+                params![chat.origin, chat.source_key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let chat_id = match existing {
+            Some(chat_id) => chat_id,
+            None => {
+                self.tx.execute(
+                    "INSERT INTO imported_chat (origin, source_key, display_name) VALUES (?1, ?2, ?3)",
+                    params![chat.origin, chat.source_key, chat.display_name],
+                )?;
+                self.summary.chats += 1;
+                self.tx.last_insert_rowid()
+            }
+        };
+        for handle in chat.handles {
+            self.tx.execute(
+                "INSERT OR IGNORE INTO imported_chat_handle (chat_id, handle) VALUES (?1, ?2)",
+                params![chat_id, handle],
+            )?;
+        }
+        self.chat_ids.insert(key, chat_id);
+        Ok(chat_id)
+    }
+
+    /// Add a message to a chat from `chat`; false if it was already imported
+    pub fn message(
+        &mut self,
+        chat_id: i64,
+        message: NewMessage,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let inserted = self.tx.execute(
+            "INSERT OR IGNORE INTO imported_message (chat_id, handle, is_from_me, text, time, service)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                chat_id,
+                message.handle,
+                message.is_from_me,
+                message.text,
+                message.time,
+                message.service
+            ],
+        )?;
+        if inserted == 0 {
+            self.summary.duplicates += 1;
+            return Ok(false);
+        }
+
+        let message_id = self.tx.last_insert_rowid();
+        for attachment in message.attachments {
+            self.tx.execute(
+                "INSERT INTO imported_attachment (message_id, filename, mime_type, data)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    message_id,
+                    attachment.filename,
+                    attachment.mime_type,
+                    attachment.data
+                ],
+            )?;
+            self.summary.attachments += 1;
+        }
+        self.summary.messages += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(time: i64, text: &str, is_from_me: bool) -> NewMessage {
+        NewMessage {
+            handle: (!is_from_me).then(|| "+15550001".to_string()),
+            is_from_me,
+            text: Some(text.to_string()),
+            time,
+            service: "SMS",
+            attachments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn reimports_are_deduplicated_and_paged_with_negated_ids() {
+        let mut db = ImportDb::open_in_memory().unwrap();
+        let handles = vec!["+15550001".to_string()];
+        let chat = NewChat {
+            origin: "android-import",
+            source_key: "+15550001",
+            display_name: None,
+            handles: &handles,
+        };
+        let import = |db: &mut ImportDb| {
+            db.import(|writer| {
+                let chat_id = writer.chat(&chat)?;
+                writer.message(chat_id, message(1000, "hi", false))?;
+                writer.message(chat_id, message(2000, "hey!", true))?;
+                writer.message(chat_id, message(3000, "lunch?", false))?;
+                Ok(chat_id)
+            })
+            .unwrap()
+        };
+
+        let (chat_id, summary) = import(&mut db);
+        assert_eq!(summary.chats, 1);
+        assert_eq!(summary.messages, 3);
+        let (_, summary) = import(&mut db);
+        assert_eq!(
+            summary,
+            ImportSummary {
+                duplicates: 3,
+                ..Default::default()
+            }
+        );
+
+        let rows = db.chat_rows().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].chat_id, -chat_id);
+        assert_eq!(rows[0].handles, handles);
+        assert_eq!(rows[0].last_message_text.as_deref(), Some("lunch?"));
+        assert_eq!(rows[0].last_message_is_from_me, Some(false));
+
+        let context_db = ContextDb::open_in_memory().unwrap();
+        let page = db.fetch_messages(-chat_id, &context_db, 2, 0).unwrap();
+        let texts: Vec<_> = page
+            .messages
+            .iter()
+            .map(|m| m.text.clone().unwrap())
+            .collect();
+        assert_eq!(texts, vec!["hey!", "lunch?"]);
+        assert!(page.has_more);
+        assert!(page.messages.iter().all(|m| is_imported(m.id)));
+        assert_eq!(page.messages[0].origin.as_deref(), Some("android-import"));
+
+        let older = MessageCursor::parse(page.older_cursor.as_deref().unwrap()).unwrap();
+        let page = db
+            .fetch_messages_page(-chat_id, &context_db, 2, PageDirection::Before(older))
+            .unwrap();
+        assert_eq!(page.messages.len(), 1);
+        assert_eq!(page.messages[0].text.as_deref(), Some("hi"));
+        assert!(!page.has_more);
+        assert!(page.has_newer);
+    }
+
+    #[test]
+    fn jumps_and_threads_stay_inside_the_import() {
+        let mut db = ImportDb::open_in_memory().unwrap();
+        let handles = vec!["+15550001".to_string()];
+        let chat = NewChat {
+            origin: "android-import",
+            source_key: "+15550001",
+            display_name: None,
+            handles: &handles,
+        };
+        let (chat_id, _) = db
+            .import(|writer| {
+                let chat_id = writer.chat(&chat)?;
+                for (i, text) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+                    writer.message(chat_id, message(1000 * (i as i64 + 1), text, false))?;
+                }
+                Ok(-chat_id)
+            })
+            .unwrap();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        let texts = |messages: &[Message]| -> Vec<String> {
+            messages.iter().map(|m| m.text.clone().unwrap()).collect()
+        };
+
+        let page = db.fetch_messages(chat_id, &context_db, 5, 0).unwrap();
+        let guid_c = page.messages[2].guid.clone().unwrap();
+
+        let around = db
+            .fetch_messages_around(chat_id, &guid_c, &context_db, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(texts(&around.messages), vec!["b", "c", "d"]);
+        assert!(around.has_more);
+        assert!(around.has_newer);
+        assert!(db
+            .fetch_messages_around(chat_id, "import:999", &context_db, 1)
+            .unwrap()
+            .is_none());
+
+        let jump = db
+            .fetch_messages_at_date(chat_id, &context_db, 2500, 4)
+            .unwrap();
+        assert_eq!(jump.anchor_guid, Some(guid_c.clone()));
+        assert_eq!(texts(&jump.page.messages), vec!["b", "c", "d", "e"]);
+        assert!(!jump.page.has_newer);
+        assert_eq!(jump.months.iter().map(|m| m.count).sum::<i64>(), 5);
+
+        let past_the_end = db
+            .fetch_messages_at_date(chat_id, &context_db, 10_000, 2)
+            .unwrap();
+        assert_eq!(past_the_end.anchor_guid, None);
+        assert_eq!(texts(&past_the_end.page.messages), vec!["d", "e"]);
+
+        let thread = db
+            .fetch_thread(chat_id, &guid_c, &context_db)
+            .unwrap()
+            .unwrap();
+        assert_eq!(thread.chat_id, chat_id);
+        assert_eq!(texts(&thread.messages), vec!["c"]);
+    }
+}
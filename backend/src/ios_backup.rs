@@ -0,0 +1,228 @@
+// iOS Backup Module
+// Reads messages from an unencrypted iPhone backup made by iTunes or Finder.
+// A backup stores every file under a hashed name; `Manifest.db` maps each
+// (domain, relativePath) to its `fileID`, and the file itself lives at
+// `<backup>/<first two hex digits>/<fileID>` (flat at `<backup>/<fileID>` in
+// pre-iOS 10 backups). The phone's `sms.db` is the same schema family as
+// chat.db, so once found it is served through the usual chat.db queries; only
+// attachment paths need translating through the manifest.
+
+use crate::models::{ORIGIN_IMESSAGE, ORIGIN_IOS_BACKUP};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const SMS_DB_DOMAIN: &str = "HomeDomain";
+const SMS_DB_PATH: &str = "Library/SMS/sms.db";
+const ATTACHMENT_DOMAIN: &str = "MediaDomain";
+
+static BACKUP: OnceLock<IosBackup> = OnceLock::new();
+
+pub struct IosBackup {
+    root: PathBuf,
+    sms_db: PathBuf,
+    info: BackupInfo,
+}
+
+/// Device details from the backup's `Info.plist`, reported by `/health`
+#[derive(Serialize, Default, Clone)]
+pub struct BackupInfo {
+    pub device_name: Option<String>,
+    pub product_type: Option<String>,
+    pub ios_version: Option<String>,
+    /// Unix ms
+    pub last_backup_time: Option<i64>,
+}
+
+impl IosBackup {
+    /// Open the backup directory and locate its sms.db
+    pub fn open(root: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if !root.join("Manifest.db").is_file() {
+            return Err(format!("{} is not an iOS backup (no Manifest.db)", root.display()).into());
+        }
+        // Encrypted backups keep Manifest.db encrypted too; fail clearly rather
+        // than with "file is not a database"
+        if let Ok(manifest) = plist::Value::from_file(root.join("Manifest.plist")) {
+            let encrypted = manifest
+                .as_dictionary()
+                .and_then(|dict| dict.get("IsEncrypted"))
+                .and_then(|value| value.as_boolean())
+                .unwrap_or(false);
+            if encrypted {
+                return Err(format!(
+                    "{} is an encrypted backup; only unencrypted backups can be read",
+                    root.display()
+                )
+                .into());
+            }
+        }
+
+        let backup = IosBackup {
+            root: root.to_path_buf(),
+            sms_db: PathBuf::new(),
+            info: read_info(&root.join("Info.plist")),
+        };
+        let manifest = backup.open_manifest()?;
+        let sms_db = backup
+            .lookup(&manifest, SMS_DB_DOMAIN, SMS_DB_PATH)?
+            .ok_or_else(|| format!("{} contains no Messages database", root.display()))?;
+        Ok(IosBackup { sms_db, ..backup })
+    }
+
+    /// The backup's copy of sms.db, to be opened in place of chat.db
+    pub fn sms_db(&self) -> &Path {
+        &self.sms_db
+    }
+
+    pub fn info(&self) -> &BackupInfo {
+        &self.info
+    }
+
+    /// Where the backup stores an attachment, given its `attachment.filename`
+    /// (e.g. `~/Library/SMS/Attachments/0a/10/<guid>/IMG_0001.HEIC`)
+    pub fn attachment_path(&self, filename: &str) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+        let Some(relative_path) = device_relative_path(filename) else {
+            return Ok(None);
+        };
+        let manifest = self.open_manifest()?;
+        self.lookup(&manifest, ATTACHMENT_DOMAIN, relative_path)
+    }
+
+    fn open_manifest(&self) -> Result<Connection, Box<dyn std::error::Error>> {
+        Ok(Connection::open_with_flags(
+            self.root.join("Manifest.db"),
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?)
+    }
+
+    /// Path of a backed-up file, if the manifest lists it and it was copied
+    fn lookup(
+        &self,
+        manifest: &Connection,
+        domain: &str,
+        relative_path: &str,
+    ) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+        let file_id: Option<String> = manifest
+            .query_row(
+                "SELECT fileID FROM Files WHERE domain = ?1 AND relativePath = ?2",
+                params![domain, relative_path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(file_id.and_then(|file_id| self.stored_path(&file_id)))
+    }
+
+    fn stored_path(&self, file_id: &str) -> Option<PathBuf> {
+        let nested = file_id
+            .get(..2)
+            .map(|prefix| self.root.join(prefix).join(file_id));
+        nested
+            .into_iter()
+            .chain(std::iter::once(self.root.join(file_id)))
+            .find(|path| path.is_file())
+    }
+}
+
+/// `relativePath` in the manifest for a path as the phone recorded it
+fn device_relative_path(filename: &str) -> Option<&str> {
+    ["~/", "/var/mobile/", "/private/var/mobile/"]
+        .iter()
+        .find_map(|prefix| filename.strip_prefix(prefix))
+}
+
+fn read_info(path: &Path) -> BackupInfo {
+    let Ok(value) = plist::Value::from_file(path) else {
+        return BackupInfo::default();
+    };
+    let Some(dict) = value.as_dictionary() else {
+        return BackupInfo::default();
+    };
+    let string = |key: &str| dict.get(key).and_then(|v| v.as_string()).map(str::to_string);
+    BackupInfo {
+        device_name: string("Device Name"),
+        product_type: string("Product Type"),
+        ios_version: string("Product Version"),
+        last_backup_time: dict
+            .get("Last Backup Date")
+            .and_then(|v| v.as_date())
+            .and_then(|date| std::time::SystemTime::from(date).duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_millis() as i64),
+    }
+}
+
+/// Serve this backup instead of the Mac's chat.db
+pub fn install(backup: IosBackup) -> &'static IosBackup {
+    BACKUP.get_or_init(|| backup)
+}
+
+pub fn installed() -> Option<&'static IosBackup> {
+    BACKUP.get()
+}
+
+/// `Chat::origin` for chats read from the configured database
+pub fn chat_origin() -> &'static str {
+    if installed().is_some() {
+        ORIGIN_IOS_BACKUP
+    } else {
+        ORIGIN_IMESSAGE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_files_through_the_manifest() {
+        let root = std::env::temp_dir().join(format!("ios_backup_test_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("3d")).unwrap();
+        std::fs::create_dir_all(root.join("b1")).unwrap();
+        std::fs::write(root.join("3d/3d0d7e5fb2ce288813306e4d4636395e047a3d28"), b"").unwrap();
+        std::fs::write(root.join("b1/b1a2"), b"jpeg").unwrap();
+
+        let manifest = Connection::open(root.join("Manifest.db")).unwrap();
+        manifest
+            .execute_batch(
+                "
+                CREATE TABLE Files (fileID TEXT PRIMARY KEY, domain TEXT, relativePath TEXT,
+                                    flags INTEGER, file BLOB);
+                INSERT INTO Files VALUES ('3d0d7e5fb2ce288813306e4d4636395e047a3d28',
+                                          'HomeDomain', 'Library/SMS/sms.db', 1, NULL);
+                INSERT INTO Files VALUES ('b1a2', 'MediaDomain',
+                                          'Library/SMS/Attachments/0a/10/X/IMG_1.jpeg', 1, NULL);
+                INSERT INTO Files VALUES ('c3d4', 'MediaDomain',
+                                          'Library/SMS/Attachments/0b/11/Y/IMG_2.jpeg', 1, NULL);
+                ",
+            )
+            .unwrap();
+        drop(manifest);
+
+        let backup = IosBackup::open(&root).unwrap();
+        assert_eq!(
+            backup.sms_db(),
+            root.join("3d/3d0d7e5fb2ce288813306e4d4636395e047a3d28")
+        );
+        assert_eq!(
+            backup
+                .attachment_path("~/Library/SMS/Attachments/0a/10/X/IMG_1.jpeg")
+                .unwrap(),
+            Some(root.join("b1/b1a2"))
+        );
+        assert_eq!(
+            backup
+                .attachment_path("/var/mobile/Library/SMS/Attachments/0a/10/X/IMG_1.jpeg")
+                .unwrap(),
+            Some(root.join("b1/b1a2"))
+        );
+        // Listed in the manifest but not copied into the backup
+        assert_eq!(
+            backup
+                .attachment_path("~/Library/SMS/Attachments/0b/11/Y/IMG_2.jpeg")
+                .unwrap(),
+            None
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
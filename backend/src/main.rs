@@ -1,42 +1,87 @@
 mod api;
+mod chat_schema;
+mod config;
 mod context_db;
 mod extraction;
+mod import_db;
+mod ios_backup;
+mod message_summary;
 mod models;
 mod openrouter;
+mod search_index;
 mod services;
 mod state;
+mod typedstream;
 
-use api::{ai, chats, context, media, messages, suggestions, ws};
+use api::{ai, chats, context, imports, media, messages, people, suggestions, ws};
+use axum::extract::DefaultBodyLimit;
+use axum::http::HeaderValue;
+use clap::Parser;
+use config::{Cli, Config};
 use openrouter::OpenRouterClient;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::OpenFlags;
-use services::{contacts::contact_resolve_worker, watcher::start_file_watcher};
+use services::{
+    address_book::address_book_worker,
+    chat_summaries::{warm_chat_summaries, ChatSummaries},
+    contacts::contact_resolve_worker, search::search_index_worker, watcher::start_file_watcher,
+};
 use state::{AppState, DbChangeEvent};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::{broadcast, mpsc};
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tracing::{error, info};
 
 #[tokio::main]
 async fn main() {
     let _ = dotenvy::dotenv();
 
+    // Defaults < config file < environment < flags; see config.rs
+    let mut config = match Config::load(&Cli::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    // An iPhone backup's sms.db stands in for chat.db
+    if let Some(root) = &config.ios_backup {
+        match ios_backup::IosBackup::open(root) {
+            Ok(backup) => config.chat_db = ios_backup::install(backup).sms_db().to_path_buf(),
+            Err(e) => {
+                eprintln!("Configuration error: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    let config = config::install(config);
+
     // Initialize tracing subscriber for logging
-    // Use RUST_LOG env var to control log levels, e.g.:
+    // `log.filter` (or RUST_LOG / --log-filter) controls log levels, e.g.:
     //   RUST_LOG=openrouter=debug  (full request/response bodies)
     //   RUST_LOG=server=info,ws=info,watcher=info
-    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+    let filter = tracing_subscriber::EnvFilter::try_new(&config.log.filter)
         .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("openrouter=info"));
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_env_filter(filter)
-        .with_target(true)
-        .init();
-    let db_path = format!(
-        "{}/Library/Messages/chat.db",
-        std::env::var("HOME").expect("HOME not set")
-    );
+        .with_target(true);
+    match &config.log.file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap_or_else(|e| {
+                    eprintln!("Failed to open log file {}: {}", path.display(), e);
+                    std::process::exit(2);
+                });
+            subscriber.with_ansi(false).with_writer(Mutex::new(file)).init();
+        }
+        None => subscriber.init(),
+    }
+    let db_path = config.chat_db.to_string_lossy().into_owned();
 
     // Create a broadcast channel for database change notifications
     // Capacity of 16 means we can buffer 16 events before slow receivers are dropped
@@ -50,18 +95,43 @@ async fn main() {
     let chat_manager =
         SqliteConnectionManager::file(&db_path).with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY);
     let chat_pool = Pool::builder()
-        .max_size(4)
+        .max_size(config.pool_size)
         .build(chat_manager)
         .expect("Failed to create chat.db pool");
 
+    // Adapt queries to the columns this macOS release's chat.db has
+    match chat_pool.get() {
+        Ok(conn) => match chat_schema::install(&conn) {
+            Ok(schema) => {
+                let profile = schema.profile();
+                if !profile.compatible {
+                    error!(
+                        target: "server",
+                        "chat.db is missing required columns: {}",
+                        profile.missing_required.join(", ")
+                    );
+                }
+                if !profile.missing_optional.is_empty() {
+                    info!(
+                        target: "server",
+                        "chat.db lacks optional columns (features degraded): {}",
+                        profile.missing_optional.join(", ")
+                    );
+                }
+            }
+            Err(e) => error!(target: "server", "Failed to read chat.db schema: {}", e),
+        },
+        Err(e) => error!(target: "server", "Failed to open chat.db for schema detection: {}", e),
+    }
+
     let assist_client_primary = OpenRouterClient::with_shared_client(
         String::new(),
-        "anthropic/claude-opus-4.5".to_string(),
+        config.assist.primary_model.clone(),
         http_client.clone(),
     );
     let assist_client_fallback = OpenRouterClient::with_shared_client(
         String::new(),
-        "anthropic/claude-3.5-sonnet".to_string(),
+        config.assist.fallback_model.clone(),
         http_client,
     );
 
@@ -69,32 +139,77 @@ async fn main() {
         chat_pool,
         contact_resolve_tx: contact_resolve_tx.clone(),
         suggestion_cache: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        chat_summaries: Arc::new(Mutex::new(ChatSummaries::new())),
         assist_client_primary,
         assist_client_fallback,
         db_change_tx: db_change_tx.clone(),
     };
 
+    // Load the chat list before the first request asks for it
+    let warm_pool = state.chat_pool.clone();
+    let warm_summaries = state.chat_summaries.clone();
+    tokio::task::spawn_blocking(move || {
+        let result = warm_pool
+            .get()
+            .map_err(|e| e.to_string())
+            .and_then(|conn| warm_chat_summaries(&conn, &warm_summaries).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!(target: "chat_summaries", "Failed to load chat summaries: {}", e);
+        }
+    });
+
     // Background worker to resolve contact names without blocking requests
     let resolve_tx = db_change_tx.clone();
     tokio::spawn(async move {
         contact_resolve_worker(contact_resolve_rx, resolve_tx).await;
     });
 
+    // Load names from Contacts' own database, and reload them when it changes
+    let address_book_pool = state.chat_pool.clone();
+    let address_book_tx = db_change_tx.clone();
+    tokio::spawn(async move {
+        address_book_worker(address_book_pool, address_book_tx).await;
+    });
+
     // Start the file watcher in a background task
     let watch_path = db_path.clone();
+    let watch_pool = state.chat_pool.clone();
+    let watch_summaries = state.chat_summaries.clone();
     let watch_tx = db_change_tx.clone();
     tokio::spawn(async move {
-        if let Err(e) = start_file_watcher(&watch_path, watch_tx).await {
+        if let Err(e) = start_file_watcher(&watch_path, watch_pool, watch_summaries, watch_tx).await {
             error!(target: "watcher", "File watcher error: {}", e);
         }
     });
 
+    // Background indexer for full-text message search
+    let search_pool = state.chat_pool.clone();
+    let search_rx = db_change_tx.subscribe();
+    tokio::spawn(async move {
+        search_index_worker(search_pool, search_rx).await;
+    });
+
     let suggestion_cache = state.suggestion_cache.clone();
     let mut suggestion_cache_rx = db_change_tx.subscribe();
     tokio::spawn(async move {
-        while let Ok(_event) = suggestion_cache_rx.recv().await {
-            if let Ok(mut cache) = suggestion_cache.lock() {
-                cache.clear();
+        loop {
+            let event = match suggestion_cache_rx.recv().await {
+                Ok(event) => Some(event),
+                // Missed events could have touched any chat
+                Err(broadcast::error::RecvError::Lagged(_)) => None,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let Ok(mut cache) = suggestion_cache.lock() else {
+                continue;
+            };
+            // Suggestions only read message text, so tapbacks don't invalidate them
+            match event {
+                Some(DbChangeEvent::NewMessages { chat_id, .. })
+                | Some(DbChangeEvent::MessageEdited { chat_id, .. }) => {
+                    cache.remove(&chat_id);
+                }
+                Some(DbChangeEvent::ReactionChanged { .. }) => {}
+                Some(DbChangeEvent::ChatListChanged) | None => cache.clear(),
             }
         }
     });
@@ -104,8 +219,26 @@ async fn main() {
         .route("/chats", axum::routing::get(chats::get_chats))
         .route("/chats/by-ids", axum::routing::post(chats::get_chats_by_ids))
         .route("/chats/search", axum::routing::get(chats::search_chats))
+        .route("/chats/unread", axum::routing::get(chats::get_unread_chats))
         .route("/chats/:id/messages", axum::routing::get(chats::get_messages))
+        .route(
+            "/chats/:id/messages/around/:guid",
+            axum::routing::get(chats::get_messages_around),
+        )
+        .route("/chats/:id/threads/:guid", axum::routing::get(chats::get_thread))
+        .route("/chats/:id/avatar", axum::routing::get(media::get_chat_avatar))
+        .route("/messages/search", axum::routing::get(messages::search_messages))
+        .route(
+            "/messages/:guid/reactions",
+            axum::routing::get(messages::get_message_reactions),
+        )
         .route("/contacts/:handle/photo", axum::routing::get(media::get_contact_photo))
+        .route("/contacts/:handle/vcard", axum::routing::get(context::get_contact_vcard))
+        .route(
+            "/contacts/import",
+            axum::routing::post(imports::import_vcards)
+                .layer(DefaultBodyLimit::max(imports::IMPORT_BODY_LIMIT)),
+        )
         .route("/draft", axum::routing::post(messages::draft_message))
         .route("/send", axum::routing::post(messages::send_message))
         .route("/send-attachment", axum::routing::post(messages::send_attachment))
@@ -120,6 +253,20 @@ async fn main() {
             axum::routing::put(context::update_contact_notes),
         )
         .route("/context/analyze", axum::routing::post(context::analyze_contact_context))
+        .route("/people", axum::routing::get(people::get_people))
+        .route("/people/:id", axum::routing::get(people::get_person))
+        .route("/people/:id/merge", axum::routing::post(people::merge_people))
+        .route("/people/:id/split", axum::routing::post(people::split_person))
+        .route(
+            "/imports/android",
+            axum::routing::post(imports::import_android)
+                .layer(DefaultBodyLimit::max(imports::IMPORT_BODY_LIMIT)),
+        )
+        .route(
+            "/imports/whatsapp",
+            axum::routing::post(imports::import_whatsapp)
+                .layer(DefaultBodyLimit::max(imports::IMPORT_BODY_LIMIT)),
+        )
         .route(
             "/api/suggest",
             axum::routing::post(suggestions::suggest_message),
@@ -129,18 +276,37 @@ async fn main() {
             axum::routing::post(ai::assist_message_stream),
         )
         .route("/ws", axum::routing::get(ws::ws_handler))
-        .layer(CorsLayer::permissive())
+        .layer(cors_layer(&config.allowed_origins))
         .with_state(Arc::new(state));
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3883")
+    let listener = tokio::net::TcpListener::bind(&config.listen)
         .await
-        .expect("Failed to bind to port 3883");
+        .unwrap_or_else(|e| panic!("Failed to bind to {}: {}", config.listen, e));
 
-    info!(target: "server", "Server running on http://127.0.0.1:3883");
-    info!(target: "server", "WebSocket available at ws://127.0.0.1:3883/ws");
+    info!(target: "server", "Server running on http://{}", config.listen);
+    info!(target: "server", "WebSocket available at ws://{}/ws", config.listen);
     info!(target: "server", "Using database: {}", db_path);
 
     axum::serve(listener, app)
         .await
         .expect("Failed to start server");
 }
+
+/// CORS for the configured origins only, so other pages open in a browser
+/// can't read chats or start imports through the API
+fn cors_layer(allowed_origins: &[String]) -> CorsLayer {
+    let origins: Vec<HeaderValue> = allowed_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                error!(target: "server", origin = %origin, "Ignoring invalid allowed origin");
+                None
+            }
+        })
+        .collect();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(Any)
+        .allow_headers(Any)
+}
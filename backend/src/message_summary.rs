@@ -0,0 +1,145 @@
+//! Parser for `message.message_summary_info`.
+//!
+//! Since macOS Ventura, Messages records edits and unsends in a binary plist on
+//! the message row:
+//! - `ec`: edited content, keyed by message part index. Each entry is a list of
+//!   `{ d: date, t: attributedBody }` versions, oldest first.
+//! - `rp`: part indexes that were retracted (unsent).
+//! - `ep`: part indexes that were edited.
+
+use crate::models::MessageEdit;
+use crate::typedstream::decode_attributed_body;
+
+const APPLE_EPOCH_MS: i64 = 978_307_200_000;
+
+/// Edit and unsend details decoded from `message_summary_info`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MessageSummaryInfo {
+    /// Every recorded version of every edited part, oldest first
+    pub edits: Vec<MessageEdit>,
+    pub retracted_parts: Vec<i64>,
+    pub edited_parts: Vec<i64>,
+}
+
+impl MessageSummaryInfo {
+    pub fn parse(data: &[u8]) -> Result<Self, plist::Error> {
+        let value = plist::Value::from_reader(std::io::Cursor::new(data))?;
+        let mut info = MessageSummaryInfo::default();
+        let Some(root) = value.as_dictionary() else {
+            return Ok(info);
+        };
+
+        if let Some(edited_content) = root.get("ec").and_then(|v| v.as_dictionary()) {
+            for (part, versions) in edited_content {
+                let Ok(part) = part.parse::<i64>() else {
+                    continue;
+                };
+                for version in versions.as_array().into_iter().flatten() {
+                    let Some(version) = version.as_dictionary() else {
+                        continue;
+                    };
+                    let Some(time) = version.get("d").and_then(plist_date_to_unix_ms) else {
+                        continue;
+                    };
+                    let text = version
+                        .get("t")
+                        .and_then(|t| t.as_data())
+                        .and_then(|body| decode_attributed_body(body).ok())
+                        .map(|body| body.text);
+                    info.edits.push(MessageEdit { part, time, text });
+                }
+            }
+        }
+        info.edits.sort_by_key(|edit| (edit.time, edit.part));
+
+        info.retracted_parts = int_array(root.get("rp"));
+        info.edited_parts = int_array(root.get("ep"));
+        Ok(info)
+    }
+}
+
+fn int_array(value: Option<&plist::Value>) -> Vec<i64> {
+    value
+        .and_then(|v| v.as_array())
+        .map(|items| items.iter().filter_map(|item| item.as_signed_integer()).collect())
+        .unwrap_or_default()
+}
+
+/// Edit dates are seconds since 2001 as a real, or nanoseconds as an integer
+fn plist_date_to_unix_ms(value: &plist::Value) -> Option<i64> {
+    if let Some(nanos) = value.as_signed_integer() {
+        return Some(nanos / 1_000_000 + APPLE_EPOCH_MS);
+    }
+    if let Some(seconds) = value.as_real() {
+        return Some((seconds * 1000.0) as i64 + APPLE_EPOCH_MS);
+    }
+    value
+        .as_date()
+        .map(|date| chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::from(date)).timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plist::{Dictionary, Value};
+
+    fn body_fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!(
+            "{}/fixtures/attributed_body/{}.bin",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+        .unwrap()
+    }
+
+    fn to_binary_plist(root: Dictionary) -> Vec<u8> {
+        let mut out = Vec::new();
+        Value::Dictionary(root).to_writer_binary(&mut out).unwrap();
+        out
+    }
+
+    fn version(seconds: f64, body: Vec<u8>) -> Value {
+        let mut entry = Dictionary::new();
+        entry.insert("d".to_string(), Value::Real(seconds));
+        entry.insert("t".to_string(), Value::Data(body));
+        Value::Dictionary(entry)
+    }
+
+    #[test]
+    fn parses_edit_history_in_order() {
+        let mut edited = Dictionary::new();
+        edited.insert(
+            "0".to_string(),
+            Value::Array(vec![
+                version(736_000_000.0, body_fixture("plain")),
+                version(736_000_060.0, body_fixture("emoji")),
+            ]),
+        );
+        let mut root = Dictionary::new();
+        root.insert("ec".to_string(), Value::Dictionary(edited));
+        root.insert("ep".to_string(), Value::Array(vec![Value::Integer(0.into())]));
+
+        let info = MessageSummaryInfo::parse(&to_binary_plist(root)).unwrap();
+        assert_eq!(info.edited_parts, vec![0]);
+        assert!(info.retracted_parts.is_empty());
+        assert_eq!(info.edits.len(), 2);
+        assert_eq!(info.edits[0].text.as_deref(), Some("hello"));
+        assert_eq!(info.edits[0].time, 736_000_000_000 + APPLE_EPOCH_MS);
+        assert_eq!(info.edits[1].time - info.edits[0].time, 60_000);
+        assert!(info.edits[1].text.as_deref().unwrap().starts_with("Happy birthday"));
+    }
+
+    #[test]
+    fn parses_retracted_parts() {
+        let mut root = Dictionary::new();
+        root.insert("rp".to_string(), Value::Array(vec![Value::Integer(0.into())]));
+        let info = MessageSummaryInfo::parse(&to_binary_plist(root)).unwrap();
+        assert_eq!(info.retracted_parts, vec![0]);
+        assert!(info.edits.is_empty());
+    }
+
+    #[test]
+    fn rejects_non_plist_data() {
+        assert!(MessageSummaryInfo::parse(b"not a plist").is_err());
+    }
+}
@@ -6,6 +6,33 @@ pub struct PaginationParams {
     pub limit: i64,
     #[serde(default)]
     pub offset: i64,
+    /// List each person's 1:1 chats (phone, email, SMS) as one entry
+    #[serde(default)]
+    pub merged: bool,
+}
+
+#[derive(Deserialize)]
+pub struct MessagesParams {
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+    /// Cursor from `older_cursor`: load the page just before it (takes precedence over offset)
+    pub before: Option<String>,
+    /// Cursor from `newer_cursor`: load the page just after it
+    pub after: Option<String>,
+    /// Jump to the first message on or after this date (YYYY-MM-DD, local time)
+    pub at: Option<String>,
+    /// Interleave the messages of every chat merged with this one (see `Chat::merged_chat_ids`)
+    #[serde(default)]
+    pub merged: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AroundParams {
+    /// Messages to load on each side of the target
+    #[serde(default = "default_limit")]
+    pub limit: i64,
 }
 
 #[derive(Deserialize)]
@@ -23,6 +50,16 @@ pub fn default_search_limit() -> i64 {
     200
 }
 
+/// Largest page of chats or messages a client can ask for
+pub const MAX_PAGE_LIMIT: i64 = 500;
+
+/// A client's `limit`, kept to 1..=`MAX_PAGE_LIMIT`: an empty page would never
+/// advance a client paging on `has_more`, and SQLite reads a negative LIMIT as
+/// no limit at all
+pub fn page_limit(limit: i64) -> i64 {
+    limit.clamp(1, MAX_PAGE_LIMIT)
+}
+
 #[derive(Serialize)]
 pub struct ChatsResponse {
     pub chats: Vec<Chat>,
@@ -36,6 +73,40 @@ pub struct SearchChatsResponse {
     pub query: String,
 }
 
+#[derive(Serialize)]
+pub struct MessageSearchResponse {
+    pub results: Vec<MessageSearchResult>,
+    pub query: String,
+    /// The background indexer is still catching up; results only cover the
+    /// messages indexed so far
+    pub indexing: bool,
+    /// Share of chat.db messages indexed so far (0-1), while indexing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<f64>,
+}
+
+/// A message matching a full-text search, best match first
+#[derive(Serialize)]
+pub struct MessageSearchResult {
+    pub chat_id: i64,
+    pub message_id: i64,
+    pub guid: String,
+    /// Excerpt around the match
+    pub snippet: String,
+    /// Matched terms within `snippet`, in UTF-16 code units
+    pub highlights: Vec<SearchHighlight>,
+    pub time: i64,
+    pub is_from_me: bool,
+    pub handle: Option<String>,
+    pub contact_name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchHighlight {
+    pub start: usize,
+    pub length: usize,
+}
+
 #[derive(Deserialize)]
 pub struct ChatsByIdsRequest {
     pub ids: Vec<i64>,
@@ -46,6 +117,22 @@ pub struct ChatsByIdsResponse {
     pub chats: Vec<Chat>,
 }
 
+#[derive(Serialize)]
+pub struct UnreadChatsResponse {
+    pub chats: Vec<Chat>,
+    /// Sum of `unread_count` across all chats
+    pub total_unread: i64,
+}
+
+/// `Chat::origin` for chats read from a Mac's chat.db (live or a copy)
+pub const ORIGIN_IMESSAGE: &str = "imessage";
+/// `Chat::origin` for chats read from an iPhone backup's sms.db
+pub const ORIGIN_IOS_BACKUP: &str = "ios-backup";
+/// `Chat::origin` for threads imported from an Android SMS Backup & Restore archive
+pub const ORIGIN_ANDROID_IMPORT: &str = "android-import";
+/// `Chat::origin` for chats imported from a WhatsApp chat export
+pub const ORIGIN_WHATSAPP_IMPORT: &str = "whatsapp-import";
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Chat {
     pub id: i64,
@@ -56,12 +143,67 @@ pub struct Chat {
     pub is_group: bool,
     pub handles: Vec<String>,
     pub chat_identifier: Option<String>,
+    /// Incoming messages not yet read on this Mac
+    pub unread_count: i64,
+    /// Time of the newest incoming message that has been read (Unix ms)
+    pub last_read_message_time: Option<i64>,
+    /// Which data source the chat comes from (`ORIGIN_*`)
+    pub origin: String,
+    /// Imported chats are history only; messages can't be sent to them
+    pub read_only: bool,
+    /// In the merged chat list, every chat this entry stands for, newest first.
+    /// `id` is the newest of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_chat_ids: Vec<i64>,
+    /// The person at the other end of a 1:1 chat, if the people directory has them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub person_id: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Reaction {
     pub emoji: String,
     pub is_from_me: bool,
+    /// Handle of the person who reacted (None for reactions from me)
+    pub handle: Option<String>,
+    pub contact_name: Option<String>,
+    /// When the reaction was left, in Unix ms
+    pub time: i64,
+    pub kind: TapbackKind,
+    /// Part of the message reacted to, e.g. one photo of several (0 for the first)
+    pub part: u32,
+    /// Attachment holding the sticker, for sticker tapbacks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sticker_attachment_id: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TapbackKind {
+    Love,
+    Like,
+    Dislike,
+    Laugh,
+    Emphasize,
+    Question,
+    /// Any emoji, picked from the keyboard (`associated_message_emoji`)
+    Emoji,
+    Sticker,
+}
+
+/// Everyone who left the same reaction on a message
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReactionGroup {
+    pub emoji: String,
+    pub kind: TapbackKind,
+    pub count: usize,
+    pub reactors: Vec<Reaction>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MessageReactionsResponse {
+    pub guid: String,
+    pub groups: Vec<ReactionGroup>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -73,6 +215,25 @@ pub struct Attachment {
     pub total_bytes: i64,
 }
 
+/// Attribute run over a message's text, decoded from `attributedBody`.
+/// `start` and `length` are in UTF-16 code units, matching JS string indexing.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TextRun {
+    pub start: usize,
+    pub length: usize,
+    /// Message part index (`__kIMMessagePartAttributeName`)
+    pub part: Option<i64>,
+    /// Handle of a confirmed @mention
+    pub mention: Option<String>,
+    pub link: Option<String>,
+    /// Attachment GUID for an inline U+FFFC placeholder
+    pub attachment_guid: Option<String>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Message {
     pub id: i64,
@@ -84,13 +245,125 @@ pub struct Message {
     pub contact_name: Option<String>,
     pub reactions: Vec<Reaction>,
     pub attachments: Vec<Attachment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<TextRun>,
+    /// GUID of the message this inline reply answers (`thread_originator_guid`)
+    pub reply_to_guid: Option<String>,
+    /// Part of the original message being replied to (`thread_originator_part`)
+    pub reply_to_part: Option<i64>,
+    /// Short quote of the replied-to message for rendering above the bubble
+    pub reply_preview: Option<String>,
+    /// When the message was last edited (Unix ms)
+    pub edited_at: Option<i64>,
+    /// The sender unsent (retracted) this message; `text` is cleared
+    pub is_unsent: bool,
+    /// Prior versions of edited parts, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edit_history: Vec<MessageEdit>,
+    /// Delivery state; only meaningful for outgoing messages
+    pub is_delivered: bool,
+    pub is_read: bool,
+    /// When the message reached the recipient (Unix ms)
+    pub date_delivered: Option<i64>,
+    /// When the recipient read the message (Unix ms), if they share read receipts
+    pub date_read: Option<i64>,
+    /// Failed-send code from `message.error`; None when the send succeeded
+    pub error: Option<i64>,
+    /// Regular message or a group timeline event (`item_type`/`group_action_type`)
+    #[serde(default)]
+    pub kind: MessageKind,
+    /// Participant added or removed by a timeline event (`other_handle`)
+    pub target_handle: Option<String>,
+    pub target_contact_name: Option<String>,
+    /// New group name, for rename events
+    pub group_title: Option<String>,
+    /// Set on imported messages to their chat's `origin`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Transport the message went over: "iMessage", "SMS", ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// The chat the message belongs to, which tells chats apart in merged
+    /// timelines (chat.db messages only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    #[default]
+    Message,
+    ParticipantAdded,
+    ParticipantRemoved,
+    ParticipantLeft,
+    GroupRenamed,
+    GroupPhotoChanged,
+    GroupPhotoRemoved,
+    /// Other non-message rows (location sharing, FaceTime, ...)
+    System,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MessageEdit {
+    /// Message part the version belongs to
+    pub part: i64,
+    /// When this version was sent (Unix ms)
+    pub time: i64,
+    pub text: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct MessagesResponse {
     pub messages: Vec<Message>,
     pub total: i64,
+    /// Older messages exist before this page
     pub has_more: bool,
+    /// Newer messages exist after this page
+    pub has_newer: bool,
+    /// Pass as `before` to load the previous (older) page
+    pub older_cursor: Option<String>,
+    /// Pass as `after` to load the next (newer) page
+    pub newer_cursor: Option<String>,
+}
+
+/// Messages in a chat added or changed since a client's last-seen ROWID
+#[derive(Serialize)]
+pub struct MessagesDelta {
+    /// New and changed messages, oldest first
+    pub messages: Vec<Message>,
+    pub total: i64,
+    /// Newest ROWID covered by this delta; the client's next last-seen ROWID
+    pub last_rowid: i64,
+    /// False if more new messages exist than fit in one delta
+    #[serde(skip)]
+    pub complete: bool,
+}
+
+/// A page of messages from `/chats/:id/messages?at=DATE`
+#[derive(Serialize)]
+pub struct DateJumpResponse {
+    #[serde(flatten)]
+    pub page: MessagesResponse,
+    /// First message on or after the requested date; None if the date is past the newest message
+    pub anchor_guid: Option<String>,
+    /// Message counts per month across the whole chat, for a scrubber
+    pub months: Vec<MonthCount>,
+}
+
+#[derive(Serialize)]
+pub struct MonthCount {
+    /// "YYYY-MM", local time
+    pub month: String,
+    pub count: i64,
+}
+
+#[derive(Serialize)]
+pub struct ThreadResponse {
+    pub chat_id: i64,
+    /// GUID of the message that started the thread
+    pub thread_guid: String,
+    pub messages: Vec<Message>,
 }
 
 #[derive(Deserialize)]
@@ -203,3 +476,40 @@ pub struct UpdateContextRequest {
     pub notes: Option<String>,
 }
 
+
+#[derive(Deserialize)]
+pub struct PeopleParams {
+    /// Only people whose name or a handle contains this
+    pub q: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+#[derive(Serialize)]
+pub struct PeopleResponse {
+    pub people: Vec<crate::context_db::Person>,
+    pub total: i64,
+    pub has_more: bool,
+}
+
+#[derive(Serialize)]
+pub struct PersonResponse {
+    #[serde(flatten)]
+    pub person: crate::context_db::Person,
+    /// Context learned from conversations, one per handle that has any
+    pub contexts: Vec<crate::context_db::ContactContext>,
+}
+
+#[derive(Deserialize)]
+pub struct MergePeopleRequest {
+    /// People to fold into the one in the path
+    pub ids: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct SplitPersonRequest {
+    /// Handles to move to a new person
+    pub handles: Vec<String>,
+}
@@ -0,0 +1,340 @@
+// Search Index Module
+// Sidecar SQLite database holding an FTS5 index of decoded message text.
+// chat.db is opened read-only, so the index lives next to context.db and is
+// fed incrementally by message ROWID. Imported messages (import_db.rs) are
+// indexed under their negated ids, with a watermark of their own.
+
+use crate::config;
+use rusqlite::{params, Connection};
+use std::time::Duration;
+
+/// Private-use markers passed to `snippet()`; stripped into highlight ranges
+const HIGHLIGHT_START: char = '\u{E000}';
+const HIGHLIGHT_END: char = '\u{E001}';
+const SNIPPET_TOKENS: i64 = 16;
+
+/// A message as stored in the index
+pub struct IndexedMessage {
+    /// `message.ROWID`, used as the FTS rowid; negative for imported messages
+    pub rowid: i64,
+    pub guid: String,
+    pub chat_id: i64,
+    pub text: String,
+    /// Unix ms
+    pub time: i64,
+    pub is_from_me: bool,
+    pub handle: Option<String>,
+}
+
+/// A ranked match; `highlights` are (start, length) in UTF-16 code units of `snippet`
+pub struct SearchHit {
+    pub rowid: i64,
+    pub guid: String,
+    pub chat_id: i64,
+    pub snippet: String,
+    pub highlights: Vec<(usize, usize)>,
+    pub time: i64,
+    pub is_from_me: bool,
+    pub handle: Option<String>,
+}
+
+pub struct SearchIndex {
+    conn: Connection,
+}
+
+impl SearchIndex {
+    /// Open or create the search index database
+    pub fn open() -> Result<Self, Box<dyn std::error::Error>> {
+        let db_path = config::current().search_db();
+
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(&db_path)?;
+        // The background indexer and request handlers write concurrently
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Box<dyn std::error::Error>> {
+        let index = SearchIndex { conn };
+        index.init_schema()?;
+        Ok(index)
+    }
+
+    fn init_schema(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn.execute_batch(
+            "
+            CREATE VIRTUAL TABLE IF NOT EXISTS message_fts USING fts5(
+                text,
+                guid UNINDEXED,
+                chat_id UNINDEXED,
+                time UNINDEXED,
+                is_from_me UNINDEXED,
+                handle UNINDEXED,
+                tokenize = 'unicode61 remove_diacritics 2'
+            );
+
+            CREATE TABLE IF NOT EXISTS index_state (
+                key TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );
+            ",
+        )?;
+        Ok(())
+    }
+
+    // ============================================================================
+    // Watermarks
+    // ============================================================================
+
+    fn get_state(&self, key: &str) -> Result<i64, Box<dyn std::error::Error>> {
+        let result = self.conn.query_row(
+            "SELECT value FROM index_state WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        );
+
+        match result {
+            Ok(value) => Ok(value),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn set_state(&self, key: &str, value: i64) -> Result<(), Box<dyn std::error::Error>> {
+        self.conn.execute(
+            "INSERT INTO index_state (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = ?2",
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Highest `message.ROWID` that has been indexed
+    pub fn last_indexed_rowid(&self) -> Result<i64, Box<dyn std::error::Error>> {
+        self.get_state("last_rowid")
+    }
+
+    /// Newest raw `date_edited`/`date_retracted` already applied to the index
+    pub fn last_edit_date(&self) -> Result<i64, Box<dyn std::error::Error>> {
+        self.get_state("last_edit_date")
+    }
+
+    pub fn set_last_edit_date(&self, date: i64) -> Result<(), Box<dyn std::error::Error>> {
+        self.set_state("last_edit_date", date)
+    }
+
+    /// Highest `imported_message.id` that has been indexed
+    pub fn last_imported_id(&self) -> Result<i64, Box<dyn std::error::Error>> {
+        self.get_state("last_import_id")
+    }
+
+    // ============================================================================
+    // Indexing
+    // ============================================================================
+
+    /// Add a batch of new messages and advance the ROWID watermark to `last_rowid`.
+    /// `last_rowid` covers rows that were scanned but had no text to index.
+    pub fn add_messages(
+        &mut self,
+        messages: &[IndexedMessage],
+        last_rowid: i64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.add_batch(messages, "last_rowid", last_rowid)
+    }
+
+    /// Add a batch of imported messages and advance the import watermark to `last_id`
+    pub fn add_imported_messages(
+        &mut self,
+        messages: &[IndexedMessage],
+        last_id: i64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.add_batch(messages, "last_import_id", last_id)
+    }
+
+    fn add_batch(
+        &mut self,
+        messages: &[IndexedMessage],
+        watermark: &str,
+        last: i64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO message_fts
+                    (rowid, text, guid, chat_id, time, is_from_me, handle)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for message in messages {
+                stmt.execute(params![
+                    message.rowid,
+                    message.text,
+                    message.guid,
+                    message.chat_id,
+                    message.time,
+                    message.is_from_me,
+                    message.handle,
+                ])?;
+            }
+        }
+        tx.execute(
+            "INSERT INTO index_state (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = MAX(value, ?2)",
+            params![watermark, last],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Re-index messages that changed after being indexed; `None` text removes
+    /// the message (unsent, or edited down to nothing)
+    pub fn replace_messages(
+        &mut self,
+        updates: &[(i64, Option<IndexedMessage>)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.transaction()?;
+        for (rowid, message) in updates {
+            tx.execute("DELETE FROM message_fts WHERE rowid = ?1", params![rowid])?;
+            if let Some(message) = message {
+                tx.execute(
+                    "INSERT INTO message_fts
+                        (rowid, text, guid, chat_id, time, is_from_me, handle)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        message.rowid,
+                        message.text,
+                        message.guid,
+                        message.chat_id,
+                        message.time,
+                        message.is_from_me,
+                        message.handle,
+                    ],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // ============================================================================
+    // Search
+    // ============================================================================
+
+    /// Matches for an FTS5 MATCH expression (see `SearchQuery::fts_expression`),
+    /// best bm25 rank first
+    pub fn search(
+        &self,
+        expression: &str,
+        limit: i64,
+    ) -> Result<Vec<SearchHit>, Box<dyn std::error::Error>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT rowid, guid, chat_id, time, is_from_me, handle,
+                    snippet(message_fts, 0, ?2, ?3, '…', ?4)
+             FROM message_fts
+             WHERE message_fts MATCH ?1
+             ORDER BY bm25(message_fts), time DESC
+             LIMIT ?5",
+        )?;
+
+        let rows = stmt.query_map(
+            params![
+                expression,
+                HIGHLIGHT_START.to_string(),
+                HIGHLIGHT_END.to_string(),
+                SNIPPET_TOKENS,
+                limit
+            ],
+            |row| {
+                let (snippet, highlights) = split_highlights(&row.get::<_, String>(6)?);
+                Ok(SearchHit {
+                    rowid: row.get(0)?,
+                    guid: row.get(1)?,
+                    chat_id: row.get(2)?,
+                    time: row.get(3)?,
+                    is_from_me: row.get(4)?,
+                    handle: row.get(5)?,
+                    snippet,
+                    highlights,
+                })
+            },
+        )?;
+
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+    }
+}
+
+/// Strip the highlight markers from a snippet, returning UTF-16 ranges of the matches
+fn split_highlights(marked: &str) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::with_capacity(marked.len());
+    let mut highlights = Vec::new();
+    let mut offset = 0;
+    let mut start = None;
+
+    for c in marked.chars() {
+        match c {
+            HIGHLIGHT_START => start = Some(offset),
+            HIGHLIGHT_END => {
+                if let Some(start) = start.take() {
+                    highlights.push((start, offset - start));
+                }
+            }
+            _ => {
+                text.push(c);
+                offset += c.len_utf16();
+            }
+        }
+    }
+
+    (text, highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(rowid: i64, text: &str) -> IndexedMessage {
+        IndexedMessage {
+            rowid,
+            guid: format!("GUID-{}", rowid),
+            chat_id: 1,
+            text: text.to_string(),
+            time: rowid * 1000,
+            is_from_me: false,
+            handle: Some("+15551234567".to_string()),
+        }
+    }
+
+    #[test]
+    fn splits_highlight_markers_into_utf16_ranges() {
+        let (text, highlights) = split_highlights("🍕 at \u{E000}Joe's\u{E001} tonight");
+        assert_eq!(text, "🍕 at Joe's tonight");
+        assert_eq!(highlights, vec![(6, 5)]);
+    }
+
+    #[test]
+    fn indexes_incrementally_and_replaces_edits() {
+        let mut index = SearchIndex::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        index
+            .add_messages(&[message(1, "Dinner at Joe's?"), message(3, "See you at dinner")], 4)
+            .unwrap();
+        assert_eq!(index.last_indexed_rowid().unwrap(), 4);
+
+        let hits = index.search("\"dinn\"*", 10).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.highlights.len() == 1));
+
+        index
+            .replace_messages(&[(1, Some(message(1, "Lunch at Joe's?"))), (3, None)])
+            .unwrap();
+        assert!(index.search("\"dinner\"", 10).unwrap().is_empty());
+        assert_eq!(index.search("\"lunch\"", 10).unwrap()[0].guid, "GUID-1");
+
+        // An older batch never moves the watermark backwards
+        index.add_messages(&[], 2).unwrap();
+        assert_eq!(index.last_indexed_rowid().unwrap(), 4);
+    }
+}
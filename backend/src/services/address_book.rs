@@ -0,0 +1,440 @@
+use crate::config;
+use crate::context_db::ContextDb;
+use crate::services::avatars::content_hash;
+use crate::services::contacts::{forget_contact_photos, HandleMatcher};
+use crate::state::DbChangeEvent;
+use chrono::{DateTime, Datelike};
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+// ============================================================================
+// ADDRESS BOOK
+// ============================================================================
+//
+// Contacts.app keeps each account's cards in a Core Data SQLite store,
+// `Sources/<account>/AddressBook-v22.abcddb`, plus one at the top level for
+// cards stored "On My Mac". Reading the stores directly takes milliseconds and
+// needs no Automation permission, unlike asking Contacts over AppleScript one
+// handle at a time. So:
+// - every card's name, numbers, emails and birthday are loaded into the people
+//   directory at startup, and again whenever a store changes
+// - numbers and emails are mapped onto chat.db's own handle strings first
+// - cards are compared with the last load, and only handles whose card
+//   changed (name, birthday or photo) have their cached photo dropped
+// - the per-handle AppleScript lookup only runs when no store could be read
+// ============================================================================
+
+const STORE_FILE: &str = "AddressBook-v22.abcddb";
+
+/// Seconds between 1970-01-01 and 2001-01-01, Core Data's epoch
+const CORE_DATA_EPOCH: i64 = 978307200;
+
+/// Contacts stores birthdays without a year in this year
+const YEARLESS_BIRTHDAY_YEAR: i32 = 1604;
+
+/// Set once a store has been read; from then on the stores are the source of names
+static LOADED: AtomicBool = AtomicBool::new(false);
+
+/// One Contacts card
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactCard {
+    pub name: String,
+    /// Phone numbers and emails
+    pub handles: Vec<String>,
+    /// YYYY-MM-DD, or --MM-DD without a year
+    pub birthday: Option<String>,
+    /// Content hash of the card's photo, to notice when it changes
+    pub photo_hash: Option<String>,
+}
+
+/// Whether a store has been read, so names missing from the cache are unknown
+/// to Contacts too
+pub fn loaded() -> bool {
+    LOADED.load(Ordering::Relaxed)
+}
+
+/// Every AddressBook store under `dir`
+pub fn store_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![dir.join(STORE_FILE)];
+    if let Ok(entries) = std::fs::read_dir(dir.join("Sources")) {
+        let mut sources: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().join(STORE_FILE))
+            .collect();
+        sources.sort();
+        paths.extend(sources);
+    }
+    paths.retain(|path| path.is_file());
+    paths
+}
+
+/// Cards in an opened store
+pub fn read_cards(conn: &Connection) -> Result<Vec<ContactCard>, Box<dyn std::error::Error>> {
+    // Older stores lack some of these; a missing column reads as NULL
+    let record_columns: HashSet<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('ZABCDRECORD')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let column = |name: &str| {
+        if record_columns.contains(name) {
+            name.to_string()
+        } else {
+            "NULL".to_string()
+        }
+    };
+
+    // Cards without a name have nothing to offer
+    let mut cards: BTreeMap<i64, ContactCard> = BTreeMap::new();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT Z_PK, {}, {}, {}, {}, {}, {}, {} FROM ZABCDRECORD",
+        column("ZFIRSTNAME"),
+        column("ZLASTNAME"),
+        column("ZNICKNAME"),
+        column("ZORGANIZATION"),
+        column("ZDISPLAYFLAGS"),
+        column("ZBIRTHDAY"),
+        // The thumbnail, or a reference to it in external storage that changes with it
+        column("ZTHUMBNAILIMAGEDATA")
+    ))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let Some(name) = card_name(
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get::<_, Option<i64>>(5)?.unwrap_or(0),
+        ) else {
+            continue;
+        };
+        cards.insert(
+            row.get(0)?,
+            ContactCard {
+                name,
+                handles: Vec::new(),
+                birthday: row.get::<_, Option<f64>>(6)?.and_then(birthday_from_core_data),
+                photo_hash: row.get_ref(7)?.as_bytes_or_null()?.map(content_hash),
+            },
+        );
+    }
+
+    for sql in [
+        "SELECT ZOWNER, ZFULLNUMBER FROM ZABCDPHONENUMBER ORDER BY ZOWNER, ZORDERINGINDEX",
+        "SELECT ZOWNER, ZADDRESS FROM ZABCDEMAILADDRESS ORDER BY ZOWNER, ZORDERINGINDEX",
+    ] {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let owner: Option<i64> = row.get(0)?;
+            let handle: Option<String> = row.get(1)?;
+            if let (Some(card), Some(handle)) = (owner.and_then(|id| cards.get_mut(&id)), handle) {
+                let handle = handle.trim();
+                if !handle.is_empty() {
+                    card.handles.push(handle.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(cards
+        .into_values()
+        .filter(|card| !card.handles.is_empty())
+        .collect())
+}
+
+/// The name Contacts shows for a card: "First Last", or the company for
+/// company cards and cards with no personal name
+fn card_name(
+    first: Option<String>,
+    last: Option<String>,
+    nickname: Option<String>,
+    organization: Option<String>,
+    display_flags: i64,
+) -> Option<String> {
+    let present = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let organization = present(organization);
+    // Bit 0 of ZDISPLAYFLAGS marks a company card
+    if display_flags & 1 == 1 && organization.is_some() {
+        return organization;
+    }
+    let personal: Vec<String> = [present(first), present(last)].into_iter().flatten().collect();
+    if !personal.is_empty() {
+        return Some(personal.join(" "));
+    }
+    present(nickname).or(organization)
+}
+
+/// Core Data stores birthdays as seconds since 2001 at noon GMT on the day
+fn birthday_from_core_data(seconds: f64) -> Option<String> {
+    let date = DateTime::from_timestamp(CORE_DATA_EPOCH + seconds.floor() as i64, 0)?.date_naive();
+    if date.year() == YEARLESS_BIRTHDAY_YEAR {
+        Some(date.format("--%m-%d").to_string())
+    } else {
+        Some(date.format("%Y-%m-%d").to_string())
+    }
+}
+
+/// Read every store under `dir` into the people directory. Returns the cards
+/// loaded, or `None` if there was no store to read.
+pub fn load_address_book(
+    dir: &Path,
+    conn: &Connection,
+    context_db: &ContextDb,
+) -> Result<Option<Vec<ContactCard>>, Box<dyn std::error::Error>> {
+    let paths = store_paths(dir);
+    if paths.is_empty() {
+        return Ok(None);
+    }
+
+    let mut matcher = HandleMatcher::load(conn)?;
+    let mut cards = Vec::new();
+    let mut read_any = false;
+    for path in &paths {
+        let store = match Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY) {
+            Ok(store) => store,
+            Err(e) => {
+                error!(target: "contacts", "Failed to open {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        match read_cards(&store) {
+            Ok(read) => {
+                read_any = true;
+                cards.extend(read);
+            }
+            Err(e) => error!(target: "contacts", "Failed to read {}: {}", path.display(), e),
+        }
+    }
+    if !read_any {
+        return Ok(None);
+    }
+
+    // Match chat.db's spelling of each number so lookups by handle find it
+    for card in &mut cards {
+        for handle in &mut card.handles {
+            *handle = matcher.resolve(handle);
+        }
+    }
+    context_db.apply_contact_cards(&cards)?;
+    LOADED.store(true, Ordering::Relaxed);
+    Ok(Some(cards))
+}
+
+/// Handles whose card was added, removed or changed between two loads
+fn changed_handles(old: &[ContactCard], new: &[ContactCard]) -> Vec<String> {
+    let by_handle = |cards: &[ContactCard]| -> BTreeMap<String, ContactCard> {
+        cards
+            .iter()
+            .flat_map(|card| card.handles.iter().map(move |handle| (handle.clone(), card.clone())))
+            .collect()
+    };
+    let (old, new) = (by_handle(old), by_handle(new));
+    let mut changed: Vec<String> = old
+        .iter()
+        .filter(|(handle, card)| new.get(*handle) != Some(card))
+        .map(|(handle, _)| handle.clone())
+        .collect();
+    changed.extend(new.keys().filter(|handle| !old.contains_key(*handle)).cloned());
+    changed
+}
+
+/// Load the address book now and again whenever one of its stores changes,
+/// telling clients to refetch names afterwards
+pub async fn address_book_worker(
+    chat_pool: Pool<SqliteConnectionManager>,
+    tx: broadcast::Sender<DbChangeEvent>,
+) {
+    let dir = config::current().address_book.clone();
+    let mut cards = reload(&dir, &chat_pool, &tx).await.unwrap_or_default();
+    if !dir.is_dir() {
+        info!(target: "contacts", "No address book at {}; names come from AppleScript", dir.display());
+        return;
+    }
+
+    // Bridge the blocking notify thread to this task, as the chat.db watcher does
+    let (async_tx, mut async_rx) = tokio::sync::mpsc::channel::<()>(4);
+    let watch_dir = dir.clone();
+    std::thread::spawn(move || {
+        let (file_tx, file_rx) = std::sync::mpsc::channel();
+        // Contacts rewrites a store in bursts; wait for it to settle
+        let mut debouncer = match new_debouncer(Duration::from_secs(2), file_tx) {
+            Ok(d) => d,
+            Err(e) => {
+                error!(target: "contacts", "Failed to create address book debouncer: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = debouncer.watcher().watch(&watch_dir, RecursiveMode::Recursive) {
+            error!(target: "contacts", "Failed to watch address book: {}", e);
+            return;
+        }
+
+        while let Ok(result) = file_rx.recv() {
+            let Ok(events) = result else {
+                continue;
+            };
+            let store_changed = events.iter().any(|event| {
+                event
+                    .path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(STORE_FILE))
+            });
+            if store_changed && async_tx.blocking_send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    while async_rx.recv().await.is_some() {
+        // Coalesce changes that arrived while the last reload ran
+        while async_rx.try_recv().is_ok() {}
+        let Some(reloaded) = reload(&dir, &chat_pool, &tx).await else {
+            continue;
+        };
+        // A changed card may have a new photo; ask Contacts again for those only
+        match forget_contact_photos(&changed_handles(&cards, &reloaded)) {
+            Ok(0) => {}
+            Ok(count) => info!(target: "contacts", "Forgot {} cached contact photos", count),
+            Err(e) => error!(target: "contacts", "Failed to clear cached contact photos: {}", e),
+        }
+        cards = reloaded;
+    }
+}
+
+async fn reload(
+    dir: &Path,
+    chat_pool: &Pool<SqliteConnectionManager>,
+    tx: &broadcast::Sender<DbChangeEvent>,
+) -> Option<Vec<ContactCard>> {
+    let started = std::time::Instant::now();
+    let dir = dir.to_path_buf();
+    let chat_pool = chat_pool.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let conn = chat_pool.get().map_err(|e| e.to_string())?;
+        let context_db = ContextDb::open().map_err(|e| e.to_string())?;
+        load_address_book(&dir, &conn, &context_db).map_err(|e| e.to_string())
+    })
+    .await;
+
+    match result {
+        Ok(Ok(Some(cards))) => {
+            info!(
+                target: "contacts",
+                "Loaded {} contacts from the address book in {:?}",
+                cards.len(),
+                started.elapsed()
+            );
+            let _ = tx.send(DbChangeEvent::ChatListChanged);
+            Some(cards)
+        }
+        Ok(Ok(None)) => None,
+        Ok(Err(e)) => {
+            error!(target: "contacts", "Failed to load the address book: {}", e);
+            None
+        }
+        Err(e) => {
+            error!(target: "contacts", "Address book task failed: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_cards_into_people() {
+        let store = Connection::open_in_memory().unwrap();
+        store
+            .execute_batch(
+                "
+                CREATE TABLE ZABCDRECORD (
+                    Z_PK INTEGER PRIMARY KEY, ZFIRSTNAME TEXT, ZLASTNAME TEXT, ZNICKNAME TEXT,
+                    ZORGANIZATION TEXT, ZDISPLAYFLAGS INTEGER, ZBIRTHDAY REAL
+                );
+                CREATE TABLE ZABCDPHONENUMBER (
+                    Z_PK INTEGER PRIMARY KEY, ZOWNER INTEGER, ZFULLNUMBER TEXT, ZORDERINGINDEX INTEGER
+                );
+                CREATE TABLE ZABCDEMAILADDRESS (
+                    Z_PK INTEGER PRIMARY KEY, ZOWNER INTEGER, ZADDRESS TEXT, ZORDERINGINDEX INTEGER
+                );
+                -- 1990-04-12 and April 3rd without a year, both at noon GMT
+                INSERT INTO ZABCDRECORD VALUES
+                    (1, 'Sam', 'Rivera', NULL, NULL, 0, -338385600),
+                    (2, NULL, NULL, NULL, 'Crag Gym', 1, NULL),
+                    (3, 'Alex', NULL, NULL, NULL, 0, -12520094400),
+                    (4, 'No', 'Handles', NULL, NULL, 0, NULL);
+                INSERT INTO ZABCDPHONENUMBER VALUES
+                    (1, 1, '(555) 000-1234', 0), (2, 2, '555-000-9999', 0), (3, 3, '+44 20 7946 0000', 0);
+                INSERT INTO ZABCDEMAILADDRESS VALUES (1, 1, 'sam@example.com', 1);
+                ",
+            )
+            .unwrap();
+
+        let cards = read_cards(&store).unwrap();
+        assert_eq!(
+            cards[0],
+            ContactCard {
+                name: "Sam Rivera".to_string(),
+                handles: vec!["(555) 000-1234".to_string(), "sam@example.com".to_string()],
+                birthday: Some("1990-04-12".to_string()),
+                photo_hash: None,
+            }
+        );
+        assert_eq!(cards[1].name, "Crag Gym");
+        assert_eq!(cards[2].birthday.as_deref(), Some("--04-03"));
+        assert_eq!(cards.len(), 3);
+
+        let context_db = ContextDb::open_in_memory().unwrap();
+        context_db.set_cached_contact_name("+15550001234", "Sammy").unwrap();
+        context_db.apply_contact_cards(&cards).unwrap();
+        context_db.apply_contact_cards(&cards).unwrap();
+        assert_eq!(
+            context_db.get_cached_contact_name("+1 555 000 1234").unwrap().as_deref(),
+            Some("Sam Rivera")
+        );
+        let person_id = context_db.person_id("SAM@example.com").unwrap().unwrap();
+        let person = context_db.get_person(person_id).unwrap().unwrap();
+        assert_eq!(person.handles, vec!["+15550001234", "sam@example.com"]);
+        assert_eq!(person.birthday.as_deref(), Some("1990-04-12"));
+        assert_eq!(context_db.list_people(None, 10, 0).unwrap().1, 3);
+    }
+
+    #[test]
+    fn only_changed_cards_forget_their_photos() {
+        let card = |name: &str, handles: &[&str], photo: Option<&[u8]>| ContactCard {
+            name: name.to_string(),
+            handles: handles.iter().map(|handle| handle.to_string()).collect(),
+            birthday: None,
+            photo_hash: photo.map(content_hash),
+        };
+        let old = vec![
+            card("Sam Rivera", &["+15550001"], Some(b"sam")),
+            card("Alex Kim", &["+15550002", "alex@example.com"], None),
+            card("Jo", &["+15550003"], None),
+        ];
+        assert!(changed_handles(&old, &old).is_empty());
+
+        let new = vec![
+            card("Sam Rivera", &["+15550001"], Some(b"sam again")),
+            card("Alex Kim", &["+15550002", "alex@example.com"], None),
+            card("Jo", &["+15550004"], None),
+        ];
+        let mut changed = changed_handles(&old, &new);
+        changed.sort();
+        assert_eq!(changed, vec!["+15550001", "+15550003", "+15550004"]);
+    }
+}
@@ -0,0 +1,533 @@
+use crate::context_db::ContextDb;
+use crate::import_db::{ImportDb, ImportSummary, NewAttachment, NewChat, NewMessage};
+use crate::models::ORIGIN_ANDROID_IMPORT;
+use crate::services::contacts::HandleMatcher;
+use base64::Engine;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+// ============================================================================
+// ANDROID IMPORT
+// ============================================================================
+//
+// "SMS Backup & Restore" archives are one XML document:
+//
+//   <smses count="3">
+//     <sms address="+15550001" date="1700000000000" type="1" body="hi"
+//          contact_name="Ann" ... />
+//     <mms date="1700000100000" msg_box="2" address="+15550001~+15550002" ...>
+//       <parts>
+//         <part ct="application/smil" ... />
+//         <part ct="text/plain" text="photos from saturday" ... />
+//         <part ct="image/jpeg" cl="IMG_0001.jpg" data="/9j/4AAQ..." ... />
+//       </parts>
+//       <addrs>
+//         <addr address="+15550003" type="137" ... />
+//       </addrs>
+//     </mms>
+//   </smses>
+//
+// - Dates are Unix ms
+// - `sms@type` and `mms@msg_box`: 1 received, 2 sent, 3 draft, 4 outbox,
+//   5 failed, 6 queued
+// - `addr@type` 137 is the sender (151 to, 130 cc, 129 bcc)
+// - absent values are often the literal string "null"
+// - emoji are commonly written as two UTF-16 surrogate character references
+//   (`&#55357;&#56832;`), which strict XML unescaping rejects
+//
+// Archives can be gigabytes of base64, so the file is streamed and each
+// message handed over as soon as its element closes.
+// ============================================================================
+
+/// `addr@type` of an MMS sender (PduHeaders.FROM)
+const MMS_ADDR_FROM: &str = "137";
+const UNKNOWN_CONTACT: &str = "(Unknown)";
+
+/// One `<sms>` or `<mms>` element
+#[derive(Debug, PartialEq)]
+pub struct AndroidMessage {
+    /// "SMS" or "MMS"
+    pub service: &'static str,
+    /// Everyone in the conversation except me, as written in the archive
+    pub participants: Vec<String>,
+    /// None for messages I sent
+    pub sender: Option<String>,
+    /// Unix ms
+    pub time: i64,
+    pub text: Option<String>,
+    /// Name the phone's contacts had for the conversation
+    pub contact_name: Option<String>,
+    pub attachments: Vec<AndroidAttachment>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AndroidAttachment {
+    pub mime_type: String,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// An `<mms>` whose parts and addresses are still being read
+struct PendingMms {
+    attributes: HashMap<String, String>,
+    parts: Vec<HashMap<String, String>>,
+    addrs: Vec<HashMap<String, String>>,
+}
+
+/// Stream the messages out of an archive in document order, skipping drafts
+pub fn parse_backup<R: BufRead>(
+    source: R,
+    mut on_message: impl FnMut(AndroidMessage) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_reader(source);
+    let mut buf = Vec::new();
+    let mut mms: Option<PendingMms> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Empty(element) => match element.name().as_ref() {
+                "sms" => {
+                    if let Some(message) = sms_message(&attributes(&element)?) {
+                        on_message(message)?;
+                    }
+                }
+                // An MMS without parts or addresses
+                "mms" => {
+                    let pending = PendingMms {
+                        attributes: attributes(&element)?,
+                        parts: Vec::new(),
+                        addrs: Vec::new(),
+                    };
+                    if let Some(message) = mms_message(pending)? {
+                        on_message(message)?;
+                    }
+                }
+                "part" => {
+                    if let Some(pending) = mms.as_mut() {
+                        pending.parts.push(attributes(&element)?);
+                    }
+                }
+                "addr" => {
+                    if let Some(pending) = mms.as_mut() {
+                        pending.addrs.push(attributes(&element)?);
+                    }
+                }
+                _ => {}
+            },
+            Event::Start(element) if element.name().as_ref() == "mms" => {
+                mms = Some(PendingMms {
+                    attributes: attributes(&element)?,
+                    parts: Vec::new(),
+                    addrs: Vec::new(),
+                });
+            }
+            Event::End(element) if element.name().as_ref() == "mms" => {
+                if let Some(message) = mms.take().map(mms_message).transpose()?.flatten() {
+                    on_message(message)?;
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+/// Unescaped attributes of an element; "null" values are dropped
+fn attributes(element: &BytesStart) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut values = HashMap::new();
+    for attribute in element.attributes().with_checks(false) {
+        let attribute = attribute?;
+        if attribute.value.as_ref() == "null" {
+            continue;
+        }
+        values.insert(
+            attribute.key.as_ref().to_string(),
+            unescape(&attribute.value),
+        );
+    }
+    Ok(values)
+}
+
+/// Box types for messages I wrote: sent, outbox, failed, queued
+fn is_outgoing(box_type: &str) -> bool {
+    matches!(box_type, "2" | "4" | "5" | "6")
+}
+
+fn parse_time(attributes: &HashMap<String, String>) -> Option<i64> {
+    attributes.get("date")?.trim().parse().ok()
+}
+
+fn contact_name(attributes: &HashMap<String, String>) -> Option<String> {
+    attributes
+        .get("contact_name")
+        .filter(|name| !name.is_empty() && name.as_str() != UNKNOWN_CONTACT)
+        .cloned()
+}
+
+fn sms_message(attributes: &HashMap<String, String>) -> Option<AndroidMessage> {
+    let box_type = attributes.get("type").map(String::as_str).unwrap_or("1");
+    if box_type == "3" {
+        return None;
+    }
+    let address = attributes
+        .get("address")
+        .filter(|a| !a.trim().is_empty())?
+        .clone();
+    let is_from_me = is_outgoing(box_type);
+
+    Some(AndroidMessage {
+        service: "SMS",
+        participants: vec![address.clone()],
+        sender: (!is_from_me).then_some(address),
+        time: parse_time(attributes)?,
+        text: attributes
+            .get("body")
+            .filter(|body| !body.is_empty())
+            .cloned(),
+        contact_name: contact_name(attributes),
+        attachments: Vec::new(),
+    })
+}
+
+fn mms_message(pending: PendingMms) -> Result<Option<AndroidMessage>, Box<dyn std::error::Error>> {
+    let attributes = &pending.attributes;
+    let box_type = attributes.get("msg_box").map(String::as_str).unwrap_or("1");
+    if box_type == "3" {
+        return Ok(None);
+    }
+    let Some(time) = parse_time(attributes) else {
+        return Ok(None);
+    };
+    let is_from_me = is_outgoing(box_type);
+
+    let from = pending
+        .addrs
+        .iter()
+        .find(|addr| addr.get("type").map(String::as_str) == Some(MMS_ADDR_FROM))
+        .and_then(|addr| addr.get("address"))
+        .filter(|address| !address.contains("insert-address-token"))
+        .cloned();
+
+    // `mms@address` lists the conversation's other members; on messages I
+    // sent, the FROM address is my own number
+    let mut participants: Vec<String> = attributes
+        .get("address")
+        .map(|addresses| {
+            addresses
+                .split('~')
+                .map(str::trim)
+                .filter(|address| !address.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if is_from_me {
+        if let Some(mine) = &from {
+            participants.retain(|address| address != mine);
+        }
+    } else if let Some(sender) = &from {
+        if !participants.contains(sender) {
+            participants.push(sender.clone());
+        }
+    }
+    if participants.is_empty() {
+        return Ok(None);
+    }
+
+    let mut texts: Vec<String> = Vec::new();
+    let mut attachments = Vec::new();
+    for part in &pending.parts {
+        let mime_type = part.get("ct").map(String::as_str).unwrap_or("");
+        match mime_type {
+            "application/smil" => {}
+            "text/plain" => {
+                if let Some(text) = part.get("text").filter(|text| !text.is_empty()) {
+                    texts.push(text.clone());
+                }
+            }
+            _ => {
+                let Some(data) = part.get("data") else {
+                    continue;
+                };
+                // Some writers wrap base64 across lines
+                let data: String = data.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+                attachments.push(AndroidAttachment {
+                    mime_type: mime_type.to_string(),
+                    filename: ["cl", "name", "fn"]
+                        .iter()
+                        .find_map(|key| part.get(*key))
+                        .cloned(),
+                    data: base64::engine::general_purpose::STANDARD.decode(data)?,
+                });
+            }
+        }
+    }
+
+    Ok(Some(AndroidMessage {
+        service: "MMS",
+        sender: if is_from_me { None } else { from },
+        participants,
+        time,
+        text: (!texts.is_empty()).then(|| texts.join("\n")),
+        contact_name: contact_name(attributes),
+        attachments,
+    }))
+}
+
+/// Resolve XML escapes in an attribute value. Numeric references are gathered
+/// as UTF-16 code units, so a surrogate pair written as two references decodes
+/// to one character.
+fn unescape(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    let mut units: Vec<u16> = Vec::new();
+    let mut rest = raw;
+
+    while let Some(amp) = rest.find('&') {
+        let (text, tail) = rest.split_at(amp);
+        if !text.is_empty() {
+            flush_utf16(&mut units, &mut out);
+            out.push_str(text);
+        }
+        let name = tail[1..]
+            .split_once(';')
+            .map(|(name, _)| name)
+            .filter(|name| {
+                !name.is_empty()
+                    && name.len() <= 10
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '#')
+            });
+        let Some(name) = name else {
+            // A bare ampersand
+            flush_utf16(&mut units, &mut out);
+            out.push('&');
+            rest = &tail[1..];
+            continue;
+        };
+        rest = &tail[name.len() + 2..];
+
+        let code = match name.strip_prefix('#') {
+            Some(number) => match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => number.parse().ok(),
+            },
+            None => None,
+        };
+        if let Some(code) = code {
+            match char::from_u32(code) {
+                Some(c) => units.extend(c.encode_utf16(&mut [0u16; 2]).iter()),
+                // A lone surrogate; its partner should be the next reference
+                None if code <= 0xFFFF => units.push(code as u16),
+                None => units.push(char::REPLACEMENT_CHARACTER as u16),
+            }
+            continue;
+        }
+
+        flush_utf16(&mut units, &mut out);
+        match name {
+            "amp" => out.push('&'),
+            "lt" => out.push('<'),
+            "gt" => out.push('>'),
+            "quot" => out.push('"'),
+            "apos" => out.push('\''),
+            _ => {
+                out.push('&');
+                out.push_str(name);
+                out.push(';');
+            }
+        }
+    }
+
+    flush_utf16(&mut units, &mut out);
+    out.push_str(rest);
+    out
+}
+
+fn flush_utf16(units: &mut Vec<u16>, out: &mut String) {
+    if !units.is_empty() {
+        out.push_str(&String::from_utf16_lossy(units));
+        units.clear();
+    }
+}
+
+/// Import an archive into the import database. Threads are keyed by their
+/// participants after matching them to chat.db handles, so re-importing a newer
+/// archive extends the same chats and skips messages already imported.
+pub fn import_android_backup<R: BufRead>(
+    source: R,
+    matcher: &mut HandleMatcher,
+    import_db: &mut ImportDb,
+    context_db: &ContextDb,
+) -> Result<ImportSummary, Box<dyn std::error::Error>> {
+    let ((), summary) = import_db.import(|writer| {
+        parse_backup(source, |message| {
+            let mut handles: Vec<String> = message
+                .participants
+                .iter()
+                .map(|address| matcher.resolve(address))
+                .collect();
+            handles.sort();
+            handles.dedup();
+
+            let chat_id = writer.chat(&NewChat {
+                origin: ORIGIN_ANDROID_IMPORT,
+                source_key: &handles.join(","),
+                display_name: None,
+                handles: &handles,
+            })?;
+            // The phone knew who this was; keep that unless we already know better
+            if let ([handle], Some(name)) = (handles.as_slice(), &message.contact_name) {
+                context_db.set_cached_contact_name(handle, name)?;
+            }
+
+            writer.message(
+                chat_id,
+                NewMessage {
+                    is_from_me: message.sender.is_none(),
+                    handle: message.sender.map(|sender| matcher.resolve(&sender)),
+                    text: message.text,
+                    time: message.time,
+                    service: message.service,
+                    attachments: message
+                        .attachments
+                        .into_iter()
+                        .map(|attachment| NewAttachment {
+                            filename: attachment.filename,
+                            mime_type: Some(attachment.mime_type),
+                            data: Some(attachment.data),
+                        })
+                        .collect(),
+                },
+            )?;
+            Ok(())
+        })
+    })?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARCHIVE: &str = r#"<?xml version='1.0' encoding='UTF-8' standalone='yes' ?>
+<smses count="4">
+  <sms protocol="0" address="(555) 000-0001" date="1700000000000" type="1" subject="null"
+       body="Dinner at 7?&#10;Bring &amp; share &#55357;&#56832;" read="1" contact_name="Ann" />
+  <sms protocol="0" address="(555) 000-0001" date="1700000060000" type="2" subject="null"
+       body="Sounds good" read="1" contact_name="Ann" />
+  <sms protocol="0" address="5550009" date="1700000090000" type="3" body="draft" />
+  <mms date="1700000100000" msg_box="1" address="+15550001~+15550002" contact_name="Ann, Bo">
+    <parts>
+      <part seq="-1" ct="application/smil" name="null" text="&lt;smil&gt;" />
+      <part seq="0" ct="text/plain" name="null" cl="text_0.txt" text="look" />
+      <part seq="1" ct="image/jpeg" name="null" cl="IMG_1.jpg" data="aGVs
+bG8=" />
+    </parts>
+    <addrs>
+      <addr address="+15550002" type="137" charset="106" />
+      <addr address="+15550009" type="151" charset="106" />
+    </addrs>
+  </mms>
+</smses>"#;
+
+    fn parse(xml: &str) -> Vec<AndroidMessage> {
+        let mut messages = Vec::new();
+        parse_backup(xml.as_bytes(), |message| {
+            messages.push(message);
+            Ok(())
+        })
+        .unwrap();
+        messages
+    }
+
+    #[test]
+    fn parses_sms_and_mms_elements() {
+        let messages = parse(ARCHIVE);
+        assert_eq!(messages.len(), 3);
+
+        assert_eq!(messages[0].sender.as_deref(), Some("(555) 000-0001"));
+        assert_eq!(
+            messages[0].text.as_deref(),
+            Some("Dinner at 7?\nBring & share 😀")
+        );
+        assert_eq!(messages[0].contact_name.as_deref(), Some("Ann"));
+        assert_eq!(messages[1].sender, None);
+
+        let mms = &messages[2];
+        assert_eq!(mms.service, "MMS");
+        assert_eq!(mms.sender.as_deref(), Some("+15550002"));
+        assert_eq!(mms.participants, vec!["+15550001", "+15550002"]);
+        assert_eq!(mms.text.as_deref(), Some("look"));
+        assert_eq!(
+            mms.attachments,
+            vec![AndroidAttachment {
+                mime_type: "image/jpeg".to_string(),
+                filename: Some("IMG_1.jpg".to_string()),
+                data: b"hello".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn imports_threads_under_chat_db_handles() {
+        let chat_db = rusqlite::Connection::open_in_memory().unwrap();
+        chat_db
+            .execute_batch(
+                "CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT);
+                 INSERT INTO handle (id) VALUES ('+15550000001');",
+            )
+            .unwrap();
+        let mut matcher = HandleMatcher::load(&chat_db).unwrap();
+        let mut import_db = ImportDb::open_in_memory().unwrap();
+        let context_db = ContextDb::open_in_memory().unwrap();
+
+        let summary = import_android_backup(
+            ARCHIVE.as_bytes(),
+            &mut matcher,
+            &mut import_db,
+            &context_db,
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                chats: 2,
+                messages: 3,
+                duplicates: 0,
+                attachments: 1
+            }
+        );
+        assert_eq!(
+            context_db
+                .get_cached_contact_name("+15550000001")
+                .unwrap()
+                .as_deref(),
+            Some("Ann")
+        );
+
+        let chats = import_db.chat_rows().unwrap();
+        let direct = chats.iter().find(|chat| chat.handles.len() == 1).unwrap();
+        assert_eq!(direct.handles, vec!["+15550000001"]);
+        assert_eq!(direct.origin, ORIGIN_ANDROID_IMPORT);
+
+        let summary = import_android_backup(
+            ARCHIVE.as_bytes(),
+            &mut matcher,
+            &mut import_db,
+            &context_db,
+        )
+        .unwrap();
+        assert_eq!(summary.duplicates, 3);
+        assert_eq!(summary.messages, 0);
+    }
+
+    #[test]
+    fn unescapes_entities_and_split_surrogates() {
+        assert_eq!(unescape("a &lt;b&gt; &#x41;&#65;"), "a <b> AA");
+        assert_eq!(unescape("&#55357;&#56832;!"), "😀!");
+        assert_eq!(unescape("fish & chips; &bogus;"), "fish & chips; &bogus;");
+    }
+}
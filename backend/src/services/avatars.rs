@@ -0,0 +1,328 @@
+use crate::config;
+use crate::context_db::ContextDb;
+use crate::models::Chat;
+use crate::services::contacts::{fetch_contact_photo, person_key};
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tracing::warn;
+
+// ============================================================================
+// AVATARS
+// ============================================================================
+//
+// Every contact and chat gets an image:
+// - a contact photo (Contacts or an imported vCard), resized once and cached
+//   as photos/resized/{handle}-{content hash}.jpg, so a changed photo gets a
+//   new file and ETag by itself
+// - otherwise a generated SVG with the contact's initials on a color picked
+//   from their name, the same on every run. Contacts is asked once; a marker
+//   remembers it had no photo (contacts.rs), so the SVG and its 304 come
+//   without running AppleScript again
+// - a group chat gets a composite of up to four participants
+// Handlers serve `Avatar::etag` and answer a matching If-None-Match with 304.
+// What Contacts said about a handle's photo is forgotten when its card
+// changes (see address_book.rs).
+// ============================================================================
+
+/// Longest side of a served photo, in pixels
+const PHOTO_SIZE: u32 = 256;
+
+const PALETTE: [&str; 8] = [
+    "#e5484d", "#f76b15", "#ffb224", "#30a46c", "#12a594", "#0090ff", "#6e56cf", "#d6409f",
+];
+
+pub struct Avatar {
+    pub bytes: Vec<u8>,
+    pub content_type: &'static str,
+    /// Quoted, ready for the ETag header
+    pub etag: String,
+}
+
+impl Avatar {
+    fn new(bytes: Vec<u8>, content_type: &'static str) -> Self {
+        let etag = format!("\"{}\"", content_hash(&bytes));
+        Avatar {
+            bytes,
+            content_type,
+            etag,
+        }
+    }
+
+    /// Whether an If-None-Match header already names this avatar
+    pub fn matches(&self, if_none_match: &str) -> bool {
+        if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == self.etag)
+    }
+}
+
+/// The contact's photo, or their initials if they have none
+pub fn contact_avatar(
+    handle: &str,
+    context_db: &ContextDb,
+) -> Result<Avatar, Box<dyn std::error::Error>> {
+    if let Some(photo) = contact_photo(handle)? {
+        return Ok(Avatar::new(photo, "image/jpeg"));
+    }
+    let name = context_db.get_cached_contact_name(handle)?;
+    let svg = svg_document(&initials_tile(
+        handle,
+        name.as_deref(),
+        0,
+        0,
+        PHOTO_SIZE,
+        PHOTO_SIZE,
+    ));
+    Ok(Avatar::new(svg.into_bytes(), "image/svg+xml"))
+}
+
+/// A 1:1 chat's contact avatar, or a group's participants side by side
+pub fn chat_avatar(
+    chat: &Chat,
+    context_db: &ContextDb,
+) -> Result<Avatar, Box<dyn std::error::Error>> {
+    if !chat.is_group {
+        if let Some(handle) = chat.handles.first() {
+            return contact_avatar(handle, context_db);
+        }
+    }
+
+    let size = PHOTO_SIZE;
+    let half = size / 2;
+    // (x, y, width, height) for 1 to 4 participants
+    let tiles: &[(u32, u32, u32, u32)] = match chat.handles.len() {
+        0 | 1 => &[(0, 0, size, size)],
+        2 => &[(0, 0, half, size), (half, 0, half, size)],
+        3 => &[
+            (0, 0, half, size),
+            (half, 0, half, half),
+            (half, half, half, half),
+        ],
+        _ => &[
+            (0, 0, half, half),
+            (half, 0, half, half),
+            (0, half, half, half),
+            (half, half, half, half),
+        ],
+    };
+
+    let mut body = String::new();
+    if chat.handles.is_empty() {
+        body.push_str(&initials_tile(
+            &chat.display_name,
+            Some(&chat.display_name),
+            0,
+            0,
+            size,
+            size,
+        ));
+    }
+    for (handle, &(x, y, width, height)) in chat.handles.iter().zip(tiles) {
+        match contact_photo(handle)? {
+            Some(photo) => body.push_str(&format!(
+                "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"xMidYMid slice\" href=\"data:image/jpeg;base64,{}\"/>",
+                x,
+                y,
+                width,
+                height,
+                base64::engine::general_purpose::STANDARD.encode(photo)
+            )),
+            None => {
+                let name = context_db.get_cached_contact_name(handle)?;
+                body.push_str(&initials_tile(handle, name.as_deref(), x, y, width, height));
+            }
+        }
+    }
+    // Hairlines between the tiles
+    if tiles.len() > 1 {
+        body.push_str(&format!(
+            "<rect x=\"{}\" y=\"0\" width=\"2\" height=\"{}\" fill=\"#fff\"/>",
+            half - 1,
+            size
+        ));
+    }
+    if tiles.len() > 2 {
+        let x = if tiles.len() == 3 { half } else { 0 };
+        body.push_str(&format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"2\" fill=\"#fff\"/>",
+            x,
+            half - 1,
+            size - x
+        ));
+    }
+    Ok(Avatar::new(
+        svg_document(&body).into_bytes(),
+        "image/svg+xml",
+    ))
+}
+
+/// The contact's photo at `PHOTO_SIZE`, resizing and caching it the first
+/// time a photo is seen
+fn contact_photo(handle: &str) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    // Without Contacts (or its permission) the contact gets initials instead
+    let source = match fetch_contact_photo(handle) {
+        Ok(Some(source)) => source,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            warn!(target: "context", handle, "Failed to fetch contact photo: {}", e);
+            return Ok(None);
+        }
+    };
+
+    let dir = resized_dir();
+    // Safe handles never contain '-', so the prefix is unique to this handle
+    let prefix = format!("{}-", handle.replace(|c: char| !c.is_alphanumeric(), "_"));
+    let path = dir.join(format!("{}{}.jpg", prefix, content_hash(&source)));
+    if let Ok(photo) = std::fs::read(&path) {
+        return Ok(Some(photo));
+    }
+
+    // Photos that can't be decoded are served as they are
+    let photo = resize_photo(&source).unwrap_or(source);
+    std::fs::create_dir_all(&dir)?;
+    for entry in std::fs::read_dir(&dir)?.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    std::fs::write(&path, &photo)?;
+    Ok(Some(photo))
+}
+
+fn resized_dir() -> PathBuf {
+    config::current().photo_cache_dir().join("resized")
+}
+
+/// A square JPEG crop no larger than `PHOTO_SIZE`
+fn resize_photo(source: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(source).ok()?;
+    let side = PHOTO_SIZE.min(image.width()).min(image.height()).max(1);
+    let resized = image
+        .resize_to_fill(side, side, FilterType::Lanczos3)
+        .to_rgb8();
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, 85)
+        .encode_image(&resized)
+        .ok()?;
+    Some(jpeg)
+}
+
+/// First 16 hex digits of the SHA-256 of `bytes`
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn svg_document(body: &str) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" viewBox=\"0 0 {0} {0}\">{1}</svg>",
+        PHOTO_SIZE, body
+    )
+}
+
+/// A colored tile with the contact's initials, or a silhouette if there are none
+fn initials_tile(
+    handle: &str,
+    name: Option<&str>,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> String {
+    // Color by name so all of a person's handles match
+    let key = name
+        .map(|name| name.to_lowercase())
+        .unwrap_or_else(|| person_key(handle));
+    let color = PALETTE[Sha256::digest(key.as_bytes())[0] as usize % PALETTE.len()];
+    let mut tile = format!(
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+        x, y, width, height, color
+    );
+    let (cx, cy, side) = (x + width / 2, y + height / 2, width.min(height));
+    match name.and_then(initials) {
+        Some(initials) => tile.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" dy=\".35em\" text-anchor=\"middle\" font-family=\"-apple-system, Helvetica, Arial, sans-serif\" font-size=\"{}\" font-weight=\"600\" fill=\"#fff\">{}</text>",
+            cx,
+            cy,
+            side * 2 / 5,
+            initials
+        )),
+        // The 20x20 person glyph the app shows for unknown contacts
+        None => tile.push_str(&format!(
+            "<path transform=\"translate({} {}) scale({})\" fill=\"#fff\" fill-rule=\"evenodd\" d=\"M10 9a3 3 0 100-6 3 3 0 000 6zm-7 9a7 7 0 1114 0H3z\"/>",
+            cx as f64 - side as f64 * 0.3,
+            cy as f64 - side as f64 * 0.3,
+            side as f64 * 0.03
+        )),
+    }
+    tile
+}
+
+/// "Sam Rivera" -> "SR"; None for names that are really a number or an email
+fn initials(name: &str) -> Option<String> {
+    if name.contains('@') || !name.chars().any(|c| c.is_alphabetic()) {
+        return None;
+    }
+    let words: Vec<&str> = name
+        .split_whitespace()
+        .filter(|word| word.chars().next().is_some_and(|c| c.is_alphabetic()))
+        .collect();
+    let initials: String = [words.first(), words.get(1).and(words.last())]
+        .into_iter()
+        .flatten()
+        .filter_map(|word| word.chars().next())
+        .flat_map(|c| c.to_uppercase())
+        .collect();
+    (!initials.is_empty()).then_some(initials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_avatars_are_deterministic() {
+        assert_eq!(initials("Sam Rivera").as_deref(), Some("SR"));
+        assert_eq!(initials("Mary Jo van Dyke").as_deref(), Some("MD"));
+        assert_eq!(initials("élodie").as_deref(), Some("É"));
+        assert_eq!(initials("+1 (555) 000-1234"), None);
+        assert_eq!(initials("sam@example.com"), None);
+
+        let tile = initials_tile("+15550001234", Some("Sam Rivera"), 0, 0, 256, 256);
+        assert_eq!(
+            tile,
+            initials_tile("sam@example.com", Some("Sam Rivera"), 0, 0, 256, 256)
+        );
+        assert!(tile.contains(">SR</text>"));
+        assert!(initials_tile("+15550001234", None, 0, 0, 256, 256).contains("<path"));
+
+        let avatar = Avatar::new(svg_document(&tile).into_bytes(), "image/svg+xml");
+        assert_eq!(
+            avatar.etag,
+            Avatar::new(svg_document(&tile).into_bytes(), "").etag
+        );
+        assert!(avatar.matches(&format!("W/\"nope\", {}", avatar.etag)));
+        assert!(!avatar.matches("\"nope\""));
+    }
+
+    #[test]
+    fn resizes_photos_to_a_square_jpeg() {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(640, 480)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let resized = image::load_from_memory(&resize_photo(&png).unwrap()).unwrap();
+        assert_eq!(
+            (resized.width(), resized.height()),
+            (PHOTO_SIZE, PHOTO_SIZE)
+        );
+        assert_eq!(resize_photo(b"not an image"), None);
+    }
+}
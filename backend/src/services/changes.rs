@@ -0,0 +1,315 @@
+use crate::chat_schema::column;
+use crate::services::messages::normalize_reaction_guid;
+use crate::services::tapbacks::tapback_kind;
+use crate::state::DbChangeEvent;
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+
+// ============================================================================
+// CHANGE TRACKING
+// ============================================================================
+//
+// The file watcher only knows that chat.db was written. To tell subscribers
+// what changed, we remember high-water marks and diff against them:
+// - `message.ROWID`: new rows are new messages, or tapbacks when
+//   `associated_message_type` is a reaction type
+// - `attachment.ROWID`: attachments added to messages we'd already seen
+// - the newest `date_edited`/`date_retracted`/`date_delivered`/`date_read`:
+//   messages changed in place
+// A write we can't attribute to any of these becomes `ChatListChanged`.
+//
+// Messages.app inserts the `message` row before its `chat_message_join` row,
+// so a poll can land between the two. Such rows are remembered and rechecked
+// on later polls rather than dropped once the high-water mark passes them.
+// ============================================================================
+
+/// Columns whose dates mark a message changed in place
+const CHANGE_DATE_COLUMNS: &[&str] = &["date_edited", "date_retracted", "date_delivered", "date_read"];
+
+/// Polls to keep rechecking a message row that has no chat yet. Rows that
+/// never get one (e.g. orphans left by a deleted chat) are then dropped.
+const UNJOINED_MESSAGE_POLLS: u32 = 10;
+
+/// Newest of the change dates on a message row
+fn change_date_sql() -> String {
+    let dates: Vec<String> = CHANGE_DATE_COLUMNS
+        .iter()
+        .map(|name| format!("COALESCE({}, 0)", column("message", "m", name)))
+        .collect();
+    format!("MAX({})", dates.join(", "))
+}
+
+/// Any change date newer than `?2`
+fn changed_since_sql() -> String {
+    let conditions: Vec<String> = CHANGE_DATE_COLUMNS
+        .iter()
+        .map(|name| format!("{} > ?2", column("message", "m", name)))
+        .collect();
+    format!("({})", conditions.join(" OR "))
+}
+
+pub struct ChangeTracker {
+    last_message_rowid: i64,
+    /// Message rows seen without a chat yet, with the polls left to recheck them
+    unjoined_messages: BTreeMap<i64, u32>,
+    last_attachment_rowid: i64,
+    /// Newest raw Apple time seen across the in-place change columns
+    last_change_date: i64,
+}
+
+impl ChangeTracker {
+    /// Start tracking from the database's current state
+    pub fn new(conn: &Connection) -> Result<Self, Box<dyn std::error::Error>> {
+        let last_message_rowid = conn.query_row(
+            "SELECT COALESCE(MAX(ROWID), 0) FROM message",
+            [],
+            |row| row.get(0),
+        )?;
+        let last_attachment_rowid = conn.query_row(
+            "SELECT COALESCE(MAX(ROWID), 0) FROM attachment",
+            [],
+            |row| row.get(0),
+        )?;
+        let last_change_date = conn.query_row(
+            &format!("SELECT COALESCE(MAX({}), 0) FROM message m", change_date_sql()),
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(ChangeTracker {
+            last_message_rowid,
+            unjoined_messages: BTreeMap::new(),
+            last_attachment_rowid,
+            last_change_date,
+        })
+    }
+
+    /// Diff the database against the last poll and describe what changed
+    pub fn poll(&mut self, conn: &Connection) -> Result<Vec<DbChangeEvent>, Box<dyn std::error::Error>> {
+        let mut new_messages: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+        let mut reactions: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+        let mut edited: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+        let previous_message_rowid = self.last_message_rowid;
+
+        // New message rows, plus earlier ones that had no chat yet
+        let unjoined = std::mem::take(&mut self.unjoined_messages);
+        let unjoined_ids: Vec<String> = unjoined.keys().map(|rowid| rowid.to_string()).collect();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT m.ROWID, cmj.chat_id, {}, {}
+             FROM message m
+             LEFT JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+             WHERE m.ROWID > ?1 OR m.ROWID IN ({})
+             ORDER BY m.ROWID ASC",
+            column("message", "m", "associated_message_type"),
+            column("message", "m", "associated_message_guid"),
+            unjoined_ids.join(", ")
+        ))?;
+        let rows = stmt.query_map(params![previous_message_rowid], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<i64>>(1)?,
+                row.get::<_, Option<i32>>(2)?.unwrap_or(0),
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?;
+        for row in rows {
+            let (rowid, chat_id, associated_message_type, associated_guid) = row?;
+            self.last_message_rowid = self.last_message_rowid.max(rowid);
+            let Some(chat_id) = chat_id else {
+                // Its chat_message_join row may not be committed yet
+                let polls_left = unjoined.get(&rowid).copied().unwrap_or(UNJOINED_MESSAGE_POLLS) - 1;
+                if polls_left > 0 {
+                    self.unjoined_messages.insert(rowid, polls_left);
+                }
+                continue;
+            };
+            match (tapback_kind(associated_message_type), associated_guid) {
+                (Some(_), Some(guid)) => {
+                    push_unique(reactions.entry(chat_id).or_default(), normalize_reaction_guid(&guid))
+                }
+                _ => push_unique(new_messages.entry(chat_id).or_default(), rowid),
+            }
+        }
+
+        // Existing messages changed in place
+        let mut stmt = conn.prepare(&format!(
+            "SELECT m.ROWID, cmj.chat_id, {}
+             FROM message m
+             JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+             WHERE m.ROWID <= ?1
+               AND {}",
+            change_date_sql(),
+            changed_since_sql()
+        ))?;
+        let rows = stmt.query_map(params![previous_message_rowid, self.last_change_date], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        let mut newest_change = self.last_change_date;
+        for row in rows {
+            let (rowid, chat_id, changed_at) = row?;
+            newest_change = newest_change.max(changed_at);
+            push_unique(edited.entry(chat_id).or_default(), rowid);
+        }
+        // New rows can carry change dates too (e.g. sent and delivered between polls)
+        let newest_new_row: i64 = conn.query_row(
+            &format!(
+                "SELECT COALESCE(MAX({}), 0) FROM message m WHERE m.ROWID > ?1",
+                change_date_sql()
+            ),
+            params![previous_message_rowid],
+            |row| row.get(0),
+        )?;
+        self.last_change_date = newest_change.max(newest_new_row);
+
+        // Attachments added to messages we'd already seen (new messages are covered above)
+        let mut stmt = conn.prepare(
+            "SELECT a.ROWID, maj.message_id, cmj.chat_id
+             FROM attachment a
+             JOIN message_attachment_join maj ON a.ROWID = maj.attachment_id
+             JOIN chat_message_join cmj ON maj.message_id = cmj.message_id
+             WHERE a.ROWID > ?1",
+        )?;
+        let rows = stmt.query_map(params![self.last_attachment_rowid], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        for row in rows {
+            let (attachment_rowid, message_rowid, chat_id) = row?;
+            self.last_attachment_rowid = self.last_attachment_rowid.max(attachment_rowid);
+            if message_rowid <= previous_message_rowid {
+                push_unique(edited.entry(chat_id).or_default(), message_rowid);
+            }
+        }
+
+        let mut events: Vec<DbChangeEvent> = Vec::new();
+        events.extend(
+            new_messages
+                .into_iter()
+                .map(|(chat_id, rowids)| DbChangeEvent::NewMessages { chat_id, rowids }),
+        );
+        events.extend(reactions.into_iter().map(|(chat_id, message_guids)| {
+            DbChangeEvent::ReactionChanged {
+                chat_id,
+                message_guids,
+            }
+        }));
+        events.extend(
+            edited
+                .into_iter()
+                .map(|(chat_id, rowids)| DbChangeEvent::MessageEdited { chat_id, rowids }),
+        );
+
+        if events.is_empty() {
+            events.push(DbChangeEvent::ChatListChanged);
+        }
+        Ok(events)
+    }
+}
+
+fn push_unique<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "
+            CREATE TABLE message (
+                ROWID INTEGER PRIMARY KEY, guid TEXT, associated_message_type INTEGER,
+                associated_message_guid TEXT, date_edited INTEGER, date_retracted INTEGER,
+                date_delivered INTEGER, date_read INTEGER
+            );
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+            CREATE TABLE attachment (ROWID INTEGER PRIMARY KEY);
+            CREATE TABLE message_attachment_join (message_id INTEGER, attachment_id INTEGER);
+
+            INSERT INTO message VALUES (1, 'A', 0, NULL, 0, 0, 0, 0);
+            INSERT INTO chat_message_join VALUES (7, 1);
+            ",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn classifies_new_rows_edits_and_attachments() {
+        let conn = chat_db();
+        let mut tracker = ChangeTracker::new(&conn).unwrap();
+
+        conn.execute_batch(
+            "
+            INSERT INTO message VALUES (2, 'B', 0, NULL, 0, 0, 0, 0);
+            INSERT INTO message VALUES (3, 'C', 2000, 'p:0/A', 0, 0, 0, 0);
+            INSERT INTO chat_message_join VALUES (7, 2), (7, 3);
+            UPDATE message SET date_read = 500 WHERE ROWID = 1;
+            ",
+        )
+        .unwrap();
+        let events = serde_json::to_value(tracker.poll(&conn).unwrap()).unwrap();
+        assert_eq!(
+            events,
+            serde_json::json!([
+                {"type": "new_messages", "chat_id": 7, "rowids": [2]},
+                {"type": "reaction_changed", "chat_id": 7, "message_guids": ["A"]},
+                {"type": "message_edited", "chat_id": 7, "rowids": [1]},
+            ])
+        );
+
+        conn.execute_batch(
+            "
+            INSERT INTO attachment VALUES (1);
+            INSERT INTO message_attachment_join VALUES (2, 1);
+            ",
+        )
+        .unwrap();
+        let events = serde_json::to_value(tracker.poll(&conn).unwrap()).unwrap();
+        assert_eq!(
+            events,
+            serde_json::json!([{"type": "message_edited", "chat_id": 7, "rowids": [2]}])
+        );
+
+        let events = serde_json::to_value(tracker.poll(&conn).unwrap()).unwrap();
+        assert_eq!(events, serde_json::json!([{"type": "chat_list_changed"}]));
+    }
+    #[test]
+    fn rechecks_messages_polled_before_their_chat_join() {
+        let conn = chat_db();
+        let mut tracker = ChangeTracker::new(&conn).unwrap();
+
+        // The message row lands first, then a later row with its join
+        conn.execute_batch(
+            "
+            INSERT INTO message VALUES (2, 'B', 0, NULL, 0, 0, 0, 0);
+            INSERT INTO message VALUES (3, 'C', 0, NULL, 0, 0, 0, 0);
+            INSERT INTO chat_message_join VALUES (7, 3);
+            ",
+        )
+        .unwrap();
+        let events = serde_json::to_value(tracker.poll(&conn).unwrap()).unwrap();
+        assert_eq!(
+            events,
+            serde_json::json!([{"type": "new_messages", "chat_id": 7, "rowids": [3]}])
+        );
+
+        conn.execute("INSERT INTO chat_message_join VALUES (7, 2)", [])
+            .unwrap();
+        let events = serde_json::to_value(tracker.poll(&conn).unwrap()).unwrap();
+        assert_eq!(
+            events,
+            serde_json::json!([{"type": "new_messages", "chat_id": 7, "rowids": [2]}])
+        );
+
+        // A row that never gets a chat is given up on after a while
+        conn.execute("INSERT INTO message VALUES (4, 'D', 0, NULL, 0, 0, 0, 0)", [])
+            .unwrap();
+        for _ in 0..UNJOINED_MESSAGE_POLLS {
+            tracker.poll(&conn).unwrap();
+        }
+        assert!(tracker.unjoined_messages.is_empty());
+    }
+}
@@ -0,0 +1,873 @@
+use crate::chat_schema::column;
+use crate::config;
+use crate::context_db::ContextDb;
+use crate::import_db::{is_imported, ImportDb};
+use crate::ios_backup;
+use crate::models::{Chat, ChatsByIdsResponse, ChatsResponse, SearchChatsResponse, UnreadChatsResponse};
+use crate::services::contacts::{
+    find_contact_handles_by_name, get_contact_name, normalize_contact_handle, person_key,
+    should_search_contacts_by_name,
+};
+use crate::services::messages::{
+    fetch_handles_map, fetch_last_messages_map, fetch_unread_map, resolve_display_name, ChatRow,
+    LastMessageEvent,
+};
+use crate::state::{ChatSummaryCache, DbChangeEvent};
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+// ============================================================================
+// CHAT SUMMARIES
+// ============================================================================
+//
+// Every chat-list endpoint needs the same per-chat data: participants, the
+// newest message as a preview, and unread counts. Working that out from
+// chat.db means a window query over each chat's messages, so we keep a
+// materialized copy in memory instead:
+// - loaded in full at startup, off the async runtime (`warm_chat_summaries`),
+//   or by the first read after the cache was invalidated
+// - refreshed per chat by the file watcher *before* it broadcasts change
+//   events, so a client refetching on an event sees the new state
+// - display names (and the names in group-event previews) are resolved on
+//   read, since contact names arrive later
+// - imported chats (negative ids, see import_db.rs) are merged in and
+//   reloaded after each import
+//
+// Merged mode lists a person's 1:1 chats (iMessage and SMS, phone and email)
+// as one entry. Chats belong to the same person when the people directory
+// (`/people`) gives their handles the same person, so merging and splitting
+// people there decides the grouping. Handles no one owns yet are matched by
+// normalized number or address. Imported chats stay separate: their messages
+// can't be paged with chat.db cursors.
+// ============================================================================
+
+/// Chats loaded per batch of `IN (...)` queries
+const LOAD_BATCH: usize = 500;
+
+#[derive(Clone)]
+struct ChatSummary {
+    chat_id: i64,
+    /// `chat.display_name`, set for named group chats
+    display_name: Option<String>,
+    chat_identifier: Option<String>,
+    handles: Vec<String>,
+    last_message_text: Option<String>,
+    /// Set when the last message is a group event, described on read
+    last_message_event: Option<LastMessageEvent>,
+    last_message_time: Option<i64>,
+    last_message_is_from_me: Option<bool>,
+    unread_count: i64,
+    last_read_message_time: Option<i64>,
+    /// `Chat::origin`
+    origin: String,
+}
+
+impl ChatSummary {
+    fn to_chat(&self, context_db: &ContextDb) -> Chat {
+        Chat {
+            id: self.chat_id,
+            display_name: resolve_display_name(&self.display_name, &self.handles, context_db),
+            last_message_text: self
+                .last_message_event
+                .as_ref()
+                .and_then(|event| event.describe(context_db))
+                .or_else(|| self.last_message_text.clone()),
+            last_message_time: self.last_message_time,
+            last_message_is_from_me: self.last_message_is_from_me,
+            is_group: self.handles.len() > 1,
+            handles: self.handles.clone(),
+            chat_identifier: self.chat_identifier.clone(),
+            unread_count: self.unread_count,
+            last_read_message_time: self.last_read_message_time,
+            origin: self.origin.clone(),
+            read_only: is_imported(self.chat_id),
+            merged_chat_ids: Vec::new(),
+            person_id: match self.handles.as_slice() {
+                [handle] => context_db.person_id(handle).ok().flatten(),
+                _ => None,
+            },
+        }
+    }
+
+    /// Keys identifying the person in a 1:1 chat: their id in the people
+    /// directory, or the normalized handle if no one owns it. `None` for chats
+    /// that are never merged.
+    fn person_keys(&self, person_ids: &HashMap<String, i64>) -> Option<Vec<String>> {
+        let [handle] = self.handles.as_slice() else {
+            return None;
+        };
+        if is_imported(self.chat_id) {
+            return None;
+        }
+        if let Some(person_id) = person_ids.get(&person_key(handle)) {
+            return Some(vec![format!("person:{}", person_id)]);
+        }
+        // Email addresses only differ in case; their digits mean nothing
+        Some(if handle.contains('@') {
+            vec![handle.trim().to_lowercase()]
+        } else {
+            normalize_contact_handle(handle)
+        })
+    }
+
+    fn matches(&self, query_lower: &str, contact_handles: &HashSet<String>) -> bool {
+        let contains = |value: &Option<String>| {
+            value
+                .as_deref()
+                .is_some_and(|v| v.to_lowercase().contains(query_lower))
+        };
+        contains(&self.display_name)
+            || contains(&self.chat_identifier)
+            || self.handles.iter().any(|handle| {
+                handle.to_lowercase().contains(query_lower) || contact_handles.contains(handle)
+            })
+    }
+}
+
+/// In-memory chat list, most recent chat first
+#[derive(Default)]
+pub struct ChatSummaries {
+    loaded: bool,
+    /// Import database whose chats are merged in; None for chat.db alone
+    imports_db: Option<PathBuf>,
+    by_id: HashMap<i64, ChatSummary>,
+    /// Chat ids, newest message first
+    order: Vec<i64>,
+}
+
+impl ChatSummaries {
+    /// Summaries of chat.db and the configured import database
+    pub fn new() -> Self {
+        Self::with_imports(config::current().imports_db())
+    }
+
+    pub fn with_imports(imports_db: PathBuf) -> Self {
+        ChatSummaries {
+            imports_db: Some(imports_db),
+            ..Self::default()
+        }
+    }
+
+    /// Drop everything; the next read reloads from chat.db
+    pub fn invalidate(&mut self) {
+        self.loaded = false;
+        self.by_id.clear();
+        self.order.clear();
+    }
+
+    fn ensure_loaded(&mut self, conn: &Connection) -> Result<(), Box<dyn std::error::Error>> {
+        if self.loaded {
+            return Ok(());
+        }
+        let started = std::time::Instant::now();
+        self.by_id.clear();
+        let chat_rows = fetch_chat_rows(conn, None)?;
+        for batch in chat_rows.chunks(LOAD_BATCH) {
+            self.load_chats(conn, batch)?;
+        }
+        // A broken import database shouldn't hide the Messages chats
+        if let Err(e) = self.load_imports() {
+            error!(target: "chat_summaries", "Failed to load imported chats: {}", e);
+        }
+        self.sort();
+        self.loaded = true;
+        info!(
+            target: "chat_summaries",
+            "Loaded {} chat summaries in {:?}",
+            self.order.len(),
+            started.elapsed()
+        );
+        Ok(())
+    }
+
+    /// Bring the summaries up to date with changes the watcher just detected
+    pub fn apply(
+        &mut self,
+        conn: &Connection,
+        events: &[DbChangeEvent],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Nothing loaded yet; the first read will see the current state
+        if !self.loaded {
+            return Ok(());
+        }
+
+        let mut chat_ids: Vec<i64> = Vec::new();
+        let mut roster_changed = false;
+        for event in events {
+            match event.chat_id() {
+                Some(chat_id) if !chat_ids.contains(&chat_id) => chat_ids.push(chat_id),
+                Some(_) => {}
+                None => roster_changed = true,
+            }
+        }
+
+        if roster_changed {
+            // Chats renamed, created or deleted: re-read the chat rows, which is cheap,
+            // and only load messages for chats we haven't seen
+            let chat_rows = fetch_chat_rows(conn, None)?;
+            let present: HashSet<i64> = chat_rows.iter().map(|(id, _, _)| *id).collect();
+            self.by_id
+                .retain(|chat_id, _| is_imported(*chat_id) || present.contains(chat_id));
+
+            let mut new_rows = Vec::new();
+            for row in chat_rows {
+                match self.by_id.get_mut(&row.0) {
+                    Some(summary) => {
+                        summary.display_name = row.1;
+                        summary.chat_identifier = row.2;
+                    }
+                    None => new_rows.push(row),
+                }
+            }
+            for batch in new_rows.chunks(LOAD_BATCH) {
+                self.load_chats(conn, batch)?;
+            }
+            // Participants can change without a message in the chat
+            let known: Vec<i64> = self
+                .by_id
+                .keys()
+                .copied()
+                .filter(|chat_id| !is_imported(*chat_id))
+                .collect();
+            for batch in known.chunks(LOAD_BATCH) {
+                let handles_map = fetch_handles_map(conn, batch)?;
+                for chat_id in batch {
+                    if let Some(summary) = self.by_id.get_mut(chat_id) {
+                        summary.handles = handles_map.get(chat_id).cloned().unwrap_or_default();
+                    }
+                }
+            }
+        }
+
+        if !chat_ids.is_empty() {
+            let chat_rows = fetch_chat_rows(conn, Some(&chat_ids))?;
+            for chat_id in &chat_ids {
+                if !chat_rows.iter().any(|(id, _, _)| id == chat_id) {
+                    self.by_id.remove(chat_id);
+                }
+            }
+            self.load_chats(conn, &chat_rows)?;
+        }
+
+        self.sort();
+        Ok(())
+    }
+
+    /// (Re)build the summaries for `chat_rows`
+    fn load_chats(
+        &mut self,
+        conn: &Connection,
+        chat_rows: &[ChatRow],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if chat_rows.is_empty() {
+            return Ok(());
+        }
+        let chat_ids: Vec<i64> = chat_rows.iter().map(|(id, _, _)| *id).collect();
+        let handles_map = fetch_handles_map(conn, &chat_ids)?;
+        let last_messages_map = fetch_last_messages_map(conn, &chat_ids)?;
+        let unread_map = fetch_unread_map(conn, &chat_ids)?;
+
+        for (chat_id, display_name, chat_identifier) in chat_rows {
+            let (last_message_text, last_message_time, last_message_is_from_me, last_message_event) =
+                last_messages_map
+                    .get(chat_id)
+                    .cloned()
+                    .unwrap_or((None, None, None, None));
+            let (unread_count, last_read_message_time) =
+                unread_map.get(chat_id).cloned().unwrap_or((0, None));
+
+            self.by_id.insert(
+                *chat_id,
+                ChatSummary {
+                    chat_id: *chat_id,
+                    display_name: display_name.clone(),
+                    chat_identifier: chat_identifier.clone(),
+                    handles: handles_map.get(chat_id).cloned().unwrap_or_default(),
+                    last_message_text,
+                    last_message_event,
+                    last_message_time,
+                    last_message_is_from_me,
+                    unread_count,
+                    last_read_message_time,
+                    origin: ios_backup::chat_origin().to_string(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Pick up chats added by an import
+    pub fn reload_imports(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Nothing loaded yet; the first read will include them
+        if !self.loaded {
+            return Ok(());
+        }
+        self.load_imports()?;
+        self.sort();
+        Ok(())
+    }
+
+    /// Replace the imported chats with the import database's contents
+    fn load_imports(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(imports_db) = &self.imports_db else {
+            return Ok(());
+        };
+        let rows = ImportDb::open_at(imports_db)?.chat_rows()?;
+        self.by_id.retain(|chat_id, _| !is_imported(*chat_id));
+        for row in rows {
+            self.by_id.insert(
+                row.chat_id,
+                ChatSummary {
+                    chat_id: row.chat_id,
+                    display_name: row.display_name,
+                    chat_identifier: match row.handles.as_slice() {
+                        [handle] => Some(handle.clone()),
+                        _ => None,
+                    },
+                    handles: row.handles,
+                    last_message_text: row.last_message_text,
+                    last_message_event: None,
+                    last_message_time: row.last_message_time,
+                    last_message_is_from_me: row.last_message_is_from_me,
+                    // Archived history is never unread
+                    unread_count: 0,
+                    last_read_message_time: None,
+                    origin: row.origin,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn sort(&mut self) {
+        let mut order: Vec<(Option<i64>, i64)> = self
+            .by_id
+            .values()
+            .map(|summary| (summary.last_message_time, summary.chat_id))
+            .collect();
+        // Newest first; chats without messages last
+        order.sort_by(|a, b| b.cmp(a));
+        self.order = order.into_iter().map(|(_, chat_id)| chat_id).collect();
+    }
+
+    fn ordered(&self) -> impl Iterator<Item = &ChatSummary> {
+        self.order.iter().filter_map(|chat_id| self.by_id.get(chat_id))
+    }
+
+    /// Chat ids grouped by person, most recent group first and each group
+    /// newest chat first. Group chats and unmatched chats are groups of one.
+    fn person_groups(&self, person_ids: &HashMap<String, i64>) -> Vec<Vec<i64>> {
+        let summaries: Vec<&ChatSummary> = self.ordered().collect();
+
+        // Union-find over positions in `order`; a root is its group's newest chat
+        let mut parent: Vec<usize> = (0..summaries.len()).collect();
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+
+        let mut first_with_key: HashMap<String, usize> = HashMap::new();
+        for (i, summary) in summaries.iter().enumerate() {
+            for key in summary.person_keys(person_ids).unwrap_or_default() {
+                match first_with_key.get(&key) {
+                    Some(&other) => {
+                        let (a, b) = (find(&mut parent, i), find(&mut parent, other));
+                        parent[a.max(b)] = a.min(b);
+                    }
+                    None => {
+                        first_with_key.insert(key, i);
+                    }
+                }
+            }
+        }
+
+        let mut groups: Vec<Vec<i64>> = Vec::new();
+        let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+        for (i, summary) in summaries.iter().enumerate() {
+            let root = find(&mut parent, i);
+            let group = *group_of_root.entry(root).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(summary.chat_id);
+        }
+        groups
+    }
+}
+
+/// `chat` rows, all of them or just `chat_ids`
+fn fetch_chat_rows(
+    conn: &Connection,
+    chat_ids: Option<&[i64]>,
+) -> Result<Vec<ChatRow>, Box<dyn std::error::Error>> {
+    let mut sql = format!(
+        "SELECT ROWID, {}, chat_identifier FROM chat",
+        column("chat", "", "display_name")
+    );
+    let mut params: Vec<&dyn rusqlite::ToSql> = Vec::new();
+    if let Some(chat_ids) = chat_ids {
+        let placeholders = chat_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        sql.push_str(&format!(" WHERE ROWID IN ({})", placeholders));
+        params.extend(chat_ids.iter().map(|id| id as &dyn rusqlite::ToSql));
+    }
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt
+        .query_map(params.as_slice(), |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Copy summaries out of the cache, loading it first if needed
+fn read_summaries<T>(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+    read: impl FnOnce(&ChatSummaries) -> T,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let mut cache = summaries
+        .lock()
+        .map_err(|_| "chat summary cache lock poisoned")?;
+    cache.ensure_loaded(conn)?;
+    Ok(read(&cache))
+}
+
+/// Load the cache ahead of the first chat-list request. A large chat.db takes
+/// a while, so call this from a blocking task.
+pub fn warm_chat_summaries(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+) -> Result<(), Box<dyn std::error::Error>> {
+    read_summaries(conn, summaries, |_| ())
+}
+
+/// Queue handles with no cached contact name for background resolution
+fn queue_missing_contacts(
+    summaries: &[ChatSummary],
+    contact_resolve_tx: &mpsc::Sender<String>,
+    context_db: &ContextDb,
+    source: &str,
+) {
+    let mut missing_handles: HashSet<&str> = HashSet::new();
+    for summary in summaries {
+        if let Some(handle) = summary.handles.first() {
+            if get_contact_name(handle, context_db).is_none() {
+                missing_handles.insert(handle);
+            }
+        }
+    }
+
+    for handle in missing_handles {
+        info!(
+            target: "context",
+            handle = handle,
+            source = source,
+            "Queuing missing handle for contact resolution"
+        );
+        let _ = contact_resolve_tx.try_send(handle.to_string());
+    }
+}
+
+pub fn fetch_chats(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+    contact_resolve_tx: &mpsc::Sender<String>,
+    context_db: &ContextDb,
+    limit: i64,
+    offset: i64,
+    merged: bool,
+) -> Result<ChatsResponse, Box<dyn std::error::Error>> {
+    if merged {
+        return fetch_merged_chats(conn, summaries, contact_resolve_tx, context_db, limit, offset);
+    }
+    let (page, total) = read_summaries(conn, summaries, |cache| {
+        let page: Vec<ChatSummary> = cache
+            .ordered()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect();
+        (page, cache.order.len() as i64)
+    })?;
+
+    queue_missing_contacts(&page, contact_resolve_tx, context_db, "fetch_chats");
+    let has_more = offset + (page.len() as i64) < total;
+
+    Ok(ChatsResponse {
+        chats: page.iter().map(|summary| summary.to_chat(context_db)).collect(),
+        total,
+        has_more,
+    })
+}
+
+/// `fetch_chats` with each person's 1:1 chats folded into one entry, which
+/// takes the id, handles and preview of the newest of them
+fn fetch_merged_chats(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+    contact_resolve_tx: &mpsc::Sender<String>,
+    context_db: &ContextDb,
+    limit: i64,
+    offset: i64,
+) -> Result<ChatsResponse, Box<dyn std::error::Error>> {
+    let person_ids = context_db.person_ids()?;
+    let (page, total) = read_summaries(conn, summaries, |cache| {
+        let groups = cache.person_groups(&person_ids);
+        let page: Vec<Vec<ChatSummary>> = groups
+            .iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .map(|group| group.iter().map(|chat_id| cache.by_id[chat_id].clone()).collect())
+            .collect();
+        (page, groups.len() as i64)
+    })?;
+
+    let newest: Vec<ChatSummary> = page.iter().map(|group| group[0].clone()).collect();
+    queue_missing_contacts(&newest, contact_resolve_tx, context_db, "fetch_chats");
+    let has_more = offset + (page.len() as i64) < total;
+
+    let chats = page
+        .iter()
+        .map(|group| {
+            let mut chat = group[0].to_chat(context_db);
+            if group.len() > 1 {
+                chat.unread_count = group.iter().map(|summary| summary.unread_count).sum();
+                chat.last_read_message_time = group
+                    .iter()
+                    .filter_map(|summary| summary.last_read_message_time)
+                    .max();
+                chat.merged_chat_ids = group.iter().map(|summary| summary.chat_id).collect();
+            }
+            chat
+        })
+        .collect();
+
+    Ok(ChatsResponse {
+        chats,
+        total,
+        has_more,
+    })
+}
+
+/// The chats merged with `chat_id` in merged mode, newest first (just
+/// `chat_id` if it isn't merged with any)
+pub fn merged_chat_ids(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+    context_db: &ContextDb,
+    chat_id: i64,
+) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+    let person_ids = context_db.person_ids()?;
+    let group = read_summaries(conn, summaries, |cache| {
+        cache
+            .person_groups(&person_ids)
+            .into_iter()
+            .find(|group| group.contains(&chat_id))
+    })?;
+    Ok(group.unwrap_or_else(|| vec![chat_id]))
+}
+
+/// Chat metadata for a specific list of chat IDs (used for pinned chats,
+/// shortcuts, and prompt context), in the order requested. Resolves display
+/// names from the context cache (no AppleScript here) and queues missing
+/// handles for background name resolution.
+pub fn fetch_chats_by_ids(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+    contact_resolve_tx: &mpsc::Sender<String>,
+    context_db: &ContextDb,
+    chat_ids: &[i64],
+) -> Result<ChatsByIdsResponse, Box<dyn std::error::Error>> {
+    if chat_ids.is_empty() {
+        return Ok(ChatsByIdsResponse { chats: vec![] });
+    }
+
+    let found = read_summaries(conn, summaries, |cache| {
+        chat_ids
+            .iter()
+            .filter_map(|chat_id| cache.by_id.get(chat_id).cloned())
+            .collect::<Vec<_>>()
+    })?;
+
+    queue_missing_contacts(&found, contact_resolve_tx, context_db, "fetch_chats_by_ids");
+
+    Ok(ChatsByIdsResponse {
+        chats: found.iter().map(|summary| summary.to_chat(context_db)).collect(),
+    })
+}
+
+/// Chats with unread incoming messages, most recent first
+pub fn fetch_unread_chats(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+    contact_resolve_tx: &mpsc::Sender<String>,
+    context_db: &ContextDb,
+) -> Result<UnreadChatsResponse, Box<dyn std::error::Error>> {
+    let unread = read_summaries(conn, summaries, |cache| {
+        cache
+            .ordered()
+            .filter(|summary| summary.unread_count > 0)
+            .cloned()
+            .collect::<Vec<_>>()
+    })?;
+
+    queue_missing_contacts(&unread, contact_resolve_tx, context_db, "fetch_unread_chats");
+    let total_unread = unread.iter().map(|summary| summary.unread_count).sum();
+
+    Ok(UnreadChatsResponse {
+        chats: unread.iter().map(|summary| summary.to_chat(context_db)).collect(),
+        total_unread,
+    })
+}
+
+/// Chats whose name, identifier or participants match `query` (participants also
+/// by contact name), most recent first
+pub fn fetch_search_chats(
+    conn: &Connection,
+    summaries: &ChatSummaryCache,
+    context_db: &ContextDb,
+    query: &str,
+    limit: i64,
+) -> Result<SearchChatsResponse, Box<dyn std::error::Error>> {
+    let query_lower = query.to_lowercase();
+    let mut contact_handles: HashSet<String> = if should_search_contacts_by_name(&query_lower) {
+        context_db
+            .search_cached_contacts_by_name(query)?
+            .into_iter()
+            .map(|(handle, _)| handle)
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    if contact_handles.is_empty() && should_search_contacts_by_name(&query_lower) {
+        contact_handles.extend(find_contact_handles_by_name(conn, context_db, query)?);
+    }
+
+    let matches = read_summaries(conn, summaries, |cache| {
+        cache
+            .ordered()
+            .filter(|summary| summary.matches(&query_lower, &contact_handles))
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect::<Vec<_>>()
+    })?;
+
+    Ok(SearchChatsResponse {
+        chats: matches.iter().map(|summary| summary.to_chat(context_db)).collect(),
+        query: query.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "
+            CREATE TABLE chat (ROWID INTEGER PRIMARY KEY, display_name TEXT, chat_identifier TEXT);
+            CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT);
+            CREATE TABLE chat_handle_join (chat_id INTEGER, handle_id INTEGER);
+            CREATE TABLE message (
+                ROWID INTEGER PRIMARY KEY, guid TEXT, text TEXT, attributedBody BLOB,
+                date INTEGER, is_from_me INTEGER, is_read INTEGER,
+                cache_has_attachments INTEGER, associated_message_type INTEGER,
+                associated_message_guid TEXT, associated_message_emoji TEXT,
+                date_edited INTEGER, date_retracted INTEGER, message_summary_info BLOB,
+                handle_id INTEGER, item_type INTEGER, group_action_type INTEGER,
+                group_title TEXT, other_handle INTEGER
+            );
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+
+            INSERT INTO chat VALUES (1, NULL, '+15550001'), (2, 'Climbing', 'chat123');
+            INSERT INTO handle VALUES (1, '+15550001'), (2, '+15550002');
+            INSERT INTO chat_handle_join VALUES (1, 1), (2, 1), (2, 2);
+            INSERT INTO message VALUES
+                (1, 'A', 'hey', NULL, 1000000000, 0, 1, 0, 0, NULL, NULL, 0, 0, NULL, 0, 0, 0, NULL, 0),
+                (2, 'B', 'send beta', NULL, 2000000000, 0, 0, 0, 0, NULL, NULL, 0, 0, NULL, 0, 0, 0, NULL, 0);
+            INSERT INTO chat_message_join VALUES (1, 1), (2, 2);
+            ",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn order(summaries: &ChatSummaries) -> Vec<i64> {
+        summaries.ordered().map(|summary| summary.chat_id).collect()
+    }
+
+    #[test]
+    fn applies_change_events_to_loaded_summaries() {
+        let conn = chat_db();
+        let mut summaries = ChatSummaries::default();
+        summaries.ensure_loaded(&conn).unwrap();
+        assert_eq!(order(&summaries), vec![2, 1]);
+        assert_eq!(summaries.by_id[&2].unread_count, 1);
+        assert_eq!(summaries.by_id[&2].handles.len(), 2);
+
+        conn.execute_batch(
+            "
+            INSERT INTO message VALUES
+                (3, 'C', 'on my way', NULL, 3000000000, 1, 1, 0, 0, NULL, NULL, 0, 0, NULL, 0, 0, 0, NULL, 0);
+            INSERT INTO chat_message_join VALUES (1, 3);
+            ",
+        )
+        .unwrap();
+        summaries
+            .apply(&conn, &[DbChangeEvent::NewMessages { chat_id: 1, rowids: vec![3] }])
+            .unwrap();
+        assert_eq!(order(&summaries), vec![1, 2]);
+        assert_eq!(summaries.by_id[&1].last_message_text.as_deref(), Some("on my way"));
+        assert_eq!(summaries.by_id[&1].last_message_is_from_me, Some(true));
+
+        conn.execute_batch(
+            "
+            UPDATE chat SET display_name = 'Crag crew' WHERE ROWID = 2;
+            INSERT INTO chat VALUES (3, NULL, '+15550003');
+            ",
+        )
+        .unwrap();
+        summaries.apply(&conn, &[DbChangeEvent::ChatListChanged]).unwrap();
+        assert_eq!(order(&summaries), vec![1, 2, 3]);
+        assert_eq!(summaries.by_id[&2].display_name.as_deref(), Some("Crag crew"));
+        assert!(summaries.by_id[&2].matches("crag", &HashSet::new()));
+        assert!(summaries.by_id[&1].matches("0001", &HashSet::new()));
+
+        // Group events are previewed the way they read in the conversation
+        conn.execute_batch(
+            "
+            INSERT INTO message VALUES
+                (4, 'D', NULL, NULL, 4000000000, 0, 1, 0, 0, NULL, NULL, 0, 0, NULL, 1, 1, 0, NULL, 2);
+            INSERT INTO chat_message_join VALUES (2, 4);
+            ",
+        )
+        .unwrap();
+        summaries
+            .apply(&conn, &[DbChangeEvent::NewMessages { chat_id: 2, rowids: vec![4] }])
+            .unwrap();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        assert_eq!(
+            summaries.by_id[&2].to_chat(&context_db).last_message_text.as_deref(),
+            Some("+15550001 added +15550002 to the group")
+        );
+    }
+
+    #[test]
+    fn groups_a_persons_chats_by_person_and_handle() {
+        let conn = chat_db();
+        conn.execute_batch(
+            "
+            INSERT INTO chat VALUES (3, NULL, '15550001'), (4, NULL, 'sam@example.com'),
+                (5, NULL, '+15550009');
+            INSERT INTO handle VALUES (3, '15550001'), (4, 'Sam@Example.com'), (5, '+15550009');
+            INSERT INTO chat_handle_join VALUES (3, 3), (4, 4), (5, 5);
+            INSERT INTO message VALUES
+                (3, 'C', 'sms', NULL, 3000000000, 0, 1, 0, 0, NULL, NULL, 0, 0, NULL, 0, 0, 0, NULL, 0),
+                (4, 'D', 'email', NULL, 4000000000, 0, 1, 0, 0, NULL, NULL, 0, 0, NULL, 0, 0, 0, NULL, 0),
+                (5, 'E', 'other', NULL, 5000000000, 0, 1, 0, 0, NULL, NULL, 0, 0, NULL, 0, 0, 0, NULL, 0);
+            INSERT INTO chat_message_join VALUES (3, 3), (4, 4), (5, 5);
+            ",
+        )
+        .unwrap();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        let mut summaries = ChatSummaries::default();
+        summaries.ensure_loaded(&conn).unwrap();
+
+        // Chat 1 and 3 share a number; the group chat stays apart
+        let groups = |context_db: &ContextDb| summaries.person_groups(&context_db.person_ids().unwrap());
+        assert_eq!(groups(&context_db), vec![vec![5], vec![4], vec![3, 1], vec![2]]);
+
+        // Two people with the same name stay apart until they are merged
+        context_db.set_cached_contact_name("Sam@Example.com", "Sam Rivera").unwrap();
+        context_db.set_cached_contact_name("+15550001", "Sam Rivera").unwrap();
+        assert_eq!(groups(&context_db), vec![vec![5], vec![4], vec![3, 1], vec![2]]);
+
+        let sam = context_db.person_id("+15550001").unwrap().unwrap();
+        let email = context_db.person_id("Sam@Example.com").unwrap().unwrap();
+        context_db.merge_people(sam, &[email]).unwrap();
+        assert_eq!(groups(&context_db), vec![vec![5], vec![4, 3, 1], vec![2]]);
+
+        // Splitting undoes it, though both keep the name
+        context_db.split_person(sam, &["Sam@Example.com".to_string()]).unwrap();
+        assert_eq!(groups(&context_db), vec![vec![5], vec![4], vec![3, 1], vec![2]]);
+    }
+
+    #[test]
+    fn serves_chat_lists_from_memory_once_warm() {
+        let conn = chat_db();
+        let cache: ChatSummaryCache = Default::default();
+        warm_chat_summaries(&conn, &cache).unwrap();
+
+        // Reads after warming never query chat.db
+        conn.execute_batch("DROP TABLE message; DROP TABLE chat_message_join; DROP TABLE chat;")
+            .unwrap();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        let (tx, _rx) = mpsc::channel(16);
+        let page = fetch_chats(&conn, &cache, &tx, &context_db, 1, 0, false).unwrap();
+        assert_eq!(page.chats[0].id, 2);
+        assert_eq!(page.chats[0].last_message_text.as_deref(), Some("send beta"));
+        assert!(page.has_more);
+        let unread = fetch_unread_chats(&conn, &cache, &tx, &context_db).unwrap();
+        assert_eq!(unread.total_unread, 1);
+        let by_ids = fetch_chats_by_ids(&conn, &cache, &tx, &context_db, &[1]).unwrap();
+        assert_eq!(by_ids.chats[0].handles, vec!["+15550001"]);
+    }
+
+    #[test]
+    fn merges_in_chats_from_the_import_database() {
+        use crate::import_db::{NewChat, NewMessage};
+
+        let path = std::env::temp_dir()
+            .join(format!("chat_summaries_imports_{}.db", std::process::id()));
+        let handles = vec!["+15550009".to_string()];
+        let (imported_id, _) = ImportDb::open_at(&path)
+            .unwrap()
+            .import(|writer| {
+                let chat_id = writer.chat(&NewChat {
+                    origin: "android-import",
+                    source_key: "+15550009",
+                    display_name: None,
+                    handles: &handles,
+                })?;
+                writer.message(
+                    chat_id,
+                    NewMessage {
+                        handle: Some("+15550009".to_string()),
+                        is_from_me: false,
+                        text: Some("from the old phone".to_string()),
+                        time: 2_000_000_000_000,
+                        service: "SMS",
+                        attachments: Vec::new(),
+                    },
+                )?;
+                Ok(-chat_id)
+            })
+            .unwrap();
+
+        let conn = chat_db();
+        let mut summaries = ChatSummaries::with_imports(path.clone());
+        summaries.ensure_loaded(&conn).unwrap();
+        assert_eq!(order(&summaries), vec![imported_id, 2, 1]);
+        let chat = summaries.by_id[&imported_id].to_chat(&ContextDb::open_in_memory().unwrap());
+        assert_eq!(chat.origin, "android-import");
+        assert!(chat.read_only);
+        assert_eq!(chat.last_message_text.as_deref(), Some("from the old phone"));
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
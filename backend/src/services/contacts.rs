@@ -1,8 +1,11 @@
+use crate::config;
 use crate::context_db::ContextDb;
+use crate::services::address_book;
+use crate::services::avatars::content_hash;
 use crate::state::DbChangeEvent;
 use rusqlite::Connection;
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use tokio::sync::{broadcast, mpsc};
 use tracing::info;
@@ -66,6 +69,62 @@ pub fn normalize_contact_handle(handle: &str) -> Vec<String> {
     variants.into_iter().collect()
 }
 
+/// One spelling per handle, for keying people: an email in lowercase, or a
+/// number's digits (the last 10, dropping any country code)
+pub fn person_key(handle: &str) -> String {
+    let trimmed = handle.trim().to_lowercase();
+    if trimmed.contains('@') {
+        return trimmed;
+    }
+    let digits: String = trimmed.chars().filter(|c| c.is_ascii_digit()).collect();
+    match digits.len() {
+        0 => trimmed,
+        len if len > 10 => digits[len - 10..].to_string(),
+        _ => digits,
+    }
+}
+
+/// Maps phone numbers and emails from other apps onto chat.db's own handle
+/// strings, so imported threads share contact names with iMessage chats
+pub struct HandleMatcher {
+    by_variant: HashMap<String, String>,
+}
+
+impl HandleMatcher {
+    pub fn load(conn: &Connection) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut by_variant = HashMap::new();
+        let mut stmt = conn.prepare("SELECT id FROM handle ORDER BY ROWID")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            let handle = row?;
+            for variant in normalize_contact_handle(&handle) {
+                by_variant.entry(variant).or_insert_with(|| handle.clone());
+            }
+        }
+        Ok(HandleMatcher { by_variant })
+    }
+
+    /// The chat.db handle for `address`. Unmatched addresses resolve to
+    /// themselves (trimmed) and are remembered, so other spellings of the same
+    /// number resolve to the first one seen.
+    pub fn resolve(&mut self, address: &str) -> String {
+        // Most specific form first: "+15550001234" before "5550001234"
+        let mut variants = normalize_contact_handle(address);
+        variants.sort_by_key(|variant| std::cmp::Reverse(variant.len()));
+        if let Some(handle) = variants
+            .iter()
+            .find_map(|variant| self.by_variant.get(variant))
+        {
+            return handle.clone();
+        }
+        let handle = address.trim().to_string();
+        for variant in variants {
+            self.by_variant.insert(variant, handle.clone());
+        }
+        handle
+    }
+}
+
 pub fn get_contact_name(handle: &str, context_db: &ContextDb) -> Option<String> {
     if let Ok(Some(cached)) = context_db.get_cached_contact_name(handle) {
         return Some(cached);
@@ -82,6 +141,10 @@ pub fn get_contact_name_from_applescript(
     if let Some(cached) = get_contact_name(handle, context_db) {
         return Some(cached);
     }
+    // The address book stores were read, so Contacts has no name for this handle
+    if address_book::loaded() {
+        return None;
+    }
 
     let script = contacts_name_lookup_script(handle);
     let lookup_start = Instant::now();
@@ -108,9 +171,6 @@ pub fn get_contact_name_from_applescript(
 
     if let Some(ref name) = result {
         let _ = context_db.set_cached_contact_name(handle, name);
-        for variant in normalize_contact_handle(handle) {
-            let _ = context_db.set_cached_contact_name(&variant, name);
-        }
     }
 
     result
@@ -143,9 +203,7 @@ pub async fn contact_resolve_worker(
             info!(target: "context", handle = handle.as_str(), "[contact_resolve_worker] Contact resolve worker resolved name");
             let now = Instant::now();
             if now.duration_since(last_emit) >= emit_interval {
-                let _ = db_change_tx.send(DbChangeEvent {
-                    timestamp: chrono::Utc::now().timestamp_millis(),
-                });
+                let _ = db_change_tx.send(DbChangeEvent::ChatListChanged);
                 last_emit = now;
                 info!(target: "context", handle = handle.as_str(), "[contact_resolve_worker] Contact resolve worker emitted db change");
             }
@@ -155,29 +213,113 @@ pub async fn contact_resolve_worker(
     }
 }
 
-pub fn fetch_contact_photo(
-    handle: &str,
-) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
-    // Create a cache directory for photos at ~/.imessage-companion/photos
-    let cache_dir = std::path::PathBuf::from(std::env::var("HOME").expect("HOME not set"))
-        .join(".imessage-companion/photos");
-    std::fs::create_dir_all(&cache_dir)?;
+/// Photo imported for a person (from a vCard). Files are named by content
+/// hash, stored on the person's row, so merged and split people share them
+/// and a reused person id can't pick up someone else's photo.
+fn person_photo_path(hash: &str) -> PathBuf {
+    config::current()
+        .photo_cache_dir()
+        .join("people")
+        .join(format!("{}.jpg", hash))
+}
+
+pub fn save_person_photo(
+    context_db: &ContextDb,
+    person_id: i64,
+    photo: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let hash = content_hash(photo);
+    let path = person_photo_path(&hash);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, photo)?;
+    context_db.set_person_photo_hash(person_id, &hash)
+}
+
+/// The person photo for `handle`, if one was imported
+fn person_photo(handle: &str) -> Option<Vec<u8>> {
+    let hash = ContextDb::open().ok()?.person_photo_hash(handle).ok()??;
+    std::fs::read(person_photo_path(&hash)).ok()
+}
 
+fn handle_photo_path(handle: &str) -> PathBuf {
     // Create a safe filename from the handle
     let safe_handle = handle.replace(|c: char| !c.is_alphanumeric(), "_");
-    let cache_path = cache_dir.join(format!("{}.jpg", safe_handle));
-
-    // Check if cached
-    if cache_path.exists() {
-        // Check if cache is less than 1 week old
-        if let Ok(metadata) = std::fs::metadata(&cache_path) {
-            if let Ok(modified) = metadata.modified() {
-                if modified.elapsed().unwrap_or_default().as_secs() < 604800 {
-                    return Ok(Some(std::fs::read(&cache_path)?));
-                }
+    config::current()
+        .photo_cache_dir()
+        .join(format!("{}.jpg", safe_handle))
+}
+
+/// Marks a handle Contacts had no photo for, so it isn't asked again
+fn no_photo_marker_path(handle: &str) -> PathBuf {
+    handle_photo_path(handle).with_extension("none")
+}
+
+/// Whether `path` exists and is less than a week old
+fn is_fresh(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().unwrap_or_default().as_secs() < 604800)
+}
+
+/// Forget what Contacts said about `handles`' photos, so the next request
+/// asks again. Returns the number of cache files removed.
+pub fn forget_contact_photos(handles: &[String]) -> std::io::Result<usize> {
+    let mut removed = 0;
+    for handle in handles {
+        for path in [handle_photo_path(handle), no_photo_marker_path(handle)] {
+            match std::fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
             }
         }
     }
+    Ok(removed)
+}
+
+/// Photo for `handle` from the imported or cached photos only, never asking Contacts
+pub fn stored_contact_photo(handle: &str) -> Option<Vec<u8>> {
+    person_photo(handle).or_else(|| std::fs::read(handle_photo_path(handle)).ok())
+}
+
+pub fn fetch_contact_photo(
+    handle: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    // An imported photo stands for all of the person's handles
+    if let Some(photo) = person_photo(handle) {
+        return Ok(Some(photo));
+    }
+
+    // Create a cache directory for photos next to context.db
+    let cache_dir = config::current().photo_cache_dir();
+    std::fs::create_dir_all(&cache_dir)?;
+
+    let cache_path = handle_photo_path(handle);
+    if is_fresh(&cache_path) {
+        return Ok(Some(std::fs::read(&cache_path)?));
+    }
+    let marker_path = no_photo_marker_path(handle);
+    if is_fresh(&marker_path) {
+        return Ok(None);
+    }
+
+    let result = fetch_contact_photo_from_contacts(handle, &cache_path);
+    // Failures are remembered too: without Contacts (or its permission) every
+    // request would otherwise run the probe again
+    if !matches!(result, Ok(Some(_))) {
+        std::fs::write(&marker_path, b"")?;
+    }
+    result
+}
+
+/// Export `handle`'s photo from Contacts as a JPEG at `cache_path`
+fn fetch_contact_photo_from_contacts(
+    handle: &str,
+    cache_path: &Path,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let safe_handle = handle.replace(|c: char| !c.is_alphanumeric(), "_");
 
     info!(target: "context", handle, "[photo] Fetching contact photo via AppleScript");
     let script = contacts_photo_probe_script(handle);
@@ -248,7 +390,7 @@ pub fn fetch_contact_photo(
 
     // Read and return the JPEG
     info!(target: "context", handle, "[photo] Resolved contact photo");
-    Ok(Some(std::fs::read(&cache_path)?))
+    Ok(Some(std::fs::read(cache_path)?))
 }
 
 // ============================================================================
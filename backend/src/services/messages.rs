@@ -1,18 +1,39 @@
+use crate::chat_schema::column;
 use crate::context_db::ContextDb;
 use crate::extraction::MessageForExtraction;
+use crate::import_db::{is_imported, ImportDb};
+use crate::ios_backup;
+use crate::message_summary::MessageSummaryInfo;
 use crate::models::{
-    Attachment, Chat, ChatsByIdsResponse, ChatsResponse, Message, MessagesResponse, Reaction, SearchChatsResponse,
+    Attachment, DateJumpResponse, Message, MessageKind, MessageReactionsResponse,
+    MessageSearchResult, MessagesDelta, MessagesResponse, MonthCount, Reaction, TextRun,
+    ThreadResponse,
 };
-use crate::services::contacts::{
-    find_contact_handles_by_name, get_contact_name, should_search_contacts_by_name,
+use crate::search_index::IndexedMessage;
+use crate::services::contacts::get_contact_name;
+use crate::services::search_query::SqlFilter;
+use crate::services::tapbacks::{
+    group_reactions, net_reactions, tapback_kind, tapback_removal_text, tapback_verb,
+    TapbackEvent,
 };
+use crate::services::timeline::{describe_event, timeline_kind};
+use crate::typedstream::decode_attributed_body;
 use rusqlite::{params, Connection};
-use tokio::sync::mpsc;
 use tracing::error;
-use tracing::info;
 
 const APPLE_EPOCH: i64 = 978307200; // Seconds between 1970-01-01 and 2001-01-01
 
+/// (chat ROWID, display_name, chat_identifier)
+pub type ChatRow = (i64, Option<String>, Option<String>);
+/// (last message preview text, time in Unix ms, is_from_me, group event it records)
+pub type LastMessageSummary = (Option<String>, Option<i64>, Option<bool>, Option<LastMessageEvent>);
+/// (file bytes, mime type)
+pub(crate) type AttachmentFile = (Vec<u8>, Option<String>);
+/// (unread incoming messages, newest read incoming message time in Unix ms)
+pub type UnreadSummary = (i64, Option<i64>);
+/// (message ROWID, replacement index entry or None to drop it)
+type IndexUpdate = (i64, Option<IndexedMessage>);
+
 struct LastMsgData {
     text: Option<String>,
     date: i64,
@@ -21,52 +42,76 @@ struct LastMsgData {
     attributed_body: Option<Vec<u8>>,
     is_from_me: bool,
     associated_message_guid: Option<String>,
+    associated_message_emoji: Option<String>,
+    date_edited: i64,
+    is_unsent: bool,
+    event: Option<LastMessageEvent>,
+}
+
+/// A group event that is a chat's last message. Kept as handles rather than
+/// text so the preview picks up contact names resolved after it was loaded.
+#[derive(Clone)]
+pub struct LastMessageEvent {
+    kind: MessageKind,
+    is_from_me: bool,
+    handle: Option<String>,
+    target_handle: Option<String>,
+    group_title: Option<String>,
+}
+
+impl LastMessageEvent {
+    /// The preview, worded as the event reads in the conversation
+    pub fn describe(&self, context_db: &ContextDb) -> Option<String> {
+        describe_event_row(
+            self.kind,
+            self.is_from_me,
+            self.handle.as_deref(),
+            self.target_handle.as_deref(),
+            self.group_title.as_deref(),
+            context_db,
+        )
+    }
+}
+
+/// Describe a timeline event row, naming the people involved by contact name
+fn describe_event_row(
+    kind: MessageKind,
+    is_from_me: bool,
+    handle: Option<&str>,
+    target_handle: Option<&str>,
+    group_title: Option<&str>,
+    context_db: &ContextDb,
+) -> Option<String> {
+    let name = |handle: &str| get_contact_name(handle, context_db).unwrap_or_else(|| handle.to_string());
+    let actor = if is_from_me {
+        None
+    } else {
+        Some(handle.map(name).unwrap_or_else(|| "Someone".to_string()))
+    };
+    let target = target_handle.map(name);
+    describe_event(kind, actor.as_deref(), target.as_deref(), group_title)
 }
 
-fn normalize_reaction_guid(guid: &str) -> String {
+pub fn normalize_reaction_guid(guid: &str) -> String {
     if let Some(pos) = guid.rfind('/') {
         guid[pos + 1..].to_string()
-    } else if guid.starts_with("bp:") {
-        guid[3..].to_string()
+    } else if let Some(stripped) = guid.strip_prefix("bp:") {
+        stripped.to_string()
     } else {
         guid.to_string()
     }
 }
 
-fn fetch_chat_rows(
-    conn: &Connection,
-    limit: i64,
-    offset: i64,
-) -> Result<Vec<(i64, Option<String>, Option<String>)>, Box<dyn std::error::Error>> {
-    let mut stmt = conn.prepare(
-        "
-        SELECT DISTINCT
-            c.ROWID as chat_id,
-            c.display_name,
-            c.chat_identifier
-        FROM chat c
-        LEFT JOIN chat_message_join cmj ON c.ROWID = cmj.chat_id
-        LEFT JOIN message m ON cmj.message_id = m.ROWID
-        GROUP BY c.ROWID, c.display_name, c.chat_identifier
-        ORDER BY MAX(m.date) DESC
-        LIMIT ?1 OFFSET ?2
-        ",
-    )?;
-
-    let chat_rows = stmt
-        .query_map(params![limit, offset], |row| {
-            Ok((
-                row.get::<_, i64>(0)?,
-                row.get::<_, Option<String>>(1)?,
-                row.get::<_, Option<String>>(2)?,
-            ))
-        })?
-        .collect::<Result<Vec<_>, _>>()?;
-
-    Ok(chat_rows)
+/// Which part of a multi-part message a reaction targets: the `N` in
+/// "p:N/GUID". "bp:GUID" reactions target the whole message, part 0.
+pub fn reaction_part(guid: &str) -> u32 {
+    guid.strip_prefix("p:")
+        .and_then(|rest| rest.split_once('/'))
+        .and_then(|(part, _)| part.parse().ok())
+        .unwrap_or(0)
 }
 
-fn fetch_handles_map(
+pub fn fetch_handles_map(
     conn: &Connection,
     chat_ids: &[i64],
 ) -> Result<std::collections::HashMap<i64, Vec<String>>, Box<dyn std::error::Error>> {
@@ -92,10 +137,7 @@ fn fetch_handles_map(
     })?;
     for row in handles_rows {
         let (chat_id, handle) = row?;
-        handles_map
-            .entry(chat_id)
-            .or_insert_with(Vec::new)
-            .push(handle);
+        handles_map.entry(chat_id).or_default().push(handle);
     }
 
     Ok(handles_map)
@@ -119,7 +161,8 @@ fn fetch_original_texts(
     let guid_placeholders: String =
         extracted_guids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
     let orig_msg_query = format!(
-        "SELECT guid, text, attributedBody FROM message WHERE guid IN ({})",
+        "SELECT guid, text, {} FROM message WHERE guid IN ({})",
+        column("message", "", "attributedBody"),
         guid_placeholders
     );
     let mut orig_stmt = conn.prepare(&orig_msg_query)?;
@@ -136,21 +179,10 @@ fn fetch_original_texts(
 
     for row in orig_rows {
         let (guid, text, attributed_body) = row?;
-        let final_text = if let Some(t) = text {
+        if let Some(t) = message_text(text, attributed_body.as_deref()) {
             if !t.trim().is_empty() {
-                Some(t)
-            } else if let Some(ref body) = attributed_body {
-                extract_text_from_attributed_body(body)
-            } else {
-                None
+                original_texts.insert(guid, t);
             }
-        } else if let Some(ref body) = attributed_body {
-            extract_text_from_attributed_body(body)
-        } else {
-            None
-        };
-        if let Some(t) = final_text {
-            original_texts.insert(guid, t);
         }
     }
 
@@ -161,59 +193,67 @@ fn format_last_message_text(
     data: &LastMsgData,
     original_texts: &std::collections::HashMap<String, String>,
 ) -> Option<String> {
-    let mut text = data.text.clone();
+    if data.is_unsent {
+        let preview = if data.is_from_me {
+            "You unsent a message"
+        } else {
+            "Unsent a message"
+        };
+        return Some(preview.to_string());
+    }
+
+    // Tapback rows carry placeholder text (and stickers carry an attachment), so
+    // describe the reaction instead
+    if let Some((kind, is_removal)) = tapback_kind(data.associated_message_type) {
+        let emoji = data.associated_message_emoji.as_deref();
+        if is_removal {
+            return Some(tapback_removal_text(kind, emoji));
+        }
+
+        let reaction_verb = tapback_verb(kind, emoji);
+        let original_text = data.associated_message_guid.as_ref().and_then(|guid| {
+            let extracted = normalize_reaction_guid(guid);
+            original_texts.get(&extracted).cloned()
+        });
+
+        return Some(match original_text {
+            Some(orig) => format!("{} \"{}\"", reaction_verb, truncate_preview(&orig, 30)),
+            None => format!("{} a message", reaction_verb),
+        });
+    }
+
+    let mut text = message_text(data.text.clone(), data.attributed_body.as_deref());
 
     if text.is_none() || text.as_ref().map(|t| t.trim().is_empty()).unwrap_or(true) {
         if data.has_attachments == 1 {
             text = Some("📎 Attachment".to_string());
-        } else if data.associated_message_type >= 2000 && data.associated_message_type <= 2005 {
-            let reaction_verb = match data.associated_message_type {
-                2000 => "loved",
-                2001 => "liked",
-                2002 => "disliked",
-                2003 => "laughed at",
-                2004 => "emphasized",
-                2005 => "questioned",
-                _ => "reacted to",
-            };
-
-            let original_text = data.associated_message_guid.as_ref().and_then(|guid| {
-                let extracted = normalize_reaction_guid(guid);
-                original_texts.get(&extracted).cloned()
-            });
-
-            text = Some(match original_text {
-                Some(orig) => {
-                    let truncated: String = if orig.chars().count() > 30 {
-                        format!("{}...", orig.chars().take(27).collect::<String>())
-                    } else {
-                        orig
-                    };
-                    format!("{} \"{}\"", reaction_verb, truncated)
-                }
-                None => format!("{} a message", reaction_verb),
-            });
-        } else if data.associated_message_type >= 3000 && data.associated_message_type <= 3005 {
-            text = Some(match data.associated_message_type {
-                3000 => "removed ❤️".to_string(),
-                3001 => "removed 👍".to_string(),
-                3002 => "removed 👎".to_string(),
-                3003 => "removed 😂".to_string(),
-                3004 => "removed ‼️".to_string(),
-                3005 => "removed ❓".to_string(),
-                _ => "removed reaction".to_string(),
-            });
-        } else if let Some(ref body) = data.attributed_body {
-            if let Some(extracted) = extract_text_from_attributed_body(body) {
-                text = Some(extracted);
-            }
         }
+    } else if data.date_edited > 0 {
+        text = text.map(|t| format!("Edited: {}", t));
     }
 
     text
 }
 
-fn resolve_display_name(
+/// Unsent messages have `date_retracted` set on recent macOS; older releases only
+/// record the retracted part in `message_summary_info` and clear the text.
+fn is_unsent_message(
+    date_retracted: i64,
+    summary_info: Option<&MessageSummaryInfo>,
+    text: Option<&str>,
+) -> bool {
+    if date_retracted > 0 {
+        return true;
+    }
+    let has_text = text.map(|t| !t.trim().is_empty()).unwrap_or(false);
+    !has_text && summary_info.is_some_and(|info| !info.retracted_parts.is_empty())
+}
+
+fn parse_summary_info(data: Option<Vec<u8>>) -> Option<MessageSummaryInfo> {
+    data.and_then(|bytes| MessageSummaryInfo::parse(&bytes).ok())
+}
+
+pub fn resolve_display_name(
     display_name: &Option<String>,
     handles: &[String],
     context_db: &ContextDb,
@@ -235,22 +275,43 @@ fn resolve_display_name(
         .join(", ")
 }
 
-fn fetch_last_messages_map(
+pub fn fetch_last_messages_map(
     conn: &Connection,
     chat_ids: &[i64],
-) -> Result<std::collections::HashMap<i64, (Option<String>, Option<i64>, Option<bool>)>, Box<dyn std::error::Error>>
-{
+) -> Result<std::collections::HashMap<i64, LastMessageSummary>, Box<dyn std::error::Error>> {
     let placeholders: String = chat_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
     let last_msg_query = format!(
-        "SELECT chat_id, text, date, cache_has_attachments, associated_message_type, attributedBody, is_from_me, associated_message_guid
+        "SELECT chat_id, text, date, cache_has_attachments, associated_message_type, attributedBody, is_from_me, associated_message_guid,
+                date_edited, date_retracted, message_summary_info, associated_message_emoji,
+                item_type, group_action_type, group_title,
+                (SELECT h.id FROM handle h WHERE h.ROWID = handle_id),
+                (SELECT h.id FROM handle h WHERE h.ROWID = other_handle)
          FROM (
-             SELECT cmj.chat_id, m.text, m.date, m.cache_has_attachments, m.associated_message_type, m.attributedBody, m.is_from_me, m.associated_message_guid,
+             SELECT cmj.chat_id, m.text, m.date, {} AS cache_has_attachments,
+                    {} AS associated_message_type, {} AS attributedBody, m.is_from_me,
+                    {} AS associated_message_guid, {} AS date_edited, {} AS date_retracted,
+                    {} AS message_summary_info, {} AS associated_message_emoji,
+                    {} AS item_type, {} AS group_action_type, {} AS group_title,
+                    {} AS handle_id, {} AS other_handle,
                     ROW_NUMBER() OVER (PARTITION BY cmj.chat_id ORDER BY m.date DESC) as rn
              FROM message m
              JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
              WHERE cmj.chat_id IN ({})
          )
          WHERE rn = 1",
+        column("message", "m", "cache_has_attachments"),
+        column("message", "m", "associated_message_type"),
+        column("message", "m", "attributedBody"),
+        column("message", "m", "associated_message_guid"),
+        column("message", "m", "date_edited"),
+        column("message", "m", "date_retracted"),
+        column("message", "m", "message_summary_info"),
+        column("message", "m", "associated_message_emoji"),
+        column("message", "m", "item_type"),
+        column("message", "m", "group_action_type"),
+        column("message", "m", "group_title"),
+        column("message", "m", "handle_id"),
+        column("message", "m", "other_handle"),
         placeholders
     );
     let mut last_msg_stmt = conn.prepare(&last_msg_query)?;
@@ -272,6 +333,26 @@ fn fetch_last_messages_map(
         let attributed_body: Option<Vec<u8>> = row.get(5).ok();
         let is_from_me: i32 = row.get(6).unwrap_or(0);
         let associated_message_guid: Option<String> = row.get(7).ok();
+        let date_edited: i64 = row.get::<_, Option<i64>>(8).ok().flatten().unwrap_or(0);
+        let date_retracted: i64 = row.get::<_, Option<i64>>(9).ok().flatten().unwrap_or(0);
+        let summary_info = parse_summary_info(row.get(10).ok().flatten());
+        let associated_message_emoji: Option<String> = row.get(11).ok().flatten();
+        let is_unsent = is_unsent_message(
+            date_retracted,
+            summary_info.as_ref(),
+            message_text(text.clone(), attributed_body.as_deref()).as_deref(),
+        );
+        let kind = timeline_kind(
+            row.get::<_, Option<i64>>(12)?.unwrap_or(0),
+            row.get::<_, Option<i64>>(13)?.unwrap_or(0),
+        );
+        let event = (kind != MessageKind::Message).then(|| LastMessageEvent {
+            kind,
+            is_from_me: is_from_me == 1,
+            handle: row.get(15).ok().flatten(),
+            target_handle: row.get(16).ok().flatten(),
+            group_title: row.get(14).ok().flatten(),
+        });
 
         Ok((
             chat_id,
@@ -283,13 +364,17 @@ fn fetch_last_messages_map(
                 attributed_body,
                 is_from_me: is_from_me == 1,
                 associated_message_guid,
+                associated_message_emoji,
+                date_edited,
+                is_unsent,
+                event,
             },
         ))
     })?;
 
     for row in last_msg_rows {
         let (chat_id, data) = row?;
-        if data.associated_message_type >= 2000 && data.associated_message_type <= 2005 {
+        if matches!(tapback_kind(data.associated_message_type), Some((_, false))) {
             if let Some(ref guid) = data.associated_message_guid {
                 reaction_guids.push(guid.clone());
             }
@@ -299,32 +384,89 @@ fn fetch_last_messages_map(
 
     let original_texts = fetch_original_texts(conn, &reaction_guids)?;
 
-    let mut last_messages_map: std::collections::HashMap<i64, (Option<String>, Option<i64>, Option<bool>)> =
+    let mut last_messages_map: std::collections::HashMap<i64, LastMessageSummary> =
         std::collections::HashMap::new();
     for (chat_id, data) in raw_last_messages {
         let text = format_last_message_text(&data, &original_texts);
         let time_ms = data.date / 1_000_000 + 978307200000;
-        last_messages_map.insert(chat_id, (text, Some(time_ms), Some(data.is_from_me)));
+        last_messages_map.insert(
+            chat_id,
+            (text, Some(time_ms), Some(data.is_from_me), data.event),
+        );
     }
 
     Ok(last_messages_map)
 }
 
+pub fn fetch_unread_map(
+    conn: &Connection,
+    chat_ids: &[i64],
+) -> Result<std::collections::HashMap<i64, UnreadSummary>, Box<dyn std::error::Error>> {
+    let placeholders: String = chat_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    let is_read = column("message", "m", "is_read");
+    let unread_query = format!(
+        "SELECT cmj.chat_id,
+                SUM(CASE WHEN {} = 0 AND m.is_from_me = 0 THEN 1 ELSE 0 END),
+                MAX(CASE WHEN {} = 1 AND m.is_from_me = 0 THEN m.date END)
+         FROM message m
+         JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+         WHERE cmj.chat_id IN ({})
+         GROUP BY cmj.chat_id",
+        is_read, is_read, placeholders
+    );
+    let mut unread_stmt = conn.prepare(&unread_query)?;
+    let params: Vec<&dyn rusqlite::ToSql> = chat_ids
+        .iter()
+        .map(|id| id as &dyn rusqlite::ToSql)
+        .collect();
+
+    let mut unread_map: std::collections::HashMap<i64, UnreadSummary> =
+        std::collections::HashMap::new();
+    let unread_rows = unread_stmt.query_map(params.as_slice(), |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Option<i64>>(1)?.unwrap_or(0),
+            optional_apple_time(row.get(2)?),
+        ))
+    })?;
+    for row in unread_rows {
+        let (chat_id, unread_count, last_read_time) = row?;
+        unread_map.insert(chat_id, (unread_count, last_read_time));
+    }
+
+    Ok(unread_map)
+}
+
 pub fn fetch_attachment_file(
     conn: &Connection,
     attachment_id: i64,
-) -> Result<Option<(Vec<u8>, Option<String>)>, Box<dyn std::error::Error>> {
+) -> Result<Option<AttachmentFile>, Box<dyn std::error::Error>> {
+    if is_imported(attachment_id) {
+        return ImportDb::open()?.fetch_attachment(attachment_id);
+    }
     let result: Result<(Option<String>, Option<String>), _> = conn.query_row(
-        "SELECT filename, mime_type FROM attachment WHERE ROWID = ?1",
+        &format!(
+            "SELECT filename, {} FROM attachment WHERE ROWID = ?1",
+            column("attachment", "", "mime_type")
+        ),
         params![attachment_id],
         |row| Ok((row.get(0)?, row.get(1)?)),
     );
 
     match result {
         Ok((Some(filename), mime_type)) => {
-            // Expand ~ to home directory
-            let home = std::env::var("HOME").unwrap_or_default();
-            let expanded_path = filename.replace("~", &home);
+            let expanded_path = match ios_backup::installed() {
+                // Backed-up files are stored under hashed names
+                Some(backup) => match backup.attachment_path(&filename)? {
+                    Some(path) => path.to_string_lossy().into_owned(),
+                    None => return Ok(None),
+                },
+                None => {
+                    // Expand ~ to home directory
+                    let home = std::env::var("HOME").unwrap_or_default();
+                    filename.replace("~", &home)
+                }
+            };
 
             if std::path::Path::new(&expanded_path).exists() {
                 // Check if this is a HEIC file that needs conversion
@@ -403,1033 +545,1053 @@ fn convert_heic_to_jpeg(heic_path: &str) -> Result<Vec<u8>, Box<dyn std::error::
     Ok(jpeg_data)
 }
 
-pub fn fetch_chats(
-    conn: &Connection,
-    contact_resolve_tx: &mpsc::Sender<String>,
-    context_db: &ContextDb,
-    limit: i64,
-    offset: i64,
-) -> Result<ChatsResponse, Box<dyn std::error::Error>> {
-    // Get total count
-    let total: i64 = conn.query_row("SELECT COUNT(*) FROM chat", [], |row| row.get(0))?;
-
-    // Step 1: Get chat IDs with basic info
-    let chat_rows = fetch_chat_rows(conn, limit, offset)?;
-
-    if chat_rows.is_empty() {
-        return Ok(ChatsResponse {
-            chats: vec![],
-            total,
-            has_more: false,
-        });
+/// Decode a message's text and attribute runs. The typedstream `attributedBody`
+/// is the source of truth (recent macOS versions often leave `text` NULL); the
+/// plain `text` column is only used when there is no body or it fails to decode.
+fn message_body(text: Option<String>, attributed_body: Option<&[u8]>) -> (Option<String>, Vec<TextRun>) {
+    if let Some(body) = attributed_body.and_then(|data| decode_attributed_body(data).ok()) {
+        return (Some(body.text), body.runs);
     }
+    (text, Vec::new())
+}
 
-    let chat_ids: Vec<i64> = chat_rows.iter().map(|(id, _, _)| *id).collect();
+fn message_text(text: Option<String>, attributed_body: Option<&[u8]>) -> Option<String> {
+    message_body(text, attributed_body).0
+}
 
-    // Step 2: Batch fetch all handles for these chats (1 query instead of N)
-    let handles_map = fetch_handles_map(conn, &chat_ids)?;
+/// `message.<name>` under the `m` alias, or its fallback on releases without it
+fn message_column(name: &str) -> String {
+    column("message", "m", name)
+}
 
-    // Step 3: Batch fetch last messages for these chats (1 query instead of N)
-    let last_messages_map = fetch_last_messages_map(conn, &chat_ids)?;
+/// Excludes tapback rows
+fn not_reaction_sql() -> String {
+    let kind = message_column("associated_message_type");
+    format!("({} = 0 OR {} IS NULL)", kind, kind)
+}
 
-    // Step 4: Build Chat objects
-    let mut chats = Vec::new();
-    let mut missing_handles: std::collections::HashSet<String> = std::collections::HashSet::new();
-    for (chat_id, display_name, chat_identifier) in chat_rows {
-        let handles = handles_map.get(&chat_id).cloned().unwrap_or_default();
-        let is_group = handles.len() > 1;
-        let (last_message_text, last_message_time, last_message_is_from_me) = last_messages_map
-            .get(&chat_id)
-            .cloned()
-            .unwrap_or((None, None, None));
+/// Excludes timeline events (renames, membership changes)
+fn not_timeline_event_sql() -> String {
+    let kind = message_column("item_type");
+    format!("({} = 0 OR {} IS NULL)", kind, kind)
+}
 
-        let display_name = resolve_display_name(&display_name, &handles, context_db);
+/// Columns read by `read_message_row`, in order. Queries selecting them join
+/// `chat_message_join` as `cmj`.
+fn message_columns() -> String {
+    format!(
+        "
+            m.ROWID,
+            m.guid,
+            m.text,
+            m.date,
+            m.is_from_me,
+            h.id as handle_id,
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            {},
+            (SELECT oh.id FROM handle oh WHERE oh.ROWID = {}) as other_handle_id,
+            {},
+            cmj.chat_id",
+        message_column("cache_has_attachments"),
+        message_column("associated_message_type"),
+        message_column("attributedBody"),
+        message_column("thread_originator_guid"),
+        message_column("thread_originator_part"),
+        message_column("date_edited"),
+        message_column("date_retracted"),
+        message_column("message_summary_info"),
+        message_column("is_delivered"),
+        message_column("is_read"),
+        message_column("date_delivered"),
+        message_column("date_read"),
+        message_column("error"),
+        message_column("item_type"),
+        message_column("group_action_type"),
+        message_column("group_title"),
+        message_column("other_handle"),
+        message_column("service"),
+    )
+}
 
-        if let Some(handle) = handles.first() {
-            if get_contact_name(handle, context_db).is_none() {
-                missing_handles.insert(handle.clone());
-            }
-        }
+const QUOTED_PREVIEW_CHARS: usize = 60;
+const SEARCH_SNIPPET_CHARS: usize = 120;
 
-        chats.push(Chat {
-            id: chat_id,
-            display_name,
-            last_message_text,
-            last_message_time,
-            last_message_is_from_me,
-            is_group,
-            handles,
-            chat_identifier,
-        });
+fn truncate_preview(text: &str, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        format!("{}...", text.chars().take(max_chars - 3).collect::<String>())
+    } else {
+        text.to_string()
     }
+}
 
-    for handle in missing_handles {
-        info!(
-            target: "context",
-            handle = handle.as_str(),
-            source = "fetch_chats",
-            "Queuing missing handle for contact resolution"
+/// Map a row selected with `message_columns()` into a `Message`. Reactions,
+/// attachments and reply previews are filled in later by `attach_message_details`.
+fn read_message_row(row: &rusqlite::Row, context_db: &ContextDb) -> rusqlite::Result<Message> {
+    let id: i64 = row.get(0)?;
+    let guid: String = row.get(1)?;
+    let has_attachments: i32 = row.get(6).unwrap_or(0);
+    let attributed_body: Option<Vec<u8>> = row.get(8).ok();
+    let (mut text, mut runs) = message_body(row.get(2)?, attributed_body.as_deref());
+
+    let date_edited: i64 = row.get::<_, Option<i64>>(11).ok().flatten().unwrap_or(0);
+    let date_retracted: i64 = row.get::<_, Option<i64>>(12).ok().flatten().unwrap_or(0);
+    let summary_info = parse_summary_info(row.get(13).ok().flatten());
+    let is_unsent = is_unsent_message(date_retracted, summary_info.as_ref(), text.as_deref());
+
+    let is_from_me = row.get::<_, i32>(4)? == 1;
+    let handle: Option<String> = row.get(5)?;
+    let contact_name = handle
+        .as_ref()
+        .and_then(|h| get_contact_name(h, context_db));
+
+    let kind = timeline_kind(
+        row.get::<_, Option<i64>>(19)?.unwrap_or(0),
+        row.get::<_, Option<i64>>(20)?.unwrap_or(0),
+    );
+    let group_title: Option<String> = row.get(21)?;
+    let target_handle: Option<String> = row.get(22)?;
+    let target_contact_name = target_handle
+        .as_ref()
+        .and_then(|h| get_contact_name(h, context_db));
+
+    if kind != MessageKind::Message {
+        // System rows have no body of their own; describe the event instead
+        text = describe_event_row(
+            kind,
+            is_from_me,
+            handle.as_deref(),
+            target_handle.as_deref(),
+            group_title.as_deref(),
+            context_db,
         );
-        let _ = contact_resolve_tx.try_send(handle);
+        runs = Vec::new();
+    } else if is_unsent {
+        // Don't surface anything the sender retracted
+        text = None;
+        runs = Vec::new();
+    } else if text.is_none() || text.as_ref().map(|t| t.trim().is_empty()).unwrap_or(true) {
+        // If no text but has attachments, show indicator
+        if has_attachments == 1 {
+            text = Some("📎 Attachment".to_string());
+        } else if attributed_body.is_some() {
+            text = Some("💬 Message".to_string());
+        }
     }
 
-    let has_more = offset + (chats.len() as i64) < total;
-
-    Ok(ChatsResponse {
-        chats,
-        total,
-        has_more,
+    Ok(Message {
+        id,
+        guid: Some(guid),
+        text,
+        time: convert_apple_time(row.get(3)?),
+        is_from_me,
+        handle,
+        contact_name,
+        reactions: Vec::new(),
+        attachments: Vec::new(),
+        runs,
+        reply_to_guid: row.get::<_, Option<String>>(9).ok().flatten(),
+        reply_to_part: row
+            .get::<_, Option<String>>(10)
+            .ok()
+            .flatten()
+            .and_then(|part| parse_thread_part(&part)),
+        reply_preview: None,
+        edited_at: (!is_unsent && date_edited > 0).then(|| convert_apple_time(date_edited)),
+        is_unsent,
+        edit_history: summary_info
+            .filter(|_| !is_unsent)
+            .map(|info| info.edits)
+            .unwrap_or_default(),
+        is_delivered: row.get::<_, Option<i32>>(14)?.unwrap_or(0) == 1,
+        is_read: row.get::<_, Option<i32>>(15)?.unwrap_or(0) == 1,
+        date_delivered: optional_apple_time(row.get(16)?),
+        date_read: optional_apple_time(row.get(17)?),
+        error: row.get::<_, Option<i64>>(18)?.filter(|code| *code != 0),
+        kind,
+        target_handle,
+        target_contact_name,
+        group_title,
+        origin: None,
+        service: row.get(23)?,
+        chat_id: row.get(24)?,
     })
 }
 
-pub fn fetch_chats_by_ids(
+/// `thread_originator_part` looks like "0:0:12" (part index, start, length)
+fn parse_thread_part(part: &str) -> Option<i64> {
+    part.split(':').next()?.trim().parse().ok()
+}
+
+/// Run a message query selecting `message_columns()` and attach per-message details
+fn load_messages(
     conn: &Connection,
-    contact_resolve_tx: &mpsc::Sender<String>,
+    sql: &str,
+    params: &[&dyn rusqlite::ToSql],
     context_db: &ContextDb,
-    chat_ids: &[i64],
-) -> Result<ChatsByIdsResponse, Box<dyn std::error::Error>> {
-    // Fetch chat metadata for a specific list of chat IDs (used for pinned chats,
-    // shortcuts, and prompt context). Loads handles and last messages in batch,
-    // resolves display names from the context cache (no AppleScript here), and
-    // queues missing handles for background name resolution.
-    if chat_ids.is_empty() {
-        return Ok(ChatsByIdsResponse { chats: vec![] });
+) -> Result<Vec<Message>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(sql)?;
+    let mut messages = stmt
+        .query_map(params, |row| read_message_row(row, context_db))?
+        .collect::<Result<Vec<_>, _>>()?;
+    attach_message_details(conn, &mut messages, context_db)?;
+    Ok(messages)
+}
+
+/// Load the net reactions on each of `guids`, with the reactor's name resolved
+fn fetch_reactions(
+    conn: &Connection,
+    guids: &[String],
+    context_db: &ContextDb,
+) -> Result<std::collections::HashMap<String, Vec<Reaction>>, Box<dyn std::error::Error>> {
+    if guids.is_empty() {
+        return Ok(std::collections::HashMap::new());
     }
 
-    // Step 1: Get chat info for the requested IDs
-    let placeholders: String = chat_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    // Query for reactions to these messages only
+    // associated_message_guid format: "p:0/GUID" or "bp:GUID"
+    // Build WHERE clause to filter by our message GUIDs
+    let guid_patterns: Vec<String> = guids.iter()
+        .flat_map(|g| vec![
+            format!("%/{}", g),      // matches "p:0/GUID"
+            format!("bp:{}", g),     // matches "bp:GUID"
+        ])
+        .collect();
+
+    let associated_guid = message_column("associated_message_guid");
+    let placeholders: Vec<String> = guid_patterns
+        .iter()
+        .map(|_| format!("{} LIKE ?", associated_guid))
+        .collect();
+    let where_clause = placeholders.join(" OR ");
+
+    // Removals (3xxx) are needed too: the net state comes from replaying
+    // every add/remove in date order
     let query = format!(
-        "SELECT ROWID as chat_id, display_name, chat_identifier FROM chat WHERE ROWID IN ({})",
-        placeholders
+        "SELECT {guid}, {kind}, m.is_from_me, m.handle_id, m.date,
+                {emoji},
+                (SELECT maj.attachment_id FROM message_attachment_join maj WHERE maj.message_id = m.ROWID LIMIT 1),
+                h.id
+         FROM message m
+         LEFT JOIN handle h ON m.handle_id = h.ROWID
+         WHERE ({kind} = 1000
+                OR {kind} BETWEEN 2000 AND 2007
+                OR {kind} BETWEEN 3000 AND 3007)
+         AND ({where_clause})
+         ORDER BY m.date ASC",
+        guid = associated_guid,
+        kind = message_column("associated_message_type"),
+        emoji = message_column("associated_message_emoji"),
+        where_clause = where_clause,
     );
-    let mut stmt = conn.prepare(&query)?;
-    let params: Vec<&dyn rusqlite::ToSql> = chat_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
-    
-    let chat_rows: Vec<(i64, Option<String>, Option<String>)> = stmt
+
+    let mut reaction_stmt = conn.prepare(&query)?;
+    let params: Vec<&dyn rusqlite::ToSql> = guid_patterns.iter().map(|s| s as &dyn rusqlite::ToSql).collect();
+
+    let events = reaction_stmt
         .query_map(params.as_slice(), |row| {
-            Ok((
-                row.get::<_, i64>(0)?,
-                row.get::<_, Option<String>>(1)?,
-                row.get::<_, Option<String>>(2)?,
-            ))
+            let assoc_guid: String = row.get(0)?;
+            let is_from_me = row.get::<_, i32>(2)? == 1;
+            let date = row.get::<_, Option<i64>>(4)?.unwrap_or(0);
+            Ok(TapbackEvent {
+                // Extract the actual guid from formats like "p:0/GUID" or "bp:GUID"
+                target_guid: normalize_reaction_guid(&assoc_guid),
+                part: reaction_part(&assoc_guid),
+                associated_message_type: row.get(1)?,
+                is_from_me,
+                handle_id: row.get::<_, Option<i64>>(3)?.unwrap_or(0),
+                handle: if is_from_me { None } else { row.get(7)? },
+                date,
+                time: convert_apple_time(date),
+                custom_emoji: row.get(5).ok().flatten(),
+                sticker_attachment_id: row.get(6)?,
+            })
         })?
         .collect::<Result<Vec<_>, _>>()?;
 
-    if chat_rows.is_empty() {
-        return Ok(ChatsByIdsResponse { chats: vec![] });
+    let mut reactions_map = net_reactions(events);
+    for reaction in reactions_map.values_mut().flatten() {
+        reaction.contact_name = reaction
+            .handle
+            .as_ref()
+            .and_then(|h| get_contact_name(h, context_db));
     }
+    Ok(reactions_map)
+}
 
-    let found_ids: Vec<i64> = chat_rows.iter().map(|(id, _, _)| *id).collect();
+/// Batch-load reactions, attachments and reply previews for a page of messages
+fn attach_message_details(
+    conn: &Connection,
+    messages: &mut [Message],
+    context_db: &ContextDb,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Collect all guids to query for reactions
+    let guids: Vec<String> = messages.iter().filter_map(|m| m.guid.clone()).collect();
 
-    // Step 2: Batch fetch all handles for these chats
-    let found_placeholders: String = found_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-    let handles_query = format!(
-        "SELECT chj.chat_id, h.id
-         FROM handle h
-         JOIN chat_handle_join chj ON h.ROWID = chj.handle_id
-         WHERE chj.chat_id IN ({})
-         ORDER BY chj.chat_id",
-        found_placeholders
-    );
-    let mut handles_stmt = conn.prepare(&handles_query)?;
-    let found_params: Vec<&dyn rusqlite::ToSql> = found_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    // Build a map from guid to the reactions still standing on it
+    let mut reactions_map = fetch_reactions(conn, &guids, context_db)?;
 
-    let mut handles_map: std::collections::HashMap<i64, Vec<String>> = std::collections::HashMap::new();
-    let handles_rows = handles_stmt.query_map(found_params.as_slice(), |row| {
-        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
-    })?;
-    for row in handles_rows {
-        let (chat_id, handle) = row?;
-        handles_map.entry(chat_id).or_insert_with(Vec::new).push(handle);
-    }
+    // Collect message IDs for attachment query
+    let message_ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
 
-    // Step 3: Batch fetch last messages for these chats
-    let last_msg_query = format!(
-        "SELECT chat_id, text, date, cache_has_attachments, associated_message_type, attributedBody, is_from_me, associated_message_guid
-         FROM (
-             SELECT cmj.chat_id, m.text, m.date, m.cache_has_attachments, m.associated_message_type, m.attributedBody, m.is_from_me, m.associated_message_guid,
-                    ROW_NUMBER() OVER (PARTITION BY cmj.chat_id ORDER BY m.date DESC) as rn
-             FROM message m
-             JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
-             WHERE cmj.chat_id IN ({})
-         )
-         WHERE rn = 1",
-        found_placeholders
-    );
-    let mut last_msg_stmt = conn.prepare(&last_msg_query)?;
+    // Build a map from message_id to attachments
+    let mut attachments_map: std::collections::HashMap<i64, Vec<Attachment>> = std::collections::HashMap::new();
 
-    // First pass: collect raw data and reaction guids
-    struct LastMsgData2 {
-        text: Option<String>,
-        date: i64,
-        has_attachments: i32,
-        associated_message_type: i32,
-        attributed_body: Option<Vec<u8>>,
-        is_from_me: bool,
-        associated_message_guid: Option<String>,
-    }
-    let mut raw_last_messages2: std::collections::HashMap<i64, LastMsgData2> = std::collections::HashMap::new();
-    let mut reaction_guids2: Vec<String> = Vec::new();
+    if !message_ids.is_empty() {
+        // Query for attachments linked to these specific messages only
+        let placeholders: String = message_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+        let query = format!(
+            "SELECT maj.message_id, a.ROWID, a.filename, {}, {}, {}
+             FROM attachment a
+             JOIN message_attachment_join maj ON a.ROWID = maj.attachment_id
+             WHERE maj.message_id IN ({})",
+            column("attachment", "a", "mime_type"),
+            column("attachment", "a", "transfer_name"),
+            column("attachment", "a", "total_bytes"),
+            placeholders
+        );
 
-    let last_msg_rows = last_msg_stmt.query_map(found_params.as_slice(), |row| {
-        let chat_id: i64 = row.get(0)?;
-        let text: Option<String> = row.get(1)?;
-        let date: i64 = row.get(2)?;
-        let has_attachments: i32 = row.get(3).unwrap_or(0);
-        let associated_message_type: i32 = row.get(4).unwrap_or(0);
-        let attributed_body: Option<Vec<u8>> = row.get(5).ok();
-        let is_from_me: i32 = row.get(6).unwrap_or(0);
-        let associated_message_guid: Option<String> = row.get(7).ok();
+        let mut attachment_stmt = conn.prepare(&query)?;
+        let params: Vec<&dyn rusqlite::ToSql> = message_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
 
-        Ok((chat_id, LastMsgData2 {
-            text,
-            date,
-            has_attachments,
-            associated_message_type,
-            attributed_body,
-            is_from_me: is_from_me == 1,
-            associated_message_guid,
-        }))
-    })?;
+        let attachments = attachment_stmt.query_map(params.as_slice(), |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, i64>(5).unwrap_or(0),
+            ))
+        })?;
 
-    for row in last_msg_rows {
-        let (chat_id, data) = row?;
-        if data.associated_message_type >= 2000 && data.associated_message_type <= 2005 {
-            if let Some(ref guid) = data.associated_message_guid {
-                reaction_guids2.push(guid.clone());
-            }
+        for attachment in attachments {
+            let (message_id, id, filename, mime_type, transfer_name, total_bytes) = attachment?;
+            attachments_map
+                .entry(message_id)
+                .or_default()
+                .push(Attachment {
+                    id,
+                    filename,
+                    mime_type,
+                    transfer_name,
+                    total_bytes,
+                });
         }
-        raw_last_messages2.insert(chat_id, data);
     }
 
-    // Fetch original message texts for reactions
-    let mut original_texts2: std::collections::HashMap<String, String> = std::collections::HashMap::new();
-    if !reaction_guids2.is_empty() {
-        let extracted_guids2: Vec<String> = reaction_guids2.iter().map(|g| {
-            if let Some(pos) = g.rfind('/') {
-                g[pos + 1..].to_string()
-            } else if g.starts_with("bp:") {
-                g[3..].to_string()
-            } else {
-                g.clone()
-            }
-        }).collect();
-
-        let guid_placeholders2: String = extracted_guids2.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-        let orig_msg_query2 = format!(
-            "SELECT guid, text, attributedBody FROM message WHERE guid IN ({})",
-            guid_placeholders2
-        );
-        let mut orig_stmt2 = conn.prepare(&orig_msg_query2)?;
-        let orig_params2: Vec<&dyn rusqlite::ToSql> = extracted_guids2.iter().map(|g| g as &dyn rusqlite::ToSql).collect();
-        let orig_rows2 = orig_stmt2.query_map(orig_params2.as_slice(), |row| {
-            let guid: String = row.get(0)?;
-            let text: Option<String> = row.get(1)?;
-            let attributed_body: Option<Vec<u8>> = row.get(2).ok();
-            Ok((guid, text, attributed_body))
-        })?;
-        for row in orig_rows2 {
-            let (guid, text, attributed_body) = row?;
-            let final_text = if let Some(t) = text {
-                if !t.trim().is_empty() {
-                    Some(t)
-                } else if let Some(ref body) = attributed_body {
-                    extract_text_from_attributed_body(body)
-                } else {
-                    None
-                }
-            } else if let Some(ref body) = attributed_body {
-                extract_text_from_attributed_body(body)
-            } else {
-                None
-            };
-            if let Some(t) = final_text {
-                original_texts2.insert(guid, t);
-            }
+    // Look up the messages that inline replies point at (1 query for the whole page)
+    let mut reply_guids: Vec<String> = messages
+        .iter()
+        .filter_map(|m| m.reply_to_guid.clone())
+        .collect();
+    reply_guids.sort();
+    reply_guids.dedup();
+    let originals = fetch_reply_originals(conn, &reply_guids)?;
+
+    // Attach reactions, attachments and reply previews to messages
+    for msg in messages.iter_mut() {
+        if let Some(reactions) = msg.guid.as_ref().and_then(|g| reactions_map.remove(g)) {
+            msg.reactions = reactions;
+        }
+        if let Some(attachments) = attachments_map.remove(&msg.id) {
+            msg.attachments = attachments;
+        }
+        if let Some(original) = msg.reply_to_guid.as_ref().and_then(|g| originals.get(g)) {
+            msg.reply_preview = Some(original.preview(msg.reply_to_part));
         }
     }
 
-    // Second pass: format messages with reaction context
-    let mut last_messages_map: std::collections::HashMap<i64, (Option<String>, Option<i64>, Option<bool>)> = std::collections::HashMap::new();
-    for (chat_id, data) in raw_last_messages2 {
-        let mut text = data.text.clone();
-
-        if text.is_none() || text.as_ref().map(|t| t.trim().is_empty()).unwrap_or(true) {
-            if data.has_attachments == 1 {
-                text = Some("📎 Attachment".to_string());
-            } else if data.associated_message_type >= 2000 && data.associated_message_type <= 2005 {
-                let reaction_verb = match data.associated_message_type {
-                    2000 => "loved",
-                    2001 => "liked",
-                    2002 => "disliked",
-                    2003 => "laughed at",
-                    2004 => "emphasized",
-                    2005 => "questioned",
-                    _ => "reacted to",
-                };
-
-                let original_text = data.associated_message_guid.as_ref().and_then(|guid| {
-                    let extracted = if let Some(pos) = guid.rfind('/') {
-                        &guid[pos + 1..]
-                    } else if guid.starts_with("bp:") {
-                        &guid[3..]
-                    } else {
-                        guid.as_str()
-                    };
-                    original_texts2.get(extracted).cloned()
-                });
+    Ok(())
+}
 
-                text = Some(match original_text {
-                    Some(orig) => {
-                        let truncated: String = if orig.chars().count() > 30 {
-                            format!("{}...", orig.chars().take(27).collect::<String>())
-                        } else {
-                            orig
-                        };
-                        format!("{} \"{}\"", reaction_verb, truncated)
-                    }
-                    None => format!("{} a message", reaction_verb),
-                });
-            } else if data.associated_message_type >= 3000 && data.associated_message_type <= 3005 {
-                text = Some(match data.associated_message_type {
-                    3000 => "removed ❤️".to_string(),
-                    3001 => "removed 👍".to_string(),
-                    3002 => "removed 👎".to_string(),
-                    3003 => "removed 😂".to_string(),
-                    3004 => "removed ‼️".to_string(),
-                    3005 => "removed ❓".to_string(),
-                    _ => "removed reaction".to_string(),
-                });
-            } else if let Some(ref body) = data.attributed_body {
-                if let Some(extracted) = extract_text_from_attributed_body(body) {
-                    text = Some(extracted);
-                }
-            }
-        }
+/// Message an inline reply points at, used to build its quoted preview
+struct ReplyOriginal {
+    text: Option<String>,
+    runs: Vec<TextRun>,
+    has_attachments: bool,
+}
 
-        let time_ms = data.date / 1_000_000 + 978307200000;
-        last_messages_map.insert(chat_id, (text, Some(time_ms), Some(data.is_from_me)));
-    }
+impl ReplyOriginal {
+    fn preview(&self, part: Option<i64>) -> String {
+        let text = self.text.as_deref().unwrap_or_default();
+
+        // Replies can target a single part of a multi-part message; quote only that part
+        let part_text = part.and_then(|part| {
+            let units: Vec<u16> = text.encode_utf16().collect();
+            let selected: Vec<u16> = self
+                .runs
+                .iter()
+                .filter(|run| run.part == Some(part))
+                .filter_map(|run| units.get(run.start..run.start + run.length))
+                .flatten()
+                .copied()
+                .collect();
+            (!selected.is_empty()).then(|| String::from_utf16_lossy(&selected))
+        });
+
+        let quoted = part_text
+            .as_deref()
+            .unwrap_or(text)
+            .replace('\u{FFFC}', "")
+            .trim()
+            .to_string();
 
-    // Step 4: Build Chat objects
-    let mut chats = Vec::new();
-    let mut missing_handles: std::collections::HashSet<String> = std::collections::HashSet::new();
-    for (chat_id, display_name, chat_identifier) in chat_rows {
-        let handles = handles_map.get(&chat_id).cloned().unwrap_or_default();
-        let is_group = handles.len() > 1;
-        let (last_message_text, last_message_time, last_message_is_from_me) = last_messages_map
-            .get(&chat_id)
-            .cloned()
-            .unwrap_or((None, None, None));
-
-        let display_name = resolve_display_name(&display_name, &handles, context_db);
-
-        if let Some(handle) = handles.first() {
-            if get_contact_name(handle, context_db).is_none() {
-                missing_handles.insert(handle.clone());
+        if quoted.is_empty() {
+            if self.has_attachments {
+                "📎 Attachment".to_string()
+            } else {
+                "💬 Message".to_string()
             }
+        } else {
+            truncate_preview(&quoted, QUOTED_PREVIEW_CHARS)
         }
-
-        chats.push(Chat {
-            id: chat_id,
-            display_name,
-            last_message_text,
-            last_message_time,
-            last_message_is_from_me,
-            is_group,
-            handles,
-            chat_identifier,
-        });
     }
-
-    for handle in missing_handles {
-        info!(
-            target: "context",
-            handle = handle.as_str(),
-            source = "fetch_chats_by_ids",
-            "Queuing missing handle for contact resolution"
-        );
-        let _ = contact_resolve_tx.try_send(handle);
-    }
-
-    Ok(ChatsByIdsResponse { chats })
 }
 
-pub fn fetch_search_chats(
+fn fetch_reply_originals(
     conn: &Connection,
-    context_db: &ContextDb,
-    query: &str,
-    limit: i64,
-) -> Result<SearchChatsResponse, Box<dyn std::error::Error>> {
-    let query_lower = query.to_lowercase();
-    let query_pattern = format!("%{}%", query_lower);
-    let mut contact_handles = if should_search_contacts_by_name(&query_lower) {
-        context_db
-            .search_cached_contacts_by_name(query)?
-            .into_iter()
-            .map(|(handle, _)| handle)
-            .collect::<Vec<_>>()
-    } else {
-        Vec::new()
-    };
-
-    if contact_handles.is_empty() && should_search_contacts_by_name(&query_lower) {
-        let contacts = find_contact_handles_by_name(&conn, context_db, query)?;
-        contact_handles.extend(contacts);
-    }
-
-    // Search chats by:
-    // 1. display_name (from chat table)
-    // 2. chat_identifier (phone/email in chat table)
-    // 3. handle.id (phone/email of participants)
-    // Order by last message time so most recent matching chats appear first
-    let mut sql = String::from(
-        "
-        SELECT DISTINCT
-            c.ROWID as chat_id,
-            c.display_name,
-            c.chat_identifier
-        FROM chat c
-        LEFT JOIN chat_handle_join chj ON c.ROWID = chj.chat_id
-        LEFT JOIN handle h ON chj.handle_id = h.ROWID
-        WHERE 
-            LOWER(COALESCE(c.display_name, '')) LIKE ?
-            OR LOWER(COALESCE(c.chat_identifier, '')) LIKE ?
-            OR LOWER(COALESCE(h.id, '')) LIKE ?
-        "
-    );
-
-    let mut params: Vec<&dyn rusqlite::ToSql> = Vec::new();
-    params.push(&query_pattern);
-    params.push(&query_pattern);
-    params.push(&query_pattern);
-
-    if !contact_handles.is_empty() {
-        let placeholders = contact_handles.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-        sql.push_str(&format!(" OR h.id IN ({})", placeholders));
-        for handle in &contact_handles {
-            params.push(handle as &dyn rusqlite::ToSql);
-        }
+    guids: &[String],
+) -> Result<std::collections::HashMap<String, ReplyOriginal>, Box<dyn std::error::Error>> {
+    let mut originals = std::collections::HashMap::new();
+    if guids.is_empty() {
+        return Ok(originals);
     }
 
-    sql.push_str(
-        "
-        GROUP BY c.ROWID
-        "
+    let placeholders: String = guids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    let query = format!(
+        "SELECT guid, text, {}, {} FROM message WHERE guid IN ({})",
+        column("message", "", "attributedBody"),
+        column("message", "", "cache_has_attachments"),
+        placeholders
     );
-
-    let mut stmt = conn.prepare(&sql)?;
-
-    let chat_rows = stmt.query_map(params.as_slice(), |row| {
-        Ok((
-            row.get::<_, i64>(0)?,
-            row.get::<_, Option<String>>(1)?,
-            row.get::<_, Option<String>>(2)?,
-        ))
+    let mut stmt = conn.prepare(&query)?;
+    let params: Vec<&dyn rusqlite::ToSql> = guids.iter().map(|g| g as &dyn rusqlite::ToSql).collect();
+    let rows = stmt.query_map(params.as_slice(), |row| {
+        let guid: String = row.get(0)?;
+        let attributed_body: Option<Vec<u8>> = row.get(2).ok();
+        let (text, runs) = message_body(row.get(1)?, attributed_body.as_deref());
+        let has_attachments = row.get::<_, i32>(3).unwrap_or(0) == 1;
+        Ok((guid, ReplyOriginal { text, runs, has_attachments }))
     })?;
 
-    let mut chat_rows_vec = Vec::new();
-    for chat_row in chat_rows {
-        chat_rows_vec.push(chat_row?);
-    }
-
-    if chat_rows_vec.is_empty() {
-        return Ok(SearchChatsResponse {
-            chats: vec![],
-            query: query.to_string(),
-        });
+    for row in rows {
+        let (guid, original) = row?;
+        originals.insert(guid, original);
     }
 
-    let chat_ids: Vec<i64> = chat_rows_vec.iter().map(|(id, _, _)| *id).collect();
-    let placeholders: String = chat_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    Ok(originals)
+}
 
-    let handles_query = format!(
-        "SELECT chj.chat_id, h.id
-         FROM handle h
-         JOIN chat_handle_join chj ON h.ROWID = chj.handle_id
-         WHERE chj.chat_id IN ({})
-         ORDER BY chj.chat_id",
-        placeholders
-    );
-    let mut handles_stmt = conn.prepare(&handles_query)?;
-    let params: Vec<&dyn rusqlite::ToSql> = chat_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+/// Position of a message in a chat's timeline: (`message.date`, `ROWID`).
+/// Keyset pages stay stable when new messages arrive, unlike OFFSET.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageCursor {
+    pub date: i64,
+    pub rowid: i64,
+}
 
-    let mut handles_map: std::collections::HashMap<i64, Vec<String>> = std::collections::HashMap::new();
-    let handles_rows = handles_stmt.query_map(params.as_slice(), |row| {
-        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
-    })?;
-    for row in handles_rows {
-        let (chat_id, handle) = row?;
-        handles_map.entry(chat_id).or_insert_with(Vec::new).push(handle);
+impl MessageCursor {
+    /// Parse a cursor from `encode`, e.g. "726433318000000000-48213"
+    pub fn parse(value: &str) -> Option<Self> {
+        let (date, rowid) = value.rsplit_once('-')?;
+        Some(MessageCursor {
+            date: date.parse().ok()?,
+            rowid: rowid.parse().ok()?,
+        })
     }
 
-    let last_msg_query = format!(
-        "SELECT chat_id, text, date, cache_has_attachments, associated_message_type, attributedBody, is_from_me, associated_message_guid
-         FROM (
-             SELECT cmj.chat_id, m.text, m.date, m.cache_has_attachments, m.associated_message_type, m.attributedBody, m.is_from_me, m.associated_message_guid,
-                    ROW_NUMBER() OVER (PARTITION BY cmj.chat_id ORDER BY m.date DESC) as rn
-             FROM message m
-             JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
-             WHERE cmj.chat_id IN ({})
-         )
-         WHERE rn = 1",
-        placeholders
-    );
+    pub fn encode(&self) -> String {
+        format!("{}-{}", self.date, self.rowid)
+    }
+}
 
-    let mut last_msg_stmt = conn.prepare(&last_msg_query)?;
+pub enum PageDirection {
+    /// Older than the cursor
+    Before(MessageCursor),
+    /// Newer than the cursor
+    After(MessageCursor),
+}
 
-    // First pass: collect raw data and reaction guids
-    struct LastMsgData3 {
-        text: Option<String>,
-        date: i64,
-        has_attachments: i32,
-        associated_message_type: i32,
-        attributed_body: Option<Vec<u8>>,
-        is_from_me: bool,
-        associated_message_guid: Option<String>,
-    }
-    let mut raw_last_messages3: std::collections::HashMap<i64, LastMsgData3> = std::collections::HashMap::new();
-    let mut reaction_guids3: Vec<String> = Vec::new();
+/// FROM/WHERE for a chat's non-reaction messages; `?1` is the chat id
+fn chat_message_filter() -> String {
+    format!(
+        "
+        FROM message m
+        JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+        LEFT JOIN handle h ON m.handle_id = h.ROWID
+        WHERE cmj.chat_id = ?1
+          AND {}",
+        not_reaction_sql()
+    )
+}
 
-    let last_msg_rows = last_msg_stmt.query_map(params.as_slice(), |row| {
-        let chat_id: i64 = row.get(0)?;
-        let text: Option<String> = row.get(1)?;
-        let date: i64 = row.get(2)?;
-        let has_attachments: i32 = row.get(3).unwrap_or(0);
-        let associated_message_type: i32 = row.get(4).unwrap_or(0);
-        let attributed_body: Option<Vec<u8>> = row.get(5).ok();
-        let is_from_me: i32 = row.get(6).unwrap_or(0);
-        let associated_message_guid: Option<String> = row.get(7).ok();
+/// Like `chat_message_filter`, for one or more chats (a merged timeline). Chat
+/// ids are integers, so they are inlined rather than bound.
+fn chats_message_filter(chat_ids: &[i64]) -> String {
+    format!(
+        "
+        FROM message m
+        JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+        LEFT JOIN handle h ON m.handle_id = h.ROWID
+        WHERE cmj.chat_id IN ({})
+          AND {}",
+        id_list(chat_ids),
+        not_reaction_sql()
+    )
+}
 
-        Ok((chat_id, LastMsgData3 {
-            text,
-            date,
-            has_attachments,
-            associated_message_type,
-            attributed_body,
-            is_from_me: is_from_me == 1,
-            associated_message_guid,
-        }))
-    })?;
+/// "1,2,3" for an `IN (...)` list
+fn id_list(chat_ids: &[i64]) -> String {
+    chat_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+}
 
-    for row in last_msg_rows {
-        let (chat_id, data) = row?;
-        if data.associated_message_type >= 2000 && data.associated_message_type <= 2005 {
-            if let Some(ref guid) = data.associated_message_guid {
-                reaction_guids3.push(guid.clone());
-            }
-        }
-        raw_last_messages3.insert(chat_id, data);
-    }
+fn count_chat_messages(conn: &Connection, chat_ids: &[i64]) -> Result<i64, Box<dyn std::error::Error>> {
+    // Total count of non-reaction messages for these chats
+    Ok(conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM chat_message_join cmj
+             JOIN message m ON cmj.message_id = m.ROWID
+             WHERE cmj.chat_id IN ({}) AND {}",
+            id_list(chat_ids),
+            not_reaction_sql()
+        ),
+        [],
+        |row| row.get(0)
+    )?)
+}
 
-    // Fetch original message texts for reactions
-    let mut original_texts3: std::collections::HashMap<String, String> = std::collections::HashMap::new();
-    if !reaction_guids3.is_empty() {
-        let extracted_guids3: Vec<String> = reaction_guids3.iter().map(|g| {
-            if let Some(pos) = g.rfind('/') {
-                g[pos + 1..].to_string()
-            } else if g.starts_with("bp:") {
-                g[3..].to_string()
-            } else {
-                g.clone()
-            }
-        }).collect();
+/// Cursor for a message in `chat_id`, by guid
+fn find_message_cursor(
+    conn: &Connection,
+    chat_id: i64,
+    guid: &str,
+) -> Result<Option<MessageCursor>, Box<dyn std::error::Error>> {
+    let result = conn.query_row(
+        "SELECT m.date, m.ROWID
+         FROM message m
+         JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+         WHERE cmj.chat_id = ?1 AND m.guid = ?2",
+        params![chat_id, guid],
+        |row| {
+            Ok(MessageCursor {
+                date: row.get(0)?,
+                rowid: row.get(1)?,
+            })
+        },
+    );
 
-        let guid_placeholders3: String = extracted_guids3.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-        let orig_msg_query3 = format!(
-            "SELECT guid, text, attributedBody FROM message WHERE guid IN ({})",
-            guid_placeholders3
-        );
-        let mut orig_stmt3 = conn.prepare(&orig_msg_query3)?;
-        let orig_params3: Vec<&dyn rusqlite::ToSql> = extracted_guids3.iter().map(|g| g as &dyn rusqlite::ToSql).collect();
-        let orig_rows3 = orig_stmt3.query_map(orig_params3.as_slice(), |row| {
-            let guid: String = row.get(0)?;
-            let text: Option<String> = row.get(1)?;
-            let attributed_body: Option<Vec<u8>> = row.get(2).ok();
-            Ok((guid, text, attributed_body))
-        })?;
-        for row in orig_rows3 {
-            let (guid, text, attributed_body) = row?;
-            let final_text = if let Some(t) = text {
-                if !t.trim().is_empty() {
-                    Some(t)
-                } else if let Some(ref body) = attributed_body {
-                    extract_text_from_attributed_body(body)
-                } else {
-                    None
-                }
-            } else if let Some(ref body) = attributed_body {
-                extract_text_from_attributed_body(body)
-            } else {
-                None
-            };
-            if let Some(t) = final_text {
-                original_texts3.insert(guid, t);
-            }
-        }
+    match result {
+        Ok(cursor) => Ok(Some(cursor)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(Box::new(e)),
     }
+}
 
-    // Second pass: format messages with reaction context
-    let mut last_messages_map: std::collections::HashMap<i64, (Option<String>, Option<i64>, Option<bool>)> = std::collections::HashMap::new();
-    for (chat_id, data) in raw_last_messages3 {
-        let mut text = data.text.clone();
-
-        if text.is_none() || text.as_ref().map(|t| t.trim().is_empty()).unwrap_or(true) {
-            if data.has_attachments == 1 {
-                text = Some("📎 Attachment".to_string());
-            } else if data.associated_message_type >= 2000 && data.associated_message_type <= 2005 {
-                let reaction_verb = match data.associated_message_type {
-                    2000 => "loved",
-                    2001 => "liked",
-                    2002 => "disliked",
-                    2003 => "laughed at",
-                    2004 => "emphasized",
-                    2005 => "questioned",
-                    _ => "reacted to",
-                };
-
-                let original_text = data.associated_message_guid.as_ref().and_then(|guid| {
-                    let extracted = if let Some(pos) = guid.rfind('/') {
-                        &guid[pos + 1..]
-                    } else if guid.starts_with("bp:") {
-                        &guid[3..]
-                    } else {
-                        guid.as_str()
-                    };
-                    original_texts3.get(extracted).cloned()
-                });
+/// Cursors of the oldest and newest message on a page (oldest first).
+/// `Message.time` is truncated to seconds, so the raw date is re-read by ROWID.
+fn page_cursors(
+    conn: &Connection,
+    messages: &[Message],
+) -> Result<(Option<String>, Option<String>), Box<dyn std::error::Error>> {
+    let (Some(oldest), Some(newest)) = (messages.first(), messages.last()) else {
+        return Ok((None, None));
+    };
 
-                text = Some(match original_text {
-                    Some(orig) => {
-                        let truncated: String = if orig.chars().count() > 30 {
-                            format!("{}...", orig.chars().take(27).collect::<String>())
-                        } else {
-                            orig
-                        };
-                        format!("{} \"{}\"", reaction_verb, truncated)
-                    }
-                    None => format!("{} a message", reaction_verb),
-                });
-            } else if data.associated_message_type >= 3000 && data.associated_message_type <= 3005 {
-                text = Some(match data.associated_message_type {
-                    3000 => "removed ❤️".to_string(),
-                    3001 => "removed 👍".to_string(),
-                    3002 => "removed 👎".to_string(),
-                    3003 => "removed 😂".to_string(),
-                    3004 => "removed ‼️".to_string(),
-                    3005 => "removed ❓".to_string(),
-                    _ => "removed reaction".to_string(),
-                });
-            } else if let Some(ref body) = data.attributed_body {
-                if let Some(extracted) = extract_text_from_attributed_body(body) {
-                    text = Some(extracted);
-                }
-            }
-        }
+    let mut stmt = conn.prepare("SELECT date FROM message WHERE ROWID = ?1")?;
+    let mut cursor_for = |rowid: i64| -> rusqlite::Result<String> {
+        let date: i64 = stmt.query_row(params![rowid], |row| row.get(0))?;
+        Ok(MessageCursor { date, rowid }.encode())
+    };
+    Ok((Some(cursor_for(oldest.id)?), Some(cursor_for(newest.id)?)))
+}
 
-        let time_ms = data.date / 1_000_000 + 978307200000;
-        last_messages_map.insert(chat_id, (text, Some(time_ms), Some(data.is_from_me)));
-    }
+/// Up to `limit` messages on one side of `cursor`, oldest first, and whether more
+/// exist beyond them
+fn load_messages_beside(
+    conn: &Connection,
+    chat_ids: &[i64],
+    context_db: &ContextDb,
+    limit: i64,
+    direction: &PageDirection,
+) -> Result<(Vec<Message>, bool), Box<dyn std::error::Error>> {
+    let (cursor, condition, order) = match direction {
+        PageDirection::Before(cursor) => (
+            cursor,
+            "(m.date < ?1 OR (m.date = ?1 AND m.ROWID < ?2))",
+            "DESC",
+        ),
+        PageDirection::After(cursor) => (
+            cursor,
+            "(m.date > ?1 OR (m.date = ?1 AND m.ROWID > ?2))",
+            "ASC",
+        ),
+    };
 
-    let mut chats = Vec::new();
-    for (chat_id, display_name, chat_identifier) in chat_rows_vec {
-        let handles = handles_map.get(&chat_id).cloned().unwrap_or_default();
-        let is_group = handles.len() > 1;
-        let (last_message_text, last_message_time, last_message_is_from_me) = last_messages_map
-            .get(&chat_id)
-            .cloned()
-            .unwrap_or((None, None, None));
-
-        let display_name = resolve_display_name(&display_name, &handles, context_db);
-
-        chats.push(Chat {
-            id: chat_id,
-            display_name,
-            last_message_text,
-            last_message_time,
-            last_message_is_from_me,
-            is_group,
-            handles,
-            chat_identifier,
-        });
-    }
+    // One extra row tells us whether there is another page
+    let sql = format!(
+        "
+        SELECT {}
+        {}
+          AND {}
+        ORDER BY m.date {}, m.ROWID {}
+        LIMIT ?3
+        ",
+        message_columns(), chats_message_filter(chat_ids), condition, order, order
+    );
+    let mut messages = load_messages(
+        conn,
+        &sql,
+        params![cursor.date, cursor.rowid, limit + 1],
+        context_db,
+    )?;
 
-    chats.sort_by(|a, b| b.last_message_time.cmp(&a.last_message_time));
-    if chats.len() > limit as usize {
-        chats.truncate(limit as usize);
+    let has_more = messages.len() as i64 > limit;
+    messages.truncate(limit.max(0) as usize);
+    if let PageDirection::Before(_) = direction {
+        messages.reverse();
     }
-
-    Ok(SearchChatsResponse {
-        chats,
-        query: query.to_string(),
-    })
+    Ok((messages, has_more))
 }
 
-fn extract_text_from_attributed_body(data: &[u8]) -> Option<String> {
-    // AttributedBody is a typedstream/NSKeyedArchiver binary format.
-    // Structure after "NSString": [markers] 2B [length] [UTF-8 text]
-    // Where 2B is '+' and length is typically 1 byte (or 2 bytes for longer messages)
-
-    // Strategy 1: Find "NSString" marker, then look for the text after the '+' and length byte
-    let nsstring_marker = b"NSString";
-
-    if let Some(nsstring_pos) = find_subsequence(data, nsstring_marker) {
-        // Look for the '+' (0x2B) marker after NSString
-        // It's typically within 10 bytes after "NSString"
-        let search_start = nsstring_pos + nsstring_marker.len();
-        let search_end = (search_start + 20).min(data.len());
-
-        for i in search_start..search_end {
-            if data[i] == 0x2B && i + 2 < data.len() {
-                // Found '+', next byte indicates length encoding
-                let length_byte = data[i + 1];
-
-                // Handle variable-length encoding:
-                // - If < 0x80: single-byte length
-                // - If 0x81: 2-byte length follows (little-endian)
-                let (text_start, text_len) = if length_byte < 0x80 {
-                    // Simple single-byte length
-                    (i + 2, length_byte as usize)
-                } else if length_byte == 0x81 && i + 4 < data.len() {
-                    // 2-byte length (little-endian)
-                    let len = (data[i + 2] as usize) | ((data[i + 3] as usize) << 8);
-                    (i + 4, len)
-                } else if length_byte == 0x82 && i + 5 < data.len() {
-                    // 3-byte length (little-endian) - for very long messages
-                    let len = (data[i + 2] as usize) | ((data[i + 3] as usize) << 8) | ((data[i + 4] as usize) << 16);
-                    (i + 5, len)
-                } else {
-                    // Unknown encoding, skip
-                    continue;
-                };
-
-                if text_start + text_len <= data.len() {
-                    if let Ok(text) = std::str::from_utf8(&data[text_start..text_start + text_len]) {
-                        let trimmed = text.trim();
-                        // We found NSString marker with proper length encoding - trust the content
-                        // even if it's a single character (like "k" or "a")
-                        if !trimmed.is_empty() {
-                            return Some(trimmed.to_string());
-                        }
-                    }
-                }
-            }
-        }
+pub fn fetch_messages(
+    conn: &Connection,
+    chat_id: i64,
+    context_db: &ContextDb,
+    limit: i64,
+    offset: i64,
+) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+    if is_imported(chat_id) {
+        return ImportDb::open()?.fetch_messages(chat_id, context_db, limit, offset);
     }
+    load_messages_at_offset(conn, &[chat_id], context_db, limit, offset)
+}
 
-    // Strategy 2: Fallback - scan for the longest clean UTF-8 string
-    // that doesn't contain plist keywords
-    let plist_keywords = [
-        "NSString", "NSDictionary", "NSAttributedString", "NSNumber", "NSValue",
-        "NSObject", "NSArray", "streamtyped", "__kIM", "MessagePart", "AttributeName",
-    ];
-
-    let mut best_text = String::new();
-    let mut i = 0;
-
-    while i < data.len() {
-        // Try to find a valid UTF-8 sequence starting here
-        let mut best_end = i;
+/// `fetch_messages` over several chats' messages interleaved by date; each
+/// message's `chat_id` tells them apart. Only chat.db chats can be merged.
+pub fn fetch_merged_messages(
+    conn: &Connection,
+    chat_ids: &[i64],
+    context_db: &ContextDb,
+    limit: i64,
+    offset: i64,
+) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+    load_messages_at_offset(conn, chat_ids, context_db, limit, offset)
+}
 
-        for end in i + 1..=data.len().min(i + 2000) {
-            if std::str::from_utf8(&data[i..end]).is_ok() {
-                best_end = end;
-            } else {
-                break;
-            }
-        }
+/// The page `offset` messages back from the newest, oldest first
+fn load_messages_at_offset(
+    conn: &Connection,
+    chat_ids: &[i64],
+    context_db: &ContextDb,
+    limit: i64,
+    offset: i64,
+) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+    let total = count_chat_messages(conn, chat_ids)?;
 
-        if best_end > i + 5 {
-            if let Ok(text) = std::str::from_utf8(&data[i..best_end]) {
-                let trimmed = text.trim();
+    // Fetch non-reaction messages with their guids
+    let sql = format!(
+        "
+        SELECT {}
+        {}
+        ORDER BY m.date DESC, m.ROWID DESC
+        LIMIT ?1 OFFSET ?2
+        ",
+        message_columns(), chats_message_filter(chat_ids)
+    );
+    let mut result = load_messages(conn, &sql, params![limit, offset], context_db)?;
 
-                // Check if this looks like real message content
-                let has_keyword = plist_keywords.iter().any(|&kw| trimmed.contains(kw));
-                let alpha_count = trimmed.chars().filter(|c| c.is_alphabetic()).count();
-                let is_mostly_printable = trimmed.chars().all(|c| c >= ' ' || c == '\n' || c == '\t');
+    // Reverse to show chronologically (oldest first) for display
+    result.reverse();
 
-                if !has_keyword && alpha_count > 3 && is_mostly_printable {
-                    // Score by length and alpha ratio
-                    let score = alpha_count * 2 + trimmed.len();
-                    let best_score = best_text.chars().filter(|c| c.is_alphabetic()).count() * 2 + best_text.len();
+    let has_more = offset + (result.len() as i64) < total;
+    let (older_cursor, newer_cursor) = page_cursors(conn, &result)?;
 
-                    if score > best_score {
-                        best_text = trimmed.to_string();
-                    }
-                }
-            }
-            i = best_end;
-        } else {
-            i += 1;
-        }
-    }
+    Ok(MessagesResponse {
+        messages: result,
+        total,
+        has_more,
+        has_newer: offset > 0,
+        older_cursor,
+        newer_cursor,
+    })
+}
 
-    if !best_text.is_empty() {
-        Some(best_text)
-    } else {
-        None
+/// Keyset page of messages next to a cursor from a previous page
+pub fn fetch_messages_page(
+    conn: &Connection,
+    chat_id: i64,
+    context_db: &ContextDb,
+    limit: i64,
+    direction: PageDirection,
+) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+    if is_imported(chat_id) {
+        return ImportDb::open()?.fetch_messages_page(chat_id, context_db, limit, direction);
     }
+    load_messages_page(conn, &[chat_id], context_db, limit, direction)
 }
 
-fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    haystack.windows(needle.len()).position(|window| window == needle)
+/// `fetch_messages_page` for a merged timeline (see `fetch_merged_messages`).
+/// ROWIDs are unique across chats, so the same cursors work.
+pub fn fetch_merged_messages_page(
+    conn: &Connection,
+    chat_ids: &[i64],
+    context_db: &ContextDb,
+    limit: i64,
+    direction: PageDirection,
+) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+    load_messages_page(conn, chat_ids, context_db, limit, direction)
 }
 
-pub fn fetch_messages(
+fn load_messages_page(
     conn: &Connection,
-    chat_id: i64,
+    chat_ids: &[i64],
     context_db: &ContextDb,
     limit: i64,
-    offset: i64,
+    direction: PageDirection,
 ) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
-    // Get total count of non-reaction messages for this chat
-    let total: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM chat_message_join cmj
-         JOIN message m ON cmj.message_id = m.ROWID
-         WHERE cmj.chat_id = ?1 AND (m.associated_message_type = 0 OR m.associated_message_type IS NULL)",
-        params![chat_id],
-        |row| row.get(0)
-    )?;
+    let total = count_chat_messages(conn, chat_ids)?;
+    let (messages, has_more_beyond) =
+        load_messages_beside(conn, chat_ids, context_db, limit, &direction)?;
+    let (older_cursor, newer_cursor) = page_cursors(conn, &messages)?;
+
+    // We came from the other side of the cursor, so messages exist there
+    let (has_more, has_newer) = match direction {
+        PageDirection::Before(_) => (has_more_beyond, true),
+        PageDirection::After(_) => (true, has_more_beyond),
+    };
 
-    // Fetch non-reaction messages with their guids
-    let mut stmt = conn.prepare(
-        "
-        SELECT
-            m.ROWID,
-            m.guid,
-            m.text,
-            m.date,
-            m.is_from_me,
-            h.id as handle_id,
-            m.cache_has_attachments,
-            m.associated_message_type,
-            m.attributedBody
-        FROM message m
-        JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
-        LEFT JOIN handle h ON m.handle_id = h.ROWID
-        WHERE cmj.chat_id = ?1
-          AND (m.associated_message_type = 0 OR m.associated_message_type IS NULL)
-        ORDER BY m.date DESC
-        LIMIT ?2 OFFSET ?3
-        "
-    )?;
+    Ok(MessagesResponse {
+        messages,
+        total,
+        has_more,
+        has_newer,
+        older_cursor,
+        newer_cursor,
+    })
+}
 
-    // Collect message data with guids
-    let mut messages_with_guids: Vec<(i64, String, Message)> = Vec::new();
+/// `count` messages either side of the message with `guid`, plus the message
+/// itself, oldest first. `None` if the message isn't in this chat.
+pub fn fetch_messages_around(
+    conn: &Connection,
+    chat_id: i64,
+    guid: &str,
+    context_db: &ContextDb,
+    count: i64,
+) -> Result<Option<MessagesResponse>, Box<dyn std::error::Error>> {
+    if is_imported(chat_id) {
+        return ImportDb::open()?.fetch_messages_around(chat_id, guid, context_db, count);
+    }
+    let Some(cursor) = find_message_cursor(conn, chat_id, guid)? else {
+        return Ok(None);
+    };
+    messages_around(conn, chat_id, context_db, cursor, count, count).map(Some)
+}
 
-    let rows = stmt.query_map(params![chat_id, limit, offset], |row| {
-        let id: i64 = row.get(0)?;
-        let guid: String = row.get(1)?;
-        let mut text: Option<String> = row.get(2)?;
-        let has_attachments: i32 = row.get(6).unwrap_or(0);
-        let attributed_body: Option<Vec<u8>> = row.get(8).ok();
+/// Jump to a date: a `limit`-message page starting around the first message at
+/// or after `unix_ms`, plus the chat's per-month message counts. Dates past the
+/// newest message land on the latest page.
+pub fn fetch_messages_at_date(
+    conn: &Connection,
+    chat_id: i64,
+    context_db: &ContextDb,
+    unix_ms: i64,
+    limit: i64,
+) -> Result<DateJumpResponse, Box<dyn std::error::Error>> {
+    if is_imported(chat_id) {
+        return ImportDb::open()?.fetch_messages_at_date(chat_id, context_db, unix_ms, limit);
+    }
+    let first_on_or_after = conn.query_row(
+        &format!(
+            "SELECT m.date, m.ROWID, m.guid {} AND m.date >= ?2 ORDER BY m.date ASC, m.ROWID ASC LIMIT 1",
+            chat_message_filter()
+        ),
+        params![chat_id, apple_time_from_unix_ms(unix_ms)],
+        |row| {
+            Ok((
+                MessageCursor {
+                    date: row.get(0)?,
+                    rowid: row.get(1)?,
+                },
+                row.get::<_, String>(2)?,
+            ))
+        },
+    );
 
-        // If no text but has attachments, show indicator
-        if text.is_none() || text.as_ref().map(|t| t.trim().is_empty()).unwrap_or(true) {
-            if has_attachments == 1 {
-                text = Some("📎 Attachment".to_string());
-            } else if let Some(body_data) = attributed_body {
-                if let Some(extracted) = extract_text_from_attributed_body(&body_data) {
-                    text = Some(extracted);
-                } else {
-                    text = Some("💬 Message".to_string());
-                }
-            }
+    let (page, anchor_guid) = match first_on_or_after {
+        Ok((cursor, guid)) => {
+            // Keep a little context above the target, the rest of the page below it
+            let older = limit / 4;
+            let newer = (limit - older - 1).max(0);
+            (messages_around(conn, chat_id, context_db, cursor, older, newer)?, Some(guid))
+        }
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            (fetch_messages(conn, chat_id, context_db, limit, 0)?, None)
         }
+        Err(e) => return Err(Box::new(e)),
+    };
 
-        let handle: Option<String> = row.get(5)?;
-        let contact_name = handle
-            .as_ref()
-            .and_then(|h| get_contact_name(h, context_db));
+    Ok(DateJumpResponse {
+        page,
+        anchor_guid,
+        months: fetch_month_counts(conn, chat_id)?,
+    })
+}
 
-        Ok((id, guid.clone(), Message {
-            id,
-            guid: Some(guid),
-            text,
-            time: convert_apple_time(row.get(3)?),
-            is_from_me: row.get::<_, i32>(4)? == 1,
-            handle,
-            contact_name,
-            reactions: Vec::new(),
-            attachments: Vec::new(),
-        }))
-    })?;
+/// `older` messages before the message at `cursor`, the message itself, then `newer` after it
+fn messages_around(
+    conn: &Connection,
+    chat_id: i64,
+    context_db: &ContextDb,
+    cursor: MessageCursor,
+    older: i64,
+    newer: i64,
+) -> Result<MessagesResponse, Box<dyn std::error::Error>> {
+    let total = count_chat_messages(conn, &[chat_id])?;
+    let (mut messages, has_more) =
+        load_messages_beside(conn, &[chat_id], context_db, older, &PageDirection::Before(cursor))?;
 
-    for row in rows {
-        messages_with_guids.push(row?);
-    }
+    let sql = format!("SELECT {} {} AND m.ROWID = ?2", message_columns(), chat_message_filter());
+    messages.extend(load_messages(conn, &sql, params![chat_id, cursor.rowid], context_db)?);
 
-    // Collect all guids to query for reactions
-    let guids: Vec<String> = messages_with_guids.iter().map(|(_, g, _)| g.clone()).collect();
+    let (newer_messages, has_newer) =
+        load_messages_beside(conn, &[chat_id], context_db, newer, &PageDirection::After(cursor))?;
+    messages.extend(newer_messages);
 
-    // Build a map from guid to reactions
-    let mut reactions_map: std::collections::HashMap<String, Vec<Reaction>> = std::collections::HashMap::new();
+    let (older_cursor, newer_cursor) = page_cursors(conn, &messages)?;
 
-    if !guids.is_empty() {
-        // Query for reactions to these messages only
-        // associated_message_guid format: "p:0/GUID" or "bp:GUID"
-        // Build WHERE clause to filter by our message GUIDs
-        let guid_patterns: Vec<String> = guids.iter()
-            .flat_map(|g| vec![
-                format!("%/{}", g),      // matches "p:0/GUID"
-                format!("bp:{}", g),     // matches "bp:GUID"
-            ])
-            .collect();
+    Ok(MessagesResponse {
+        messages,
+        total,
+        has_more,
+        has_newer,
+        older_cursor,
+        newer_cursor,
+    })
+}
 
-        let placeholders: Vec<&str> = guid_patterns.iter().map(|_| "associated_message_guid LIKE ?").collect();
-        let where_clause = placeholders.join(" OR ");
+/// Messages per calendar month (local time), oldest month first
+fn fetch_month_counts(
+    conn: &Connection,
+    chat_id: i64,
+) -> Result<Vec<MonthCount>, Box<dyn std::error::Error>> {
+    let sql = format!(
+        "
+        SELECT strftime('%Y-%m', m.date / 1000000000 + {}, 'unixepoch', 'localtime') as month,
+               COUNT(*)
+        {}
+        GROUP BY month
+        ORDER BY month ASC
+        ",
+        APPLE_EPOCH, chat_message_filter()
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let months = stmt
+        .query_map(params![chat_id], |row| {
+            Ok(MonthCount {
+                month: row.get(0)?,
+                count: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(months)
+}
 
-        let query = format!(
-            "SELECT associated_message_guid, associated_message_type, is_from_me
-             FROM message
-             WHERE associated_message_type BETWEEN 2000 AND 2005
-             AND ({})",
-            where_clause
-        );
+/// Newest message ROWID in a chat, reactions included; the starting point for deltas
+pub fn latest_chat_rowid(conn: &Connection, chat_id: i64) -> Result<i64, Box<dyn std::error::Error>> {
+    Ok(conn.query_row(
+        "SELECT COALESCE(MAX(message_id), 0) FROM chat_message_join WHERE chat_id = ?1",
+        params![chat_id],
+        |row| row.get(0),
+    )?)
+}
 
-        let mut reaction_stmt = conn.prepare(&query)?;
-        let params: Vec<&dyn rusqlite::ToSql> = guid_patterns.iter().map(|s| s as &dyn rusqlite::ToSql).collect();
+/// Messages in a chat added after `after_rowid` (at most `limit`), plus the
+/// already-seen messages in `changed_rowids` / `changed_guids`, oldest first
+pub fn fetch_messages_delta(
+    conn: &Connection,
+    chat_id: i64,
+    context_db: &ContextDb,
+    after_rowid: i64,
+    changed_rowids: &[i64],
+    changed_guids: &[String],
+    limit: i64,
+) -> Result<MessagesDelta, Box<dyn std::error::Error>> {
+    // Cap the range first so a row inserted mid-query isn't skipped next time
+    let last_rowid = latest_chat_rowid(conn, chat_id)?.max(after_rowid);
 
-        let reactions = reaction_stmt.query_map(params.as_slice(), |row| {
-            Ok((
-                row.get::<_, String>(0)?,
-                row.get::<_, i32>(1)?,
-                row.get::<_, i32>(2)? == 1,
-            ))
-        })?;
+    let sql = format!(
+        "
+        SELECT {}
+        {}
+          AND m.ROWID > ?2 AND m.ROWID <= ?3
+        ORDER BY m.ROWID ASC
+        LIMIT ?4
+        ",
+        message_columns(), chat_message_filter()
+    );
+    let mut messages = load_messages(
+        conn,
+        &sql,
+        params![chat_id, after_rowid, last_rowid, limit + 1],
+        context_db,
+    )?;
+    let complete = messages.len() as i64 <= limit;
+    messages.truncate(limit.max(0) as usize);
+    let last_rowid = match messages.last() {
+        Some(newest) if !complete => newest.id,
+        _ => last_rowid,
+    };
 
-        for reaction in reactions {
-            let (assoc_guid, reaction_type, is_from_me) = reaction?;
+    let changed_rowids: Vec<i64> = changed_rowids
+        .iter()
+        .copied()
+        .filter(|rowid| *rowid <= after_rowid)
+        .collect();
+    if !changed_rowids.is_empty() || !changed_guids.is_empty() {
+        let rowid_placeholders = vec!["?"; changed_rowids.len()].join(",");
+        let guid_placeholders = vec!["?"; changed_guids.len()].join(",");
+        let sql = format!(
+            "SELECT {} {} AND m.ROWID <= ?2 AND (m.ROWID IN ({}) OR m.guid IN ({}))",
+            message_columns(), chat_message_filter(), rowid_placeholders, guid_placeholders
+        );
+        let mut params: Vec<&dyn rusqlite::ToSql> = vec![&chat_id, &after_rowid];
+        params.extend(changed_rowids.iter().map(|id| id as &dyn rusqlite::ToSql));
+        params.extend(changed_guids.iter().map(|guid| guid as &dyn rusqlite::ToSql));
+        messages.extend(load_messages(conn, &sql, params.as_slice(), context_db)?);
+    }
 
-            // Extract the actual guid from formats like "p:0/GUID" or "bp:GUID"
-            let parent_guid = if let Some(pos) = assoc_guid.rfind('/') {
-                &assoc_guid[pos + 1..]
-            } else if assoc_guid.starts_with("bp:") {
-                &assoc_guid[3..]
-            } else {
-                &assoc_guid
-            };
+    messages.sort_by_key(|message| (message.time, message.id));
 
-            let emoji = match reaction_type {
-                2000 => "❤️",
-                2001 => "👍",
-                2002 => "👎",
-                2003 => "😂",
-                2004 => "‼️",
-                2005 => "❓",
-                _ => continue,
-            };
+    Ok(MessagesDelta {
+        messages,
+        total: count_chat_messages(conn, &[chat_id])?,
+        last_rowid,
+        complete,
+    })
+}
 
-            reactions_map
-                .entry(parent_guid.to_string())
-                .or_insert_with(Vec::new)
-                .push(Reaction {
-                    emoji: emoji.to_string(),
-                    is_from_me,
-                });
-        }
+/// Load an inline-reply thread: the originating message followed by every reply
+/// to it, oldest first. `guid` may be the originator or any reply in the thread.
+pub fn fetch_thread(
+    conn: &Connection,
+    chat_id: i64,
+    guid: &str,
+    context_db: &ContextDb,
+) -> Result<Option<ThreadResponse>, Box<dyn std::error::Error>> {
+    if is_imported(chat_id) {
+        return ImportDb::open()?.fetch_thread(chat_id, guid, context_db);
     }
+    let originator: Option<String> = match conn.query_row(
+        &format!(
+            "SELECT COALESCE(NULLIF({}, ''), m.guid)
+             FROM message m
+             JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+             WHERE cmj.chat_id = ?1 AND m.guid = ?2",
+            message_column("thread_originator_guid")
+        ),
+        params![chat_id, guid],
+        |row| row.get(0),
+    ) {
+        Ok(originator) => Some(originator),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(Box::new(e)),
+    };
 
-    // Collect message IDs for attachment query
-    let message_ids: Vec<i64> = messages_with_guids.iter().map(|(id, _, _)| *id).collect();
-
-    // Build a map from message_id to attachments
-    let mut attachments_map: std::collections::HashMap<i64, Vec<Attachment>> = std::collections::HashMap::new();
-
-    if !message_ids.is_empty() {
-        // Query for attachments linked to these specific messages only
-        let placeholders: String = message_ids.iter().map(|_| "?").collect::<Vec<_>>().join(",");
-        let query = format!(
-            "SELECT maj.message_id, a.ROWID, a.filename, a.mime_type, a.transfer_name, a.total_bytes
-             FROM attachment a
-             JOIN message_attachment_join maj ON a.ROWID = maj.attachment_id
-             WHERE maj.message_id IN ({})",
-            placeholders
-        );
+    let Some(thread_guid) = originator else {
+        return Ok(None);
+    };
 
-        let mut attachment_stmt = conn.prepare(&query)?;
-        let params: Vec<&dyn rusqlite::ToSql> = message_ids.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    let sql = format!(
+        "
+        SELECT {}
+        FROM message m
+        JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+        LEFT JOIN handle h ON m.handle_id = h.ROWID
+        WHERE cmj.chat_id = ?1
+          AND (m.guid = ?2 OR {} = ?2)
+          AND {}
+        ORDER BY m.date ASC
+        ",
+        message_columns(),
+        message_column("thread_originator_guid"),
+        not_reaction_sql()
+    );
+    let messages = load_messages(conn, &sql, params![chat_id, thread_guid], context_db)?;
 
-        let attachments = attachment_stmt.query_map(params.as_slice(), |row| {
-            Ok((
-                row.get::<_, i64>(0)?,
-                row.get::<_, i64>(1)?,
-                row.get::<_, Option<String>>(2)?,
-                row.get::<_, Option<String>>(3)?,
-                row.get::<_, Option<String>>(4)?,
-                row.get::<_, i64>(5).unwrap_or(0),
-            ))
-        })?;
+    Ok(Some(ThreadResponse {
+        chat_id,
+        thread_guid,
+        messages,
+    }))
+}
 
-        for attachment in attachments {
-            let (message_id, id, filename, mime_type, transfer_name, total_bytes) = attachment?;
-            attachments_map
-                .entry(message_id)
-                .or_insert_with(Vec::new)
-                .push(Attachment {
-                    id,
-                    filename,
-                    mime_type,
-                    transfer_name,
-                    total_bytes,
-                });
-        }
+/// Everyone who reacted to a message, grouped by emoji. `None` if no message has this guid.
+pub fn fetch_message_reactions(
+    conn: &Connection,
+    guid: &str,
+    context_db: &ContextDb,
+) -> Result<Option<MessageReactionsResponse>, Box<dyn std::error::Error>> {
+    let exists = match conn.query_row("SELECT 1 FROM message WHERE guid = ?1", params![guid], |_| Ok(())) {
+        Ok(()) => true,
+        Err(rusqlite::Error::QueryReturnedNoRows) => false,
+        Err(e) => return Err(Box::new(e)),
+    };
+    if !exists {
+        return Ok(None);
     }
 
-    // Attach reactions and attachments to messages
-    let mut result: Vec<Message> = messages_with_guids
-        .into_iter()
-        .map(|(id, guid, mut msg)| {
-            if let Some(reactions) = reactions_map.remove(&guid) {
-                msg.reactions = reactions;
-            }
-            if let Some(attachments) = attachments_map.remove(&id) {
-                msg.attachments = attachments;
-            }
-            msg
-        })
-        .collect();
+    let reactions = fetch_reactions(conn, &[guid.to_string()], context_db)?
+        .remove(guid)
+        .unwrap_or_default();
 
-    // Reverse to show chronologically (oldest first) for display
-    result.reverse();
-
-    let has_more = offset + (result.len() as i64) < total;
-
-    Ok(MessagesResponse {
-        messages: result,
-        total,
-        has_more,
-    })
+    Ok(Some(MessageReactionsResponse {
+        guid: guid.to_string(),
+        groups: group_reactions(reactions),
+    }))
 }
 
 pub fn fetch_messages_for_extraction(
     conn: &Connection,
     chat_id: i64,
 ) -> Result<Vec<MessageForExtraction>, Box<dyn std::error::Error>> {
-    let mut stmt = conn.prepare(
+    if is_imported(chat_id) {
+        return ImportDb::open()?.fetch_messages_for_extraction(chat_id);
+    }
+    let mut stmt = conn.prepare(&format!(
         "
-        SELECT m.text, m.date, m.is_from_me, m.attributedBody, m.cache_has_attachments
+        SELECT m.text, m.date, m.is_from_me, {}, {}
         FROM message m
         JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
         WHERE cmj.chat_id = ?1
-          AND (m.associated_message_type = 0 OR m.associated_message_type IS NULL)
+          AND {}
         ORDER BY m.date ASC
-        "
-    )?;
+        ",
+        message_column("attributedBody"),
+        message_column("cache_has_attachments"),
+        not_reaction_sql()
+    ))?;
 
     let rows = stmt.query_map(params![chat_id], |row| {
-        let mut text: Option<String> = row.get(0)?;
         let date: i64 = row.get(1)?;
         let is_from_me: i32 = row.get(2)?;
         let attributed_body: Option<Vec<u8>> = row.get(3).ok();
-
-        if text.as_ref().map(|t| t.trim().is_empty()).unwrap_or(true) {
-            if let Some(body_data) = attributed_body {
-                if let Some(extracted) = extract_text_from_attributed_body(&body_data) {
-                    text = Some(extracted);
-                }
-            }
-        }
+        let text = message_text(row.get(0)?, attributed_body.as_deref())
+            .filter(|t| !t.trim().is_empty());
 
         Ok(text.map(|text| MessageForExtraction {
             text,
@@ -1453,32 +1615,29 @@ pub fn fetch_recent_messages_for_suggestion(
     chat_id: i64,
     limit: usize,
 ) -> Result<Vec<MessageForExtraction>, Box<dyn std::error::Error>> {
-    let mut stmt = conn.prepare(
+    let attributed_body = message_column("attributedBody");
+    let mut stmt = conn.prepare(&format!(
         "
-        SELECT m.text, m.date, m.is_from_me, m.attributedBody
+        SELECT m.text, m.date, m.is_from_me, {}
         FROM message m
         JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
         WHERE cmj.chat_id = ?1
-          AND (m.associated_message_type = 0 OR m.associated_message_type IS NULL)
-          AND (m.text IS NOT NULL OR m.attributedBody IS NOT NULL)
+          AND {}
+          AND (m.text IS NOT NULL OR {} IS NOT NULL)
         ORDER BY m.date DESC
         LIMIT ?2
         ",
-    )?;
+        attributed_body,
+        not_reaction_sql(),
+        attributed_body
+    ))?;
 
     let rows = stmt.query_map(params![chat_id, limit as i64], |row| {
-        let mut text: Option<String> = row.get(0)?;
         let date: i64 = row.get(1)?;
         let is_from_me: i32 = row.get(2)?;
         let attributed_body: Option<Vec<u8>> = row.get(3).ok();
-
-        if text.as_ref().map(|t| t.trim().is_empty()).unwrap_or(true) {
-            if let Some(body_data) = attributed_body {
-                if let Some(extracted) = extract_text_from_attributed_body(&body_data) {
-                    text = Some(extracted);
-                }
-            }
-        }
+        let text = message_text(row.get(0)?, attributed_body.as_deref())
+            .filter(|t| !t.trim().is_empty());
 
         Ok(text.map(|text| MessageForExtraction {
             text,
@@ -1498,6 +1657,218 @@ pub fn fetch_recent_messages_for_suggestion(
     Ok(messages)
 }
 
+/// Columns read by `read_index_row`, in order
+fn index_columns() -> String {
+    format!(
+        "
+            m.ROWID,
+            m.guid,
+            m.text,
+            {},
+            m.date,
+            m.is_from_me,
+            h.id,
+            (SELECT MIN(cmj.chat_id) FROM chat_message_join cmj WHERE cmj.message_id = m.ROWID),
+            {},
+            {}",
+        message_column("attributedBody"),
+        message_column("date_retracted"),
+        message_column("message_summary_info"),
+    )
+}
+
+/// Map a row selected with `index_columns()`; None when there is nothing searchable
+/// (no text, unsent, or not attached to a chat)
+fn read_index_row(row: &rusqlite::Row) -> rusqlite::Result<IndexUpdate> {
+    let rowid: i64 = row.get(0)?;
+    let attributed_body: Option<Vec<u8>> = row.get(3).ok();
+    let text = message_text(row.get(2)?, attributed_body.as_deref())
+        .map(|t| t.replace('\u{FFFC}', "").trim().to_string())
+        .filter(|t| !t.is_empty());
+    let chat_id: Option<i64> = row.get(7)?;
+    let date_retracted: i64 = row.get::<_, Option<i64>>(8)?.unwrap_or(0);
+    let summary_info = parse_summary_info(row.get(9).ok().flatten());
+    let is_unsent = is_unsent_message(date_retracted, summary_info.as_ref(), text.as_deref());
+
+    let message = match (text, chat_id) {
+        (Some(text), Some(chat_id)) if !is_unsent => Some(IndexedMessage {
+            rowid,
+            guid: row.get(1)?,
+            chat_id,
+            text,
+            time: convert_apple_time(row.get(4)?),
+            is_from_me: row.get::<_, i32>(5)? == 1,
+            handle: row.get(6)?,
+        }),
+        _ => None,
+    };
+    Ok((rowid, message))
+}
+
+/// Next batch of messages to add to the search index, in ROWID order.
+/// Returns the indexable messages and the highest ROWID scanned.
+pub fn fetch_messages_for_index(
+    conn: &Connection,
+    after_rowid: i64,
+    limit: i64,
+) -> Result<(Vec<IndexedMessage>, Option<i64>), Box<dyn std::error::Error>> {
+    let sql = format!(
+        "
+        SELECT {}
+        FROM message m
+        LEFT JOIN handle h ON m.handle_id = h.ROWID
+        WHERE m.ROWID > ?1
+          AND {}
+          AND {}
+        ORDER BY m.ROWID ASC
+        LIMIT ?2
+        ",
+        index_columns(),
+        not_reaction_sql(),
+        not_timeline_event_sql()
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt
+        .query_map(params![after_rowid, limit], read_index_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let last_rowid = rows.last().map(|(rowid, _)| *rowid);
+    let messages = rows.into_iter().filter_map(|(_, message)| message).collect();
+    Ok((messages, last_rowid))
+}
+
+/// Already-indexed messages edited or unsent after `since` (raw Apple time).
+/// Returns the replacement for each ROWID and the newest edit date seen.
+pub fn fetch_edited_messages_for_index(
+    conn: &Connection,
+    max_rowid: i64,
+    since: i64,
+) -> Result<(Vec<IndexUpdate>, i64), Box<dyn std::error::Error>> {
+    let sql = format!(
+        "
+        SELECT {columns}, MAX(COALESCE({edited}, 0), COALESCE({retracted}, 0))
+        FROM message m
+        LEFT JOIN handle h ON m.handle_id = h.ROWID
+        WHERE m.ROWID <= ?1
+          AND ({edited} > ?2 OR {retracted} > ?2)
+          AND {not_reaction}
+        ",
+        columns = index_columns(),
+        edited = message_column("date_edited"),
+        retracted = message_column("date_retracted"),
+        not_reaction = not_reaction_sql(),
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut newest = since;
+    let rows = stmt.query_map(params![max_rowid, since], |row| {
+        Ok((read_index_row(row)?, row.get::<_, i64>(10)?))
+    })?;
+
+    let mut updates = Vec::new();
+    for row in rows {
+        let (update, changed_at) = row?;
+        newest = newest.max(changed_at);
+        updates.push(update);
+    }
+    Ok((updates, newest))
+}
+
+/// Of the messages in `within`, those matching `filter`
+pub fn fetch_filtered_message_ids(
+    conn: &Connection,
+    filter: &SqlFilter,
+    within: &[i64],
+) -> Result<std::collections::HashSet<i64>, Box<dyn std::error::Error>> {
+    if within.is_empty() {
+        return Ok(std::collections::HashSet::new());
+    }
+
+    let placeholders: String = within.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    let sql = format!(
+        "
+        SELECT m.ROWID
+        FROM message m
+        JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+        LEFT JOIN handle h ON m.handle_id = h.ROWID
+        WHERE m.ROWID IN ({})
+          AND {}
+        ",
+        placeholders,
+        filter.where_sql()
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut params: Vec<&dyn rusqlite::ToSql> =
+        within.iter().map(|id| id as &dyn rusqlite::ToSql).collect();
+    params.extend(filter.params.iter().map(|p| p as &dyn rusqlite::ToSql));
+
+    let ids = stmt
+        .query_map(params.as_slice(), |row| row.get::<_, i64>(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(ids)
+}
+
+/// Newest messages matching `filter`, for searches with operators but no text
+pub fn fetch_filtered_messages(
+    conn: &Connection,
+    context_db: &ContextDb,
+    filter: &SqlFilter,
+    limit: i64,
+) -> Result<Vec<MessageSearchResult>, Box<dyn std::error::Error>> {
+    let sql = format!(
+        "
+        SELECT m.ROWID, m.guid, cmj.chat_id, m.date, m.is_from_me, h.id,
+               m.text, {}, {}
+        FROM message m
+        JOIN chat_message_join cmj ON m.ROWID = cmj.message_id
+        LEFT JOIN handle h ON m.handle_id = h.ROWID
+        WHERE {}
+          AND {}
+          AND COALESCE({}, 0) = 0
+          AND {}
+        ORDER BY m.date DESC
+        LIMIT ?
+        ",
+        message_column("attributedBody"),
+        message_column("cache_has_attachments"),
+        not_reaction_sql(),
+        not_timeline_event_sql(),
+        message_column("date_retracted"),
+        filter.where_sql()
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut params: Vec<&dyn rusqlite::ToSql> =
+        filter.params.iter().map(|p| p as &dyn rusqlite::ToSql).collect();
+    params.push(&limit);
+
+    let rows = stmt.query_map(params.as_slice(), |row| {
+        let attributed_body: Option<Vec<u8>> = row.get(7).ok();
+        let text = message_text(row.get(6)?, attributed_body.as_deref())
+            .map(|t| t.replace('\u{FFFC}', "").trim().to_string())
+            .filter(|t| !t.is_empty());
+        let snippet = match text {
+            Some(text) => truncate_preview(&text, SEARCH_SNIPPET_CHARS),
+            None if row.get::<_, i32>(8).unwrap_or(0) == 1 => "📎 Attachment".to_string(),
+            None => "💬 Message".to_string(),
+        };
+        let handle: Option<String> = row.get(5)?;
+
+        Ok(MessageSearchResult {
+            chat_id: row.get(2)?,
+            message_id: row.get(0)?,
+            guid: row.get(1)?,
+            snippet,
+            highlights: Vec::new(),
+            time: convert_apple_time(row.get(3)?),
+            is_from_me: row.get::<_, i32>(4)? == 1,
+            contact_name: handle.as_ref().and_then(|h| get_contact_name(h, context_db)),
+            handle,
+        })
+    })?;
+
+    rows.collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+}
+
 fn convert_apple_time(nanoseconds: i64) -> i64 {
     // Apple time is in nanoseconds since 2001-01-01
     // Convert to Unix milliseconds
@@ -1505,6 +1876,245 @@ fn convert_apple_time(nanoseconds: i64) -> i64 {
     (APPLE_EPOCH + seconds) * 1000
 }
 
+/// Delivery/read dates are 0 until the event happens
+fn optional_apple_time(nanoseconds: Option<i64>) -> Option<i64> {
+    nanoseconds.filter(|ns| *ns > 0).map(convert_apple_time)
+}
+
+/// Inverse of `convert_apple_time`, for comparing against `message.date`
+pub fn apple_time_from_unix_ms(unix_ms: i64) -> i64 {
+    (unix_ms / 1000 - APPLE_EPOCH) * 1_000_000_000
+}
+
 fn convert_apple_time_seconds(nanoseconds: i64) -> i64 {
     APPLE_EPOCH + nanoseconds / 1_000_000_000
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    fn chat_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "
+            CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT);
+            CREATE TABLE message (
+                ROWID INTEGER PRIMARY KEY, guid TEXT, text TEXT, attributedBody BLOB,
+                date INTEGER, is_from_me INTEGER DEFAULT 0, handle_id INTEGER DEFAULT 1,
+                cache_has_attachments INTEGER DEFAULT 0, associated_message_type INTEGER DEFAULT 0,
+                associated_message_guid TEXT, associated_message_emoji TEXT,
+                thread_originator_guid TEXT, thread_originator_part TEXT,
+                date_edited INTEGER DEFAULT 0, date_retracted INTEGER DEFAULT 0,
+                message_summary_info BLOB, is_delivered INTEGER DEFAULT 0,
+                is_read INTEGER DEFAULT 0, date_delivered INTEGER DEFAULT 0,
+                date_read INTEGER DEFAULT 0, error INTEGER DEFAULT 0,
+                item_type INTEGER DEFAULT 0, group_action_type INTEGER DEFAULT 0,
+                group_title TEXT, other_handle INTEGER DEFAULT 0, service TEXT DEFAULT 'iMessage'
+            );
+            CREATE TABLE chat_message_join (chat_id INTEGER, message_id INTEGER);
+            CREATE TABLE attachment (
+                ROWID INTEGER PRIMARY KEY, filename TEXT, mime_type TEXT, transfer_name TEXT,
+                total_bytes INTEGER
+            );
+            CREATE TABLE message_attachment_join (message_id INTEGER, attachment_id INTEGER);
+
+            INSERT INTO handle VALUES (1, '+15550001');
+            ",
+        )
+        .unwrap();
+        conn
+    }
+
+    /// Add message `rowid` (guid "G<rowid>") to `chat_id`, sent at `unix_s`
+    fn insert_message(conn: &Connection, rowid: i64, chat_id: i64, unix_s: i64) {
+        conn.execute(
+            "INSERT INTO message (ROWID, guid, text, date) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                rowid,
+                format!("G{}", rowid),
+                format!("message {}", rowid),
+                apple_time_from_unix_ms(unix_s * 1000)
+            ],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO chat_message_join VALUES (?1, ?2)", params![chat_id, rowid])
+            .unwrap();
+    }
+
+    fn ids(messages: &[Message]) -> Vec<i64> {
+        messages.iter().map(|m| m.id).collect()
+    }
+
+    fn cursor(value: &Option<String>) -> MessageCursor {
+        MessageCursor::parse(value.as_deref().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn loads_a_thread_from_any_message_in_it() {
+        let conn = chat_db();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        for rowid in 1..=4 {
+            insert_message(&conn, rowid, 1, 1_700_000_000 + rowid * 100);
+        }
+        insert_message(&conn, 5, 2, 1_700_000_000);
+        conn.execute(
+            "UPDATE message SET thread_originator_guid = 'G1', thread_originator_part = '0:0:9'
+             WHERE ROWID IN (2, 4, 5)",
+            [],
+        )
+        .unwrap();
+
+        let thread = fetch_thread(&conn, 1, "G4", &context_db).unwrap().unwrap();
+        assert_eq!(thread.thread_guid, "G1");
+        assert_eq!(ids(&thread.messages), vec![1, 2, 4]);
+        assert_eq!(thread.messages[1].reply_to_guid.as_deref(), Some("G1"));
+        assert_eq!(thread.messages[1].reply_to_part, Some(0));
+
+        let from_originator = fetch_thread(&conn, 1, "G1", &context_db).unwrap().unwrap();
+        assert_eq!(ids(&from_originator.messages), vec![1, 2, 4]);
+        // The reply in the other chat isn't this chat's
+        assert!(fetch_thread(&conn, 1, "G5", &context_db).unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_delivery_and_read_receipts() {
+        let conn = chat_db();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        insert_message(&conn, 1, 1, 1_700_000_000);
+        insert_message(&conn, 2, 1, 1_700_000_100);
+        insert_message(&conn, 3, 1, 1_700_000_200);
+        conn.execute_batch(&format!(
+            "
+            UPDATE message SET is_from_me = 1, handle_id = 0;
+            UPDATE message SET is_delivered = 1, date_delivered = {}, is_read = 1, date_read = {}
+                WHERE ROWID = 1;
+            UPDATE message SET is_delivered = 1, date_delivered = {} WHERE ROWID = 2;
+            UPDATE message SET error = 22 WHERE ROWID = 3;
+            ",
+            apple_time_from_unix_ms(1_700_000_010_000),
+            apple_time_from_unix_ms(1_700_000_060_000),
+            apple_time_from_unix_ms(1_700_000_110_000),
+        ))
+        .unwrap();
+
+        let messages = fetch_messages(&conn, 1, &context_db, 10, 0).unwrap().messages;
+        assert!(messages[0].is_delivered && messages[0].is_read);
+        assert_eq!(messages[0].date_delivered, Some(1_700_000_010_000));
+        assert_eq!(messages[0].date_read, Some(1_700_000_060_000));
+        assert!(messages[1].is_delivered && !messages[1].is_read);
+        assert_eq!(messages[1].date_read, None);
+        assert!(!messages[2].is_delivered);
+        assert_eq!(messages[2].date_delivered, None);
+        assert_eq!(messages[2].error, Some(22));
+        assert_eq!(messages[1].error, None);
+    }
+
+    #[test]
+    fn counts_unread_incoming_messages_per_chat() {
+        let conn = chat_db();
+        for rowid in 1..=4 {
+            insert_message(&conn, rowid, 1, 1_700_000_000 + rowid * 100);
+        }
+        insert_message(&conn, 5, 2, 1_700_000_000);
+        conn.execute_batch(
+            "
+            UPDATE message SET is_read = 1 WHERE ROWID IN (1, 2, 5);
+            -- Unread flags on my own messages don't count
+            UPDATE message SET is_from_me = 1, handle_id = 0 WHERE ROWID = 4;
+            ",
+        )
+        .unwrap();
+
+        let unread = fetch_unread_map(&conn, &[1, 2, 3]).unwrap();
+        assert_eq!(unread[&1], (1, Some(1_700_000_200_000)));
+        assert_eq!(unread[&2], (0, Some(1_700_000_000_000)));
+        assert!(!unread.contains_key(&3));
+    }
+
+    #[test]
+    fn pages_by_keyset_through_equal_dates() {
+        let conn = chat_db();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        // Messages 2-4 share a timestamp; ROWID breaks the tie
+        let dates = [1_700_000_000, 1_700_000_100, 1_700_000_100, 1_700_000_100, 1_700_000_200];
+        for (rowid, unix_s) in (1..).zip(dates) {
+            insert_message(&conn, rowid, 1, unix_s);
+        }
+        insert_message(&conn, 6, 2, 1_700_000_100);
+
+        let newest = fetch_messages(&conn, 1, &context_db, 2, 0).unwrap();
+        assert_eq!(ids(&newest.messages), vec![4, 5]);
+        assert_eq!(newest.total, 5);
+        assert!(newest.has_more);
+        assert!(!newest.has_newer);
+
+        let older = fetch_messages_page(
+            &conn,
+            1,
+            &context_db,
+            2,
+            PageDirection::Before(cursor(&newest.older_cursor)),
+        )
+        .unwrap();
+        assert_eq!(ids(&older.messages), vec![2, 3]);
+        assert!(older.has_more);
+        assert!(older.has_newer);
+
+        // Exactly `limit` messages left on either side: no further page
+        let oldest = fetch_messages_page(
+            &conn,
+            1,
+            &context_db,
+            1,
+            PageDirection::Before(cursor(&older.older_cursor)),
+        )
+        .unwrap();
+        assert_eq!(ids(&oldest.messages), vec![1]);
+        assert!(!oldest.has_more);
+        let newer = fetch_messages_page(
+            &conn,
+            1,
+            &context_db,
+            2,
+            PageDirection::After(cursor(&older.newer_cursor)),
+        )
+        .unwrap();
+        assert_eq!(ids(&newer.messages), vec![4, 5]);
+        assert!(!newer.has_newer);
+        assert!(newer.has_more);
+    }
+
+    #[test]
+    fn jumps_to_dates_and_buckets_months_in_local_time() {
+        let conn = chat_db();
+        let context_db = ContextDb::open_in_memory().unwrap();
+        let local = |month, day, hour, minute| {
+            Local
+                .with_ymd_and_hms(2024, month, day, hour, minute, 0)
+                .unwrap()
+                .timestamp()
+        };
+        // Either side of local midnight at the start of February
+        insert_message(&conn, 1, 1, local(1, 31, 23, 30));
+        insert_message(&conn, 2, 1, local(2, 1, 0, 30));
+        insert_message(&conn, 3, 1, local(2, 10, 12, 0));
+
+        let jump =
+            fetch_messages_at_date(&conn, 1, &context_db, local(2, 1, 0, 0) * 1000, 4).unwrap();
+        assert_eq!(jump.anchor_guid.as_deref(), Some("G2"));
+        assert_eq!(ids(&jump.page.messages), vec![1, 2, 3]);
+        assert!(!jump.page.has_more);
+        assert!(!jump.page.has_newer);
+        let months: Vec<_> = jump.months.iter().map(|m| (m.month.as_str(), m.count)).collect();
+        assert_eq!(months, vec![("2024-01", 1), ("2024-02", 2)]);
+
+        // Past the newest message: the latest page, with no anchor
+        let past_the_end =
+            fetch_messages_at_date(&conn, 1, &context_db, local(3, 1, 0, 0) * 1000, 2).unwrap();
+        assert_eq!(past_the_end.anchor_guid, None);
+        assert_eq!(ids(&past_the_end.page.messages), vec![2, 3]);
+        assert!(past_the_end.page.has_more);
+    }
+}
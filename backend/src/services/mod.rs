@@ -1,6 +1,17 @@
+pub mod address_book;
+pub mod android_import;
 pub mod applescript;
+pub mod avatars;
+pub mod changes;
+pub mod chat_summaries;
 pub mod contacts;
 pub mod messages;
 pub mod openrouter_config;
+pub mod search;
+pub mod search_query;
+pub mod tapbacks;
+pub mod timeline;
+pub mod vcard;
 pub mod watcher;
+pub mod whatsapp_import;
 
@@ -0,0 +1,237 @@
+use crate::context_db::ContextDb;
+use crate::import_db::ImportDb;
+use crate::models::{MessageSearchResponse, MessageSearchResult, SearchHighlight};
+use crate::search_index::{SearchHit, SearchIndex};
+use crate::services::contacts::{get_contact_name, should_search_contacts_by_name};
+use crate::services::messages::{
+    apple_time_from_unix_ms, fetch_edited_messages_for_index, fetch_filtered_message_ids,
+    fetch_filtered_messages, fetch_messages_for_index,
+};
+use crate::services::search_query::SearchQuery;
+use crate::state::DbChangeEvent;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+use std::time::Instant;
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+// ============================================================================
+// MESSAGE SEARCH
+// ============================================================================
+//
+// chat.db has no full-text index and is read-only to us, so decoded message
+// text is copied into an FTS5 table in a sidecar database (search_index.rs).
+// - New messages are picked up by ROWID: everything above the last indexed
+//   ROWID is read in batches and appended.
+// - Edits and unsends don't create rows, so indexed messages whose
+//   `date_edited`/`date_retracted` is newer than the last one seen are re-read.
+// - Imported messages never change, so they are only appended, by id.
+// The index syncs at startup and on every chat.db change, in the background
+// worker only. Queries search whatever it has indexed so far and say so while
+// it is still catching up.
+// Operators (search_query.rs) filter the text matches with SQL over chat.db,
+// so a query with operators only finds chat.db messages.
+// ============================================================================
+
+const INDEX_BATCH_SIZE: i64 = 2000;
+/// Text matches considered when operators narrow the results further
+const FILTERED_CANDIDATES: i64 = 5000;
+
+/// Bring the index up to date with chat.db. Returns the number of rows scanned.
+pub fn sync_search_index(
+    conn: &Connection,
+    index: &mut SearchIndex,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut scanned = 0;
+    let mut last_rowid = index.last_indexed_rowid()?;
+
+    // Edits first, so only rows indexed by an earlier sync are re-read
+    let since = index.last_edit_date()?;
+    let (updates, newest_edit) = fetch_edited_messages_for_index(conn, last_rowid, since)?;
+    if !updates.is_empty() {
+        scanned += updates.len();
+        index.replace_messages(&updates)?;
+    }
+    if newest_edit > since {
+        index.set_last_edit_date(newest_edit)?;
+    }
+
+    loop {
+        let (messages, batch_last_rowid) =
+            fetch_messages_for_index(conn, last_rowid, INDEX_BATCH_SIZE)?;
+        let Some(batch_last_rowid) = batch_last_rowid else {
+            break;
+        };
+        scanned += (batch_last_rowid - last_rowid) as usize;
+        index.add_messages(&messages, batch_last_rowid)?;
+        last_rowid = batch_last_rowid;
+    }
+
+    scanned += sync_imported_messages(&ImportDb::open()?, index)?;
+    Ok(scanned)
+}
+
+/// Index messages added by imports since the last sync
+fn sync_imported_messages(
+    import_db: &ImportDb,
+    index: &mut SearchIndex,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut scanned = 0;
+    let mut last_id = index.last_imported_id()?;
+    loop {
+        let (messages, batch_last_id) = import_db.messages_for_index(last_id, INDEX_BATCH_SIZE)?;
+        let Some(batch_last_id) = batch_last_id else {
+            break;
+        };
+        scanned += (batch_last_id - last_id) as usize;
+        index.add_imported_messages(&messages, batch_last_id)?;
+        last_id = batch_last_id;
+    }
+    Ok(scanned)
+}
+
+/// Run a parsed query. Text terms are matched in the FTS index; operators are
+/// applied as SQL over chat.db, to the FTS candidates or on their own.
+pub fn search_messages(
+    conn: &Connection,
+    index: &SearchIndex,
+    context_db: &ContextDb,
+    query: &SearchQuery,
+    raw_query: &str,
+    limit: i64,
+) -> Result<MessageSearchResponse, Box<dyn std::error::Error>> {
+    let from_handles = query
+        .from
+        .iter()
+        .map(|name| resolve_name_handles(context_db, name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let chat_handles = query
+        .chats
+        .iter()
+        .map(|name| resolve_name_handles(context_db, name))
+        .collect::<Result<Vec<_>, _>>()?;
+    let filter = query.to_sql_filter(&from_handles, &chat_handles, apple_time_from_unix_ms);
+
+    let results = match query.fts_expression() {
+        Some(expression) if filter.is_empty() => index
+            .search(&expression, limit)?
+            .into_iter()
+            .map(|hit| search_result(hit, context_db))
+            .collect(),
+        Some(expression) => {
+            // Filter the best text matches, keeping their rank order
+            let hits = index.search(&expression, FILTERED_CANDIDATES)?;
+            let rowids: Vec<i64> = hits.iter().map(|hit| hit.rowid).collect();
+            let matching = fetch_filtered_message_ids(conn, &filter, &rowids)?;
+            hits.into_iter()
+                .filter(|hit| matching.contains(&hit.rowid))
+                .take(limit.max(0) as usize)
+                .map(|hit| search_result(hit, context_db))
+                .collect()
+        }
+        None if filter.is_empty() => Vec::new(),
+        None => fetch_filtered_messages(conn, context_db, &filter, limit)?,
+    };
+
+    let progress = index_progress(conn, index)?;
+    Ok(MessageSearchResponse {
+        results,
+        query: raw_query.to_string(),
+        indexing: progress.is_some(),
+        progress,
+    })
+}
+
+/// How far the indexer has got through chat.db, or None once it has caught up
+fn index_progress(
+    conn: &Connection,
+    index: &SearchIndex,
+) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+    let indexed = index.last_indexed_rowid()?;
+    let latest: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(ROWID), 0) FROM message",
+        [],
+        |row| row.get(0),
+    )?;
+    if indexed >= latest {
+        return Ok(None);
+    }
+    Ok(Some(indexed as f64 / latest as f64))
+}
+
+/// Handles whose cached contact name matches `name`, for `from:` and `in:`
+fn resolve_name_handles(
+    context_db: &ContextDb,
+    name: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if !should_search_contacts_by_name(name) {
+        return Ok(Vec::new());
+    }
+    Ok(context_db
+        .search_cached_contacts_by_name(name)?
+        .into_iter()
+        .map(|(handle, _)| handle)
+        .collect())
+}
+
+fn search_result(hit: SearchHit, context_db: &ContextDb) -> MessageSearchResult {
+    MessageSearchResult {
+        chat_id: hit.chat_id,
+        message_id: hit.rowid,
+        guid: hit.guid,
+        snippet: hit.snippet,
+        highlights: hit
+            .highlights
+            .into_iter()
+            .map(|(start, length)| SearchHighlight { start, length })
+            .collect(),
+        time: hit.time,
+        is_from_me: hit.is_from_me,
+        contact_name: hit
+            .handle
+            .as_ref()
+            .and_then(|h| get_contact_name(h, context_db)),
+        handle: hit.handle,
+    }
+}
+
+/// Keep the search index current: one full catch-up at startup, then an
+/// incremental sync after every chat.db change.
+pub async fn search_index_worker(
+    chat_pool: Pool<SqliteConnectionManager>,
+    mut db_change_rx: broadcast::Receiver<DbChangeEvent>,
+) {
+    loop {
+        let pool = chat_pool.clone();
+        let sync_start = Instant::now();
+        let result = tokio::task::spawn_blocking(move || {
+            let conn = pool.get().map_err(|e| e.to_string())?;
+            let mut index = SearchIndex::open().map_err(|e| e.to_string())?;
+            sync_search_index(&conn, &mut index).map_err(|e| e.to_string())
+        })
+        .await;
+
+        match result {
+            Ok(Ok(scanned)) if scanned > 0 => info!(
+                target: "search",
+                scanned,
+                duration_ms = sync_start.elapsed().as_millis(),
+                "Synced message search index"
+            ),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => error!(target: "search", "Failed to sync search index: {}", e),
+            Err(e) => error!(target: "search", "Search index sync task failed: {}", e),
+        }
+
+        // Tapbacks don't change any text; lagged receivers just sync once for
+        // all the missed changes
+        loop {
+            match db_change_rx.recv().await {
+                Ok(DbChangeEvent::ReactionChanged { .. }) => continue,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => break,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+}
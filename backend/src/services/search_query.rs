@@ -0,0 +1,396 @@
+use crate::chat_schema::column;
+use chrono::{Local, NaiveDate, TimeZone};
+use rusqlite::types::Value;
+
+// ============================================================================
+// SEARCH QUERY LANGUAGE
+// ============================================================================
+//
+// Gmail-style operators on top of full-text message search:
+// - from:NAME / from:me      sender, by contact name or handle
+// - in:NAME                  chat, by group name, identifier or participant
+// - before:DATE / after:DATE YYYY-MM-DD, local time (before is exclusive)
+// - has:attachment, has:link
+// - is:from-me, is:group
+// - "quoted phrase"          exact phrase, for both text and operator values
+// Anything else is free text. Unknown `key:value` words are treated as text
+// so times ("5:30") and URLs still search normally.
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchTerm {
+    Word(String),
+    Phrase(String),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SearchQuery {
+    pub terms: Vec<SearchTerm>,
+    /// `from:` values other than "me"
+    pub from: Vec<String>,
+    /// `in:` values
+    pub chats: Vec<String>,
+    pub before: Option<NaiveDate>,
+    pub after: Option<NaiveDate>,
+    pub has_attachment: bool,
+    pub has_link: bool,
+    pub is_from_me: bool,
+    pub is_group: bool,
+}
+
+/// Malformed query; `position` is a character offset into the query
+#[derive(Debug, PartialEq)]
+pub struct QueryParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryParseError {}
+
+/// WHERE clauses over `message m`, `chat_message_join cmj` and `handle h`
+#[derive(Debug, Default)]
+pub struct SqlFilter {
+    pub clauses: Vec<String>,
+    pub params: Vec<Value>,
+}
+
+impl SqlFilter {
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    pub fn where_sql(&self) -> String {
+        if self.clauses.is_empty() {
+            "1 = 1".to_string()
+        } else {
+            self.clauses.join(" AND ")
+        }
+    }
+}
+
+const OPERATORS: [&str; 6] = ["from", "in", "before", "after", "has", "is"];
+
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Scanner {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Read up to the next whitespace
+    fn read_word(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Read a `"..."` string starting at the opening quote
+    fn read_quoted(&mut self) -> Result<String, QueryParseError> {
+        let open = self.pos;
+        self.pos += 1;
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == '"' {
+                let value = self.chars[start..self.pos].iter().collect();
+                self.pos += 1;
+                return Ok(value);
+            }
+            self.pos += 1;
+        }
+        Err(QueryParseError {
+            position: open,
+            message: "Unterminated quote".to_string(),
+        })
+    }
+
+    /// Operator name at the cursor, if the next word is `key:`
+    fn operator(&self) -> Option<&'static str> {
+        let rest: String = self.chars[self.pos..]
+            .iter()
+            .take_while(|c| c.is_alphabetic())
+            .collect();
+        if self.chars.get(self.pos + rest.chars().count()) != Some(&':') {
+            return None;
+        }
+        let key = rest.to_lowercase();
+        OPERATORS.iter().copied().find(|op| *op == key)
+    }
+}
+
+pub fn parse_search_query(input: &str) -> Result<SearchQuery, QueryParseError> {
+    let mut scanner = Scanner {
+        chars: input.chars().collect(),
+        pos: 0,
+    };
+    let mut query = SearchQuery::default();
+
+    loop {
+        scanner.skip_whitespace();
+        let Some(c) = scanner.peek() else {
+            break;
+        };
+
+        if c == '"' {
+            let phrase = scanner.read_quoted()?;
+            if phrase.chars().any(char::is_alphanumeric) {
+                query.terms.push(SearchTerm::Phrase(phrase));
+            }
+            continue;
+        }
+
+        let Some(op) = scanner.operator() else {
+            let word = scanner.read_word().replace('"', "");
+            if word.chars().any(char::is_alphanumeric) {
+                query.terms.push(SearchTerm::Word(word));
+            }
+            continue;
+        };
+
+        scanner.pos += op.len() + 1;
+        let value_pos = scanner.pos;
+        let value = match scanner.peek() {
+            Some('"') => scanner.read_quoted()?,
+            _ => scanner.read_word(),
+        };
+        let value = value.trim().to_string();
+        let error = |message: String| QueryParseError {
+            position: value_pos,
+            message,
+        };
+        if value.is_empty() {
+            return Err(error(format!("Missing value for {}:", op)));
+        }
+
+        match op {
+            "from" if value.eq_ignore_ascii_case("me") => query.is_from_me = true,
+            "from" => query.from.push(value),
+            "in" => query.chats.push(value),
+            "before" | "after" => {
+                let date = parse_date(&value).ok_or_else(|| {
+                    error(format!("Expected a date like 2024-05-01 for {}:, got \"{}\"", op, value))
+                })?;
+                if op == "before" {
+                    query.before = Some(date);
+                } else {
+                    query.after = Some(date);
+                }
+            }
+            "has" => match value.to_lowercase().as_str() {
+                "attachment" | "attachments" => query.has_attachment = true,
+                "link" | "links" => query.has_link = true,
+                _ => return Err(error(format!("Unknown filter has:{}", value))),
+            },
+            _ => match value.to_lowercase().as_str() {
+                "from-me" | "fromme" => query.is_from_me = true,
+                "group" => query.is_group = true,
+                _ => return Err(error(format!("Unknown filter is:{}", value))),
+            },
+        }
+    }
+
+    Ok(query)
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y/%m/%d"))
+        .ok()
+}
+
+/// Local midnight at the start of `date`, in Unix ms
+pub fn local_day_start_ms(date: NaiveDate) -> Option<i64> {
+    let midnight = date.and_hms_opt(0, 0, 0)?;
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|dt| dt.timestamp_millis())
+}
+
+impl SearchQuery {
+    /// FTS5 expression for the text part of the query. Words are quoted so FTS5
+    /// syntax characters can't cause errors, and a trailing word matches as a
+    /// prefix so results update while typing.
+    pub fn fts_expression(&self) -> Option<String> {
+        let last = self.terms.len().checked_sub(1)?;
+        let parts: Vec<String> = self
+            .terms
+            .iter()
+            .enumerate()
+            .map(|(i, term)| match term {
+                SearchTerm::Word(word) if i == last => format!("\"{}\"*", word),
+                SearchTerm::Word(word) | SearchTerm::Phrase(word) => {
+                    format!("\"{}\"", word.replace('"', ""))
+                }
+            })
+            .collect();
+        Some(parts.join(" "))
+    }
+
+    /// Build SQL for the operators. `from_handles[i]` and `chat_handles[i]` are the
+    /// contact-cache handles whose name matched `from[i]` and `chats[i]`.
+    pub fn to_sql_filter(
+        &self,
+        from_handles: &[Vec<String>],
+        chat_handles: &[Vec<String>],
+        to_apple_time: impl Fn(i64) -> i64,
+    ) -> SqlFilter {
+        let mut filter = SqlFilter::default();
+
+        for (value, handles) in self.from.iter().zip(from_handles) {
+            let mut clause = String::from("(m.is_from_me = 0 AND (LOWER(h.id) LIKE ?");
+            filter.params.push(Value::Text(handle_pattern(value)));
+            push_handle_list(&mut clause, &mut filter.params, "h.id", handles);
+            clause.push_str("))");
+            filter.clauses.push(clause);
+        }
+
+        for (value, handles) in self.chats.iter().zip(chat_handles) {
+            let pattern = Value::Text(format!("%{}%", value.to_lowercase()));
+            let mut clause = format!(
+                "cmj.chat_id IN (
+                    SELECT c.ROWID FROM chat c
+                    LEFT JOIN chat_handle_join chj ON c.ROWID = chj.chat_id
+                    LEFT JOIN handle ch ON chj.handle_id = ch.ROWID
+                    WHERE LOWER(COALESCE({}, '')) LIKE ?
+                       OR LOWER(COALESCE(c.chat_identifier, '')) LIKE ?
+                       OR LOWER(COALESCE(ch.id, '')) LIKE ?",
+                column("chat", "c", "display_name")
+            );
+            filter.params.push(pattern.clone());
+            filter.params.push(pattern);
+            filter.params.push(Value::Text(handle_pattern(value)));
+            push_handle_list(&mut clause, &mut filter.params, "ch.id", handles);
+            clause.push(')');
+            filter.clauses.push(clause);
+        }
+
+        if let Some(ms) = self.after.and_then(local_day_start_ms) {
+            filter.clauses.push("m.date >= ?".to_string());
+            filter.params.push(Value::Integer(to_apple_time(ms)));
+        }
+        if let Some(ms) = self.before.and_then(local_day_start_ms) {
+            filter.clauses.push("m.date < ?".to_string());
+            filter.params.push(Value::Integer(to_apple_time(ms)));
+        }
+        if self.has_attachment {
+            filter
+                .clauses
+                .push(format!("{} = 1", column("message", "m", "cache_has_attachments")));
+        }
+        if self.has_link {
+            filter.clauses.push(format!(
+                "({} = 'com.apple.messages.URLBalloonProvider'
+                  OR m.text LIKE '%http://%' OR m.text LIKE '%https://%')",
+                column("message", "m", "balloon_bundle_id")
+            ));
+        }
+        if self.is_from_me {
+            filter.clauses.push("m.is_from_me = 1".to_string());
+        }
+        if self.is_group {
+            filter.clauses.push(
+                "(SELECT COUNT(*) FROM chat_handle_join gchj WHERE gchj.chat_id = cmj.chat_id) > 1"
+                    .to_string(),
+            );
+        }
+
+        filter
+    }
+}
+
+/// LIKE pattern for a handle: phone-number-looking values match on digits only,
+/// so "555-1234" finds "+15555551234"
+fn handle_pattern(value: &str) -> String {
+    let lower = value.to_lowercase();
+    let digits: String = lower.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.len() >= 3 && !lower.chars().any(char::is_alphabetic) {
+        format!("%{}%", digits)
+    } else {
+        format!("%{}%", lower)
+    }
+}
+
+fn push_handle_list(clause: &mut String, params: &mut Vec<Value>, column: &str, handles: &[String]) {
+    if handles.is_empty() {
+        return;
+    }
+    let placeholders = handles.iter().map(|_| "?").collect::<Vec<_>>().join(",");
+    clause.push_str(&format!(" OR {} IN ({})", column, placeholders));
+    params.extend(handles.iter().cloned().map(Value::Text));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_operators_phrases_and_words() {
+        let query = parse_search_query(
+            r#"from:"Sam Lee" in:Trail after:2024-05-01 has:link is:group "see you" dinn"#,
+        )
+        .unwrap();
+        assert_eq!(query.from, vec!["Sam Lee".to_string()]);
+        assert_eq!(query.chats, vec!["Trail".to_string()]);
+        assert_eq!(query.after, NaiveDate::from_ymd_opt(2024, 5, 1));
+        assert!(query.has_link && query.is_group && !query.has_attachment);
+        assert_eq!(
+            query.fts_expression().as_deref(),
+            Some("\"see you\" \"dinn\"*")
+        );
+    }
+
+    #[test]
+    fn treats_unknown_keys_as_text() {
+        let query = parse_search_query("meet 5:30 https://example.com from:me").unwrap();
+        assert!(query.is_from_me);
+        assert!(query.from.is_empty());
+        assert_eq!(query.terms.len(), 3);
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let err = parse_search_query("pizza before:tomorrow").unwrap_err();
+        assert_eq!(err.position, 13);
+
+        let err = parse_search_query("is:archived").unwrap_err();
+        assert_eq!(err.position, 3);
+
+        let err = parse_search_query("from: sam").unwrap_err();
+        assert_eq!(err.position, 5);
+
+        let err = parse_search_query("hi \"unfinished").unwrap_err();
+        assert_eq!(err.position, 3);
+        assert_eq!(err.message, "Unterminated quote");
+    }
+
+    #[test]
+    fn builds_sql_filters() {
+        let query = parse_search_query("from:555-1234 has:attachment").unwrap();
+        let filter = query.to_sql_filter(&[vec!["+15555551234".to_string()]], &[], |ms| ms);
+        assert_eq!(filter.clauses.len(), 2);
+        assert!(filter.clauses[0].contains("h.id IN (?)"));
+        assert_eq!(filter.params[0], Value::Text("%5551234%".to_string()));
+
+        assert!(parse_search_query("pizza")
+            .unwrap()
+            .to_sql_filter(&[], &[], |ms| ms)
+            .is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::models::{Reaction, ReactionGroup, TapbackKind};
+use std::collections::HashMap;
+
+// ============================================================================
+// TAPBACKS
+// ============================================================================
+//
+// Reactions are stored as their own `message` rows pointing at the target via
+// `associated_message_guid` ("p:0/GUID" or "bp:GUID"):
+// - 2000-2005: classic tapbacks (love, like, dislike, laugh, emphasize, question)
+// - 2006: custom emoji tapback, the emoji is in `associated_message_emoji`
+// - 2007: sticker tapback, the sticker is an attachment on the reaction row
+// - 3000-3007: removal of the matching 200x tapback
+// - 1000: sticker placed on a bubble (pre-tapback stickers, never removed)
+//
+// A person has at most one tapback per message part (each attachment or text
+// run of a message is a part): a newer one replaces the old, and a removal
+// clears it. Rows must be replayed in date order to get the net state.
+// ============================================================================
+
+/// A single reaction row, as read from chat.db
+pub struct TapbackEvent {
+    /// GUID of the message being reacted to (already normalized)
+    pub target_guid: String,
+    /// Part of the target the reaction is on (the `N` in "p:N/GUID")
+    pub part: u32,
+    pub associated_message_type: i32,
+    pub custom_emoji: Option<String>,
+    pub is_from_me: bool,
+    pub handle_id: i64,
+    pub handle: Option<String>,
+    /// Raw `message.date`, used to replay rows in order
+    pub date: i64,
+    /// `date` in Unix ms
+    pub time: i64,
+    pub sticker_attachment_id: Option<i64>,
+}
+
+/// Decode `associated_message_type` into a tapback kind and whether it is a removal
+pub fn tapback_kind(associated_message_type: i32) -> Option<(TapbackKind, bool)> {
+    let is_removal = match associated_message_type {
+        1000 => return Some((TapbackKind::Sticker, false)),
+        2000..=2007 => false,
+        3000..=3007 => true,
+        _ => return None,
+    };
+
+    let kind = match associated_message_type % 1000 {
+        0 => TapbackKind::Love,
+        1 => TapbackKind::Like,
+        2 => TapbackKind::Dislike,
+        3 => TapbackKind::Laugh,
+        4 => TapbackKind::Emphasize,
+        5 => TapbackKind::Question,
+        6 => TapbackKind::Emoji,
+        _ => TapbackKind::Sticker,
+    };
+    Some((kind, is_removal))
+}
+
+pub fn tapback_emoji(kind: TapbackKind, custom_emoji: Option<&str>) -> String {
+    match kind {
+        TapbackKind::Love => "❤️",
+        TapbackKind::Like => "👍",
+        TapbackKind::Dislike => "👎",
+        TapbackKind::Laugh => "😂",
+        TapbackKind::Emphasize => "‼️",
+        TapbackKind::Question => "❓",
+        TapbackKind::Emoji => custom_emoji.filter(|e| !e.is_empty()).unwrap_or("❔"),
+        TapbackKind::Sticker => "🖼️",
+    }
+    .to_string()
+}
+
+/// Verb used in chat-list previews, e.g. `loved "see you soon"`
+pub fn tapback_verb(kind: TapbackKind, custom_emoji: Option<&str>) -> String {
+    match kind {
+        TapbackKind::Love => "loved".to_string(),
+        TapbackKind::Like => "liked".to_string(),
+        TapbackKind::Dislike => "disliked".to_string(),
+        TapbackKind::Laugh => "laughed at".to_string(),
+        TapbackKind::Emphasize => "emphasized".to_string(),
+        TapbackKind::Question => "questioned".to_string(),
+        TapbackKind::Emoji => format!("reacted {} to", tapback_emoji(kind, custom_emoji)),
+        TapbackKind::Sticker => "reacted with a sticker to".to_string(),
+    }
+}
+
+/// Preview text for a removal row, e.g. "removed ❤️"
+pub fn tapback_removal_text(kind: TapbackKind, custom_emoji: Option<&str>) -> String {
+    match kind {
+        TapbackKind::Sticker => "removed a sticker".to_string(),
+        _ => format!("removed {}", tapback_emoji(kind, custom_emoji)),
+    }
+}
+
+/// Replay reaction rows and return the reactions still standing, keyed by target GUID
+pub fn net_reactions(mut events: Vec<TapbackEvent>) -> HashMap<String, Vec<Reaction>> {
+    events.sort_by_key(|event| event.date);
+
+    // (target guid, part, reactor) -> current tapback. Stickers placed with type 1000
+    // aren't tapbacks, so each one stands on its own instead of replacing.
+    let mut current: HashMap<(String, u32, bool, i64), TapbackEvent> = HashMap::new();
+    let mut placed_stickers: Vec<TapbackEvent> = Vec::new();
+
+    for event in events {
+        let Some((kind, is_removal)) = tapback_kind(event.associated_message_type) else {
+            continue;
+        };
+        if event.associated_message_type == 1000 {
+            placed_stickers.push(event);
+            continue;
+        }
+
+        // Reactions from me can come from any of my handles
+        let reactor_handle = if event.is_from_me { 0 } else { event.handle_id };
+        let key = (event.target_guid.clone(), event.part, event.is_from_me, reactor_handle);
+        if is_removal {
+            let matches_current = current.get(&key).is_some_and(|existing| {
+                tapback_kind(existing.associated_message_type).map(|(k, _)| k) == Some(kind)
+                    && (kind != TapbackKind::Emoji || existing.custom_emoji == event.custom_emoji)
+            });
+            if matches_current {
+                current.remove(&key);
+            }
+        } else {
+            current.insert(key, event);
+        }
+    }
+
+    let mut standing: Vec<TapbackEvent> = current.into_values().chain(placed_stickers).collect();
+    standing.sort_by_key(|event| event.date);
+
+    let mut reactions: HashMap<String, Vec<Reaction>> = HashMap::new();
+    for event in standing {
+        let Some((kind, _)) = tapback_kind(event.associated_message_type) else {
+            continue;
+        };
+        reactions
+            .entry(event.target_guid.clone())
+            .or_default()
+            .push(Reaction {
+                emoji: tapback_emoji(kind, event.custom_emoji.as_deref()),
+                is_from_me: event.is_from_me,
+                handle: event.handle,
+                contact_name: None,
+                time: event.time,
+                kind,
+                part: event.part,
+                sticker_attachment_id: event.sticker_attachment_id,
+            });
+    }
+    reactions
+}
+
+/// Group reactions by emoji, in the order each emoji was first used
+pub fn group_reactions(reactions: Vec<Reaction>) -> Vec<ReactionGroup> {
+    let mut groups: Vec<ReactionGroup> = Vec::new();
+    for reaction in reactions {
+        match groups.iter_mut().find(|group| group.emoji == reaction.emoji) {
+            Some(group) => group.reactors.push(reaction),
+            None => groups.push(ReactionGroup {
+                emoji: reaction.emoji.clone(),
+                kind: reaction.kind,
+                count: 0,
+                reactors: vec![reaction],
+            }),
+        }
+    }
+    for group in &mut groups {
+        group.count = group.reactors.len();
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: i32, handle_id: i64, date: i64) -> TapbackEvent {
+        TapbackEvent {
+            target_guid: "TARGET".to_string(),
+            part: 0,
+            associated_message_type: kind,
+            custom_emoji: None,
+            is_from_me: handle_id == 0,
+            handle_id,
+            handle: (handle_id != 0).then(|| format!("+1555555010{}", handle_id)),
+            date,
+            time: date,
+            sticker_attachment_id: None,
+        }
+    }
+
+    fn emojis(events: Vec<TapbackEvent>) -> Vec<String> {
+        net_reactions(events)
+            .remove("TARGET")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| r.emoji)
+            .collect()
+    }
+
+    #[test]
+    fn removal_clears_tapback() {
+        assert!(emojis(vec![event(2000, 1, 1), event(3000, 1, 2)]).is_empty());
+    }
+
+    #[test]
+    fn newer_tapback_replaces_older_from_same_person() {
+        assert_eq!(emojis(vec![event(2000, 1, 1), event(2003, 1, 2)]), vec!["😂"]);
+    }
+
+    #[test]
+    fn stale_removal_does_not_clear_replacement() {
+        // Switching love -> like can emit the like before the love removal
+        let events = vec![event(2000, 1, 1), event(2001, 1, 2), event(3000, 1, 3)];
+        assert_eq!(emojis(events), vec!["👍"]);
+    }
+
+    #[test]
+    fn reactors_are_tracked_independently() {
+        let events = vec![event(2000, 1, 1), event(2001, 2, 2), event(2003, 0, 3), event(3000, 1, 4)];
+        assert_eq!(emojis(events), vec!["👍", "😂"]);
+    }
+
+    #[test]
+    fn custom_emoji_tapbacks() {
+        let mut add = event(2006, 1, 1);
+        add.custom_emoji = Some("🔥".to_string());
+        let mut other_removal = event(3006, 1, 2);
+        other_removal.custom_emoji = Some("🎉".to_string());
+        assert_eq!(emojis(vec![add, other_removal]), vec!["🔥"]);
+
+        let mut add = event(2006, 1, 1);
+        add.custom_emoji = Some("🔥".to_string());
+        let mut removal = event(3006, 1, 2);
+        removal.custom_emoji = Some("🔥".to_string());
+        assert!(emojis(vec![add, removal]).is_empty());
+    }
+
+    #[test]
+    fn sticker_tapbacks_keep_their_attachment() {
+        let mut sticker = event(2007, 1, 1);
+        sticker.sticker_attachment_id = Some(42);
+        let reactions = net_reactions(vec![sticker]).remove("TARGET").unwrap();
+        assert_eq!(reactions[0].kind, TapbackKind::Sticker);
+        assert_eq!(reactions[0].sticker_attachment_id, Some(42));
+    }
+
+    #[test]
+    fn parts_of_a_message_hold_separate_tapbacks() {
+        let mut on_photo = event(2001, 1, 2);
+        on_photo.part = 1;
+        let mut photo_removal = event(3001, 1, 3);
+        photo_removal.part = 1;
+        let reactions = net_reactions(vec![event(2000, 1, 1), on_photo]).remove("TARGET").unwrap();
+        let parts: Vec<_> = reactions.iter().map(|r| (r.emoji.as_str(), r.part)).collect();
+        assert_eq!(parts, vec![("❤️", 0), ("👍", 1)]);
+
+        let events = vec![event(2000, 1, 1), event(2001, 1, 2), photo_removal];
+        assert_eq!(emojis(events), vec!["👍"]);
+    }
+
+    #[test]
+    fn out_of_order_rows_are_replayed_by_date() {
+        assert!(emojis(vec![event(3002, 1, 5), event(2002, 1, 4)]).is_empty());
+    }
+
+    #[test]
+    fn groups_reactors_by_emoji() {
+        let events = vec![event(2000, 1, 1), event(2001, 2, 2), event(2000, 3, 3), event(2000, 0, 4)];
+        let reactions = net_reactions(events).remove("TARGET").unwrap();
+        let groups = group_reactions(reactions);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].emoji, "❤️");
+        assert_eq!(groups[0].count, 3);
+        assert_eq!(groups[0].reactors[1].handle.as_deref(), Some("+15555550103"));
+        assert!(groups[0].reactors[2].is_from_me);
+        assert_eq!(groups[1].kind, TapbackKind::Like);
+    }
+}
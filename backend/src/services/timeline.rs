@@ -0,0 +1,85 @@
+use crate::models::MessageKind;
+
+// ============================================================================
+// TIMELINE EVENTS
+// ============================================================================
+//
+// Group changes are stored as `message` rows with a non-zero `item_type`:
+// - 1: participant change, `other_handle` is who was affected
+//      (group_action_type 0 = added, 1 = removed)
+// - 2: group renamed, the new name is in `group_title`
+// - 3: group_action_type 0 = member left, 1 = photo changed, 2 = photo removed
+// The actor is the row's sender (`handle_id`, or me when `is_from_me`).
+// ============================================================================
+
+pub fn timeline_kind(item_type: i64, group_action_type: i64) -> MessageKind {
+    match (item_type, group_action_type) {
+        (0, _) => MessageKind::Message,
+        (1, 0) => MessageKind::ParticipantAdded,
+        (1, 1) => MessageKind::ParticipantRemoved,
+        (2, _) => MessageKind::GroupRenamed,
+        (3, 0) => MessageKind::ParticipantLeft,
+        (3, 1) => MessageKind::GroupPhotoChanged,
+        (3, 2) => MessageKind::GroupPhotoRemoved,
+        _ => MessageKind::System,
+    }
+}
+
+/// Describe a timeline event, e.g. "Alex added Sam to the group".
+/// `actor` is None when the event came from me.
+pub fn describe_event(
+    kind: MessageKind,
+    actor: Option<&str>,
+    target: Option<&str>,
+    group_title: Option<&str>,
+) -> Option<String> {
+    let actor = actor.unwrap_or("You");
+    let target = target.unwrap_or("someone");
+    let description = match kind {
+        MessageKind::Message | MessageKind::System => return None,
+        MessageKind::ParticipantAdded => format!("{} added {} to the group", actor, target),
+        MessageKind::ParticipantRemoved => format!("{} removed {} from the group", actor, target),
+        MessageKind::ParticipantLeft => format!("{} left the group", actor),
+        MessageKind::GroupRenamed => match group_title.filter(|t| !t.trim().is_empty()) {
+            Some(title) => format!("{} named the group \"{}\"", actor, title),
+            None => format!("{} removed the group name", actor),
+        },
+        MessageKind::GroupPhotoChanged => format!("{} changed the group photo", actor),
+        MessageKind::GroupPhotoRemoved => format!("{} removed the group photo", actor),
+    };
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_item_and_group_action_types() {
+        assert_eq!(timeline_kind(0, 0), MessageKind::Message);
+        assert_eq!(timeline_kind(1, 0), MessageKind::ParticipantAdded);
+        assert_eq!(timeline_kind(1, 1), MessageKind::ParticipantRemoved);
+        assert_eq!(timeline_kind(2, 0), MessageKind::GroupRenamed);
+        assert_eq!(timeline_kind(3, 0), MessageKind::ParticipantLeft);
+        assert_eq!(timeline_kind(3, 1), MessageKind::GroupPhotoChanged);
+        assert_eq!(timeline_kind(3, 2), MessageKind::GroupPhotoRemoved);
+        assert_eq!(timeline_kind(6, 0), MessageKind::System);
+    }
+
+    #[test]
+    fn describes_events() {
+        assert_eq!(
+            describe_event(MessageKind::ParticipantAdded, Some("Alex"), Some("Sam"), None).as_deref(),
+            Some("Alex added Sam to the group")
+        );
+        assert_eq!(
+            describe_event(MessageKind::GroupRenamed, None, None, Some("Trail crew")).as_deref(),
+            Some("You named the group \"Trail crew\"")
+        );
+        assert_eq!(
+            describe_event(MessageKind::GroupRenamed, Some("Alex"), None, Some("")).as_deref(),
+            Some("Alex removed the group name")
+        );
+        assert_eq!(describe_event(MessageKind::Message, Some("Alex"), None, None), None);
+    }
+}
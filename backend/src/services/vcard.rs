@@ -0,0 +1,489 @@
+use crate::context_db::{BasicInfo, ContactContext, ContextDb};
+use crate::services::address_book::ContactCard;
+use crate::services::avatars::content_hash;
+use crate::services::contacts::{save_person_photo, stored_contact_photo, HandleMatcher};
+use base64::Engine;
+use chrono::NaiveDate;
+use serde::Serialize;
+
+// ============================================================================
+// VCARDS
+// ============================================================================
+//
+// Contacts can be bootstrapped from .vcf files (Google Contacts, an iPhone, a
+// test fixture) instead of Contacts.app. Import reads vCard 3.0 and 4.0:
+// - FN (or N) becomes the person's name, every TEL and EMAIL one of their
+//   handles, the same way Contacts cards are loaded (see address_book.rs)
+// - BDAY fills the person's birthday and `BasicInfo.birthday`
+// - an inline PHOTO becomes the person's photo; linked photos are skipped
+// Export writes vCard 3.0, which every contacts app reads, with the AI notes
+// from each handle's `ContactContext` in NOTE.
+// ============================================================================
+
+/// One card read from a .vcf file
+#[derive(Debug, Default, PartialEq)]
+pub struct VCard {
+    pub name: Option<String>,
+    /// Phone numbers, then emails
+    pub handles: Vec<String>,
+    /// YYYY-MM-DD, or --MM-DD without a year
+    pub birthday: Option<String>,
+    pub photo: Option<Vec<u8>>,
+}
+
+/// Counts reported back to the client after an import
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct VCardImportSummary {
+    pub contacts: usize,
+    pub handles: usize,
+    pub photos: usize,
+    /// Cards with no name or no number or email
+    pub skipped: usize,
+}
+
+/// What goes into an exported card
+pub struct VCardExport<'a> {
+    pub name: Option<&'a str>,
+    pub handles: &'a [String],
+    pub birthday: Option<&'a str>,
+    pub notes: Option<&'a str>,
+    /// JPEG
+    pub photo: Option<&'a [u8]>,
+}
+
+/// Every card in a .vcf file
+pub fn parse_vcards(text: &str) -> Vec<VCard> {
+    let mut cards = Vec::new();
+    let mut current: Option<VCard> = None;
+    // Structured name, used when a card has no FN
+    let mut structured_name: Option<String> = None;
+
+    for line in unfold(text) {
+        let Some((name, params, value)) = split_property(&line) else {
+            continue;
+        };
+        match name.as_str() {
+            "BEGIN" if value.eq_ignore_ascii_case("VCARD") => {
+                current = Some(VCard::default());
+                structured_name = None;
+            }
+            "END" if value.eq_ignore_ascii_case("VCARD") => {
+                if let Some(mut card) = current.take() {
+                    card.name = card.name.or(structured_name.take());
+                    cards.push(card);
+                }
+            }
+            _ => {
+                let Some(card) = current.as_mut() else {
+                    continue;
+                };
+                match name.as_str() {
+                    "FN" => {
+                        card.name = Some(unescape(value)).filter(|name| !name.trim().is_empty())
+                    }
+                    "N" => structured_name = name_from_components(value),
+                    "TEL" => push_handle(&mut card.handles, value, "tel:"),
+                    "EMAIL" => push_handle(&mut card.handles, value, "mailto:"),
+                    "BDAY" => card.birthday = parse_birthday(value, &params),
+                    "PHOTO" => card.photo = decode_photo(value, &params),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Numbers first, as Contacts lists them
+    for card in &mut cards {
+        card.handles.sort_by_key(|handle| handle.contains('@'));
+    }
+    cards
+}
+
+/// Join folded lines: a line starting with a space or tab continues the last one
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// "item1.TEL;TYPE=CELL:+1 555" -> ("TEL", ["TYPE=CELL"], "+1 555")
+fn split_property(line: &str) -> Option<(String, Vec<String>, &str)> {
+    // Parameter values may be quoted and contain ':'
+    let mut in_quotes = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            in_quotes = !in_quotes;
+            None
+        }
+        ':' if !in_quotes => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = head.split(';');
+    let name = parts.next()?;
+    let name = name.rsplit('.').next()?.trim().to_ascii_uppercase();
+    Some((name, parts.map(|param| param.to_string()).collect(), value))
+}
+
+/// Value of parameter `key`, e.g. "ENCODING" in "ENCODING=b"
+fn param<'a>(params: &'a [String], key: &str) -> Option<&'a str> {
+    params.iter().find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.eq_ignore_ascii_case(key)
+            .then(|| value.trim_matches('"'))
+    })
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+    result
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+}
+
+/// "Rivera;Sam;;Dr.;" -> "Sam Rivera"
+fn name_from_components(value: &str) -> Option<String> {
+    let components: Vec<String> = value.split(';').map(unescape).collect();
+    let name = [components.get(1), components.first()]
+        .into_iter()
+        .flatten()
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!name.is_empty()).then_some(name)
+}
+
+fn push_handle(handles: &mut Vec<String>, value: &str, uri_scheme: &str) {
+    let value = unescape(value);
+    let value = value.trim();
+    let handle = match value.get(..uri_scheme.len()) {
+        Some(scheme) if scheme.eq_ignore_ascii_case(uri_scheme) => &value[uri_scheme.len()..],
+        _ => value,
+    };
+    if !handle.is_empty() && !handles.iter().any(|known| known == handle) {
+        handles.push(handle.to_string());
+    }
+}
+
+/// BDAY as YYYY-MM-DD or --MM-DD. Accepts 1990-04-12, 19900412, --0412 and
+/// --04-12, with or without a time, and Apple's X-APPLE-OMIT-YEAR.
+fn parse_birthday(value: &str, params: &[String]) -> Option<String> {
+    let date = value.trim().split('T').next()?;
+    if let Some(month_day) = date.strip_prefix("--") {
+        let digits: String = month_day.chars().filter(|c| c.is_ascii_digit()).collect();
+        // Validate against a leap year so --02-29 is allowed
+        let parsed = NaiveDate::parse_from_str(&format!("2000{}", digits), "%Y%m%d").ok()?;
+        return Some(parsed.format("--%m-%d").to_string());
+    }
+    let digits: String = date.chars().filter(|c| c.is_ascii_digit()).collect();
+    let parsed = NaiveDate::parse_from_str(&digits, "%Y%m%d").ok()?;
+    let omitted_year = param(params, "X-APPLE-OMIT-YEAR");
+    if omitted_year.is_some_and(|year| parsed.format("%Y").to_string() == year) {
+        return Some(parsed.format("--%m-%d").to_string());
+    }
+    Some(parsed.format("%Y-%m-%d").to_string())
+}
+
+/// Inline photo data: 3.0's `ENCODING=b` or 4.0's `data:` URI
+fn decode_photo(value: &str, params: &[String]) -> Option<Vec<u8>> {
+    let data = if let Some(uri) = value.strip_prefix("data:") {
+        uri.split_once("base64,")?.1
+    } else if param(params, "ENCODING").is_some_and(|encoding| {
+        encoding.eq_ignore_ascii_case("b") || encoding.eq_ignore_ascii_case("base64")
+    }) {
+        value
+    } else {
+        return None;
+    };
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    base64::engine::general_purpose::STANDARD.decode(data).ok()
+}
+
+/// Load `text`'s cards into the people directory, with birthdays copied into
+/// each handle's context and photos saved for their person
+pub fn import_vcards(
+    text: &str,
+    matcher: &mut HandleMatcher,
+    context_db: &ContextDb,
+) -> Result<VCardImportSummary, Box<dyn std::error::Error>> {
+    let mut summary = VCardImportSummary::default();
+    let mut cards = Vec::new();
+    let mut photos = Vec::new();
+    for vcard in parse_vcards(text) {
+        let Some(name) = vcard.name.filter(|_| !vcard.handles.is_empty()) else {
+            summary.skipped += 1;
+            continue;
+        };
+        // Match chat.db's spelling of each number so lookups by handle find it
+        let handles: Vec<String> = vcard
+            .handles
+            .iter()
+            .map(|handle| matcher.resolve(handle))
+            .collect();
+        let photo_hash = vcard.photo.as_deref().map(content_hash);
+        if let Some(photo) = vcard.photo {
+            photos.push((handles[0].clone(), photo));
+        }
+        summary.contacts += 1;
+        summary.handles += handles.len();
+        cards.push(ContactCard {
+            name,
+            handles,
+            birthday: vcard.birthday,
+            photo_hash,
+        });
+    }
+    context_db.apply_contact_cards(&cards)?;
+
+    for card in &cards {
+        let Some(birthday) = &card.birthday else {
+            continue;
+        };
+        for handle in &card.handles {
+            set_context_birthday(context_db, handle, birthday)?;
+        }
+    }
+    for (handle, photo) in photos {
+        if let Some(person_id) = context_db.person_id(&handle)? {
+            save_person_photo(context_db, person_id, &photo)?;
+            summary.photos += 1;
+        }
+    }
+    Ok(summary)
+}
+
+/// Fill in `BasicInfo.birthday` unless the context already has one
+fn set_context_birthday(
+    context_db: &ContextDb,
+    handle: &str,
+    birthday: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut context = match context_db.get_context(handle)? {
+        Some(context) if context.basic_info.birthday.is_some() => return Ok(()),
+        Some(context) => context,
+        None => {
+            let now = chrono::Utc::now().timestamp();
+            ContactContext {
+                handle: handle.to_string(),
+                display_name: None,
+                basic_info: BasicInfo::default(),
+                notes: None,
+                last_analyzed_at: None,
+                last_analyzed_message_id: None,
+                created_at: now,
+                updated_at: now,
+            }
+        }
+    };
+    context.basic_info.birthday = Some(birthday.to_string());
+    context_db.save_context(&context)
+}
+
+/// The card for `handle`'s person: every handle, their name and birthday, the
+/// AI notes from each handle's context, and any stored photo. None if nothing
+/// is known about the handle.
+pub fn export_vcard(
+    handle: &str,
+    context_db: &ContextDb,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let person = match context_db.person_id(handle)? {
+        Some(id) => context_db.get_person(id)?,
+        None => None,
+    };
+    let (name, handles, mut birthday) = match person {
+        Some(person) => (person.display_name, person.handles, person.birthday),
+        None => (
+            context_db.get_cached_contact_name(handle)?,
+            vec![handle.to_string()],
+            None,
+        ),
+    };
+
+    let mut notes = Vec::new();
+    let mut has_context = false;
+    for handle in &handles {
+        if let Some(context) = context_db.get_context(handle)? {
+            has_context = true;
+            birthday = birthday.or(context.basic_info.birthday);
+            notes.extend(context.notes.filter(|notes| !notes.trim().is_empty()));
+        }
+    }
+    if name.is_none() && !has_context {
+        return Ok(None);
+    }
+
+    let notes = notes.join("\n\n");
+    let photo = stored_contact_photo(handle);
+    Ok(Some(write_vcard(&VCardExport {
+        name: name.as_deref(),
+        handles: &handles,
+        birthday: birthday.as_deref(),
+        notes: Some(notes.as_str()),
+        photo: photo.as_deref(),
+    })))
+}
+
+/// A vCard 3.0 for one person
+pub fn write_vcard(export: &VCardExport) -> String {
+    let mut lines = vec!["BEGIN:VCARD".to_string(), "VERSION:3.0".to_string()];
+    let name = export
+        .name
+        .or(export.handles.first().map(|handle| handle.as_str()));
+    if let Some(name) = name {
+        lines.push(format!("FN:{}", escape(name)));
+        // N is required in 3.0: family name last
+        let (given, family) = match name.rsplit_once(' ') {
+            Some((given, family)) if export.name.is_some() => (given, family),
+            _ => (name, ""),
+        };
+        lines.push(format!("N:{};{};;;", escape(family), escape(given)));
+    }
+    for handle in export.handles {
+        if handle.contains('@') {
+            lines.push(format!("EMAIL;TYPE=INTERNET:{}", escape(handle)));
+        } else {
+            lines.push(format!("TEL;TYPE=CELL:{}", escape(handle)));
+        }
+    }
+    match export.birthday {
+        // 3.0 has no year-less dates; Contacts' own extension marks a placeholder year
+        Some(birthday) if birthday.starts_with("--") => {
+            lines.push(format!(
+                "BDAY;X-APPLE-OMIT-YEAR=1604:1604{}",
+                &birthday[1..]
+            ));
+        }
+        Some(birthday) => lines.push(format!("BDAY:{}", birthday)),
+        None => {}
+    }
+    if let Some(notes) = export.notes.filter(|notes| !notes.trim().is_empty()) {
+        lines.push(format!("NOTE:{}", escape(notes)));
+    }
+    if let Some(photo) = export.photo {
+        lines.push(format!(
+            "PHOTO;ENCODING=b;TYPE=JPEG:{}",
+            base64::engine::general_purpose::STANDARD.encode(photo)
+        ));
+    }
+    lines.push("END:VCARD".to_string());
+
+    lines
+        .iter()
+        .map(|line| fold(line))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// Fold a content line at 75 octets without splitting a character, ending it in CRLF
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + line.len() / 74 * 3 + 2);
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_vcard_3_and_4() {
+        let text = "BEGIN:VCARD\r\n\
+            VERSION:3.0\r\n\
+            N:Rivera;Sam;;;\r\n\
+            item1.TEL;type=CELL;type=pref:+1 (555) 000-1234\r\n\
+            TEL;TYPE=HOME:555-000-9999\r\n\
+            EMAIL;TYPE=INTERNET:sam@example.com\r\n\
+            BDAY;X-APPLE-OMIT-YEAR=1604:1604-04-03\r\n\
+            PHOTO;ENCODING=b;TYPE=JPEG:/9j/\r\n \
+            4AA=\r\n\
+            END:VCARD\r\n\
+            BEGIN:VCARD\r\n\
+            VERSION:4.0\r\n\
+            FN:Crag Gym\\, Downtown\r\n\
+            EMAIL:info@crag.example\r\n\
+            TEL;VALUE=uri:tel:+15550004444\r\n\
+            BDAY:19900412\r\n\
+            PHOTO:data:image/jpeg;base64,/9j/4AA=\r\n\
+            END:VCARD\r\n\
+            BEGIN:VCARD\r\n\
+            VERSION:4.0\r\n\
+            FN:No handles\r\n\
+            END:VCARD\r\n";
+
+        let cards = parse_vcards(text);
+        assert_eq!(cards.len(), 3);
+        assert_eq!(cards[0].name.as_deref(), Some("Sam Rivera"));
+        assert_eq!(
+            cards[0].handles,
+            vec!["+1 (555) 000-1234", "555-000-9999", "sam@example.com"]
+        );
+        assert_eq!(cards[0].birthday.as_deref(), Some("--04-03"));
+        assert_eq!(
+            cards[0].photo.as_deref(),
+            Some(&[0xff, 0xd8, 0xff, 0xe0, 0x00][..])
+        );
+        assert_eq!(cards[1].name.as_deref(), Some("Crag Gym, Downtown"));
+        assert_eq!(cards[1].handles, vec!["+15550004444", "info@crag.example"]);
+        assert_eq!(cards[1].birthday.as_deref(), Some("1990-04-12"));
+        assert_eq!(cards[1].photo, cards[0].photo);
+    }
+
+    #[test]
+    fn round_trips_an_export() {
+        let handles = vec!["+15550001234".to_string(), "sam@example.com".to_string()];
+        let notes = "Climbs on weekends; training for a 5.12.\nAllergic to cats.".repeat(3);
+        let text = write_vcard(&VCardExport {
+            name: Some("Sam Rivera"),
+            handles: &handles,
+            birthday: Some("--04-03"),
+            notes: Some(&notes),
+            photo: Some(&[0xff, 0xd8, 0xff]),
+        });
+        assert!(text.lines().all(|line| line.len() <= 76));
+        assert!(text.contains("\r\nNOTE:Climbs on weekends\\; training for a 5.12.\\nAllergic"));
+
+        let cards = parse_vcards(&text);
+        assert_eq!(
+            cards,
+            vec![VCard {
+                name: Some("Sam Rivera".to_string()),
+                handles,
+                birthday: Some("--04-03".to_string()),
+                photo: Some(vec![0xff, 0xd8, 0xff]),
+            }]
+        );
+    }
+}
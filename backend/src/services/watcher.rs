@@ -1,5 +1,8 @@
-use crate::state::DbChangeEvent;
+use crate::services::changes::ChangeTracker;
+use crate::state::{ChatSummaryCache, DbChangeEvent};
 use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
 use std::time::Duration;
 use tokio::sync::broadcast;
 use tracing::{error, info};
@@ -16,14 +19,30 @@ use tracing::{error, info};
 // - FSEvents (macOS) / inotify (Linux) are kernel-level file monitoring APIs
 // - The `notify` crate abstracts over platform-specific APIs
 // - Debouncing batches rapid events (chat.db can change 20x for one message)
+// - After each change, `ChangeTracker` works out what changed so we can send
+//   typed events instead of "something changed"
+// - The chat summaries are updated before the events go out, so clients that
+//   refetch the chat list in response see the change
 // ============================================================================
 
 pub async fn start_file_watcher(
     db_path: &str,
+    chat_pool: Pool<SqliteConnectionManager>,
+    chat_summaries: ChatSummaryCache,
     tx: broadcast::Sender<DbChangeEvent>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let messages_dir = std::path::Path::new(db_path).parent().unwrap().to_path_buf();
-    let db_path_buf = std::path::PathBuf::from(db_path);
+    // Any copy of chat.db can be served, so watch its directory and match on
+    // its own file name (plus the -wal/-shm sidecars) rather than "chat.db"
+    let db_path_buf = std::fs::canonicalize(db_path)?;
+    let messages_dir = db_path_buf
+        .parent()
+        .ok_or("chat.db path has no parent directory")?
+        .to_path_buf();
+    let db_file_name = db_path_buf
+        .file_name()
+        .ok_or("chat.db path has no file name")?
+        .to_string_lossy()
+        .into_owned();
 
     info!(target: "watcher", "Watching for changes: {}", messages_dir.display());
 
@@ -60,9 +79,12 @@ pub async fn start_file_watcher(
         loop {
             match file_rx.recv() {
                 Ok(Ok(events)) => {
-                    let db_changed = events
-                        .iter()
-                        .any(|event| event.path.to_string_lossy().contains("chat.db"));
+                    let db_changed = events.iter().any(|event| {
+                        event
+                            .path
+                            .file_name()
+                            .is_some_and(|name| name.to_string_lossy().starts_with(&db_file_name))
+                    });
 
                     if db_changed {
                         info!(target: "watcher", "FSEvents: Detected chat.db change");
@@ -82,9 +104,11 @@ pub async fn start_file_watcher(
 
     // Also spawn a polling task as fallback (iMessage doesn't always trigger FSEvents)
     let poll_tx = async_tx.clone();
-    let poll_db_path = db_path_buf.clone();
+    // Check if the WAL file was modified (more reliable than main db file)
+    let mut wal_path = db_path_buf.clone().into_os_string();
+    wal_path.push("-wal");
     tokio::spawn(async move {
-        let mut last_modified = std::fs::metadata(&poll_db_path)
+        let mut last_modified = std::fs::metadata(&wal_path)
             .and_then(|m| m.modified())
             .ok();
 
@@ -96,8 +120,6 @@ pub async fn start_file_watcher(
         loop {
             tokio::time::sleep(Duration::from_secs(2)).await;
 
-            // Check if the WAL file was modified (more reliable than main db file)
-            let wal_path = poll_db_path.with_extension("db-wal");
             let current_modified = std::fs::metadata(&wal_path)
                 .and_then(|m| m.modified())
                 .ok();
@@ -115,19 +137,79 @@ pub async fn start_file_watcher(
         }
     });
 
-    // Async loop: receive signals and broadcast to WebSocket clients
+    // Async loop: receive signals, work out what changed, and broadcast it
+    let init_pool = chat_pool.clone();
+    let mut tracker = tokio::task::spawn_blocking(move || {
+        let conn = init_pool.get().ok()?;
+        ChangeTracker::new(&conn)
+            .map_err(|e| error!(target: "watcher", "Failed to start change tracking: {}", e))
+            .ok()
+    })
+    .await
+    .ok()
+    .flatten();
+
     while let Some(()) = async_rx.recv().await {
-        let event = DbChangeEvent {
-            timestamp: chrono::Utc::now().timestamp_millis(),
+        let pool = chat_pool.clone();
+        let summaries = chat_summaries.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let conn = pool.get().map_err(|e| e.to_string())?;
+            let mut tracker = match tracker {
+                Some(tracker) => tracker,
+                // Tracking wasn't running yet: start from here, this change can't be attributed
+                None => {
+                    let tracker = ChangeTracker::new(&conn).map_err(|e| e.to_string())?;
+                    invalidate_summaries(&summaries);
+                    return Ok((tracker, vec![DbChangeEvent::ChatListChanged]));
+                }
+            };
+            let events = tracker.poll(&conn).map_err(|e| e.to_string())?;
+            if let Ok(mut summaries) = summaries.lock() {
+                if let Err(e) = summaries.apply(&conn, &events) {
+                    error!(target: "watcher", "Failed to update chat summaries: {}", e);
+                    summaries.invalidate();
+                }
+            }
+            Ok::<_, String>((tracker, events))
+        })
+        .await;
+
+        let events = match result {
+            Ok(Ok((next_tracker, events))) => {
+                tracker = Some(next_tracker);
+                events
+            }
+            Ok(Err(e)) => {
+                error!(target: "watcher", "Failed to read changes: {}", e);
+                tracker = None;
+                invalidate_summaries(&chat_summaries);
+                vec![DbChangeEvent::ChatListChanged]
+            }
+            Err(e) => {
+                error!(target: "watcher", "Change tracking task failed: {}", e);
+                tracker = None;
+                invalidate_summaries(&chat_summaries);
+                vec![DbChangeEvent::ChatListChanged]
+            }
         };
+
         info!(
             target: "watcher",
-            "Broadcasting db change to {} subscribers",
+            "Broadcasting {} change events to {} subscribers",
+            events.len(),
             tx.receiver_count()
         );
-
-        let _ = tx.send(event);
+        for event in events {
+            let _ = tx.send(event);
+        }
     }
 
     Ok(())
 }
+
+/// Changes we couldn't attribute may have touched any chat
+fn invalidate_summaries(chat_summaries: &ChatSummaryCache) {
+    if let Ok(mut summaries) = chat_summaries.lock() {
+        summaries.invalidate();
+    }
+}
@@ -0,0 +1,610 @@
+use crate::context_db::ContextDb;
+use crate::import_db::{ImportDb, ImportSummary, NewAttachment, NewChat, NewMessage};
+use crate::models::ORIGIN_WHATSAPP_IMPORT;
+use crate::services::contacts::HandleMatcher;
+use chrono::{Local, NaiveDate, TimeZone};
+use std::collections::HashMap;
+
+// ============================================================================
+// WHATSAPP IMPORT
+// ============================================================================
+//
+// "Export chat" writes one text file (`_chat.txt` on iOS, `WhatsApp Chat with
+// <name>.txt` on Android), plus the media files next to it when exported with
+// media. Clients upload the text file and whichever media files they have. Each message starts a line with a timestamp in the phone's locale:
+//
+//   [31/12/2023, 21:41:05] Ann: Happy new year!            (iOS)
+//   [12/31/23, 9:41:05 PM] Ann: ‎<attached: 00000012-PHOTO-2023-12-31-21-41-05.jpg>
+//   12/31/23, 9:41 PM - Ann: Happy new year!               (Android)
+//   31.12.23, 21:41 - Ann: IMG-20231231-WA0001.jpg (file attached)
+//   31.12.23, 21:41 - Ann added Bo                         (system line)
+//
+// - Lines without a timestamp continue the previous message
+// - Day/month order isn't written down; it's inferred from any date whose day
+//   is over 12, falling back to month-first for "/" and day-first otherwise
+// - iOS marks system lines, and media lines, with a left-to-right mark
+// - Senders are names as saved on the phone, or numbers for unsaved contacts.
+//   Nothing marks which sender is me; in a 1:1 export it's whoever isn't the
+//   chat title, otherwise the caller says.
+// - Times are local and often only to the minute, so messages sharing a
+//   timestamp are spaced 1 ms apart in file order. That keeps their order and
+//   lets a re-export of the same chat skip the messages already imported.
+// ============================================================================
+
+const SERVICE: &str = "WhatsApp";
+/// Left-to-right mark
+const LRM: char = '\u{200e}';
+/// Android's marker after a media file name
+const FILE_ATTACHED: &str = " (file attached)";
+
+/// An uploaded export
+pub struct WhatsAppExport {
+    /// Name of the text file, which carries the chat's title on Android and
+    /// in renamed iOS exports, e.g. "WhatsApp Chat - Climbing.txt"
+    pub file_name: Option<String>,
+    pub text: String,
+    /// Media files sent along, by file name
+    pub media: HashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WhatsAppMessage {
+    /// Unix ms
+    pub time: i64,
+    pub sender: String,
+    pub text: Option<String>,
+    /// Media file names, as exported next to the text file
+    pub attachments: Vec<String>,
+}
+
+/// A line's timestamp as written, before the date order is known
+struct Stamp {
+    date: [u32; 3],
+    year_first: bool,
+    separator: char,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+#[derive(Clone, Copy)]
+enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// Messages in an export's text, in file order; system lines are skipped
+pub fn parse_export(text: &str) -> Vec<WhatsAppMessage> {
+    let mut entries: Vec<(Stamp, String)> = Vec::new();
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        match parse_header(line) {
+            Some((stamp, body)) => entries.push((stamp, body.to_string())),
+            None => {
+                if let Some((_, body)) = entries.last_mut() {
+                    body.push('\n');
+                    body.push_str(line);
+                }
+            }
+        }
+    }
+
+    let order = date_order(entries.iter().map(|(stamp, _)| stamp));
+    let mut messages: Vec<WhatsAppMessage> = Vec::new();
+    let mut previous_time = None;
+    let mut offset = 0;
+    for (stamp, body) in entries {
+        let Some(time) = stamp_time(&stamp, order) else {
+            continue;
+        };
+        offset = if previous_time == Some(time) {
+            offset + 1
+        } else {
+            0
+        };
+        previous_time = Some(time);
+
+        let Some((sender, text)) = split_sender(&body) else {
+            continue;
+        };
+        let (text, attachments) = split_attachments(text);
+        messages.push(WhatsAppMessage {
+            time: time + offset,
+            sender,
+            text,
+            attachments,
+        });
+    }
+    messages
+}
+
+/// The timestamp at the start of a message line, and the rest of the line
+fn parse_header(line: &str) -> Option<(Stamp, &str)> {
+    let line = line.trim_start_matches(LRM);
+    let (bracketed, rest) = match line.strip_prefix('[') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    let (first, first_len, rest) = leading_number(rest, 4)?;
+    let separator = rest
+        .chars()
+        .next()
+        .filter(|c| matches!(c, '/' | '.' | '-'))?;
+    let (second, _, rest) = leading_number(&rest[1..], 2)?;
+    let rest = rest.strip_prefix(separator)?;
+    let (third, _, rest) = leading_number(rest, 4)?;
+    let rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+
+    let (mut hour, _, rest) = leading_number(rest, 2)?;
+    let (minute, _, rest) = leading_number(rest.strip_prefix(':')?, 2)?;
+    let (second_of_minute, rest) = match rest.strip_prefix(':') {
+        Some(rest) => {
+            let (second, _, rest) = leading_number(rest, 2)?;
+            (second, rest)
+        }
+        None => (0, rest),
+    };
+    let rest = match strip_meridiem(rest) {
+        Some((pm, rest)) => {
+            hour = match (hour, pm) {
+                (12, false) => 0,
+                (hour, true) if hour < 12 => hour + 12,
+                (hour, _) => hour,
+            };
+            rest
+        }
+        None => rest,
+    };
+
+    let body = if bracketed {
+        rest.strip_prefix("] ")?
+    } else {
+        rest.strip_prefix(" - ")?
+    };
+    Some((
+        Stamp {
+            date: [first, second, third],
+            year_first: first_len == 4,
+            separator,
+            hour,
+            minute,
+            second: second_of_minute,
+        },
+        body,
+    ))
+}
+
+/// Up to `max_digits` leading ASCII digits: (value, digit count, rest)
+fn leading_number(text: &str, max_digits: usize) -> Option<(u32, usize, &str)> {
+    let len = text
+        .bytes()
+        .take(max_digits)
+        .take_while(u8::is_ascii_digit)
+        .count();
+    if len == 0 {
+        return None;
+    }
+    Some((text[..len].parse().ok()?, len, &text[len..]))
+}
+
+/// An AM/PM marker after the time (iOS puts a narrow no-break space before it)
+fn strip_meridiem(text: &str) -> Option<(bool, &str)> {
+    let text = text.trim_start_matches([' ', '\u{202f}', '\u{a0}']);
+    [("am", false), ("pm", true), ("a.m.", false), ("p.m.", true)]
+        .into_iter()
+        .find_map(|(marker, pm)| {
+            let head = text.get(..marker.len())?;
+            head.eq_ignore_ascii_case(marker)
+                .then(|| (pm, &text[marker.len()..]))
+        })
+}
+
+fn date_order<'a>(stamps: impl Iterator<Item = &'a Stamp>) -> DateOrder {
+    let mut slash_separated = false;
+    let mut month_first = false;
+    for stamp in stamps {
+        if stamp.year_first {
+            return DateOrder::YearMonthDay;
+        }
+        if stamp.date[0] > 12 {
+            return DateOrder::DayMonthYear;
+        }
+        month_first |= stamp.date[1] > 12;
+        slash_separated |= stamp.separator == '/';
+    }
+    if month_first || slash_separated {
+        DateOrder::MonthDayYear
+    } else {
+        DateOrder::DayMonthYear
+    }
+}
+
+/// Unix ms of a local timestamp
+fn stamp_time(stamp: &Stamp, order: DateOrder) -> Option<i64> {
+    let [a, b, c] = stamp.date;
+    let (year, month, day) = match order {
+        DateOrder::DayMonthYear => (c, b, a),
+        DateOrder::MonthDayYear => (c, a, b),
+        DateOrder::YearMonthDay => (a, b, c),
+    };
+    let year = if year < 100 { 2000 + year } else { year };
+    let local = NaiveDate::from_ymd_opt(year as i32, month, day)?.and_hms_opt(
+        stamp.hour,
+        stamp.minute,
+        stamp.second,
+    )?;
+    Some(
+        Local
+            .from_local_datetime(&local)
+            .earliest()?
+            .timestamp_millis(),
+    )
+}
+
+/// Sender and text of a message line; None for system lines
+fn split_sender(body: &str) -> Option<(String, &str)> {
+    let (sender, text) = body.split_once(": ")?;
+    let sender = clean_sender(sender);
+    if sender.is_empty() {
+        return None;
+    }
+    // iOS writes "<group>: ‎Ann added Bo"; media lines carry the mark too
+    match text.strip_prefix(LRM) {
+        Some(rest) if !rest.starts_with("<attached: ") && !rest.ends_with(" omitted") => None,
+        Some(rest) => Some((sender, rest)),
+        None => Some((sender, text)),
+    }
+}
+
+/// Drop the direction marks iOS wraps phone numbers in
+fn clean_sender(sender: &str) -> String {
+    sender
+        .chars()
+        .filter(|c| !matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}' | '\u{202c}'))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Text and referenced media files of a message
+fn split_attachments(text: &str) -> (Option<String>, Vec<String>) {
+    let mut attachments = Vec::new();
+    let mut text = text;
+
+    if let Some(rest) = text.strip_prefix("<attached: ") {
+        if let Some((filename, rest)) = rest.split_once('>') {
+            attachments.push(filename.trim().to_string());
+            text = rest;
+        }
+    } else {
+        let (first_line, rest) = text.split_once('\n').unwrap_or((text, ""));
+        if let Some(filename) = first_line.strip_suffix(FILE_ATTACHED) {
+            attachments.push(filename.trim().to_string());
+            text = rest;
+        }
+    }
+
+    let text = text.trim();
+    ((!text.is_empty()).then(|| text.to_string()), attachments)
+}
+
+/// "Ann" from "WhatsApp Chat with Ann" or "WhatsApp Chat - Ann"
+fn chat_title(name: &str) -> Option<String> {
+    ["WhatsApp Chat with ", "WhatsApp Chat - "]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// Which sender is me: `me` if given, else whoever isn't the title of a 1:1 chat.
+/// None if I never wrote.
+fn identify_me(
+    messages: &[WhatsAppMessage],
+    me: Option<&str>,
+    title: Option<&str>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if let Some(me) = me {
+        return Ok(Some(me.trim().to_string()));
+    }
+    let mut senders: Vec<&str> = Vec::new();
+    for message in messages {
+        if !senders.contains(&message.sender.as_str()) {
+            senders.push(&message.sender);
+        }
+    }
+    match (senders.as_slice(), title) {
+        ([only], Some(title)) if *only == title => Ok(None),
+        ([a, b], Some(title)) if *a == title => Ok(Some(b.to_string())),
+        ([a, b], Some(title)) if *b == title => Ok(Some(a.to_string())),
+        _ => Err(
+            "Can't tell which sender is you; pass your name as the export shows it in \"me\""
+                .into(),
+        ),
+    }
+}
+
+fn looks_like_phone_number(sender: &str) -> bool {
+    sender
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '(' | ')' | ' ' | '.' | '\u{a0}'))
+        && sender.chars().filter(char::is_ascii_digit).count() >= 6
+}
+
+/// The handle for a sender: numbers are matched to chat.db handles; names to a
+/// handle already cached under exactly that name, or kept as the handle
+fn sender_handle(
+    sender: &str,
+    matcher: &mut HandleMatcher,
+    context_db: &ContextDb,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if looks_like_phone_number(sender) {
+        return Ok(matcher.resolve(sender));
+    }
+    let named: Vec<String> = context_db
+        .search_cached_contacts_by_name(sender)?
+        .into_iter()
+        .filter(|(_, name)| name.eq_ignore_ascii_case(sender))
+        .map(|(handle, _)| handle)
+        .collect();
+    Ok(match named.as_slice() {
+        [handle] => handle.clone(),
+        _ => sender.to_string(),
+    })
+}
+
+/// Import an uploaded export into the import database. Media copied into
+/// the database comes from `export.media`; messages whose files weren't sent
+/// along still import, their attachments just can't be opened.
+pub fn import_whatsapp_export(
+    mut export: WhatsAppExport,
+    me: Option<&str>,
+    matcher: &mut HandleMatcher,
+    import_db: &mut ImportDb,
+    context_db: &ContextDb,
+) -> Result<ImportSummary, Box<dyn std::error::Error>> {
+    let title = export
+        .file_name
+        .as_deref()
+        .and_then(|name| chat_title(name.strip_suffix(".txt").unwrap_or(name)));
+    let messages = parse_export(&export.text);
+    if messages.is_empty() {
+        return Err("No messages found in the export".into());
+    }
+    let me = identify_me(&messages, me, title.as_deref())?;
+
+    // Sender name -> handle, for everyone but me
+    let mut senders: Vec<(String, String)> = Vec::new();
+    for message in &messages {
+        if Some(&message.sender) == me.as_ref() || senders.iter().any(|(s, _)| *s == message.sender)
+        {
+            continue;
+        }
+        let handle = sender_handle(&message.sender, matcher, context_db)?;
+        senders.push((message.sender.clone(), handle));
+    }
+    let mut handles: Vec<String> = senders.iter().map(|(_, handle)| handle.clone()).collect();
+    handles.sort();
+    handles.dedup();
+
+    // A 1:1 chat is titled with the other person's name
+    let display_name = title
+        .as_deref()
+        .filter(|title| !matches!(senders.as_slice(), [(sender, _)] if sender == title));
+    let source_key = title.clone().unwrap_or_else(|| handles.join(","));
+
+    let ((), summary) = import_db.import(|writer| {
+        let chat_id = writer.chat(&NewChat {
+            origin: ORIGIN_WHATSAPP_IMPORT,
+            source_key: &source_key,
+            display_name,
+            handles: &handles,
+        })?;
+        for message in messages {
+            let is_from_me = Some(&message.sender) == me.as_ref();
+            writer.message(
+                chat_id,
+                NewMessage {
+                    handle: senders
+                        .iter()
+                        .find(|(sender, _)| *sender == message.sender)
+                        .map(|(_, handle)| handle.clone()),
+                    is_from_me,
+                    text: message.text,
+                    time: message.time,
+                    service: SERVICE,
+                    attachments: message
+                        .attachments
+                        .into_iter()
+                        .map(|filename| NewAttachment {
+                            mime_type: mime_type_for(&filename).map(str::to_string),
+                            data: export.media.remove(&filename),
+                            filename: Some(filename),
+                        })
+                        .collect(),
+                },
+            )?;
+        }
+        Ok(())
+    })?;
+
+    // Numbers of people saved on the phone are only known by their name here
+    if let ([(sender, handle)], None) = (senders.as_slice(), display_name) {
+        if sender != handle {
+            context_db.set_cached_contact_name(handle, sender)?;
+        }
+    }
+    Ok(summary)
+}
+
+fn mime_type_for(filename: &str) -> Option<&'static str> {
+    let extension = filename.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "heic" => "image/heic",
+        "mp4" => "video/mp4",
+        "mov" => "video/quicktime",
+        "3gp" => "video/3gpp",
+        "opus" => "audio/ogg",
+        "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "pdf" => "application/pdf",
+        "vcf" => "text/vcard",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_ms(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> i64 {
+        let local = NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap();
+        Local
+            .from_local_datetime(&local)
+            .earliest()
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn parses_ios_exports() {
+        let export = "\u{feff}[12/31/23, 9:41:05\u{202f}PM] Ann Lee: \u{200e}Messages and calls are end-to-end encrypted.\n\
+                      [12/31/23, 9:41:05\u{202f}PM] Ann Lee: Happy new year!\n\
+                      See you soon: tomorrow?\n\
+                      [12/31/23, 9:42:00\u{202f}PM] Me Myself: \u{200e}<attached: 00000012-PHOTO-2023-12-31-21-42-00.jpg>\n\
+                      [1/2/24, 8:00:00\u{202f}AM] \u{202a}+1 (555) 000-1111\u{202c}: \u{200e}image omitted\n";
+        let messages = parse_export(export);
+        assert_eq!(messages.len(), 3);
+
+        assert_eq!(messages[0].sender, "Ann Lee");
+        assert_eq!(
+            messages[0].text.as_deref(),
+            Some("Happy new year!\nSee you soon: tomorrow?")
+        );
+        // Shares its second with the skipped system line
+        assert_eq!(messages[0].time, local_ms(2023, 12, 31, 21, 41, 5) + 1);
+
+        assert_eq!(messages[1].text, None);
+        assert_eq!(
+            messages[1].attachments,
+            vec!["00000012-PHOTO-2023-12-31-21-42-00.jpg"]
+        );
+
+        assert_eq!(messages[2].sender, "+1 (555) 000-1111");
+        assert_eq!(messages[2].text.as_deref(), Some("image omitted"));
+        assert_eq!(messages[2].time, local_ms(2024, 1, 2, 8, 0, 0));
+    }
+
+    #[test]
+    fn parses_android_exports_and_infers_day_first_dates() {
+        let export = "05.01.24, 21:41 - Messages and calls are end-to-end encrypted.\n\
+                      05.01.24, 21:41 - Ann: IMG-20240105-WA0001.jpg (file attached)\n\
+                      look at this\n\
+                      05.01.24, 21:41 - Ann: second\n\
+                      13.01.24, 07:05 - Ann added Bo\n\
+                      13.01.24, 07:05 - Bo: hi all\n";
+        let messages = parse_export(export);
+        assert_eq!(messages.len(), 3);
+
+        assert_eq!(messages[0].attachments, vec!["IMG-20240105-WA0001.jpg"]);
+        assert_eq!(messages[0].text.as_deref(), Some("look at this"));
+        assert_eq!(messages[0].time, local_ms(2024, 1, 5, 21, 41, 0) + 1);
+        assert_eq!(messages[1].time, local_ms(2024, 1, 5, 21, 41, 0) + 2);
+        assert_eq!(messages[2].sender, "Bo");
+        assert_eq!(messages[2].time, local_ms(2024, 1, 13, 7, 5, 0) + 1);
+    }
+
+    #[test]
+    fn imports_one_to_one_chats_and_skips_reimported_messages() {
+        let export = |text: &str| WhatsAppExport {
+            file_name: Some("WhatsApp Chat with Ann.txt".to_string()),
+            text: text.to_string(),
+            media: HashMap::from([("IMG-20240105-WA0001.jpg".to_string(), b"jpeg".to_vec())]),
+        };
+
+        let chat_db = rusqlite::Connection::open_in_memory().unwrap();
+        chat_db
+            .execute_batch("CREATE TABLE handle (ROWID INTEGER PRIMARY KEY, id TEXT);")
+            .unwrap();
+        let mut matcher = HandleMatcher::load(&chat_db).unwrap();
+        let mut import_db = ImportDb::open_in_memory().unwrap();
+        let context_db = ContextDb::open_in_memory().unwrap();
+
+        let summary = import_whatsapp_export(
+            export(
+                "1/5/24, 9:41 PM - Ann: IMG-20240105-WA0001.jpg (file attached)\n\
+                 1/5/24, 9:42 PM - Sam: nice\n",
+            ),
+            None,
+            &mut matcher,
+            &mut import_db,
+            &context_db,
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                chats: 1,
+                messages: 2,
+                duplicates: 0,
+                attachments: 1
+            }
+        );
+        let chats = import_db.chat_rows().unwrap();
+        assert_eq!(chats[0].handles, vec!["Ann"]);
+        assert_eq!(chats[0].display_name, None);
+        assert_eq!(chats[0].last_message_is_from_me, Some(true));
+
+        let page = import_db
+            .fetch_messages(chats[0].chat_id, &context_db, 10, 0)
+            .unwrap();
+        let attachment = &page.messages[0].attachments[0];
+        assert_eq!(attachment.mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(
+            import_db.fetch_attachment(attachment.id).unwrap(),
+            Some((b"jpeg".to_vec(), Some("image/jpeg".to_string())))
+        );
+
+        // Exported again, a day later
+        let summary = import_whatsapp_export(
+            export(
+                "1/5/24, 9:41 PM - Ann: IMG-20240105-WA0001.jpg (file attached)\n\
+                 1/5/24, 9:42 PM - Sam: nice\n\
+                 1/6/24, 10:00 AM - Ann: morning\n",
+            ),
+            None,
+            &mut matcher,
+            &mut import_db,
+            &context_db,
+        )
+        .unwrap();
+        assert_eq!(
+            (summary.chats, summary.messages, summary.duplicates),
+            (0, 1, 2)
+        );
+    }
+
+    #[test]
+    fn group_exports_need_to_know_who_i_am() {
+        let messages = parse_export(
+            "[1/5/24, 9:41:00 PM] Ann: hi\n\
+             [1/5/24, 9:42:00 PM] Bo: hey\n\
+             [1/5/24, 9:43:00 PM] Sam: yo\n",
+        );
+        assert!(identify_me(&messages, None, Some("Climbing")).is_err());
+        assert_eq!(
+            identify_me(&messages, Some("Sam"), Some("Climbing"))
+                .unwrap()
+                .as_deref(),
+            Some("Sam")
+        );
+    }
+}
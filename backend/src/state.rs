@@ -1,5 +1,6 @@
 use crate::extraction::MessageForExtraction;
 use crate::openrouter::OpenRouterClient;
+use crate::services::chat_summaries::ChatSummaries;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use serde::Serialize;
@@ -9,12 +10,36 @@ use std::time::{Duration, Instant};
 use tokio::sync::{broadcast, mpsc};
 
 pub type SuggestionCache = Arc<Mutex<HashMap<i64, SuggestionCacheEntry>>>;
+/// Materialized chat list, kept current by the file watcher
+pub type ChatSummaryCache = Arc<Mutex<ChatSummaries>>;
 
-/// Event sent to all connected WebSocket clients when the database changes
+/// Event broadcast when the database changes, so subscribers can update only
+/// what changed. Produced by the watcher's `ChangeTracker`.
 #[derive(Clone, Debug, Serialize)]
-pub struct DbChangeEvent {
-    /// Timestamp when the change was detected (Unix ms)
-    pub timestamp: i64,
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DbChangeEvent {
+    /// Messages (including timeline events) added to a chat
+    NewMessages { chat_id: i64, rowids: Vec<i64> },
+    /// Tapbacks added or removed on these messages
+    ReactionChanged { chat_id: i64, message_guids: Vec<String> },
+    /// Existing messages changed in place: edited, unsent, delivered, read, or
+    /// gained an attachment
+    MessageEdited { chat_id: i64, rowids: Vec<i64> },
+    /// Chat-level change with no specific message (renames, deletions, contact
+    /// names resolved), or a change the tracker couldn't attribute
+    ChatListChanged,
+}
+
+impl DbChangeEvent {
+    /// Chat the event belongs to, if it is about specific messages
+    pub fn chat_id(&self) -> Option<i64> {
+        match self {
+            DbChangeEvent::NewMessages { chat_id, .. }
+            | DbChangeEvent::ReactionChanged { chat_id, .. }
+            | DbChangeEvent::MessageEdited { chat_id, .. } => Some(*chat_id),
+            DbChangeEvent::ChatListChanged => None,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -22,6 +47,7 @@ pub struct AppState {
     pub chat_pool: Pool<SqliteConnectionManager>,
     pub contact_resolve_tx: mpsc::Sender<String>,
     pub suggestion_cache: SuggestionCache,
+    pub chat_summaries: ChatSummaryCache,
     pub assist_client_primary: OpenRouterClient,
     pub assist_client_fallback: OpenRouterClient,
     /// Broadcast channel to notify WebSocket clients of database changes
@@ -0,0 +1,845 @@
+//! Decoder for the `typedstream` archives stored in `message.attributedBody`.
+//!
+//! Messages serializes rich text as an `NSAttributedString` using the legacy
+//! NeXTSTEP `NSArchiver` format ("streamtyped"). The stream is a sequence of
+//! typed groups: a type encoding string (e.g. `@`, `iI`, `+`) followed by one
+//! value per type character. Type strings and class names live in a shared
+//! string table, while objects and classes share an object table; both can be
+//! back-referenced later in the stream by index.
+//!
+//! `decode_attributed_body` walks that structure and returns the exact message
+//! text together with its attribute runs (message parts, mentions, links,
+//! formatting and inline attachment placeholders).
+
+use crate::models::TextRun;
+use std::rc::Rc;
+
+const I16: u8 = 0x81;
+const I32: u8 = 0x82;
+const REAL: u8 = 0x83;
+const START: u8 = 0x84;
+const EMPTY: u8 = 0x85;
+const END: u8 = 0x86;
+const REFERENCE_TAG: u8 = 0x92;
+
+const STREAM_SIGNATURE: &[u8] = b"streamtyped";
+
+/// Deepest nesting of objects, or of array and struct types, a stream may use.
+/// Message bodies stay under ten levels; this keeps a malformed blob from
+/// recursing until the stack overflows.
+const MAX_DEPTH: usize = 64;
+
+/// Typedstream decoding errors
+#[derive(Debug)]
+pub enum TypedStreamError {
+    /// The stream ended in the middle of a value
+    UnexpectedEof(usize),
+    /// Missing or unknown `streamtyped` header
+    InvalidHeader,
+    /// A back-reference pointed outside the shared string or object table
+    InvalidReference(usize),
+    /// A type encoding character we don't know how to read
+    UnsupportedType(char),
+    /// The archive decoded but its root is not an `NSAttributedString`
+    NotAttributedString,
+    /// Objects or types nested deeper than `MAX_DEPTH`
+    TooDeep(usize),
+}
+
+impl std::fmt::Display for TypedStreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypedStreamError::UnexpectedEof(pos) => {
+                write!(f, "Unexpected end of typedstream at byte {}", pos)
+            }
+            TypedStreamError::InvalidHeader => write!(f, "Missing streamtyped header"),
+            TypedStreamError::InvalidReference(pos) => {
+                write!(f, "Invalid back-reference at byte {}", pos)
+            }
+            TypedStreamError::UnsupportedType(c) => write!(f, "Unsupported type encoding '{}'", c),
+            TypedStreamError::NotAttributedString => {
+                write!(f, "Archive root is not an NSAttributedString")
+            }
+            TypedStreamError::TooDeep(pos) => {
+                write!(f, "Typedstream nested more than {} levels at byte {}", MAX_DEPTH, pos)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TypedStreamError {}
+
+/// Decoded `attributedBody`: the message text plus the attribute runs covering it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributedBody {
+    pub text: String,
+    pub runs: Vec<TextRun>,
+}
+
+/// Decode an `attributedBody` blob into its text and attribute runs
+pub fn decode_attributed_body(data: &[u8]) -> Result<AttributedBody, TypedStreamError> {
+    let values = Reader::new(data).read_archive()?;
+    let root = values
+        .iter()
+        .find_map(|value| match value {
+            Value::Object(Some(object)) if object.is_kind_of("NSAttributedString") => Some(object),
+            _ => None,
+        })
+        .ok_or(TypedStreamError::NotAttributedString)?;
+
+    let mut fields = root.fields.iter();
+    let text = match fields.next() {
+        Some(Value::Object(Some(string))) => string
+            .string_value()
+            .ok_or(TypedStreamError::NotAttributedString)?
+            .to_string(),
+        _ => return Err(TypedStreamError::NotAttributedString),
+    };
+
+    // Runs are written as ("iI" dictionary index, UTF-16 length) pairs. The first
+    // time an index appears, the attribute dictionary object follows the pair.
+    let mut dictionaries: Vec<Option<&Object>> = Vec::new();
+    let mut runs = Vec::new();
+    let mut cursor = 0usize;
+    while let Some(Value::Int(index)) = fields.next() {
+        let Some(Value::Int(length)) = fields.next() else {
+            break;
+        };
+        let index = *index as usize;
+        if index > dictionaries.len() {
+            match fields.next() {
+                Some(Value::Object(dictionary)) => dictionaries.push(dictionary.as_deref()),
+                _ => break,
+            }
+        }
+
+        let length = (*length).max(0) as usize;
+        let attributes = index
+            .checked_sub(1)
+            .and_then(|i| dictionaries.get(i).copied().flatten());
+        runs.push(build_run(cursor, length, attributes));
+        cursor += length;
+    }
+
+    Ok(AttributedBody { text, runs })
+}
+
+fn build_run(start: usize, length: usize, attributes: Option<&Object>) -> TextRun {
+    let mut run = TextRun {
+        start,
+        length,
+        ..Default::default()
+    };
+
+    let Some(attributes) = attributes else {
+        return run;
+    };
+
+    for (key, value) in attributes.dictionary_entries() {
+        let Some(value) = value else {
+            continue;
+        };
+        match key {
+            "__kIMMessagePartAttributeName" => run.part = value.number_value().map(|n| n as i64),
+            "__kIMMentionConfirmedMention" => run.mention = value.string_value().map(str::to_string),
+            "__kIMLinkAttributeName" => run.link = value.string_value().map(str::to_string),
+            "__kIMFileTransferGUIDAttributeName" => {
+                run.attachment_guid = value.string_value().map(str::to_string)
+            }
+            "__kIMTextBoldAttributeName" => run.bold = value.number_value().unwrap_or(0.0) != 0.0,
+            "__kIMTextItalicAttributeName" => {
+                run.italic = value.number_value().unwrap_or(0.0) != 0.0
+            }
+            "__kIMTextUnderlineAttributeName" => {
+                run.underline = value.number_value().unwrap_or(0.0) != 0.0
+            }
+            "__kIMTextStrikethroughAttributeName" => {
+                run.strikethrough = value.number_value().unwrap_or(0.0) != 0.0
+            }
+            _ => {}
+        }
+    }
+
+    run
+}
+
+// ============================================================================
+// Archive model
+// ============================================================================
+
+// Only text, numbers and objects are inspected today; the other payloads are
+// still decoded so the reader stays aligned and shows up in `Debug` output.
+// Objects and classes are shared with the object table, so a back-reference
+// costs a pointer rather than a copy of the whole tree.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    Float(f64),
+    /// `+`: UTF-8 string contents of an NSString
+    Text(String),
+    /// `*`, `%` and `:`: C strings stored in the shared string table
+    CString(Option<String>),
+    Object(Option<Rc<Object>>),
+    Class(Option<Rc<[String]>>),
+    Bytes(Vec<u8>),
+    Array(Vec<Value>),
+}
+
+#[derive(Debug, Clone)]
+struct Object {
+    /// Class hierarchy, most derived first
+    classes: Rc<[String]>,
+    fields: Vec<Value>,
+}
+
+impl Object {
+    fn is_kind_of(&self, class: &str) -> bool {
+        self.classes.iter().any(|name| name == class)
+    }
+
+    /// Text of an NSString, or of the NSString inside a wrapper like NSURL
+    fn string_value(&self) -> Option<&str> {
+        self.text_field().or_else(|| {
+            self.fields.iter().find_map(|field| match field {
+                Value::Object(Some(inner)) => inner.text_field(),
+                _ => None,
+            })
+        })
+    }
+
+    fn text_field(&self) -> Option<&str> {
+        self.fields.iter().find_map(|field| match field {
+            Value::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Value of an NSNumber (its fields are the objCType C string then the number)
+    fn number_value(&self) -> Option<f64> {
+        self.fields.iter().find_map(|field| match field {
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        })
+    }
+
+    /// Key/value pairs of an NSDictionary (fields are the count, then alternating keys and values)
+    fn dictionary_entries(&self) -> Vec<(&str, Option<&Object>)> {
+        if !self.is_kind_of("NSDictionary") {
+            return Vec::new();
+        }
+        self.fields
+            .iter()
+            .skip(1)
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .filter_map(|pair| match pair {
+                [Value::Object(Some(key)), Value::Object(value)] => {
+                    Some((key.string_value()?, value.as_deref()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum TypeCode {
+    Object,
+    Text,
+    CString,
+    Class,
+    Char(bool),
+    Signed,
+    Unsigned,
+    Float,
+    Double,
+    Array(usize, Box<TypeCode>),
+    Struct(Vec<TypeCode>),
+}
+
+fn parse_types(encoding: &[u8]) -> Result<Vec<TypeCode>, TypedStreamError> {
+    let mut pos = 0;
+    let mut types = Vec::new();
+    while pos < encoding.len() {
+        types.push(parse_type(encoding, &mut pos, 0)?);
+    }
+    Ok(types)
+}
+
+fn parse_type(encoding: &[u8], pos: &mut usize, depth: usize) -> Result<TypeCode, TypedStreamError> {
+    if depth > MAX_DEPTH {
+        return Err(TypedStreamError::TooDeep(*pos));
+    }
+    let byte = *encoding
+        .get(*pos)
+        .ok_or(TypedStreamError::UnsupportedType('\0'))?;
+    *pos += 1;
+
+    let code = match byte {
+        b'@' => TypeCode::Object,
+        b'+' => TypeCode::Text,
+        b'*' | b'%' | b':' => TypeCode::CString,
+        b'#' => TypeCode::Class,
+        b'c' => TypeCode::Char(true),
+        b'C' => TypeCode::Char(false),
+        b's' | b'i' | b'l' | b'q' | b'B' => TypeCode::Signed,
+        b'S' | b'I' | b'L' | b'Q' => TypeCode::Unsigned,
+        b'f' => TypeCode::Float,
+        b'd' => TypeCode::Double,
+        b'[' => {
+            let digits_start = *pos;
+            while encoding.get(*pos).is_some_and(u8::is_ascii_digit) {
+                *pos += 1;
+            }
+            let count = std::str::from_utf8(&encoding[digits_start..*pos])
+                .ok()
+                .and_then(|digits| digits.parse().ok())
+                .ok_or(TypedStreamError::UnsupportedType('['))?;
+            let element = parse_type(encoding, pos, depth + 1)?;
+            if encoding.get(*pos) != Some(&b']') {
+                return Err(TypedStreamError::UnsupportedType('['));
+            }
+            *pos += 1;
+            TypeCode::Array(count, Box::new(element))
+        }
+        b'{' => {
+            // Skip the struct name up to '=' and read members until '}'
+            while encoding.get(*pos).is_some_and(|b| *b != b'=' && *b != b'}') {
+                *pos += 1;
+            }
+            if encoding.get(*pos) == Some(&b'=') {
+                *pos += 1;
+            }
+            let mut members = Vec::new();
+            while encoding.get(*pos).is_some_and(|b| *b != b'}') {
+                members.push(parse_type(encoding, pos, depth + 1)?);
+            }
+            *pos += 1;
+            TypeCode::Struct(members)
+        }
+        other => return Err(TypedStreamError::UnsupportedType(other as char)),
+    };
+    Ok(code)
+}
+
+enum Entry {
+    /// Reserved slot for an object or class that is still being decoded
+    Placeholder,
+    Class(Rc<[String]>),
+    Object(Rc<Object>),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    strings: Vec<Vec<u8>>,
+    objects: Vec<Entry>,
+    /// Objects and classes currently being decoded, innermost last
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader {
+            data,
+            pos: 0,
+            strings: Vec::new(),
+            objects: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn read_archive(&mut self) -> Result<Vec<Value>, TypedStreamError> {
+        let _version = self.read_int(false)?;
+        let signature_len = self.read_int(false)? as usize;
+        if self.take(signature_len)? != STREAM_SIGNATURE {
+            return Err(TypedStreamError::InvalidHeader);
+        }
+        let _system_version = self.read_int(true)?;
+
+        let mut values = Vec::new();
+        while self.pos < self.data.len() {
+            if self.peek()? == END {
+                self.pos += 1;
+                continue;
+            }
+            values.extend(self.read_group()?);
+        }
+        Ok(values)
+    }
+
+    /// Step into a nested object or class, failing past `MAX_DEPTH`
+    fn enter(&mut self) -> Result<(), TypedStreamError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(TypedStreamError::TooDeep(self.pos));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn peek(&self) -> Result<u8, TypedStreamError> {
+        self.data
+            .get(self.pos)
+            .copied()
+            .ok_or(TypedStreamError::UnexpectedEof(self.pos))
+    }
+
+    fn next(&mut self) -> Result<u8, TypedStreamError> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], TypedStreamError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(TypedStreamError::UnexpectedEof(self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Integers are a single byte unless prefixed with the 16- or 32-bit tag
+    fn read_int(&mut self, signed: bool) -> Result<i64, TypedStreamError> {
+        let value = match self.next()? {
+            I16 => {
+                let bytes = [self.next()?, self.next()?];
+                if signed {
+                    i16::from_le_bytes(bytes) as i64
+                } else {
+                    u16::from_le_bytes(bytes) as i64
+                }
+            }
+            I32 => {
+                let bytes = [self.next()?, self.next()?, self.next()?, self.next()?];
+                if signed {
+                    i32::from_le_bytes(bytes) as i64
+                } else {
+                    u32::from_le_bytes(bytes) as i64
+                }
+            }
+            byte if signed => byte as i8 as i64,
+            byte => byte as i64,
+        };
+        Ok(value)
+    }
+
+    /// Back-references are table indices offset by `REFERENCE_TAG`
+    fn read_reference(&mut self) -> Result<usize, TypedStreamError> {
+        let start = self.pos;
+        let value = match self.peek()? {
+            I16 | I32 => self.read_int(false)?,
+            _ => self.next()? as i64,
+        };
+        value
+            .checked_sub(REFERENCE_TAG as i64)
+            .filter(|index| *index >= 0)
+            .map(|index| index as usize)
+            .ok_or(TypedStreamError::InvalidReference(start))
+    }
+
+    fn read_shared_string(&mut self) -> Result<Option<usize>, TypedStreamError> {
+        match self.peek()? {
+            EMPTY => {
+                self.pos += 1;
+                Ok(None)
+            }
+            START => {
+                self.pos += 1;
+                self.read_new_shared_string().map(Some)
+            }
+            _ => {
+                let start = self.pos;
+                let index = self.read_reference()?;
+                if index >= self.strings.len() {
+                    return Err(TypedStreamError::InvalidReference(start));
+                }
+                Ok(Some(index))
+            }
+        }
+    }
+
+    fn read_new_shared_string(&mut self) -> Result<usize, TypedStreamError> {
+        let len = self.read_int(false)? as usize;
+        let bytes = self.take(len)?.to_vec();
+        self.strings.push(bytes);
+        Ok(self.strings.len() - 1)
+    }
+
+    fn shared_string(&self, index: usize) -> String {
+        String::from_utf8_lossy(&self.strings[index]).into_owned()
+    }
+
+    fn read_c_string(&mut self) -> Result<Option<String>, TypedStreamError> {
+        let index = match self.peek()? {
+            EMPTY => {
+                self.pos += 1;
+                return Ok(None);
+            }
+            START => {
+                self.pos += 1;
+                match self.peek()? {
+                    byte if byte == START || byte == EMPTY || byte >= REFERENCE_TAG => {
+                        self.read_shared_string()?
+                    }
+                    _ => Some(self.read_new_shared_string()?),
+                }
+            }
+            _ => self.read_shared_string()?,
+        };
+        Ok(index.map(|index| self.shared_string(index)))
+    }
+
+    fn read_class(&mut self) -> Result<Option<Rc<[String]>>, TypedStreamError> {
+        match self.peek()? {
+            EMPTY => {
+                self.pos += 1;
+                Ok(None)
+            }
+            START => {
+                self.pos += 1;
+                self.enter()?;
+                let name_index = self
+                    .read_shared_string()?
+                    .ok_or(TypedStreamError::InvalidReference(self.pos))?;
+                let _version = self.read_int(true)?;
+
+                let slot = self.objects.len();
+                self.objects.push(Entry::Placeholder);
+                let mut classes = vec![self.shared_string(name_index)];
+                if let Some(superclasses) = self.read_class()? {
+                    classes.extend(superclasses.iter().cloned());
+                }
+                let classes: Rc<[String]> = classes.into();
+                self.objects[slot] = Entry::Class(classes.clone());
+                self.depth -= 1;
+                Ok(Some(classes))
+            }
+            _ => {
+                let start = self.pos;
+                let index = self.read_reference()?;
+                match self.objects.get(index) {
+                    Some(Entry::Class(classes)) => Ok(Some(classes.clone())),
+                    _ => Err(TypedStreamError::InvalidReference(start)),
+                }
+            }
+        }
+    }
+
+    fn read_object(&mut self) -> Result<Option<Rc<Object>>, TypedStreamError> {
+        match self.peek()? {
+            EMPTY => {
+                self.pos += 1;
+                Ok(None)
+            }
+            START => {
+                self.pos += 1;
+                self.enter()?;
+                let slot = self.objects.len();
+                self.objects.push(Entry::Placeholder);
+                let classes = self
+                    .read_class()?
+                    .ok_or(TypedStreamError::InvalidReference(self.pos))?;
+
+                let mut fields = Vec::new();
+                while self.peek()? != END {
+                    fields.extend(self.read_group()?);
+                }
+                self.pos += 1;
+
+                let object = Rc::new(Object { classes, fields });
+                self.objects[slot] = Entry::Object(object.clone());
+                self.depth -= 1;
+                Ok(Some(object))
+            }
+            _ => {
+                let start = self.pos;
+                let index = self.read_reference()?;
+                match self.objects.get(index) {
+                    Some(Entry::Object(object)) => Ok(Some(object.clone())),
+                    // A reference to an object that is still being decoded (a cycle)
+                    Some(Entry::Placeholder) => Ok(None),
+                    _ => Err(TypedStreamError::InvalidReference(start)),
+                }
+            }
+        }
+    }
+
+    fn read_group(&mut self) -> Result<Vec<Value>, TypedStreamError> {
+        let index = self
+            .read_shared_string()?
+            .ok_or(TypedStreamError::InvalidReference(self.pos))?;
+        let types = parse_types(&self.strings[index].clone())?;
+        types.iter().map(|code| self.read_value(code)).collect()
+    }
+
+    fn read_value(&mut self, code: &TypeCode) -> Result<Value, TypedStreamError> {
+        let value = match code {
+            TypeCode::Object => Value::Object(self.read_object()?),
+            TypeCode::Text => {
+                let len = self.read_int(false)? as usize;
+                Value::Text(String::from_utf8_lossy(self.take(len)?).into_owned())
+            }
+            TypeCode::CString => Value::CString(self.read_c_string()?),
+            TypeCode::Class => Value::Class(self.read_class()?),
+            TypeCode::Char(signed) => Value::Int(self.read_int(*signed)?),
+            TypeCode::Signed => Value::Int(self.read_int(true)?),
+            TypeCode::Unsigned => Value::Int(self.read_int(false)?),
+            TypeCode::Float | TypeCode::Double => {
+                if self.peek()? == REAL {
+                    self.pos += 1;
+                    if matches!(code, TypeCode::Float) {
+                        let bytes = self.take(4)?;
+                        Value::Float(f32::from_le_bytes(bytes.try_into().unwrap()) as f64)
+                    } else {
+                        let bytes = self.take(8)?;
+                        Value::Float(f64::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                } else {
+                    Value::Float(self.read_int(true)? as f64)
+                }
+            }
+            TypeCode::Array(count, element) => match element.as_ref() {
+                TypeCode::Char(_) => Value::Bytes(self.take(*count)?.to_vec()),
+                element => Value::Array(
+                    (0..*count)
+                        .map(|_| self.read_value(element))
+                        .collect::<Result<_, _>>()?,
+                ),
+            },
+            TypeCode::Struct(members) => Value::Array(
+                members
+                    .iter()
+                    .map(|member| self.read_value(member))
+                    .collect::<Result<_, _>>()?,
+            ),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixtures are hand-assembled to the layout Messages writes, not captured
+    /// from a real chat.db
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = format!(
+            "{}/fixtures/attributed_body/{}.bin",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read(&path).unwrap_or_else(|e| panic!("missing fixture {}: {}", path, e))
+    }
+
+    fn run_text(body: &AttributedBody, run: &TextRun) -> String {
+        let units: Vec<u16> = body.text.encode_utf16().collect();
+        String::from_utf16(&units[run.start..run.start + run.length]).unwrap()
+    }
+
+    fn assert_runs_cover_text(body: &AttributedBody) {
+        let covered: usize = body.runs.iter().map(|run| run.length).sum();
+        assert_eq!(covered, body.text.encode_utf16().count());
+    }
+
+    #[test]
+    fn decodes_plain_message() {
+        let body = decode_attributed_body(&fixture("plain")).unwrap();
+        assert_eq!(body.text, "hello");
+        assert_eq!(body.runs.len(), 1);
+        assert_eq!(body.runs[0].part, Some(0));
+        assert_runs_cover_text(&body);
+    }
+
+    #[test]
+    fn decodes_long_message_with_wide_length() {
+        let body = decode_attributed_body(&fixture("long")).unwrap();
+        assert!(body.text.starts_with("Okay so here's the full plan"));
+        assert!(body.text.ends_with("See you all there!"));
+        assert!(body.text.len() > 255);
+        assert_runs_cover_text(&body);
+    }
+
+    #[test]
+    fn decodes_emoji_with_utf16_run_lengths() {
+        let body = decode_attributed_body(&fixture("emoji")).unwrap();
+        assert_eq!(body.text, "Happy birthday 🎂🎉 see you tonight! 👨‍👩‍👧 ❤️");
+        assert_runs_cover_text(&body);
+    }
+
+    #[test]
+    fn keeps_text_that_mentions_class_names() {
+        let body = decode_attributed_body(&fixture("nsstring_literal")).unwrap();
+        assert_eq!(
+            body.text,
+            "why does my parser keep printing NSString and NSDictionary instead of the text"
+        );
+    }
+
+    #[test]
+    fn decodes_mentions_and_reused_dictionaries() {
+        let body = decode_attributed_body(&fixture("mention")).unwrap();
+        assert_eq!(body.text, "Hey Alex, are you coming tonight?");
+        assert_eq!(body.runs.len(), 3);
+        assert_eq!(run_text(&body, &body.runs[1]), "Alex");
+        assert_eq!(body.runs[1].mention.as_deref(), Some("+15555550123"));
+        assert_eq!(body.runs[1].part, Some(0));
+        assert_eq!(body.runs[2].mention, None);
+        assert_eq!(body.runs[2].part, Some(0));
+        assert_runs_cover_text(&body);
+    }
+
+    #[test]
+    fn decodes_links() {
+        let body = decode_attributed_body(&fixture("link")).unwrap();
+        let link = body.runs.iter().find(|run| run.link.is_some()).unwrap();
+        assert_eq!(
+            link.link.as_deref(),
+            Some("https://example.com/trail?id=42&ref=msg")
+        );
+        assert_eq!(run_text(&body, link), "https://example.com/trail?id=42&ref=msg");
+        assert_eq!(link.part, Some(0));
+    }
+
+    #[test]
+    fn decodes_text_formatting() {
+        let body = decode_attributed_body(&fixture("formatting")).unwrap();
+        assert_eq!(
+            body.text,
+            "this is bold and italic and underlined and struck"
+        );
+        let styled: Vec<(String, bool, bool, bool, bool)> = body
+            .runs
+            .iter()
+            .filter(|run| run.bold || run.italic || run.underline || run.strikethrough)
+            .map(|run| {
+                (
+                    run_text(&body, run),
+                    run.bold,
+                    run.italic,
+                    run.underline,
+                    run.strikethrough,
+                )
+            })
+            .collect();
+        assert_eq!(
+            styled,
+            vec![
+                ("bold".to_string(), true, false, false, false),
+                ("italic".to_string(), false, true, false, false),
+                ("underlined".to_string(), false, false, true, false),
+                ("struck".to_string(), false, false, false, true),
+            ]
+        );
+        assert_runs_cover_text(&body);
+    }
+
+    #[test]
+    fn decodes_inline_attachment_placeholders() {
+        let body = decode_attributed_body(&fixture("attachment")).unwrap();
+        assert_eq!(body.text, "\u{FFFC}look at this view 🏔");
+        assert_eq!(body.runs.len(), 2);
+        assert_eq!(
+            body.runs[0].attachment_guid.as_deref(),
+            Some("at_0_5A1B2C3D-1111-2222-3333-444455556666")
+        );
+        assert_eq!(body.runs[0].length, 1);
+        assert_eq!(body.runs[1].part, Some(1));
+        assert_runs_cover_text(&body);
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign_blobs() {
+        let blob = fixture("mention");
+        assert!(decode_attributed_body(&blob[..blob.len() / 2]).is_err());
+        assert!(decode_attributed_body(b"bplist00\xd1\x01\x02").is_err());
+        assert!(decode_attributed_body(&[]).is_err());
+    }
+
+    /// The stream header, then the opening of a root `NSObject` in object
+    /// table slot 0. Its class takes slot 1; the strings "@" and "NSObject"
+    /// take indices 0 and 1.
+    fn root_object() -> Vec<u8> {
+        let mut blob = vec![0x04, 0x0b];
+        blob.extend_from_slice(STREAM_SIGNATURE);
+        blob.extend_from_slice(&[I16, 0xe8, 0x03, START, 0x01, b'@', START, START, START, 0x08]);
+        blob.extend_from_slice(b"NSObject");
+        blob.extend_from_slice(&[0x00, EMPTY]);
+        blob
+    }
+
+    #[test]
+    fn rejects_deeply_nested_objects() {
+        // Each object holds another of the same class until `depth` levels
+        let nested = |depth: usize| {
+            let mut blob = root_object();
+            for _ in 1..depth {
+                blob.extend_from_slice(&[REFERENCE_TAG, START, REFERENCE_TAG + 1]);
+            }
+            blob.extend(std::iter::repeat_n(END, depth));
+            blob
+        };
+        assert!(matches!(
+            decode_attributed_body(&nested(10)),
+            Err(TypedStreamError::NotAttributedString)
+        ));
+        assert!(matches!(
+            decode_attributed_body(&nested(100_000)),
+            Err(TypedStreamError::TooDeep(_))
+        ));
+
+        let mut types = vec![0x04, 0x0b];
+        types.extend_from_slice(STREAM_SIGNATURE);
+        types.extend_from_slice(&[I16, 0xe8, 0x03, START, I16, 0x00, 0x28]);
+        for _ in 0..0x1400 {
+            types.extend_from_slice(b"{=");
+        }
+        assert!(matches!(
+            decode_attributed_body(&types),
+            Err(TypedStreamError::TooDeep(_))
+        ));
+    }
+
+    #[test]
+    fn shares_self_and_repeated_references() {
+        // The root holds a reference to itself, which is still being decoded
+        let mut blob = root_object();
+        blob.extend_from_slice(&[REFERENCE_TAG, REFERENCE_TAG, END]);
+        let values = Reader::new(&blob).read_archive().unwrap();
+        let [Value::Object(Some(root))] = values.as_slice() else {
+            panic!("expected one root object, got {:?}", values);
+        };
+        assert!(matches!(root.fields.as_slice(), [Value::Object(None)]));
+
+        // Each object holds its predecessor twice: 2^60 objects if copied
+        let mut blob = root_object();
+        blob.extend_from_slice(&[REFERENCE_TAG, START, REFERENCE_TAG + 1, END]);
+        for slot in 3..63u8 {
+            blob.extend_from_slice(&[REFERENCE_TAG, START, REFERENCE_TAG + 1]);
+            if slot == 3 {
+                blob.extend_from_slice(&[START, 0x02, b'@', b'@']);
+            } else {
+                blob.push(REFERENCE_TAG + 2);
+            }
+            blob.extend_from_slice(&[REFERENCE_TAG + slot - 1, REFERENCE_TAG + slot - 1, END]);
+        }
+        blob.push(END);
+        let values = Reader::new(&blob).read_archive().unwrap();
+        let [Value::Object(Some(root))] = values.as_slice() else {
+            panic!("expected one root object");
+        };
+        assert_eq!(root.fields.len(), 61);
+        let Value::Object(Some(last)) = &root.fields[60] else {
+            panic!("expected an object");
+        };
+        match last.fields.as_slice() {
+            [Value::Object(Some(a)), Value::Object(Some(b))] => assert!(Rc::ptr_eq(a, b)),
+            fields => panic!("expected two references, got {} fields", fields.len()),
+        }
+    }
+}